@@ -0,0 +1,28 @@
+use std::{env, fs, io, process};
+
+use small_db::{sql::repl::run_repl, Database};
+
+/// Interactive shell for local experimentation, SQL statements are read from
+/// stdin and the results are printed as ASCII grids.
+///
+/// Usage: `small-db-cli [path]`, the data files are kept under
+/// `<path>/data`, `path` defaults to the current directory.
+fn main() {
+    if let Some(path) = env::args().nth(1) {
+        fs::create_dir_all(&path).unwrap();
+        env::set_current_dir(&path).unwrap();
+    }
+
+    Database::reset();
+
+    let stdin = io::stdin();
+    if let Err(e) = run_repl(stdin.lock(), io::stdout(), true) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = Database::close() {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -1,21 +1,26 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
     fs::File,
-    io::{self, prelude::*, Seek, SeekFrom},
+    hash::{Hash, Hasher},
+    io::{prelude::*, Seek, SeekFrom},
+    mem,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc, RwLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
     },
 };
 
+use log::error;
+
 use super::page::{
-    BTreeHeaderPage, BTreeInternalPage, BTreeLeafPage, BTreePage, BTreePageID,
+    BTreeBasePage, BTreeHeaderPage, BTreeInternalPage, BTreeLeafPage, BTreePage, BTreePageID,
     BTreeRootPointerPage, PageCategory,
 };
 use crate::{
-    error::SmallError,
-    transaction::{ConcurrentStatus, LogManager, Permission, Transaction},
-    types::ResultPod,
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::Cell,
+    transaction::{ConcurrentStatus, Lock, LogManager, Permission, Transaction},
+    types::{ResultPod, SmallResult},
     utils::HandyRwLock,
     BTreeTable, Database, TableSchema,
 };
@@ -23,28 +28,230 @@ use crate::{
 pub const DEFAULT_PAGE_SIZE: usize = 4096;
 static PAGE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_PAGE_SIZE);
 
+pub const DEFAULT_SHARDS_COUNT: usize = 16;
+static SHARDS_COUNT: AtomicUsize = AtomicUsize::new(DEFAULT_SHARDS_COUNT);
+
 pub struct BufferPool {
-    pub root_pointer_buffer: HashMap<BTreePageID, Arc<RwLock<BTreeRootPointerPage>>>,
-    pub internal_buffer: HashMap<BTreePageID, Arc<RwLock<BTreeInternalPage>>>,
-    pub leaf_buffer: HashMap<BTreePageID, Arc<RwLock<BTreeLeafPage>>>,
-    pub header_buffer: HashMap<BTreePageID, Arc<RwLock<BTreeHeaderPage>>>,
+    pub root_pointer_buffer: PageCache<BTreeRootPointerPage>,
+    pub internal_buffer: PageCache<BTreeInternalPage>,
+    pub leaf_buffer: PageCache<BTreeLeafPage>,
+    pub header_buffer: PageCache<BTreeHeaderPage>,
+
+    /// Pages pinned by `pin`, along with the number of times they are
+    /// pinned. They are never chosen as eviction victims.
+    pub bufferfixed: Mutex<HashMap<BTreePageID, usize>>,
+
+    /// The clock hand used to choose eviction victims, see `evict_pages`.
+    clock: Clock,
 
-    pub bufferfixed: HashSet<BTreePageID>,
+    /// The maximum number of cached pages, 0 means unbounded.
+    capacity: AtomicUsize,
+
+    /// Number of page requests that had to read the page from disk.
+    miss_count: AtomicUsize,
 }
 
 type Key = BTreePageID;
 
+/// The ids of the evictable cached pages, in the order the clock hand visits
+/// them. Shared by the page caches which push their newly cached pages to
+/// the back.
+///
+/// Discarded pages are not removed from the ring (it would take a linear
+/// search), they are skipped by the hand and dropped by `compact_clock`.
+type Clock = Arc<Mutex<VecDeque<Key>>>;
+
+/// A map from page id to cached page, partitioned into shards by the hash
+/// of the page id.
+///
+/// Each shard has its own lock, so transactions requesting pages of
+/// different shards don't contend with each other.
+pub struct PageCache<PAGE> {
+    shards: Vec<RwLock<HashMap<Key, CachedPage<PAGE>>>>,
+
+    /// The clock the cached pages are pushed to, None if the pages of this
+    /// cache are never evicted.
+    clock: Option<Clock>,
+}
+
+struct CachedPage<PAGE> {
+    page: Arc<RwLock<PAGE>>,
+
+    /// Whether the page has been accessed since the clock hand last passed
+    /// over it.
+    referenced: AtomicBool,
+}
+
+impl<PAGE> CachedPage<PAGE> {
+    fn new(page: Arc<RwLock<PAGE>>) -> Self {
+        Self {
+            page,
+            referenced: AtomicBool::new(true),
+        }
+    }
+
+    fn touch(&self) -> Arc<RwLock<PAGE>> {
+        self.referenced.store(true, Ordering::Relaxed);
+        self.page.clone()
+    }
+}
+
+impl<PAGE> PageCache<PAGE> {
+    fn new(shards_count: usize, clock: Option<Clock>) -> Self {
+        let shards = (0..shards_count.max(1))
+            .map(|_| RwLock::new(HashMap::new()))
+            .collect();
+        Self { shards, clock }
+    }
+
+    /// Hand a newly cached page to the clock.
+    ///
+    /// Must be called without holding the lock of a shard, the clock is
+    /// locked before the shards when it's compacted.
+    fn push_to_clock(&self, key: Key) {
+        if let Some(clock) = &self.clock {
+            clock.lock().unwrap().push_back(key);
+        }
+    }
+
+    fn shard(&self, key: &Key) -> &RwLock<HashMap<Key, CachedPage<PAGE>>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    pub fn get(&self, key: &Key) -> Option<Arc<RwLock<PAGE>>> {
+        self.shard(key).rl().get(key).map(CachedPage::touch)
+    }
+
+    /// Cache `page` under `key`.
+    ///
+    /// A pid is never mapped to two different pages, otherwise the holders
+    /// of the previous page would read and write a stale copy. If `key` is
+    /// already cached with another page, the cached page is kept and a
+    /// `Corruption` error is returned. Use `replace` to swap the page on
+    /// purpose.
+    pub fn insert(&self, key: Key, page: Arc<RwLock<PAGE>>) -> SmallResult {
+        {
+            let mut shard = self.shard(&key).wl();
+            if let Some(cached) = shard.get(&key) {
+                if Arc::ptr_eq(&cached.page, &page) {
+                    cached.touch();
+                    return Ok(());
+                }
+
+                let err_msg = format!("page {:?} is already cached as another page", key);
+                error!("{}", err_msg);
+                return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+            }
+
+            shard.insert(key, CachedPage::new(page));
+        }
+
+        self.push_to_clock(key);
+        Ok(())
+    }
+
+    /// Cache `page` under `key`, the page previously cached under `key` (if
+    /// any) is dropped.
+    pub fn replace(&self, key: Key, page: Arc<RwLock<PAGE>>) {
+        let previous = self.shard(&key).wl().insert(key, CachedPage::new(page));
+        if previous.is_none() {
+            self.push_to_clock(key);
+        }
+    }
+
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.shard(key).rl().contains_key(key)
+    }
+
+    /// Get the cached page, or insert the page built by `load` if the page
+    /// is not cached. Return the page and whether `load` has been called.
+    ///
+    /// `load` runs without holding the lock of the shard.
+    fn get_or_load<F>(&self, key: &Key, load: F) -> Result<(Arc<RwLock<PAGE>>, bool), SmallError>
+    where
+        F: FnOnce() -> ResultPod<PAGE>,
+    {
+        if let Some(page) = self.get(key) {
+            return Ok((page, false));
+        }
+
+        let page = load()?;
+
+        // another transaction may have loaded the page in the meantime, keep
+        // the first copy so all transactions share the same page
+        let (page, inserted) = {
+            let mut shard = self.shard(key).wl();
+            match shard.get(key) {
+                Some(cached) => (cached.touch(), false),
+                None => {
+                    shard.insert(*key, CachedPage::new(Arc::clone(&page)));
+                    (page, true)
+                }
+            }
+        };
+        if inserted {
+            self.push_to_clock(*key);
+        }
+        Ok((page, true))
+    }
+
+    pub fn remove(&self, key: &Key) {
+        self.shard(key).wl().remove(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.rl().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.wl().clear();
+        }
+    }
+
+    pub fn keys(&self) -> Vec<Key> {
+        let mut keys = Vec::new();
+        for shard in &self.shards {
+            keys.extend(shard.rl().keys().cloned());
+        }
+        keys
+    }
+
+    /// Clear the reference bit of the page and return its previous value,
+    /// None if the page is not cached.
+    fn clear_reference(&self, key: &Key) -> Option<bool> {
+        self.shard(key)
+            .rl()
+            .get(key)
+            .map(|cached| cached.referenced.swap(false, Ordering::Relaxed))
+    }
+}
+
 impl BufferPool {
     pub fn new() -> Self {
         BufferPool::set_page_size(DEFAULT_PAGE_SIZE);
 
+        let shards_count = Self::get_shards_count();
+        let clock = Clock::default();
         Self {
-            root_pointer_buffer: HashMap::new(),
-            header_buffer: HashMap::new(),
-            internal_buffer: HashMap::new(),
-            leaf_buffer: HashMap::new(),
+            // the root pointer pages are never evicted
+            root_pointer_buffer: PageCache::new(shards_count, None),
+            header_buffer: PageCache::new(shards_count, Some(Arc::clone(&clock))),
+            internal_buffer: PageCache::new(shards_count, Some(Arc::clone(&clock))),
+            leaf_buffer: PageCache::new(shards_count, Some(Arc::clone(&clock))),
 
-            bufferfixed: HashSet::new(),
+            bufferfixed: Mutex::new(HashMap::new()),
+            clock,
+
+            capacity: AtomicUsize::new(0),
+
+            miss_count: AtomicUsize::new(0),
         }
     }
 
@@ -53,6 +260,7 @@ impl BufferPool {
         self.header_buffer.clear();
         self.internal_buffer.clear();
         self.leaf_buffer.clear();
+        self.clock.lock().unwrap().clear();
     }
 
     /// Retrieve the specified page with the associated permissions.
@@ -77,8 +285,7 @@ impl BufferPool {
         let table = v.read().unwrap();
 
         // stage 2: read page content from disk
-        let buf = Self::read_page(&mut table.get_file(), pid)
-            .or(Err(SmallError::new("read page content failed")))?;
+        let buf = Self::read_page(&mut table.get_file(), pid)?;
 
         // stage 3: page instantiation
         let page = PAGE::new(pid, &buf, &table.schema);
@@ -87,14 +294,24 @@ impl BufferPool {
         return Ok(Arc::new(RwLock::new(page)));
     }
 
-    fn read_page(file: &mut File, key: &Key) -> io::Result<Vec<u8>> {
+    /// Read the content of a page from the table file.
+    ///
+    /// Return a `ShortPage` error if the file ends before the end of the
+    /// page. The page of a compressed table is inflated, a `Corruption`
+    /// error is returned if it can't be.
+    fn read_page(file: &mut File, key: &Key) -> Result<Vec<u8>, SmallError> {
         let page_size = Self::get_page_size();
         let start_pos = key.page_index as usize * page_size;
-        file.seek(SeekFrom::Start(start_pos as u64))
-            .expect("io error");
+        file.seek(SeekFrom::Start(start_pos as u64))?;
+
+        let mut buf: Vec<u8> = Vec::with_capacity(page_size);
+        file.take(page_size as u64).read_to_end(&mut buf)?;
 
-        let mut buf: Vec<u8> = vec![0; page_size];
-        file.read_exact(&mut buf).expect("io error");
+        BTreeBasePage::check_page_data(key, &buf)?;
+        if BTreeBasePage::is_compressed_page(&buf) {
+            return BTreeBasePage::decompress_page_data(&buf)
+                .map_err(|e| SmallError::with_kind(e.kind(), &format!("pid: {:?}, {}", key, e)));
+        }
         Ok(buf)
     }
 
@@ -116,7 +333,7 @@ impl BufferPool {
         tx: &Transaction,
         perm: Permission,
         key: &Key,
-        get_pool_fn: fn(&mut BufferPool) -> &mut HashMap<Key, Arc<RwLock<PAGE>>>,
+        get_pool_fn: fn(&BufferPool) -> &PageCache<PAGE>,
     ) -> ResultPod<PAGE> {
         // We need to request lock on the page before access the
         // buffer pool. Here are the reasons:
@@ -133,24 +350,33 @@ impl BufferPool {
         // 3. The lock scope of buffer pool should be as small as possible, since most
         //    of its operations require exclusive access.
 
+        // a read-only transaction never writes a page
+        if perm == Permission::ReadWrite {
+            tx.check_writable()?;
+        }
+
         // step 1: request page latch
         if key.need_page_latch() {
             ConcurrentStatus::request_latch(tx, &perm.to_lock(), key)?;
         }
 
-        // step 2: mark the page as dirty if it is a read-write page
+        // step 2: count the request, and mark the page as dirty if it is a
+        // read-write page
+        tx.count_pages_read(1);
         if perm == Permission::ReadWrite {
             Database::mut_concurrent_status().set_dirty_page(tx, key);
         }
 
         // step 3: get page from buffer pool
-        let mut bp = Database::mut_buffer_pool();
-        let pool = get_pool_fn(&mut bp);
-        let v = pool.entry(key.clone()).or_insert_with(|| {
-            let page = Self::load_page(key).unwrap();
-            page
-        });
-        let page = v.clone();
+        //
+        // (only the shard of the page is locked exclusively, so requests for
+        // pages of other shards are not blocked)
+        let bp = Database::buffer_pool();
+        let (page, loaded) = get_pool_fn(&bp).get_or_load(key, || Self::load_page(key))?;
+        if loaded {
+            bp.miss_count.fetch_add(1, Ordering::Relaxed);
+            bp.evict_to_capacity();
+        }
 
         return Ok(page);
     }
@@ -160,15 +386,15 @@ impl BufferPool {
         perm: Permission,
         key: &Key,
     ) -> ResultPod<BTreeRootPointerPage> {
-        Self::get_page(tx, perm, key, |bp| &mut bp.root_pointer_buffer)
+        Self::get_page(tx, perm, key, |bp| &bp.root_pointer_buffer)
     }
 
-    pub(crate) fn get_header_page(
+    pub fn get_header_page(
         tx: &Transaction,
         perm: Permission,
         key: &Key,
     ) -> ResultPod<BTreeHeaderPage> {
-        Self::get_page(tx, perm, key, |bp| &mut bp.header_buffer)
+        Self::get_page(tx, perm, key, |bp| &bp.header_buffer)
     }
 
     pub fn get_internal_page(
@@ -176,7 +402,7 @@ impl BufferPool {
         perm: Permission,
         key: &Key,
     ) -> ResultPod<BTreeInternalPage> {
-        Self::get_page(tx, perm, key, |bp| &mut bp.internal_buffer)
+        Self::get_page(tx, perm, key, |bp| &bp.internal_buffer)
     }
 
     pub fn get_leaf_page(
@@ -184,7 +410,121 @@ impl BufferPool {
         perm: Permission,
         key: &Key,
     ) -> ResultPod<BTreeLeafPage> {
-        Self::get_page(tx, perm, key, |bp| &mut bp.leaf_buffer)
+        Self::get_page(tx, perm, key, |bp| &bp.leaf_buffer)
+    }
+
+    /// Get the cells of field `field_index` of the tuples of the leaf page
+    /// `key` visible to `tx`, and the right sibling of the page, for the
+    /// scans which only need a single column (see `BTreeTable::keys` and
+    /// `BTreeTable::scan_column`).
+    ///
+    /// A page in the buffer pool is read from there. Otherwise only the
+    /// cells of the field are decoded from the disk (see
+    /// `BTreeLeafPage::decode_visible_cells`) and the page isn't cached. The
+    /// latch on the page is released before returning.
+    pub(crate) fn get_leaf_page_cells(
+        tx: &Transaction,
+        key: &Key,
+        field_index: usize,
+    ) -> Result<(Vec<Cell>, Option<Key>), SmallError> {
+        ConcurrentStatus::request_latch(tx, &Lock::SLock, key)?;
+        tx.count_pages_read(1);
+
+        let result = Self::read_leaf_page_cells(tx, key, field_index);
+        Database::mut_concurrent_status().release_latch(tx, key)?;
+        result
+    }
+
+    fn read_leaf_page_cells(
+        tx: &Transaction,
+        key: &Key,
+        field_index: usize,
+    ) -> Result<(Vec<Cell>, Option<Key>), SmallError> {
+        let cached = Database::buffer_pool().leaf_buffer.get(key);
+        if let Some(page_rc) = cached {
+            let page = page_rc.rl();
+            let cells = page.visible_cells(tx.get_id(), field_index);
+            return Ok((cells, page.get_right_pid()));
+        }
+
+        let table_rc = Database::mut_catalog()
+            .get_table(&key.get_table_id())
+            .ok_or_else(|| {
+                SmallError::with_kind(
+                    SmallErrorKind::NotFound,
+                    &format!("table {} not found", key.get_table_id()),
+                )
+            })?;
+        let table = table_rc.rl();
+        let buf = Self::read_page(&mut table.get_file(), key)?;
+        BTreeLeafPage::decode_visible_cells(key, &buf, &table.schema, tx.get_id(), field_index)
+    }
+
+    /// Get a copy of the leaf page `pid` with its content as of the log
+    /// offset `lsn` (see `LogManager::page_image_as_of`), for time-travel
+    /// reads.
+    ///
+    /// The historical page is not cached nor latched, changes on it are not
+    /// written anywhere. The current page is read (by `tx`) only when the log
+    /// holds no image of it.
+    pub fn get_leaf_page_as_of(
+        tx: &Transaction,
+        pid: &Key,
+        lsn: u64,
+    ) -> Result<BTreeLeafPage, SmallError> {
+        let schema = Database::mut_catalog()
+            .get_table(&pid.get_table_id())
+            .ok_or(SmallError::with_kind(
+                SmallErrorKind::NotFound,
+                &format!("table {} not found", pid.get_table_id()),
+            ))?
+            .rl()
+            .get_schema();
+
+        let image = Database::log_manager().page_image_as_of(pid, lsn)?;
+        let bytes = match image {
+            Some(bytes) => bytes,
+            None => Self::get_leaf_page(tx, Permission::ReadOnly, pid)?
+                .rl()
+                .get_page_data(&schema),
+        };
+
+        BTreeBasePage::check_page_data(pid, &bytes)?;
+        Ok(BTreeLeafPage::new(pid, &bytes, &schema))
+    }
+
+    /// Load up to `count` leaf pages into the buffer pool, starting from
+    /// `pid` and following the right sibling pointers, for a scan of the
+    /// transaction which is about to reach them.
+    ///
+    /// The pages are loaded without being latched, and the read-ahead stops
+    /// at a page being modified.
+    pub(crate) fn prefetch_leaf_pages(tx: &Transaction, pid: &Key, count: usize) -> SmallResult {
+        let mut next = Some(*pid);
+        for _ in 0..count {
+            let pid = match next {
+                Some(pid) => pid,
+                None => break,
+            };
+
+            let (page, loaded) = {
+                let bp = Database::buffer_pool();
+                let (page, loaded) = bp.leaf_buffer.get_or_load(&pid, || Self::load_page(&pid))?;
+                if loaded {
+                    bp.evict_to_capacity();
+                }
+                (page, loaded)
+            };
+            if loaded {
+                tx.record_prefetch(&pid);
+            }
+
+            next = match page.try_read() {
+                Ok(page) => page.get_right_pid(),
+                Err(_) => None,
+            };
+        }
+        Ok(())
     }
 
     /// Remove the specific page id from the buffer pool.
@@ -194,7 +534,7 @@ impl BufferPool {
     ///
     /// Also used by B+ tree files to ensure that deleted pages
     /// are removed from the cache so they can be reused safely
-    pub(crate) fn discard_page(&mut self, pid: &BTreePageID) {
+    pub(crate) fn discard_page(&self, pid: &BTreePageID) {
         match pid.category {
             PageCategory::Internal => {
                 self.internal_buffer.remove(pid);
@@ -211,6 +551,247 @@ impl BufferPool {
         }
     }
 
+    /// Get the current content of a cached page, None if the page is not
+    /// cached or its table is gone.
+    pub(crate) fn get_page_image(&self, pid: &BTreePageID) -> Option<Vec<u8>> {
+        let table_rc = Database::mut_catalog().get_table(&pid.get_table_id())?;
+        let table = table_rc.rl();
+
+        match pid.category {
+            PageCategory::RootPointer => {
+                Self::image_of(pid, &self.root_pointer_buffer, &table.schema)
+            }
+            PageCategory::Header => Self::image_of(pid, &self.header_buffer, &table.schema),
+            PageCategory::Internal => Self::image_of(pid, &self.internal_buffer, &table.schema),
+            PageCategory::Leaf => Self::image_of(pid, &self.leaf_buffer, &table.schema),
+        }
+    }
+
+    fn image_of<PAGE: BTreePage>(
+        pid: &BTreePageID,
+        buffer: &PageCache<PAGE>,
+        schema: &TableSchema,
+    ) -> Option<Vec<u8>> {
+        buffer
+            .get(pid)
+            .map(|page_rc| page_rc.rl().get_page_data(schema))
+    }
+
+    /// Set the content of a page to `image` in the buffer pool, the page is
+    /// loaded first if it's not cached. The before image of the page is
+    /// kept, so the page is still logged against its content on disk.
+    ///
+    /// The restored content is logged as an UPDATE record of `tx`.
+    pub(crate) fn restore_page_image(
+        &self,
+        tx: &Transaction,
+        pid: &BTreePageID,
+        image: &[u8],
+        log_manager: &mut LogManager,
+    ) -> SmallResult {
+        let schema = match Database::mut_catalog().get_table(&pid.get_table_id()) {
+            Some(table_rc) => table_rc.rl().schema.clone(),
+            None => return Ok(()),
+        };
+
+        match pid.category {
+            PageCategory::RootPointer => Self::restore_image(
+                tx,
+                pid,
+                &self.root_pointer_buffer,
+                image,
+                &schema,
+                log_manager,
+            ),
+            PageCategory::Header => {
+                Self::restore_image(tx, pid, &self.header_buffer, image, &schema, log_manager)
+            }
+            PageCategory::Internal => {
+                Self::restore_image(tx, pid, &self.internal_buffer, image, &schema, log_manager)
+            }
+            PageCategory::Leaf => {
+                Self::restore_image(tx, pid, &self.leaf_buffer, image, &schema, log_manager)
+            }
+        }
+    }
+
+    fn restore_image<PAGE: BTreePage>(
+        tx: &Transaction,
+        pid: &BTreePageID,
+        buffer: &PageCache<PAGE>,
+        image: &[u8],
+        schema: &TableSchema,
+        log_manager: &mut LogManager,
+    ) -> SmallResult {
+        let (page_rc, _) = buffer.get_or_load(pid, || Self::load_page(pid))?;
+        page_rc.wl().restore_page_data(image, schema);
+        log_manager.log_update(tx, page_rc)
+    }
+
+    /// Get the number of pages in the buffer pool.
+    pub fn cached_pages_count(&self) -> usize {
+        self.root_pointer_buffer.len()
+            + self.header_buffer.len()
+            + self.internal_buffer.len()
+            + self.leaf_buffer.len()
+    }
+
+    /// Get the approximate number of bytes held by the buffer pool.
+    ///
+    /// Each page is counted as a full page plus the overhead of its entry in
+    /// the page map, the actual in-memory size of a page may be different.
+    pub fn memory_usage(&self) -> usize {
+        let entry_overhead = mem::size_of::<Key>() + mem::size_of::<Arc<RwLock<BTreeLeafPage>>>();
+        self.cached_pages_count() * (Self::get_page_size() + entry_overhead)
+    }
+
+    /// Remove all pages of the given table from the buffer pool, the pages
+    /// will be read from disk on the next access.
+    pub(crate) fn discard_table_pages(&self, table_id: u32) {
+        for pid in self.all_keys() {
+            if pid.get_table_id() == table_id {
+                self.discard_page(&pid);
+            }
+        }
+    }
+
+    /// Set the maximum number of cached pages, `None` means unbounded (the
+    /// default).
+    ///
+    /// When a page is loaded into a full buffer pool, pages which have not
+    /// been accessed recently are evicted (see `evict_pages`). Pinned pages, root pointer pages and pages latched
+    /// or dirtied by a running transaction are never evicted, so the buffer
+    /// pool may temporarily hold more pages than its capacity.
+    pub fn set_capacity(&self, capacity: Option<usize>) {
+        self.capacity
+            .store(capacity.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn get_capacity(&self) -> Option<usize> {
+        match self.capacity.load(Ordering::Relaxed) {
+            0 => None,
+            capacity => Some(capacity),
+        }
+    }
+
+    /// Protect the page from eviction until `unpin` is called.
+    ///
+    /// The page doesn't have to be cached yet, it will be kept once it is
+    /// loaded. A page pinned several times is evictable again once it is
+    /// unpinned as many times. (`PageGuard` pins the page it holds.)
+    pub fn pin(&self, pid: &BTreePageID) {
+        *self.bufferfixed.lock().unwrap().entry(*pid).or_insert(0) += 1;
+    }
+
+    pub fn unpin(&self, pid: &BTreePageID) {
+        let mut pinned = self.bufferfixed.lock().unwrap();
+        if let Some(count) = pinned.get_mut(pid) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(pid);
+            }
+        }
+    }
+
+    pub fn is_pinned(&self, pid: &BTreePageID) -> bool {
+        self.bufferfixed.lock().unwrap().contains_key(pid)
+    }
+
+    /// Check whether the page is in the buffer pool.
+    pub fn is_cached(&self, pid: &BTreePageID) -> bool {
+        match pid.category {
+            PageCategory::Internal => self.internal_buffer.contains_key(pid),
+            PageCategory::Leaf => self.leaf_buffer.contains_key(pid),
+            PageCategory::RootPointer => self.root_pointer_buffer.contains_key(pid),
+            PageCategory::Header => self.header_buffer.contains_key(pid),
+        }
+    }
+
+    /// Called after a page is loaded, evict pages if the buffer pool is over
+    /// its capacity.
+    fn evict_to_capacity(&self) {
+        let cached_count = self.cached_pages_count();
+        self.compact_clock(cached_count);
+
+        if let Some(capacity) = self.get_capacity() {
+            if cached_count > capacity {
+                self.evict_pages(cached_count - capacity);
+            }
+        }
+    }
+
+    /// Drop the entries of discarded pages from the clock once they make up
+    /// most of it, so it doesn't grow with the pages loaded and discarded
+    /// over and over.
+    fn compact_clock(&self, cached_count: usize) {
+        let mut clock = self.clock.lock().unwrap();
+        if clock.len() <= cached_count * 2 + 64 {
+            return;
+        }
+
+        let mut seen = HashSet::new();
+        clock.retain(|pid| self.is_cached(pid) && seen.insert(*pid));
+    }
+
+    /// Evict up to `count` pages from the buffer pool and return the number
+    /// of evicted pages.
+    ///
+    /// The victims are chosen by a clock hand going round the cached pages:
+    /// a page accessed since the hand last passed over it gets a second
+    /// chance (its reference bit is cleared), otherwise it is evicted. Only
+    /// clean pages which are neither pinned nor latched are evicted, they
+    /// will be read from disk on the next access.
+    ///
+    /// Each page is passed over at most twice, so a call costs O(count)
+    /// steps in the common case and never more than two turns of the clock.
+    pub fn evict_pages(&self, count: usize) -> usize {
+        let concurrent_status = Database::concurrent_status();
+
+        // the clock is not locked while the page is checked, so the pages
+        // loaded in the meantime are not blocked
+        let mut steps = self.clock.lock().unwrap().len() * 2;
+        let mut evicted = 0;
+        while evicted < count && steps > 0 {
+            steps -= 1;
+
+            let pid = match self.clock.lock().unwrap().pop_front() {
+                Some(pid) => pid,
+                None => break,
+            };
+
+            let referenced = match pid.category {
+                PageCategory::Header => self.header_buffer.clear_reference(&pid),
+                PageCategory::Internal => self.internal_buffer.clear_reference(&pid),
+                PageCategory::Leaf => self.leaf_buffer.clear_reference(&pid),
+                PageCategory::RootPointer => None,
+            };
+            let referenced = match referenced {
+                Some(referenced) => referenced,
+                // the page has been discarded since it was cached
+                None => continue,
+            };
+
+            if referenced
+                || self.is_pinned(&pid)
+                || concurrent_status.is_latched(&pid)
+                || concurrent_status.dirty_page_tx(&pid).is_some()
+            {
+                self.clock.lock().unwrap().push_back(pid);
+                continue;
+            }
+
+            self.discard_page(&pid);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Get the number of page requests that were not served from the cache
+    /// since the buffer pool was created.
+    pub fn miss_count(&self) -> usize {
+        self.miss_count.load(Ordering::Relaxed)
+    }
+
     pub fn set_page_size(page_size: usize) {
         PAGE_SIZE.store(page_size, Ordering::Relaxed);
     }
@@ -219,10 +800,20 @@ impl BufferPool {
         PAGE_SIZE.load(Ordering::Relaxed)
     }
 
+    /// Set the number of shards of the page caches, it takes effect when the
+    /// buffer pool is created (i.e. on the next `Database::reset`).
+    pub fn set_shards_count(shards_count: usize) {
+        SHARDS_COUNT.store(shards_count.max(1), Ordering::Relaxed);
+    }
+
+    pub fn get_shards_count() -> usize {
+        SHARDS_COUNT.load(Ordering::Relaxed)
+    }
+
     fn set_before_image<PAGE: BTreePage>(
         &self,
         pid: &BTreePageID,
-        buffer: &HashMap<BTreePageID, Arc<RwLock<PAGE>>>,
+        buffer: &PageCache<PAGE>,
         table_schema: &TableSchema,
     ) {
         let page_rc = buffer.get(pid).unwrap();
@@ -230,10 +821,13 @@ impl BufferPool {
     }
 
     /// Flush all dirty pages to database.
-    pub fn flush_all_pages(&self, log_manager: &mut LogManager) {
+    ///
+    /// Stops at the first page which fails to be logged or written and
+    /// returns the error, the pages flushed before it stay on disk.
+    pub fn flush_all_pages(&self, log_manager: &mut LogManager) -> SmallResult {
         if cfg!(feature = "aries_steal") {
             for pid in self.all_keys() {
-                self.flush_page(&pid, log_manager);
+                self.flush_page(&pid, log_manager)?;
             }
         } else if cfg!(feature = "aries_no_steal") {
             // do nothing
@@ -243,19 +837,89 @@ impl BufferPool {
         } else {
             panic!("unknown aries mode");
         }
+        Ok(())
     }
 
     /// Write all pages of the specified transaction to disk.
     ///
+    /// The pages may belong to several tables. If any of them fails to be
+    /// logged or written, the pages already written are restored to their
+    /// before images and the error is returned, so no table keeps a part of
+    /// the changes and the transaction can be aborted. A page which can't be
+    /// restored is logged, it doesn't stop the restore of the others.
+    ///
     /// TODO: remove the "log_manager" parameter
-    pub fn flush_pages(&self, tx: &Transaction, log_manager: &mut LogManager) {
+    pub fn flush_pages(&self, tx: &Transaction, log_manager: &mut LogManager) -> SmallResult {
         let dirty_pages = Database::concurrent_status().get_dirty_pages(tx);
 
         // Note: current implementation of the api "flush_page" request
         // "ConcurrentStatus", so we must get "dirty_pages" before the for loop.
+        let mut written: Vec<(BTreePageID, Vec<u8>)> = Vec::new();
         for pid in dirty_pages {
-            self.flush_page(&pid, log_manager);
+            match self.flush_page(&pid, log_manager) {
+                Ok(Some(before_image)) => written.push((pid, before_image)),
+                Ok(None) => {}
+                Err(e) => {
+                    // a page which can't be restored is logged, the others
+                    // are restored anyway and the original error is returned
+                    let mut catalog = Database::mut_catalog();
+                    for (pid, before_image) in written {
+                        let result = match catalog.get_table(&pid.get_table_id()) {
+                            Some(table_rc) => {
+                                table_rc.rl().try_write_page_to_disk(&pid, &before_image)
+                            }
+                            None => Err(SmallError::with_kind(
+                                SmallErrorKind::NotFound,
+                                &format!("table of page {:?} not found", pid),
+                            )),
+                        };
+                        if let Err(restore_err) = result {
+                            error!("failed to restore page {:?}: {}", pid, restore_err);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
         }
+
+        Ok(())
+    }
+
+    /// Write all pages in the buffer pool to disk, without writing to the
+    /// log.
+    ///
+    /// Only safe when there is no active transaction, otherwise uncommitted
+    /// changes may reach the disk.
+    ///
+    /// Fails on the first page which can't be written, or whose table isn't
+    /// in the catalog (the pages of a dropped table are discarded with it).
+    pub(crate) fn write_all_pages(&self) -> SmallResult {
+        let mut catalog = Database::mut_catalog();
+        for pid in self.all_keys() {
+            let table_rc = catalog.get_table(&pid.get_table_id()).ok_or_else(|| {
+                SmallError::with_kind(
+                    SmallErrorKind::NotFound,
+                    &format!("table of page {:?} not found", pid),
+                )
+            })?;
+            let table = table_rc.rl();
+
+            match pid.category {
+                PageCategory::RootPointer => {
+                    self.try_write(&table, &pid, &self.root_pointer_buffer)?;
+                }
+                PageCategory::Header => {
+                    self.try_write(&table, &pid, &self.header_buffer)?;
+                }
+                PageCategory::Internal => {
+                    self.try_write(&table, &pid, &self.internal_buffer)?;
+                }
+                PageCategory::Leaf => {
+                    self.try_write(&table, &pid, &self.leaf_buffer)?;
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Write all dirty pages of the specified transaction to disk.
@@ -287,26 +951,32 @@ impl BufferPool {
     }
 
     /// Write the content of a specific page to disk.
-    fn flush_page(&self, pid: &BTreePageID, log_manager: &mut LogManager) {
-        // stage 1: get table
-        let table_rc = Database::mut_catalog()
-            .get_table(&pid.get_table_id())
-            .unwrap();
+    ///
+    /// Return the before image of the page if the page is written to disk.
+    fn flush_page(
+        &self,
+        pid: &BTreePageID,
+        log_manager: &mut LogManager,
+    ) -> Result<Option<Vec<u8>>, SmallError> {
+        // stage 1: get table, the pages of a table dropped by the transaction
+        // are gone with its file
+        let table_rc = match Database::mut_catalog().get_table(&pid.get_table_id()) {
+            Some(table_rc) => table_rc,
+            None => return Ok(None),
+        };
         let table = table_rc.rl();
 
         match pid.category {
             PageCategory::RootPointer => {
-                self.log_and_write(&table, pid, &self.root_pointer_buffer, log_manager);
+                self.log_and_write(&table, pid, &self.root_pointer_buffer, log_manager)
             }
             PageCategory::Header => {
-                self.log_and_write(&table, pid, &self.header_buffer, log_manager);
+                self.log_and_write(&table, pid, &self.header_buffer, log_manager)
             }
             PageCategory::Internal => {
-                self.log_and_write(&table, pid, &self.internal_buffer, log_manager);
-            }
-            PageCategory::Leaf => {
-                self.log_and_write(&table, pid, &self.leaf_buffer, log_manager);
+                self.log_and_write(&table, pid, &self.internal_buffer, log_manager)
             }
+            PageCategory::Leaf => self.log_and_write(&table, pid, &self.leaf_buffer, log_manager),
         }
     }
 
@@ -314,28 +984,35 @@ impl BufferPool {
         &self,
         table: &BTreeTable,
         pid: &BTreePageID,
-        buffer: &HashMap<BTreePageID, Arc<RwLock<PAGE>>>,
+        buffer: &PageCache<PAGE>,
         log_manager: &mut LogManager,
-    ) {
+    ) -> Result<Option<Vec<u8>>, SmallError> {
         if let Some(page_rc) = buffer.get(pid) {
             let v = Database::concurrent_status().dirty_page_tx(pid);
             if let Some(tx) = v {
-                log_manager.log_update(&tx, page_rc.clone()).unwrap();
+                log_manager.log_update(&tx, page_rc.clone())?;
 
+                let mut written = None;
                 if cfg!(feature = "aries_force") {
-                    table.write_page_to_disk(pid, &page_rc.rl().get_page_data(&table.schema));
+                    let before_image = page_rc.rl().get_before_image(&table.schema);
+                    // the savepoints taken before the page was dirtied
+                    // read it from disk, it's about to be overwritten
+                    Database::mut_concurrent_status().keep_in_savepoints(&tx, pid, &before_image);
+                    table
+                        .try_write_page_to_disk(pid, &page_rc.rl().get_page_data(&table.schema))?;
+                    written = Some(before_image);
                 }
 
                 // What's the purpose of "set_before_image" here?
                 self.set_before_image(&pid, &buffer, &table.schema);
-                return;
+                return Ok(written);
             } else {
                 // Not a dirty page, so no need to write to log or disk, just return.
                 //
                 // Q: What's the possiable scenario for this case?
                 // A: This happens when "flass_all_pages" is called, and the some pages
                 // are not dirty.
-                return;
+                return Ok(None);
             }
         } else {
             // Page not found in buffer pool, so no need to write to disk. This happens
@@ -347,7 +1024,7 @@ impl BufferPool {
             // is still recorded in the relationship map.
             //
             // TODO: remove the page from the relationship map when the page is discarded.
-            return;
+            return Ok(None);
         }
     }
 
@@ -355,11 +1032,20 @@ impl BufferPool {
         &self,
         table: &BTreeTable,
         pid: &BTreePageID,
-        buffer: &HashMap<BTreePageID, Arc<RwLock<PAGE>>>,
+        buffer: &PageCache<PAGE>,
     ) {
+        self.try_write(table, pid, buffer).expect("io error");
+    }
+
+    /// Same as `write`, but reports the io error to the caller.
+    fn try_write<PAGE: BTreePage>(
+        &self,
+        table: &BTreeTable,
+        pid: &BTreePageID,
+        buffer: &PageCache<PAGE>,
+    ) -> SmallResult {
         if let Some(page_rc) = buffer.get(pid) {
-            table.write_page_to_disk(pid, &page_rc.rl().get_page_data(&table.schema));
-            return;
+            table.try_write_page_to_disk(pid, &page_rc.rl().get_page_data(&table.schema))?;
         } else {
             // page not found in buffer pool, so no need to write to disk
             //
@@ -369,22 +1055,19 @@ impl BufferPool {
             //    2. The page becomes empty and is discarded from the buffer
             //       pool.
         }
+        Ok(())
     }
 
     /// Set the page content of "pid" to the specified "page", both in
     /// the buffer pool and on disk.
-    pub fn recover_page<PAGE: BTreePage>(
-        pid: &BTreePageID,
-        page: PAGE,
-        buffer: &mut HashMap<BTreePageID, Arc<RwLock<PAGE>>>,
-    ) {
+    pub fn recover_page<PAGE: BTreePage>(pid: &BTreePageID, page: PAGE, buffer: &PageCache<PAGE>) {
         // step 1: get table
         let mut catalog = Database::mut_catalog();
         let table_rc = catalog.get_table(&pid.get_table_id()).unwrap();
 
         // step 2: insert the page to buffer pool
         let page_rc = Arc::new(RwLock::new(page));
-        buffer.insert(pid.clone(), page_rc.clone());
+        buffer.replace(pid.clone(), page_rc.clone());
 
         // step 3: write the page to disk without write to WAL log
         let table = table_rc.read().unwrap();
@@ -393,23 +1076,10 @@ impl BufferPool {
 
     pub(crate) fn all_keys(&self) -> Vec<Key> {
         let mut keys: Vec<Key> = vec![];
-
-        for (k, _) in &self.root_pointer_buffer {
-            keys.push(k.clone());
-        }
-
-        for (k, _) in &self.header_buffer {
-            keys.push(k.clone());
-        }
-
-        for (k, _) in &self.internal_buffer {
-            keys.push(k.clone());
-        }
-
-        for (k, _) in &self.leaf_buffer {
-            keys.push(k.clone());
-        }
-
+        keys.extend(self.root_pointer_buffer.keys());
+        keys.extend(self.header_buffer.keys());
+        keys.extend(self.internal_buffer.keys());
+        keys.extend(self.leaf_buffer.keys());
         keys
     }
 }
@@ -1,8 +1,21 @@
+use std::io::{Cursor, Read, Write};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
 use super::{BTreePage, BTreePageID, PageCategory};
-use crate::{btree::buffer_pool::BufferPool, storage::table_schema::TableSchema};
+use crate::{
+    btree::buffer_pool::BufferPool,
+    error::{SmallError, SmallErrorKind},
+    io::{Serializeable, SmallWriter},
+    storage::table_schema::TableSchema,
+    types::SmallResult,
+};
 
 const EMPTY_PAGE_TOKEN: [u8; 4] = [55, 55, 55, 55];
 
+/// Marks a page stored compressed on disk, see `compress_page_data`.
+const COMPRESSED_PAGE_TOKEN: [u8; 4] = [66, 66, 66, 66];
+
 pub struct BTreeBasePage {
     pid: BTreePageID,
     parent_page_index: u32,
@@ -35,9 +48,81 @@ impl BTreeBasePage {
         data
     }
 
+    /// Check that `bytes` holds a whole page, the page constructors assume
+    /// so and must not be given the data of a short read.
+    pub(crate) fn check_page_data(pid: &BTreePageID, bytes: &[u8]) -> SmallResult {
+        let page_size = BufferPool::get_page_size();
+        if bytes.len() != page_size {
+            let err_msg = format!(
+                "short page, pid: {:?}, expect {} bytes, got {} bytes",
+                pid,
+                page_size,
+                bytes.len(),
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::ShortPage, &err_msg));
+        }
+        Ok(())
+    }
+
     pub fn is_empty_page(bytes: &[u8]) -> bool {
         bytes[0..4] == EMPTY_PAGE_TOKEN
     }
+
+    /// Compress the data of a page for the disk.
+    ///
+    /// # Binary Layout
+    ///
+    /// - 4 bytes: compressed page token
+    /// - 4 bytes: size of the deflated bytes
+    /// - n bytes: the deflated page data
+    ///
+    /// The result is not padded to the page size. Return None if it's not
+    /// smaller than the page data.
+    pub(crate) fn compress_page_data(data: &[u8]) -> Option<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).ok()?;
+        let deflated = encoder.finish().ok()?;
+
+        let mut writer = SmallWriter::new();
+        writer.write_bytes(&COMPRESSED_PAGE_TOKEN);
+        (deflated.len() as u32).encode(&mut writer, &());
+        writer.write_bytes(&deflated);
+        let bytes = writer.to_bytes();
+
+        if bytes.len() < data.len() {
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn is_compressed_page(bytes: &[u8]) -> bool {
+        bytes[0..4] == COMPRESSED_PAGE_TOKEN
+    }
+
+    /// Restore the page data from the output of `compress_page_data`, the
+    /// bytes after the deflated data are ignored.
+    pub(crate) fn decompress_page_data(bytes: &[u8]) -> Result<Vec<u8>, SmallError> {
+        let corrupted =
+            || SmallError::with_kind(SmallErrorKind::Corruption, "corrupted compressed page");
+
+        if bytes.len() < 8 || !Self::is_compressed_page(bytes) {
+            return Err(corrupted());
+        }
+        let size = u32::decode(&mut Cursor::new(&bytes[4..8]), &()) as usize;
+        if 8 + size > bytes.len() {
+            return Err(corrupted());
+        }
+
+        let mut data = Vec::with_capacity(BufferPool::get_page_size());
+        DeflateDecoder::new(&bytes[8..8 + size])
+            .read_to_end(&mut data)
+            .or(Err(corrupted()))?;
+        if data.len() != BufferPool::get_page_size() {
+            return Err(corrupted());
+        }
+        Ok(data)
+    }
 }
 
 impl BTreePage for BTreeBasePage {
@@ -74,4 +159,12 @@ impl BTreePage for BTreeBasePage {
     fn get_before_image(&self, _table_schema: &TableSchema) -> Vec<u8> {
         unimplemented!()
     }
+
+    fn restore_page_data(&mut self, bytes: &[u8], table_schema: &TableSchema) {
+        // the base page holds nothing decoded from the bytes, the parent is
+        // restored by the page owning it
+        let parent_page_index = self.parent_page_index;
+        *self = <Self as BTreePage>::new(&self.pid, bytes, table_schema);
+        self.parent_page_index = parent_page_index;
+    }
 }
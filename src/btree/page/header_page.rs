@@ -9,6 +9,7 @@ use bit_vec::BitVec;
 use super::{BTreeBasePage, BTreePage, BTreePageID, PageCategory, PageIndex};
 use crate::{
     btree::buffer_pool::BufferPool,
+    error::{SmallError, SmallErrorKind},
     io::{Serializeable, SmallWriter},
     storage::table_schema::TableSchema,
     transaction::{Permission, Transaction},
@@ -86,7 +87,7 @@ impl BTreeHeaderPage {
         self.next_pid = Some(pid.clone());
     }
 
-    fn get_next_pid(&self) -> Option<BTreePageID> {
+    pub(crate) fn get_next_pid(&self) -> Option<BTreePageID> {
         self.next_pid.clone()
     }
 
@@ -95,6 +96,10 @@ impl BTreeHeaderPage {
         self.header.set(slot_index, used);
     }
 
+    pub(crate) fn is_slot_used(&self, slot_index: usize) -> bool {
+        self.header[slot_index]
+    }
+
     pub(crate) fn get_empty_slot(&self) -> Option<u32> {
         for i in 0..self.slot_count {
             if !self.header[i] {
@@ -152,6 +157,12 @@ impl BTreePage for BTreeHeaderPage {
         }
         return self.old_data.clone();
     }
+
+    fn restore_page_data(&mut self, bytes: &[u8], table_schema: &TableSchema) {
+        let old_data = std::mem::take(&mut self.old_data);
+        *self = <Self as BTreePage>::new(&self.get_pid(), bytes, table_schema);
+        self.old_data = old_data;
+    }
 }
 
 pub(crate) struct HeaderPages {
@@ -160,13 +171,17 @@ pub(crate) struct HeaderPages {
 }
 
 impl HeaderPages {
-    pub(crate) fn new(table: &BTreeTable, tx: &Transaction) -> Self {
+    pub(crate) fn new(table: &BTreeTable, tx: &Transaction) -> Result<Self, SmallError> {
         let root_ptr_rc = table.get_root_ptr_page(tx, Permission::ReadWrite);
         let header_pid = root_ptr_rc.rl().get_header_pid();
 
         if header_pid.is_none() {
             let result = Self::init_header_pages(table, tx);
-            root_ptr_rc.wl().set_header_pid(&result.get_head_pid());
+            if let Ok(header_pages) = &result {
+                root_ptr_rc
+                    .wl()
+                    .set_header_pid(&header_pages.get_head_pid());
+            }
             Database::mut_concurrent_status()
                 .release_latch(tx, &root_ptr_rc.rl().get_pid())
                 .unwrap();
@@ -190,10 +205,10 @@ impl HeaderPages {
             pid = next_pid.unwrap();
         }
 
-        Self {
+        Ok(Self {
             header_pages,
             tx: tx.clone(),
-        }
+        })
     }
 
     /// Get the page id of the first header page.
@@ -201,24 +216,29 @@ impl HeaderPages {
         self.header_pages[0].rl().get_pid()
     }
 
-    pub(crate) fn init_header_pages(table: &BTreeTable, tx: &Transaction) -> Self {
+    pub(crate) fn init_header_pages(
+        table: &BTreeTable,
+        tx: &Transaction,
+    ) -> Result<Self, SmallError> {
         let mut header_pids = Vec::new();
         let slots_per_page = BTreeHeaderPage::calc_slots_count();
 
+        // pages with page index in range [0, page_index] are in use (the header
+        // pages created here included), mark all of them as occupied
         let mut filled_slots = 0;
         loop {
-            if filled_slots >= table.page_index.load(Ordering::Relaxed) as usize {
+            if filled_slots > table.page_index.load(Ordering::Relaxed) as usize {
                 break;
             }
 
-            let page_index = table.page_index.fetch_add(1, Ordering::Relaxed) + 1;
+            let page_index = table.next_page_index()?;
             let page_id = BTreePageID::new(PageCategory::Header, table.get_id(), page_index);
             let mut page =
                 BTreeHeaderPage::new(&page_id, &BTreeBasePage::empty_page_data(), &table.schema);
 
             let current_slots = cmp::min(
                 slots_per_page,
-                table.page_index.load(Ordering::Relaxed) as usize - filled_slots,
+                table.page_index.load(Ordering::Relaxed) as usize + 1 - filled_slots,
             );
             for i in 0..current_slots {
                 page.mark_slot_status(i as usize, true);
@@ -227,10 +247,11 @@ impl HeaderPages {
             filled_slots += current_slots;
             header_pids.push(page_id);
 
+            // the page index is a new one, nothing can be cached under it
             let page_rc = Arc::new(RwLock::new(page));
-            Database::mut_buffer_pool()
+            Database::buffer_pool()
                 .header_buffer
-                .insert(page_id, page_rc.clone());
+                .insert(page_id, page_rc)?;
         }
 
         // Q: what if the process crashes before the writing finished?
@@ -258,22 +279,52 @@ impl HeaderPages {
             page.set_next_pid(&next_pid);
         }
 
-        Self {
+        Ok(Self {
             header_pages,
             tx: tx.clone(),
+        })
+    }
+
+    pub(crate) fn get_pids(&self) -> Vec<BTreePageID> {
+        self.header_pages
+            .iter()
+            .map(|page| page.rl().get_pid())
+            .collect()
+    }
+
+    /// Get the status of all slots in the header pages, the i-th item is
+    /// true if the page with page index i is marked as occupied.
+    pub(crate) fn get_slots_status(&self) -> Vec<bool> {
+        let mut status = Vec::new();
+        for page_rc in self.header_pages.iter() {
+            let page = page_rc.rl();
+            for i in 0..page.slot_count {
+                status.push(page.is_slot_used(i));
+            }
         }
+        status
     }
 
-    /// Get the page index of the first empty slot in the header pages.
-    pub(crate) fn get_empty_page_index(&self) -> PageIndex {
+    /// Get the page index of the first empty slot in the header pages, and
+    /// mark the slot as occupied.
+    ///
+    /// The slots of the freed pages come first, so they are reused before
+    /// the file grows. Fails with `PageIndexExhausted` if the index of the
+    /// slot doesn't fit in a page index.
+    pub(crate) fn get_empty_page_index(&self) -> Result<PageIndex, SmallError> {
         let slots_per_page = BTreeHeaderPage::calc_slots_count();
 
         for (i, page_rc) in self.header_pages.iter().enumerate() {
             let mut page = page_rc.wl();
             let empty_slot = page.get_empty_slot();
             if let Some(empty_slot) = empty_slot {
+                let page_index = (i as u64 * slots_per_page as u64) + empty_slot as u64;
+                if page_index > PageIndex::MAX as u64 {
+                    return Err(page_index_exhausted());
+                }
+
                 page.mark_slot_status(empty_slot as usize, true);
-                return empty_slot + (i * slots_per_page) as u32;
+                return Ok(page_index as PageIndex);
             }
         }
 
@@ -297,3 +348,10 @@ impl HeaderPages {
         }
     }
 }
+
+pub(crate) fn page_index_exhausted() -> SmallError {
+    SmallError::with_kind(
+        SmallErrorKind::PageIndexExhausted,
+        "all page indexes of the table are in use",
+    )
+}
@@ -1,17 +1,20 @@
-use std::{fmt, io::Cursor};
+use std::{cmp, fmt, io::Cursor};
 
 use bit_vec::BitVec;
 use log::{debug, error};
 
 use super::{BTreeBasePage, BTreePage, BTreePageID, PageCategory};
 use crate::{
-    btree::{buffer_pool::BufferPool, consts::INDEX_SIZE},
-    error::SmallError,
-    io::{Serializeable, SmallWriter},
-    storage::{table_schema::TableSchema, tuple::Cell},
+    btree::{buffer_pool::BufferPool, consts::INDEX_SIZE, table::KeyComparator},
+    error::{SmallError, SmallErrorKind},
+    io::{read_exact, Serializeable, SmallWriter},
+    storage::{
+        table_schema::{TableSchema, Type},
+        tuple::Cell,
+    },
     transaction::{Permission, Transaction},
     types::{Pod, SmallResult},
-    utils::{floor_div, HandyRwLock},
+    utils::{ceil_div, floor_div, HandyRwLock},
 };
 
 /// The internal page is used to store the keys and the page id of the
@@ -27,9 +30,29 @@ use crate::{
 /// - n bytes: keys
 /// - n bytes: children
 ///
+/// # Prefix Compression
+///
+/// For bytes keys, only the keys of the used slots are written, and
+/// every key is front coded against the previous one:
+///
+/// - 1 byte: length of the prefix shared with the previous key
+/// - 1 byte: length of the suffix
+/// - n bytes: suffix
+///
+/// The full keys are reconstructed when the page is decoded, so the
+/// in-memory keys and the entries returned by
+/// `BTreeInternalPageIterator` are always complete.
+///
+/// Since the size of the keys is variable, the slots of such pages are
+/// sized for the shortest possible key, and the page is full once the
+/// bytes left for keys can't hold another key.
+///
 /// # Stable Criteria
 ///
 /// count(used_slots) >= floor_dev(slot_count, 2)
+///
+/// or, for prefix compressed pages, the keys take at least half of the
+/// bytes available for keys.
 pub struct BTreeInternalPage {
     base: BTreeBasePage,
 
@@ -79,9 +102,16 @@ pub struct BTreeInternalPage {
 
     children_category: PageCategory,
 
+    /// The type of the keys.
+    key_type: Type,
+
     old_data: Vec<u8>,
 }
 
+/// The size of the prefix length and the suffix length of a front
+/// coded key.
+const PREFIX_HEADER_SIZE: usize = 2;
+
 impl BTreeInternalPage {
     fn new(pid: &BTreePageID, bytes: &[u8], table_schema: &TableSchema) -> Self {
         let mut instance: Self;
@@ -118,11 +148,28 @@ impl BTreeInternalPage {
             let header = BitVec::decode(&mut reader, &());
 
             // read keys
+            let key_type = table_schema.get_pkey().get_type();
             let mut keys: Vec<Cell> = Vec::new();
             keys.push(Cell::Int64(0));
-            for _ in 1..slot_count {
-                let key = i64::decode(&mut reader, &());
-                keys.push(Cell::Int64(key));
+            if Self::is_prefix_compressed(table_schema) {
+                let mut previous: Vec<u8> = Vec::new();
+                for i in 1..slot_count {
+                    if !header[i] {
+                        keys.push(Cell::Int64(0));
+                        continue;
+                    }
+
+                    let shared = u8::decode(&mut reader, &()) as usize;
+                    let suffix_size = u8::decode(&mut reader, &()) as usize;
+                    let mut key = previous[..shared].to_vec();
+                    key.extend(read_exact(&mut reader, suffix_size));
+                    keys.push(Cell::Bytes(key.clone()));
+                    previous = key;
+                }
+            } else {
+                for _ in 1..slot_count {
+                    keys.push(Cell::decode(&mut reader, &key_type));
+                }
             }
 
             // read children
@@ -146,6 +193,7 @@ impl BTreeInternalPage {
                 slot_count,
                 header,
                 children_category,
+                key_type,
                 old_data: Vec::new(),
             };
         }
@@ -166,13 +214,8 @@ impl BTreeInternalPage {
         let mut header = BitVec::new();
         header.grow(slot_count, false);
 
-        // read keys
-        let mut keys: Vec<Cell> = Vec::new();
-        keys.push(Cell::Int64(0));
-        for _ in 1..slot_count {
-            let key = i64::decode(&mut reader, &());
-            keys.push(Cell::Int64(key));
-        }
+        // the keys of an empty page are all dummy values
+        let keys: Vec<Cell> = vec![Cell::Int64(0); slot_count];
 
         // read children
         let mut children: Vec<BTreePageID> = Vec::new();
@@ -195,6 +238,7 @@ impl BTreeInternalPage {
             slot_count,
             header,
             children_category,
+            key_type: schema.get_pkey().get_type(),
             old_data: Vec::new(),
         }
     }
@@ -227,12 +271,48 @@ impl BTreeInternalPage {
         entry
     }
 
-    pub fn stable(&self) -> bool {
+    /// Whether the page holds enough children, `max_children` is the fan-out
+    /// of the table (see `BTreeTable::get_fan_out`).
+    pub fn stable(&self, max_children: usize) -> bool {
         if self.get_parent_pid().category == PageCategory::RootPointer {
             return true;
         }
 
-        self.children_count() >= self.get_stable_threshold()
+        if self.children_count() >= Self::get_stable_threshold(max_children) {
+            return true;
+        }
+
+        self.holds_half_of_keys()
+    }
+
+    /// Whether the front coded keys fill about half of the key area. A page
+    /// split from a full page holds slightly less than half of it: the
+    /// reserve of the full page, the middle key moved up and the first key
+    /// of the sibling (no longer compressed) are not counted.
+    fn holds_half_of_keys(&self) -> bool {
+        self.is_prefix_compressed_page()
+            && self.keys_disk_size() * 2 + 4 * self.max_key_disk_size() >= self.keys_capacity()
+    }
+
+    /// Returns true if the entries of the other page (and the entry
+    /// pulled down from the parent) fit into this page without going over
+    /// `max_children`, the fan-out of the table.
+    pub fn can_merge(&self, other: &BTreeInternalPage, max_children: usize) -> bool {
+        if self.children_count() + other.children_count() > max_children {
+            return false;
+        }
+
+        if self.is_prefix_compressed_page() {
+            // one key for the entry pulled down from the parent, one
+            // for the first key of the other page (which is no longer
+            // compressed against its original predecessor), and one
+            // for the reserve of the page
+            let needed =
+                self.keys_disk_size() + other.keys_disk_size() + 3 * self.max_key_disk_size();
+            return needed <= self.keys_capacity();
+        }
+
+        true
     }
 
     pub fn get_entry(&self, index: usize) -> Option<Entry> {
@@ -322,12 +402,20 @@ impl BTreeInternalPage {
 
     pub fn get_first_child_pid(&self) -> BTreePageID {
         let mut it = BTreeInternalPageIterator::new(self);
-        return it.next().unwrap().get_left_child();
+        match it.next() {
+            Some(e) => e.get_left_child(),
+            // the only child of the page
+            None => self.children[0],
+        }
     }
 
     pub fn get_last_child_pid(&self) -> BTreePageID {
         let mut it = BTreeInternalPageIterator::new(self);
-        return it.next_back().unwrap().get_right_child();
+        match it.next_back() {
+            Some(e) => e.get_right_child(),
+            // the only child of the page
+            None => self.children[0],
+        }
     }
 
     pub fn get_left_sibling_pid(&self, tx: &Transaction) -> Option<BTreePageID> {
@@ -388,22 +476,33 @@ impl BTreeInternalPage {
         None
     }
 
+    /// `max_children` is the fan-out of the table to check the occupancy of
+    /// the page against, None skips the occupancy check.
     pub fn check_integrity(
         &self,
         parent_pid: &BTreePageID,
         lower_bound: &Option<Cell>,
         upper_bound: &Option<Cell>,
-        check_occupancy: bool,
+        max_children: Option<usize>,
         depth: usize,
+        comparator: &dyn KeyComparator,
     ) -> SmallResult {
         assert_eq!(self.get_pid().category, PageCategory::Internal);
-        assert_eq!(&self.get_parent_pid(), parent_pid);
+        if &self.get_parent_pid() != parent_pid {
+            let err_msg = format!(
+                "parent pid incorrect, current page: {:?}, actual parent pid: {:?}, expect parent pid: {:?}",
+                self.get_pid(),
+                self.get_parent_pid(),
+                parent_pid,
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+        }
 
         let mut previous = lower_bound.clone();
         let it = BTreeInternalPageIterator::new(self);
         for e in it {
             if let Some(previous) = previous {
-                if previous > e.get_key() {
+                if comparator.compare(&previous, &e.get_key()) == cmp::Ordering::Greater {
                     let err_msg = format!(
                         "entries are not in order, previous (lower_bound): {:?}, current entry: {}, current pid: {}, parent pid: {}",
                         previous,
@@ -411,27 +510,34 @@ impl BTreeInternalPage {
                         self.get_pid(),
                         self.get_parent_pid(),
                     );
-                    return Err(SmallError::new(&err_msg));
+                    return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
                 }
             }
             previous = Some(e.get_key());
         }
 
-        if let Some(upper_bound) = upper_bound {
-            if let Some(previous) = previous {
-                assert!(previous <= upper_bound.clone());
+        if let (Some(upper_bound), Some(previous)) = (upper_bound, previous) {
+            if comparator.compare(&previous, upper_bound) == cmp::Ordering::Greater {
+                let err_msg = format!(
+                    "the last entry exceeds upper_bound, last entry: {:?}, upper bound: {:?}, current pid: {}",
+                    previous,
+                    upper_bound,
+                    self.get_pid(),
+                );
+                return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
             }
         }
 
-        if check_occupancy && depth > 0 {
-            if self.children_count() < self.slot_count / 2 {
+        if let Some(max_children) = max_children {
+            let threshold = Self::get_stable_threshold(max_children);
+            if depth > 0 && self.children_count() < threshold && !self.holds_half_of_keys() {
                 let err_msg = format!(
-                    "children count: {}, max children: {}, pid: {:?}",
+                    "children count: {}, min children: {}, pid: {:?}",
                     self.children_count(),
-                    self.slot_count / 2,
+                    threshold,
                     self.get_pid(),
                 );
-                return Err(SmallError::new(&err_msg));
+                return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
             }
         }
 
@@ -535,7 +641,25 @@ impl BTreeInternalPage {
 // Methods for accessing dynamic attributes.
 impl BTreeInternalPage {
     /// Empty slots (entries/children) count.
+    ///
+    /// For prefix compressed pages, it's also limited by the number of
+    /// uncompressed keys that still fit in the bytes left for keys.
+    /// Room for one more key is kept in reserve, since replacing or
+    /// inserting a key may make its successor compress worse.
     pub fn empty_slots_count(&self) -> usize {
+        let free_slots = self.free_slots_count();
+        if !self.is_prefix_compressed_page() {
+            return free_slots;
+        }
+
+        let max_key_size = self.max_key_disk_size();
+        let free_bytes = self
+            .keys_capacity()
+            .saturating_sub(self.keys_disk_size() + max_key_size);
+        cmp::min(free_slots, free_bytes / max_key_size)
+    }
+
+    fn free_slots_count(&self) -> usize {
         let mut count = 0;
         // start from 1 because the first key slot is not used
         // since a page with m keys has m+1 pointers
@@ -547,39 +671,91 @@ impl BTreeInternalPage {
         count
     }
 
+    /// Get the number of children of the page. A page left with a single
+    /// child (and no entry) by a merge of its children counts it, it's
+    /// merged or refilled by `handle_unstable_internal_page` right away.
     pub fn children_count(&self) -> usize {
-        let children_count = self.slot_count - self.empty_slots_count();
+        self.slot_count - self.free_slots_count()
+    }
 
-        // The minimum number of children is 2. (Since a single child
-        // cannot form an entry.)
-        if children_count < 2 {
-            return 0;
+    pub fn entries_count(&self) -> usize {
+        self.slot_count - self.free_slots_count() - 1
+    }
+
+    /// The bytes taken by the keys when the page is written to disk.
+    pub fn keys_disk_size(&self) -> usize {
+        if !self.is_prefix_compressed_page() {
+            return (self.slot_count - 1) * self.key_type.get_disk_size();
         }
 
-        children_count
+        let mut size = 0;
+        let mut previous: &[u8] = &[];
+        for i in 1..self.slot_count {
+            if !self.is_slot_used(i) {
+                continue;
+            }
+
+            if let Cell::Bytes(key) = &self.keys[i] {
+                size += PREFIX_HEADER_SIZE + key.len() - common_prefix_len(previous, key);
+                previous = key;
+            }
+        }
+        size
     }
 
-    pub fn entries_count(&self) -> usize {
-        self.slot_count - self.empty_slots_count() - 1
+    /// The bytes available for keys in this page.
+    pub fn keys_capacity(&self) -> usize {
+        if !self.is_prefix_compressed_page() {
+            return (self.slot_count - 1) * self.key_type.get_disk_size();
+        }
+
+        // page category, parent pointer, children category
+        let fixed_size = 3 * INDEX_SIZE;
+        // the size of the header and the header bytes
+        let header_size = 2 + ceil_div(self.slot_count, 8);
+        let children_size = self.slot_count * INDEX_SIZE;
+
+        BufferPool::get_page_size() - fixed_size - header_size - children_size
     }
 
-    /// Get the minimum number of children (pages) needed to keep this
-    /// page stable.
-    pub fn get_stable_threshold(&self) -> usize {
-        floor_div(self.slot_count, 2)
+    fn is_prefix_compressed_page(&self) -> bool {
+        matches!(self.key_type, Type::Bytes(_))
+    }
+
+    /// The size of the longest possible key when it's front coded.
+    fn max_key_disk_size(&self) -> usize {
+        match self.key_type {
+            Type::Bytes(size) => PREFIX_HEADER_SIZE + size as usize,
+            _ => self.key_type.get_disk_size(),
+        }
     }
 }
 
 /// Associated functions.
 impl BTreeInternalPage {
+    /// Get the minimum number of children (pages) needed to keep a page
+    /// stable, for a table whose fan-out is `max_children`.
+    pub fn get_stable_threshold(max_children: usize) -> usize {
+        // a page needs two children to hold an entry
+        floor_div(max_children, 2).max(2)
+    }
+
     pub fn get_children_capacity(&self) -> usize {
         self.slot_count
     }
 
     /// Get the capacity of children (pages) in this page. The
     /// capacity of entries is one less than it.
+    ///
+    /// For prefix compressed keys, the slots are sized for the
+    /// shortest key, the actual number of entries is limited by
+    /// `keys_capacity`.
     pub fn get_children_cap(schema: &TableSchema) -> usize {
-        let key_size = schema.get_pkey().get_type().get_disk_size();
+        let key_size = if Self::is_prefix_compressed(schema) {
+            PREFIX_HEADER_SIZE
+        } else {
+            schema.get_pkey().get_type().get_disk_size()
+        };
 
         let bits_per_entry_including_header = key_size * 8 + INDEX_SIZE * 8 + 1;
 
@@ -596,6 +772,16 @@ impl BTreeInternalPage {
             (BufferPool::get_page_size() * 8 - extra_bits) / bits_per_entry_including_header; // round down
         return entries_per_page + 1;
     }
+
+    /// Returns true if the keys of the internal pages are stored with
+    /// prefix compression.
+    pub fn is_prefix_compressed(schema: &TableSchema) -> bool {
+        matches!(schema.get_pkey().get_type(), Type::Bytes(_))
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
 }
 
 impl BTreePage for BTreeInternalPage {
@@ -632,8 +818,30 @@ impl BTreePage for BTreeInternalPage {
 
         // write keys
         let t = table_schema.get_pkey().get_type();
-        for i in 1..self.slot_count {
-            self.keys[i].encode(&mut writer, &t);
+        if Self::is_prefix_compressed(table_schema) {
+            let mut previous: &[u8] = &[];
+            for i in 1..self.slot_count {
+                if !self.is_slot_used(i) {
+                    continue;
+                }
+
+                if let Cell::Bytes(key) = &self.keys[i] {
+                    let shared = common_prefix_len(previous, key);
+                    (shared as u8).encode(&mut writer, &());
+                    ((key.len() - shared) as u8).encode(&mut writer, &());
+                    writer.write_bytes(&key[shared..]);
+                    previous = key;
+                }
+            }
+        } else {
+            for i in 1..self.slot_count {
+                if self.is_slot_used(i) {
+                    self.keys[i].encode(&mut writer, &t);
+                } else {
+                    // the dummy keys are written as zeros
+                    writer.write_bytes(&vec![0; t.get_disk_size()]);
+                }
+            }
         }
 
         // write children
@@ -654,6 +862,12 @@ impl BTreePage for BTreeInternalPage {
         }
         return self.old_data.clone();
     }
+
+    fn restore_page_data(&mut self, bytes: &[u8], table_schema: &TableSchema) {
+        let old_data = std::mem::take(&mut self.old_data);
+        *self = <Self as BTreePage>::new(&self.get_pid(), bytes, table_schema);
+        self.old_data = old_data;
+    }
 }
 
 // All of the entries or tuples in the left child page should be less
@@ -1,4 +1,5 @@
 use std::{
+    cmp::Ordering,
     fmt,
     io::Cursor,
     sync::{Arc, RwLock},
@@ -8,19 +9,33 @@ use bit_vec::BitVec;
 
 use super::{BTreeBasePage, BTreePage, BTreePageID, PageCategory, PageDebug, EMPTY_PAGE_ID};
 use crate::{
-    btree::{buffer_pool::BufferPool, consts::INDEX_SIZE},
-    error::SmallError,
-    io::{read_into, Serializeable, SmallWriter},
+    btree::{
+        buffer_pool::BufferPool,
+        consts::INDEX_SIZE,
+        table::{KeyComparator, NaturalOrder},
+    },
+    error::{SmallError, SmallErrorKind},
+    io::{read_into, varint_size, Serializeable, SmallWriter, MAX_VARINT_SIZE},
     storage::{
         table_schema::TableSchema,
         tuple::{Cell, Tuple, WrappedTuple},
     },
-    transaction::{Transaction, TransactionID},
+    transaction::{Snapshot, Transaction, TransactionID},
     types::SmallResult,
     utils::{ceil_div, HandyRwLock},
     Predicate,
 };
 
+/// Version of the leaf page layout below, recorded in the root pointer page
+/// of every table file (see `BTreeRootPointerPage::get_leaf_format`).
+///
+/// - 0: files written before the version was recorded, every slot takes
+///   the full tuple size, the empty ones included.
+/// - 1: varint fields have variable widths and the empty slots of their
+///   pages take no space. The pages of a schema without varint fields keep
+///   the layout of format 0, so such files are read whatever the version.
+pub const LEAF_FORMAT_VERSION: u32 = 1;
+
 /// A leaf page in the B+ tree.
 ///
 /// # Binary Layout
@@ -31,7 +46,10 @@ use crate::{
 /// - 4 bytes: right sibling page index
 /// - n bytes: header bytes, indicate whether every slot of the page is used or
 ///   not.
-/// - n bytes: tuple bytes
+/// - n bytes: tuple bytes, every slot takes the tuple size, the empty ones
+///   included. Tuples of a schema with varint fields have variable widths
+///   and empty slots take no space, so the number of tuples a page can hold
+///   depends on their values.
 pub struct BTreeLeafPage {
     base: BTreeBasePage,
 
@@ -50,6 +68,16 @@ pub struct BTreeLeafPage {
 
     key_field: usize,
 
+    // size of the widest tuple
+    tuple_size: usize,
+
+    // bytes available for the tuples
+    tuple_area: usize,
+
+    // positions of the varint fields, the tuples are fixed-width if it's
+    // empty
+    varint_fields: Vec<usize>,
+
     old_data: Vec<u8>,
 }
 
@@ -88,11 +116,15 @@ impl BTreeLeafPage {
             let header = BitVec::decode(&mut reader, &());
 
             // read tuples
+            let fixed_width = schema.is_fixed_width();
             let mut tuples = Vec::new();
             for i in 0..slot_count {
                 let tuple: Tuple;
                 if !header[i] {
                     // skip empty tuple
+                    if fixed_width {
+                        Self::skip_empty_slot(&mut reader, schema);
+                    }
                     tuple = Tuple::new(&Vec::new(), 0);
                 } else {
                     tuple = Tuple::decode(&mut reader, schema);
@@ -111,6 +143,9 @@ impl BTreeLeafPage {
                 right_sibling_id,
                 left_sibling_id,
                 key_field: schema.get_key_pos(),
+                tuple_size: schema.get_tuple_size(),
+                tuple_area: Self::calc_tuple_area(slot_count),
+                varint_fields: Self::get_varint_fields(schema),
                 old_data: Vec::new(),
             };
         }
@@ -119,6 +154,59 @@ impl BTreeLeafPage {
         return instance;
     }
 
+    /// Decode the cells of field `field_index` of the tuples visible to `tid`
+    /// from the bytes of a leaf page, and the right sibling of the page. Only
+    /// the cells of the field are decoded (see `Tuple::try_decode_field`),
+    /// the page isn't instantiated.
+    pub(crate) fn decode_visible_cells(
+        pid: &BTreePageID,
+        bytes: &[u8],
+        schema: &TableSchema,
+        tid: TransactionID,
+        field_index: usize,
+    ) -> Result<(Vec<Cell>, Option<BTreePageID>), SmallError> {
+        if BTreeBasePage::is_empty_page(bytes) {
+            return Ok((Vec::new(), None));
+        }
+
+        let mut reader = Cursor::new(bytes);
+        let category = PageCategory::decode(&mut reader, &());
+        if category != PageCategory::Leaf {
+            let err_msg = format!("page category is not leaf, pid: {:?}", pid);
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+        }
+
+        // skip the parent and the left sibling page indexes
+        let _: u32 = read_into(&mut reader, &());
+        let _: u32 = read_into(&mut reader, &());
+        let right_sibling_id: u32 = read_into(&mut reader, &());
+        let header = BitVec::decode(&mut reader, &());
+
+        let fixed_width = schema.is_fixed_width();
+        let mut cells = Vec::new();
+        for i in 0..Self::calc_children_cap(schema) {
+            if header[i] {
+                let tuple = Tuple::try_decode_field(&mut reader, schema, field_index)?;
+                if tuple.visible_to(tid) {
+                    cells.push(tuple.get_cell(0));
+                }
+            } else if fixed_width {
+                Self::skip_empty_slot(&mut reader, schema);
+            }
+        }
+
+        let right_pid = if right_sibling_id == EMPTY_PAGE_ID {
+            None
+        } else {
+            Some(BTreePageID::new(
+                PageCategory::Leaf,
+                pid.get_table_id(),
+                right_sibling_id,
+            ))
+        };
+        Ok((cells, right_pid))
+    }
+
     fn new_empty_page(pid: &BTreePageID, schema: &TableSchema) -> Self {
         let slot_count = Self::calc_children_cap(&schema);
 
@@ -145,6 +233,9 @@ impl BTreeLeafPage {
             right_sibling_id: EMPTY_PAGE_ID,
             left_sibling_id: EMPTY_PAGE_ID,
             key_field: schema.get_key_pos(),
+            tuple_size: schema.get_tuple_size(),
+            tuple_area: Self::calc_tuple_area(slot_count),
+            varint_fields: Self::get_varint_fields(schema),
             old_data: Vec::new(),
         }
     }
@@ -205,10 +296,76 @@ impl BTreeLeafPage {
             return true;
         }
 
+        if !self.varint_fields.is_empty() {
+            // a page split from a full page holds half of its bytes, give or
+            // take half a tuple (see `split_move_count`), and the full page
+            // may lack up to a tuple
+            return self.used_bytes() * 2 >= self.tuple_area.saturating_sub(2 * self.tuple_size);
+        }
+
         let stable_threshold = ceil_div(self.slot_count, 2);
         return self.tuples_count() >= stable_threshold;
     }
 
+    /// Returns true if the page may not have room for another tuple.
+    ///
+    /// For fixed-width tuples this is the same as having no empty slot,
+    /// otherwise the page is also full when the widest tuple doesn't fit in
+    /// the remaining bytes.
+    pub fn is_full(&self) -> bool {
+        self.empty_slots_count() == 0 || self.used_bytes() + self.tuple_size > self.tuple_area
+    }
+
+    /// Returns true if all tuples of `other` fit in this page.
+    pub(crate) fn can_merge_with(&self, other: &BTreeLeafPage) -> bool {
+        self.tuples_count() + other.tuples_count() <= self.slot_count
+            && self.used_bytes() + other.used_bytes() <= self.tuple_area
+    }
+
+    /// Returns the number of bytes taken by the tuples of this page.
+    pub fn used_bytes(&self) -> usize {
+        let mut size = 0;
+        for i in 0..self.slot_count {
+            if self.is_slot_used(i) {
+                size += self.get_tuple_disk_size(&self.tuples[i]);
+            }
+        }
+        size
+    }
+
+    /// Get the number of tuples to move from the end of this page to a new
+    /// right sibling, so that the page keeps `ratio` of its tuples, or of its
+    /// bytes when the tuples have variable widths. Both pages keep at least
+    /// one tuple.
+    pub(crate) fn split_move_count(&self, ratio: f64) -> usize {
+        let tuple_count = self.tuples_count();
+        let keep_count = if self.varint_fields.is_empty() {
+            (tuple_count as f64 * ratio).round() as usize
+        } else {
+            // keep the tuples whose middle byte is before the split point
+            let keep_bytes = self.used_bytes() as f64 * ratio;
+            let mut offset = 0;
+            BTreeLeafPageIterator::new(self)
+                .take_while(|tuple| {
+                    let size = self.get_tuple_disk_size(tuple);
+                    let keep = (offset as f64 + size as f64 / 2.0) < keep_bytes;
+                    offset += size;
+                    keep
+                })
+                .count()
+        };
+        tuple_count - keep_count.max(1).min(tuple_count - 1)
+    }
+
+    fn get_tuple_disk_size(&self, tuple: &Tuple) -> usize {
+        let mut size = self.tuple_size;
+        for i in &self.varint_fields {
+            let v = tuple.get_cell(*i).get_int64().unwrap();
+            size -= MAX_VARINT_SIZE - varint_size(v);
+        }
+        size
+    }
+
     /// Returns the number of empty slots on this page.
     ///
     /// TODO: use a counter to keep track of empty slots
@@ -229,6 +386,28 @@ impl BTreeLeafPage {
 
     /// Adds a tuple to the page such that all tuples remain in sorted order.
     pub fn insert_tuple(&mut self, tuple: &Tuple) -> Result<(), SmallError> {
+        self.insert_tuple_by(tuple, &NaturalOrder)
+    }
+
+    /// Same as `insert_tuple`, but the tuples are kept in the order of
+    /// `comparator`.
+    pub fn insert_tuple_by(
+        &mut self,
+        tuple: &Tuple,
+        comparator: &dyn KeyComparator,
+    ) -> Result<(), SmallError> {
+        self.insert_tuple_with(tuple, comparator, None)
+    }
+
+    /// Same as `insert_tuple_by`, the tuples with equal keys are ordered by
+    /// the natural ordering of the `secondary_field` (if any), otherwise the
+    /// new tuple goes before them.
+    pub fn insert_tuple_with(
+        &mut self,
+        tuple: &Tuple,
+        comparator: &dyn KeyComparator,
+        secondary_field: Option<usize>,
+    ) -> Result<(), SmallError> {
         // find the first empty slot
         let mut first_empty_slot: i64 = 0;
         for i in 0..self.slot_count {
@@ -249,7 +428,15 @@ impl BTreeLeafPage {
         let mut last_less_slot: i64 = -1;
         for i in 0..self.slot_count {
             if self.is_slot_used(i) {
-                if self.tuples[i].get_cell(self.key_field) < tuple.get_cell(self.key_field) {
+                let mut ordering = comparator.compare(
+                    &self.tuples[i].get_cell(self.key_field),
+                    &tuple.get_cell(self.key_field),
+                );
+                if let Some(field) = secondary_field {
+                    ordering = ordering
+                        .then_with(|| self.tuples[i].get_cell(field).cmp(&tuple.get_cell(field)));
+                }
+                if ordering == Ordering::Less {
                     last_less_slot = i as i64;
                 } else {
                     break;
@@ -302,7 +489,10 @@ impl BTreeLeafPage {
     }
 
     // Delete all "deleted" tuples that are not visible to the given transaction.
-    pub(crate) fn delete_invisible_tuples(&mut self, min_action: &TransactionID) {
+    //
+    // Return the keys of the deleted tuples.
+    pub(crate) fn delete_invisible_tuples(&mut self, min_action: &TransactionID) -> Vec<Cell> {
+        let mut keys = Vec::new();
         for i in 0..self.slot_count {
             if !self.is_slot_used(i) {
                 continue;
@@ -310,9 +500,11 @@ impl BTreeLeafPage {
 
             let xmax = &self.tuples[i].get_xmax();
             if *xmax != TransactionID::MAX && xmax < min_action {
+                keys.push(self.tuples[i].get_cell(self.key_field));
                 self.delete_tuple(i);
             }
         }
+        keys
     }
 
     /// Returns true if associated slot on this page is filled.
@@ -332,9 +524,13 @@ impl BTreeLeafPage {
         upper_bound: &Option<Cell>,
         check_occupancy: bool,
         depth: usize,
+        comparator: &dyn KeyComparator,
     ) -> SmallResult {
         if self.get_pid().category != PageCategory::Leaf {
-            return Err(SmallError::new("page category is not leaf"));
+            return Err(SmallError::with_kind(
+                SmallErrorKind::Corruption,
+                "page category is not leaf",
+            ));
         }
 
         if &self.get_parent_pid() != parent_pid {
@@ -344,21 +540,23 @@ impl BTreeLeafPage {
                 self.get_parent_pid(),
                 parent_pid,
             );
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         }
 
         let mut previous = lower_bound.clone();
         let it = BTreeLeafPageIterator::new(self);
         for tuple in it {
             if let Some(previous) = previous {
-                if previous > tuple.get_cell(self.key_field) {
+                if comparator.compare(&previous, &tuple.get_cell(self.key_field))
+                    == Ordering::Greater
+                {
                     let err_msg = format!(
                         "previous: {:?}, current: {:?}, page_id: {:?}",
                         previous,
                         tuple.get_cell(self.key_field),
                         self.get_pid(),
                     );
-                    return Err(SmallError::new(&err_msg));
+                    return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
                 }
             }
             previous = Some(tuple.get_cell(self.key_field));
@@ -366,18 +564,22 @@ impl BTreeLeafPage {
 
         if let Some(upper_bound) = upper_bound {
             if let Some(previous) = previous {
-                if &previous > upper_bound {
+                if comparator.compare(&previous, upper_bound) == Ordering::Greater {
                     let err_msg = format!(
                         "the last tuple exceeds upper_bound, last tuple: {:?}, upper bound: {:?}",
                         previous, upper_bound,
                     );
-                    return Err(SmallError::new(&err_msg));
+                    return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
                 }
             }
         }
 
         if check_occupancy && depth > 0 {
-            assert!(self.tuples_count() >= self.get_slots_count() / 2);
+            if self.varint_fields.is_empty() {
+                assert!(self.tuples_count() >= self.get_slots_count() / 2);
+            } else {
+                assert!(self.stable());
+            }
         }
 
         return Ok(());
@@ -401,13 +603,86 @@ impl BTreeLeafPage {
         }
         return result;
     }
+
+    /// Find the slot of the first tuple with the given key, in the natural
+    /// order of the key field.
+    pub fn find_slot(&self, key: &Cell) -> Option<usize> {
+        self.find_slot_by(key, &NaturalOrder)
+    }
+
+    /// Same as `find_slot`, but the tuples are ordered by `comparator`.
+    ///
+    /// This is a binary search over the slots, the visibility of the tuple is
+    /// not checked.
+    pub fn find_slot_by(&self, key: &Cell, comparator: &dyn KeyComparator) -> Option<usize> {
+        let slot = self.lower_bound_by(key, comparator)?;
+        match comparator.compare(&self.tuples[slot].get_cell(self.key_field), key) {
+            Ordering::Equal => Some(slot),
+            _ => None,
+        }
+    }
+
+    /// Find the slot of the first tuple whose key is not less than `key`,
+    /// return None if all tuples in the page are less than `key`.
+    pub(crate) fn lower_bound_by(
+        &self,
+        key: &Cell,
+        comparator: &dyn KeyComparator,
+    ) -> Option<usize> {
+        // The used slots before `lo` are less than the key, and the used
+        // slots from `hi` are not less than the key.
+        let mut lo = 0;
+        let mut hi = self.slot_count;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+
+            // empty slots have no key, probe the first used slot from `mid`
+            // instead
+            match (mid..hi).find(|i| self.is_slot_used(*i)) {
+                Some(slot) => {
+                    let ordering =
+                        comparator.compare(&self.tuples[slot].get_cell(self.key_field), key);
+                    if ordering == Ordering::Less {
+                        lo = slot + 1;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                None => hi = mid,
+            }
+        }
+
+        (lo..self.slot_count).find(|i| self.is_slot_used(*i))
+    }
+
+    /// Get the tuple in the slot, return None if the slot is empty.
+    pub(crate) fn get_tuple(&self, slot_index: usize) -> Option<&Tuple> {
+        if slot_index < self.slot_count && self.is_slot_used(slot_index) {
+            Some(&self.tuples[slot_index])
+        } else {
+            None
+        }
+    }
+
+    /// Get the cells of field `field_index` of the tuples visible to `tid`,
+    /// in slot order.
+    pub(crate) fn visible_cells(&self, tid: TransactionID, field_index: usize) -> Vec<Cell> {
+        (0..self.slot_count)
+            .filter_map(|slot| self.get_tuple(slot))
+            .filter(|tuple| tuple.visible_to(tid))
+            .map(|tuple| tuple.get_cell(field_index))
+            .collect()
+    }
 }
 
 /// Methods for accessing const attributes.
 impl BTreeLeafPage {
     /// Get the capacity of children (tuples) in this page.
+    ///
+    /// For a schema with varint fields, this is the number of the narrowest
+    /// tuples that fit in the page.
     pub fn calc_children_cap(schema: &TableSchema) -> usize {
-        let bits_per_tuple_including_header = schema.get_tuple_size() * 8 + 1;
+        let bits_per_tuple_including_header = schema.get_min_tuple_size() * 8 + 1;
 
         // extraBits:
         // - page category (4 bytes)
@@ -419,6 +694,29 @@ impl BTreeLeafPage {
 
         (BufferPool::get_page_size() * 8 - extra_bits) / bits_per_tuple_including_header
     }
+
+    /// Get the number of bytes available for the tuples of a page with
+    /// `slot_count` slots.
+    fn calc_tuple_area(slot_count: usize) -> usize {
+        let extra_bytes = 4 + 3 * INDEX_SIZE + 2 + ceil_div(slot_count, 8);
+        BufferPool::get_page_size() - extra_bytes
+    }
+
+    /// Skip the bytes of an empty slot of a fixed-width page.
+    fn skip_empty_slot(reader: &mut Cursor<&[u8]>, schema: &TableSchema) {
+        let position = reader.position() + schema.get_tuple_size() as u64;
+        reader.set_position(position);
+    }
+
+    fn get_varint_fields(schema: &TableSchema) -> Vec<usize> {
+        schema
+            .get_fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.is_varint())
+            .map(|(i, _)| i)
+            .collect()
+    }
 }
 
 impl BTreePage for BTreeLeafPage {
@@ -467,7 +765,7 @@ impl BTreePage for BTreeLeafPage {
         for i in 0..self.slot_count {
             if self.is_slot_used(i) {
                 self.tuples[i].encode(&mut writer, table_schema);
-            } else {
+            } else if self.varint_fields.is_empty() {
                 // write empty bytes
                 let mut bytes: Vec<u8> = Vec::new();
                 bytes.resize(table_schema.get_tuple_size(), 0);
@@ -488,6 +786,12 @@ impl BTreePage for BTreeLeafPage {
         }
         return self.old_data.clone();
     }
+
+    fn restore_page_data(&mut self, bytes: &[u8], table_schema: &TableSchema) {
+        let old_data = std::mem::take(&mut self.old_data);
+        *self = <Self as BTreePage>::new(&self.get_pid(), bytes, table_schema);
+        self.old_data = old_data;
+    }
 }
 
 pub struct BTreeLeafPageIteratorRc {
@@ -496,6 +800,7 @@ pub struct BTreeLeafPageIteratorRc {
     reverse_cursor: i64,
 
     tx_id: TransactionID,
+    snapshot: Option<Arc<Snapshot>>,
 }
 
 impl BTreeLeafPageIteratorRc {
@@ -507,14 +812,35 @@ impl BTreeLeafPageIteratorRc {
             reverse_cursor: slot_count as i64,
 
             tx_id: tx.get_id(),
+            snapshot: None,
         }
     }
-}
 
-impl Iterator for BTreeLeafPageIteratorRc {
-    type Item = WrappedTuple;
+    /// Same as `new`, but the visibility of the tuples is decided by the
+    /// snapshot.
+    pub fn with_snapshot(
+        tx: &Transaction,
+        page: Arc<RwLock<BTreeLeafPage>>,
+        snapshot: Option<Arc<Snapshot>>,
+    ) -> Self {
+        Self {
+            snapshot,
+            ..Self::new(tx, page)
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Whether the tuple is visible to the transaction (or under the
+    /// snapshot) of the iterator, `next_back` doesn't check it.
+    pub(crate) fn is_visible(&self, tuple: &Tuple) -> bool {
+        match &self.snapshot {
+            Some(snapshot) => tuple.visible_in(snapshot),
+            None => tuple.visible_to(self.tx_id),
+        }
+    }
+
+    /// Get the next visible tuple accepted by `f`. The tuples rejected by
+    /// `f` are skipped without being copied out of the page.
+    pub fn next_matching<F: FnMut(&Tuple) -> bool>(&mut self, f: &mut F) -> Option<WrappedTuple> {
         let page = self.page.rl();
         loop {
             self.cursor += 1;
@@ -524,21 +850,25 @@ impl Iterator for BTreeLeafPageIteratorRc {
             }
 
             if page.is_slot_used(cursor) {
-                let tuple = page.tuples[cursor].clone();
-                if !tuple.visible_to(self.tx_id) {
+                let tuple = &page.tuples[cursor];
+                if !self.is_visible(tuple) || !f(tuple) {
                     continue;
                 }
 
-                return Some(WrappedTuple::new(
-                    &page.tuples[cursor].clone(),
-                    cursor,
-                    page.get_pid(),
-                ));
+                return Some(WrappedTuple::new(tuple, cursor, page.get_pid()));
             }
         }
     }
 }
 
+impl Iterator for BTreeLeafPageIteratorRc {
+    type Item = WrappedTuple;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_matching(&mut |_| true)
+    }
+}
+
 impl DoubleEndedIterator for BTreeLeafPageIteratorRc {
     fn next_back(&mut self) -> Option<Self::Item> {
         let page = self.page.rl();
@@ -5,6 +5,7 @@ mod header_page;
 mod internal_page;
 mod leaf_page;
 mod page_category;
+mod page_guard;
 mod page_id;
 mod root_pointer_page;
 mod traits;
@@ -13,6 +14,7 @@ pub use header_page::*;
 pub use internal_page::*;
 pub use leaf_page::*;
 pub use page_category::*;
+pub use page_guard::*;
 pub use page_id::*;
 pub use root_pointer_page::*;
 pub use traits::*;
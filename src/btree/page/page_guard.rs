@@ -0,0 +1,71 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use super::{BTreePage, BTreePageID};
+use crate::{transaction::Transaction, utils::HandyRwLock, Database};
+
+/// A page fetched from the buffer pool together with the latch the
+/// transaction holds on it.
+///
+/// The latch is released when the guard is dropped, so the caller doesn't
+/// have to call `release_latch` on every return path. Only a latch acquired
+/// by the request right before the guard is released: a latch the
+/// transaction held on the page already, and an X-latch, are kept until the
+/// transaction ends. The page is pinned in the buffer pool for as long as
+/// the guard lives.
+pub struct PageGuard<P: BTreePage> {
+    tx: Transaction,
+    pid: BTreePageID,
+    page_rc: Arc<RwLock<P>>,
+
+    // whether the latch was acquired for this guard
+    release: bool,
+}
+
+impl<P: BTreePage> PageGuard<P> {
+    /// Wrap a page which is already latched by `tx`, it has to be created
+    /// right after the request of the latch.
+    pub(crate) fn new(tx: &Transaction, page_rc: Arc<RwLock<P>>) -> Self {
+        let pid = page_rc.rl().get_pid();
+        Database::buffer_pool().pin(&pid);
+        let release = Database::mut_concurrent_status().take_new_latch(tx, &pid);
+        Self {
+            tx: tx.clone(),
+            pid,
+            page_rc,
+            release,
+        }
+    }
+
+    pub fn get_pid(&self) -> BTreePageID {
+        self.pid
+    }
+
+    /// Get the underlying page. The latch is still released when the guard
+    /// is dropped, no matter how long the returned pointer lives.
+    pub fn get_page_rc(&self) -> Arc<RwLock<P>> {
+        Arc::clone(&self.page_rc)
+    }
+}
+
+impl<P: BTreePage> Deref for PageGuard<P> {
+    type Target = RwLock<P>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.page_rc
+    }
+}
+
+impl<P: BTreePage> Drop for PageGuard<P> {
+    fn drop(&mut self) {
+        Database::buffer_pool().unpin(&self.pid);
+        if !self.release {
+            return;
+        }
+        if let Err(e) = Database::mut_concurrent_status().release_guard_latch(&self.tx, &self.pid) {
+            log::error!("failed to release latch on {:?}: {}", self.pid, e);
+        }
+    }
+}
@@ -44,6 +44,14 @@ impl BTreePageID {
         }
     }
 
+    pub fn get_category(&self) -> PageCategory {
+        self.category
+    }
+
+    pub fn get_page_index(&self) -> PageIndex {
+        self.page_index
+    }
+
     pub(crate) fn get_table_id(&self) -> u32 {
         self.table_id
     }
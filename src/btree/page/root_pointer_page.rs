@@ -1,6 +1,8 @@
 use std::io::Cursor;
 
-use super::{BTreeBasePage, BTreePage, BTreePageID, PageCategory, EMPTY_PAGE_ID};
+use super::{
+    BTreeBasePage, BTreePage, BTreePageID, PageCategory, EMPTY_PAGE_ID, LEAF_FORMAT_VERSION,
+};
 use crate::{
     btree::buffer_pool::BufferPool,
     io::{Serializeable, SmallWriter},
@@ -13,6 +15,8 @@ use crate::{
 /// - 4 bytes: root page index
 /// - 4 bytes: root page category (leaf/internal)
 /// - 4 bytes: header page index
+/// - 4 bytes: leaf page format version, 0 in the files written before it
+///   was recorded (the rest of the page is zero-filled)
 pub struct BTreeRootPointerPage {
     base: BTreeBasePage,
 
@@ -26,6 +30,9 @@ pub struct BTreeRootPointerPage {
     /// The page index of the first header page.
     header_page_index: u32,
 
+    /// The format of the leaf pages of the file, see `LEAF_FORMAT_VERSION`.
+    leaf_format: u32,
+
     /// Migrated from old version.
     ///
     /// TODO: Figure out what this is used for, and if it's needed.
@@ -51,6 +58,9 @@ impl BTreeRootPointerPage {
         // read header page index
         let header_page_index = u32::decode(&mut reader, &());
 
+        // read leaf page format version
+        let leaf_format = u32::decode(&mut reader, &());
+
         let root_pid = BTreePageID {
             category: root_page_category,
             page_index: root_page_index,
@@ -61,6 +71,7 @@ impl BTreeRootPointerPage {
             base: BTreeBasePage::new(pid),
             root_pid,
             header_page_index,
+            leaf_format,
             old_data: Vec::new(),
         };
 
@@ -80,6 +91,7 @@ impl BTreeRootPointerPage {
             base: BTreeBasePage::new(pid),
             root_pid,
             header_page_index: EMPTY_PAGE_ID,
+            leaf_format: LEAF_FORMAT_VERSION,
             old_data: Vec::new(),
         }
     }
@@ -109,6 +121,11 @@ impl BTreeRootPointerPage {
     pub fn set_header_pid(&mut self, pid: &BTreePageID) {
         self.header_page_index = pid.page_index;
     }
+
+    /// Get the format version of the leaf pages of the file.
+    pub fn get_leaf_format(&self) -> u32 {
+        self.leaf_format
+    }
 }
 
 impl BTreePage for BTreeRootPointerPage {
@@ -143,6 +160,9 @@ impl BTreePage for BTreeRootPointerPage {
         // write header page index
         self.header_page_index.encode(&mut writer, &());
 
+        // write leaf page format version
+        self.leaf_format.encode(&mut writer, &());
+
         return writer.to_padded_bytes(BufferPool::get_page_size());
     }
 
@@ -156,4 +176,10 @@ impl BTreePage for BTreeRootPointerPage {
         }
         return self.old_data.clone();
     }
+
+    fn restore_page_data(&mut self, bytes: &[u8], table_schema: &TableSchema) {
+        let old_data = std::mem::take(&mut self.old_data);
+        *self = <Self as BTreePage>::new(&self.get_pid(), bytes, table_schema);
+        self.old_data = old_data;
+    }
 }
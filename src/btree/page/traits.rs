@@ -29,6 +29,11 @@ pub trait BTreePage {
     /// Provide a representation of this page before any modifications
     /// were made to it. Used by recovery.
     fn get_before_image(&self, table_schema: &TableSchema) -> Vec<u8>;
+
+    /// Replace the contents of this page with `bytes` (as generated by
+    /// get_page_data), the before image is kept. Used to roll back to a
+    /// savepoint.
+    fn restore_page_data(&mut self, bytes: &[u8], table_schema: &TableSchema);
 }
 
 /// A trait that involves all the debug methods for a page. All page
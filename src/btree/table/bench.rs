@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+use super::WriteScene;
+use crate::{error::SmallError, storage::tuple::Tuple, transaction::Transaction, BTreeTable};
+
+/// The throughput measured by `BTreeTable::benchmark_insert`.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub ops: usize,
+    pub duration: Duration,
+    pub ops_per_sec: f64,
+}
+
+impl BTreeTable {
+    /// Insert `n` random tuples in a single transaction and measure the
+    /// throughput, for micro-benchmarks.
+    ///
+    /// With `WriteScene::Sequential` the tuples are inserted in key order,
+    /// with `WriteScene::Random` in the order they are generated. The
+    /// generation of the tuples is not timed, the commit is.
+    pub fn benchmark_insert(&self, n: usize, scene: WriteScene) -> Result<BenchResult, SmallError> {
+        let tx = Transaction::new();
+
+        let mut rng = rand::thread_rng();
+        let mut tuples: Vec<Tuple> = (0..n)
+            .map(|_| Tuple::random(&self.schema, &mut rng, tx.get_id()))
+            .collect();
+        if let WriteScene::Sequential = scene {
+            tuples.sort_by(|a, b| {
+                self.comparator
+                    .compare(&a.get_cell(self.key_field), &b.get_cell(self.key_field))
+            });
+        }
+
+        let start = Instant::now();
+        for tuple in &tuples {
+            if let Err(e) = self.insert_tuple(&tx, tuple) {
+                tx.abort()?;
+                return Err(e);
+            }
+        }
+        tx.commit()?;
+        let duration = start.elapsed();
+
+        Ok(BenchResult {
+            ops: n,
+            duration,
+            ops_per_sec: n as f64 / duration.as_secs_f64().max(f64::EPSILON),
+        })
+    }
+}
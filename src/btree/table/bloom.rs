@@ -0,0 +1,194 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    btree::{buffer_pool::BufferPool, page::BTreePage},
+    common::Catalog,
+    storage::tuple::Cell,
+    transaction::{Permission, Transaction},
+    types::SmallResult,
+    utils::HandyRwLock,
+    BTreeTable, Database,
+};
+
+/// An in-memory counting Bloom filter over the keys of a table, see
+/// `BTreeTable::enable_bloom_filter`.
+///
+/// Each key increments `hashes_count` counters, so a key can be removed
+/// again. A counter which overflows stays saturated, it's never
+/// decremented afterwards (the keys sharing it become false positives
+/// instead of false negatives).
+pub struct BloomFilter {
+    counters: Vec<u8>,
+    hashes_count: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter of `counters_count` counters, each key
+    /// increments `hashes_count` of them.
+    pub fn new(counters_count: usize, hashes_count: usize) -> Self {
+        Self {
+            counters: vec![0; counters_count.max(1)],
+            hashes_count: hashes_count.max(1),
+        }
+    }
+
+    pub fn insert(&mut self, key: &Cell) {
+        for i in 0..self.hashes_count {
+            let pos = self.position(key, i);
+            self.counters[pos] = self.counters[pos].saturating_add(1);
+        }
+    }
+
+    /// Remove one occurrence of a key inserted before.
+    pub fn remove(&mut self, key: &Cell) {
+        for i in 0..self.hashes_count {
+            let pos = self.position(key, i);
+            if self.counters[pos] != 0 && self.counters[pos] != u8::MAX {
+                self.counters[pos] -= 1;
+            }
+        }
+    }
+
+    /// Return false if the key has never been inserted (or has been
+    /// removed as many times), true if it may have been.
+    pub fn may_contain(&self, key: &Cell) -> bool {
+        (0..self.hashes_count).all(|i| self.counters[self.position(key, i)] != 0)
+    }
+
+    fn position(&self, key: &Cell, i: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        i.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % self.counters.len() as u64) as usize
+    }
+}
+
+impl BTreeTable {
+    /// Keep a Bloom filter over the keys of the table, so the point lookups
+    /// (`get_by_key`, `contains_key`) of the keys which are definitely
+    /// absent return without descending the tree.
+    ///
+    /// The filter is built from all tuples in the leaf pages (whatever their
+    /// visibility) and updated on insert. A deleted key is removed from the
+    /// filter once its tuple is physically removed from the pages (see
+    /// `delete_invisible_tuples` and `purge_tombstones`), until then the
+    /// tuple is still visible to the older transactions. The filter is
+    /// built again after the imports, the copies and the rebuilds.
+    ///
+    /// The size of the filter is persisted in the catalog (for the tables
+    /// persisted there), the filter is built again when the table is
+    /// loaded.
+    ///
+    /// The filter hashes the cells, it must not be used with a comparator
+    /// which treats different cells as equal.
+    pub fn enable_bloom_filter(
+        &self,
+        tx: &Transaction,
+        counters_count: usize,
+        hashes_count: usize,
+    ) -> SmallResult {
+        self.build_bloom_filter(tx, counters_count, hashes_count)?;
+        Catalog::write_table_options(self)
+    }
+
+    /// Drop the Bloom filter of the table, the change is persisted in the
+    /// catalog as well.
+    pub fn disable_bloom_filter(&self) -> SmallResult {
+        *self.bloom_filter.wl() = None;
+        Catalog::write_table_options(self)
+    }
+
+    /// Build the Bloom filter from the leaf pages. The tree latch is held
+    /// (shared) during the build, so the writers wait until it's done and
+    /// no key is missed.
+    pub(crate) fn build_bloom_filter(
+        &self,
+        tx: &Transaction,
+        counters_count: usize,
+        hashes_count: usize,
+    ) -> SmallResult {
+        let _s_latch = self.tree_latch.rl();
+
+        // install the filter first, the tuples inserted during the build are
+        // added by the insertion itself
+        *self.bloom_filter.wl() = Some(BloomFilter::new(counters_count, hashes_count));
+
+        // the pages written by `tx` (e.g. by an import) keep their latch
+        // until it ends
+        let dirty_pages = Database::concurrent_status().get_dirty_pages(tx);
+
+        let mut page_rc = self.get_first_page(tx, Permission::ReadOnly)?;
+        loop {
+            let (pid, right_pid) = {
+                let page = page_rc.rl();
+                let mut filter = self.bloom_filter.wl();
+                if let Some(filter) = filter.as_mut() {
+                    for tuple in page.iter() {
+                        filter.insert(&tuple.get_cell(self.key_field));
+                    }
+                }
+                (page.get_pid(), page.get_right_pid())
+            };
+            if !dirty_pages.contains(&pid) {
+                Database::mut_concurrent_status().release_latch(tx, &pid)?;
+            }
+
+            match right_pid {
+                Some(right_pid) => {
+                    page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &right_pid)?;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Build the Bloom filter (if any) again from the leaf pages, e.g.
+    /// after the table file is loaded again or after an import.
+    ///
+    /// If the build fails, the filter is dropped (in memory only), since a
+    /// filter missing keys would hide them from the lookups.
+    pub(super) fn rebuild_bloom_filter(&self, tx: &Transaction) -> SmallResult {
+        if let Some((counters_count, hashes_count)) = self.bloom_filter_params() {
+            if let Err(e) = self.build_bloom_filter(tx, counters_count, hashes_count) {
+                *self.bloom_filter.wl() = None;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the number of counters and the number of hashes of the Bloom
+    /// filter, None if the table has no filter.
+    pub(crate) fn bloom_filter_params(&self) -> Option<(usize, usize)> {
+        self.bloom_filter
+            .rl()
+            .as_ref()
+            .map(|filter| (filter.counters.len(), filter.hashes_count))
+    }
+
+    /// Return false if the key is definitely absent from the table.
+    pub(super) fn may_contain_key(&self, key: &Cell) -> bool {
+        match self.bloom_filter.rl().as_ref() {
+            Some(filter) => filter.may_contain(key),
+            None => true,
+        }
+    }
+
+    /// Add the key of a new tuple to the Bloom filter (if any).
+    pub(super) fn add_to_bloom_filter(&self, key: &Cell) {
+        if let Some(filter) = self.bloom_filter.wl().as_mut() {
+            filter.insert(key);
+        }
+    }
+
+    /// Remove the key of a tuple removed from the pages from the Bloom
+    /// filter (if any).
+    pub(super) fn remove_from_bloom_filter(&self, key: &Cell) {
+        if let Some(filter) = self.bloom_filter.wl().as_mut() {
+            filter.remove(key);
+        }
+    }
+}
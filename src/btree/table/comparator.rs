@@ -0,0 +1,38 @@
+use std::cmp::Ordering;
+
+use crate::storage::tuple::Cell;
+
+/// The ordering of the keys of a B+ tree.
+///
+/// A table built with `BTreeTable::new_with_comparator` uses it to place the
+/// tuples in the leaf pages and to route the searches through the internal
+/// pages, so domain-specific orderings (e.g. case-insensitive strings) don't
+/// need new `Cell` variants.
+///
+/// The comparator must be deterministic and total, otherwise the tuples end
+/// up in the wrong pages.
+pub trait KeyComparator: Send + Sync {
+    fn compare(&self, a: &Cell, b: &Cell) -> Ordering;
+
+    /// Whether the byte strings are ordered as by `NaturalOrder`, so the
+    /// keys starting with a given prefix are next to each other, right
+    /// after the prefix itself. A LIKE pattern with a fixed prefix is then
+    /// served by a seek to the prefix instead of a full scan.
+    fn orders_bytes_naturally(&self) -> bool {
+        false
+    }
+}
+
+/// The natural ordering of the cells, used by the tables created with
+/// `BTreeTable::new`.
+pub struct NaturalOrder;
+
+impl KeyComparator for NaturalOrder {
+    fn compare(&self, a: &Cell, b: &Cell) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn orders_bytes_naturally(&self) -> bool {
+        true
+    }
+}
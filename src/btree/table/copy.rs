@@ -0,0 +1,103 @@
+use std::{
+    iter,
+    sync::{Arc, RwLock},
+};
+
+use log::error;
+
+use super::BTreeTableIterator;
+use crate::{
+    common::Catalog,
+    error::{SmallError, SmallErrorKind},
+    storage::{
+        table_schema::{Field, TableSchema},
+        tuple::Tuple,
+    },
+    transaction::Transaction,
+    utils::HandyRwLock,
+    BTreeTable,
+};
+
+impl BTreeTable {
+    /// Create the table `new_name` with the schema and the tuples of this
+    /// table visible to `tx`, the table is added to the catalog.
+    ///
+    /// With `new_key_field`, the copy is keyed on that column instead of the
+    /// key field of this table, so its tuples are sorted by that column.
+    /// The values of the new key don't have to be unique.
+    ///
+    /// The copy keeps the comparator, the secondary sort field (unless it's
+    /// the new key), the fan-out cap and the Bloom filter of this table.
+    ///
+    /// The tuples are streamed from a scan of this table into the copy, so
+    /// the table is never held in memory. They come in the key order of this
+    /// table, which is also the order of the copy unless it has a new key.
+    /// The copy is persisted in the catalog only once all tuples are in, a
+    /// failed copy is dropped.
+    pub fn copy_to(
+        &self,
+        tx: &Transaction,
+        new_name: &str,
+        new_key_field: Option<usize>,
+    ) -> Result<Arc<RwLock<BTreeTable>>, SmallError> {
+        let key_field = new_key_field.unwrap_or(self.key_field);
+        let fields = self.schema.get_fields();
+        if key_field >= fields.len() {
+            let err_msg = format!(
+                "key field {} out of range, table {} has {} fields",
+                key_field,
+                self.name,
+                fields.len()
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::NotFound, &err_msg));
+        }
+
+        let fields: Vec<Field> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let mut field = field.clone();
+                field.is_primary = i == key_field;
+                field
+            })
+            .collect();
+        let schema = TableSchema::new(fields);
+
+        let mut copy =
+            BTreeTable::new_with_comparator(new_name, None, &schema, Arc::clone(&self.comparator));
+        copy.secondary_field = self.secondary_field.filter(|field| *field != key_field);
+        copy.max_fan_out = self.max_fan_out;
+
+        let mut source = BTreeTableIterator::new(tx, self)?;
+        let tuples = iter::from_fn(move || {
+            source
+                .try_next()
+                .map(|t| t.map(|t| Tuple::new(&t.get_cells(), tx.get_id())))
+                .transpose()
+        });
+
+        // the buffer pool finds the file of the pages through the catalog,
+        // the copy is only kept in memory until it's complete
+        let table_rc = Arc::new(RwLock::new(copy));
+        Catalog::add_table(Arc::clone(&table_rc), false)?;
+
+        let result = {
+            let table = table_rc.rl();
+            match self.bloom_filter_params() {
+                Some((counters_count, hashes_count)) => {
+                    table.build_bloom_filter(tx, counters_count, hashes_count)
+                }
+                None => Ok(()),
+            }
+            .and_then(|_| table.import_tuples(tx, tuples))
+        }
+        .and_then(|_| Catalog::add_table(Arc::clone(&table_rc), true));
+        if let Err(e) = result {
+            if let Err(drop_err) = Catalog::drop_table(new_name) {
+                error!("failed to drop the partial copy {}: {}", new_name, drop_err);
+            }
+            return Err(e);
+        }
+        Ok(table_rc)
+    }
+}
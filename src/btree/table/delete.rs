@@ -14,11 +14,11 @@ use crate::{
             BTreePage, BTreePageID, Entry, PageCategory,
         },
     },
-    error::SmallError,
+    error::{SmallError, SmallErrorKind},
     storage::tuple::{Cell, WrappedTuple},
-    transaction::{Permission, Transaction},
+    transaction::{Permission, Transaction, TransactionID},
     types::SmallResult,
-    utils::HandyRwLock,
+    utils::{balance_move_count, HandyRwLock},
     BTreeTable, Database, Predicate,
 };
 
@@ -27,10 +27,13 @@ impl BTreeTable {
     /// Delete a tuple from this BTreeFile.
     ///
     /// May cause pages to merge or redistribute entries/tuples if the
-    /// pages become less than half full.
+    /// pages become less than half full (unless auto merge is disabled).
     ///
     /// TODO: remove this api
     pub fn delete_tuple(&self, tx: &Transaction, tuple: &WrappedTuple) -> SmallResult {
+        tx.check_active()?;
+        tx.check_writable()?;
+
         let pid = tuple.get_pid();
         let leaf_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, &pid).unwrap();
 
@@ -39,12 +42,13 @@ impl BTreeTable {
             let mut leaf = leaf_rc.wl();
             leaf.mvcc_delete_tuple(&tx.get_id(), tuple.get_slot_number());
         }
+        self.record_change(tx, Some(tuple.get_tuple()), None);
         // release the leaf page
 
         // TODO: after implementation mvcc, only tuples which are invisible to all
         // (active) transactions should be deleted from the page.
 
-        if !leaf_rc.rl().stable() {
+        if self.auto_merge && !leaf_rc.rl().stable() {
             // Before handling the erratic page, request the X-latch on the tree
             let xlatch = self.tree_latch.wl();
 
@@ -57,30 +61,46 @@ impl BTreeTable {
         let leaf_pid = leaf_rc.rl().get_pid();
         Database::mut_concurrent_status().release_latch(tx, &leaf_pid)?;
 
+        self.delete_index_entries(tx, tuple.get_tuple())?;
+
         Ok(())
     }
 
+    /// Delete a tuple in a transaction of its own, which is committed on
+    /// success and aborted on error. For callers which don't manage
+    /// transactions.
+    pub fn delete_auto(&self, tuple: &WrappedTuple) -> SmallResult {
+        self.auto_commit(|tx| self.delete_tuple(tx, tuple))
+    }
+
     /// Delete all tuples that meet the predicate from this BTreeFile.
     ///
     /// TODO: this api is too slow.
     pub fn delete_tuples(&self, tx: &Transaction, predicate: &Predicate) -> SmallResult {
+        tx.check_active()?;
+        tx.check_writable()?;
+
         let xlatch = self.tree_latch.wl();
 
         let root_pid = self.get_root_pid(tx);
         let mut page_rc =
-            self.find_leaf_page(&tx, Permission::ReadWrite, root_pid, &SearchFor::LeftMost);
+            self.find_leaf_page(&tx, Permission::ReadWrite, root_pid, &SearchFor::LeftMost)?;
 
         // step 1: find all pages that may contian the tuples that meet the predicate
 
+        let mut deleted_tuples = Vec::new();
         loop {
             let slots = page_rc.rl().search(predicate);
 
             if slots.len() > 0 {
                 for slot in &slots {
+                    let deleted = page_rc.rl().get_tuple(*slot).cloned();
+                    self.record_change(tx, deleted.as_ref(), None);
+                    deleted_tuples.extend(deleted);
                     page_rc.wl().mvcc_delete_tuple(&tx.get_id(), slot.clone());
                 }
 
-                if !page_rc.rl().stable() {
+                if self.auto_merge && !page_rc.rl().stable() {
                     self.handle_unstable_leaf_page(tx, page_rc.clone())?;
                 }
             }
@@ -98,6 +118,10 @@ impl BTreeTable {
 
         drop(xlatch);
 
+        for tuple in &deleted_tuples {
+            self.delete_index_entries(tx, tuple)?;
+        }
+
         Ok(())
     }
 
@@ -114,6 +138,7 @@ impl BTreeTable {
         if page_rc.rl().stable() {
             return Ok(());
         }
+        self.invalidate_leaf_cache();
 
         let left_pid = page_rc.rl().get_left_pid();
         let right_pid = page_rc.rl().get_right_pid();
@@ -130,7 +155,7 @@ impl BTreeTable {
                 "page {} is unstable but has no left or right sibling",
                 page_rc.rl().get_pid()
             );
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         };
 
         // release the latch on the pages:
@@ -167,6 +192,7 @@ impl BTreeTable {
         if page_rc.rl().get_parent_pid().category == PageCategory::RootPointer {
             return Ok(());
         }
+        self.invalidate_leaf_cache();
 
         let left_pid = page_rc.rl().get_left_sibling_pid(tx);
         let right_pid = page_rc.rl().get_right_sibling_pid(tx);
@@ -242,7 +268,7 @@ impl BTreeTable {
             }
 
             // stage 3: set the right as empty
-            self.set_empty_page(tx, &right.get_pid());
+            self.set_empty_page(tx, &right.get_pid())?;
         }
         // release left_rc and right_rc
 
@@ -274,7 +300,7 @@ impl BTreeTable {
             let mut it = BTreeLeafPageIterator::new(&right);
             let mut deleted = Vec::new();
             for t in it.by_ref() {
-                left.insert_tuple(&t)?;
+                left.insert_tuple_with(&t, self.comparator.as_ref(), self.secondary_field)?;
                 deleted.push(t.get_slot_number());
             }
             for slot in deleted {
@@ -295,7 +321,7 @@ impl BTreeTable {
             }
 
             // stage 4: set the right page as empty
-            self.set_empty_page(tx, &right.get_pid());
+            self.set_empty_page(tx, &right.get_pid())?;
         }
 
         // stage 5: release the left and right page
@@ -307,9 +333,8 @@ impl BTreeTable {
     /// Method to encapsulate the process of deleting an entry
     /// (specifically the key and right child) from a parent page.
     ///
-    /// If the parent becomes empty (no keys remaining), that
-    /// indicates that it was the root page and should be replaced
-    /// by its one remaining child.
+    /// If the parent is the root page and becomes empty (no keys
+    /// remaining), it should be replaced by its one remaining child.
     ///
     /// Otherwise, if it gets below minimum occupancy for non-root
     /// internal pages, it should steal from one of its siblings
@@ -339,9 +364,11 @@ impl BTreeTable {
             parent.delete_key_and_right_child(entry.get_record_id());
 
             // stage 2: handle the parent page according to the
-            // following cases case 1: parent is empty,
+            // following cases case 1: parent is the root and is empty,
             // then the left child is now the new root
-            if parent.entries_count() == 0 {
+            if parent.entries_count() == 0
+                && parent.get_parent_pid().category == PageCategory::RootPointer
+            {
                 let root_ptr_page_rc = self.get_root_ptr_page(tx, Permission::ReadWrite);
 
                 // hold the root pointer page
@@ -359,12 +386,12 @@ impl BTreeTable {
                     .unwrap();
 
                 // release the page for reuse
-                self.set_empty_page(tx, &parent.get_pid());
+                self.set_empty_page(tx, &parent.get_pid())?;
                 return Ok(());
             }
 
             // case 2: parent is stable, return directly
-            if parent.stable() {
+            if parent.stable(self.get_fan_out()) {
                 return Ok(());
             }
         }
@@ -378,18 +405,20 @@ impl BTreeTable {
     /// Mark a page in this BTreeTable as empty. Find the
     /// corresponding header page (create it if needed), and mark
     /// the corresponding slot in the header page as empty.
-    fn set_empty_page(&self, tx: &Transaction, pid: &BTreePageID) {
+    fn set_empty_page(&self, tx: &Transaction, pid: &BTreePageID) -> SmallResult {
         Database::mut_buffer_pool().discard_page(pid);
 
-        let header_pages = self.get_header_pages(tx);
+        // the header pages exist, the page was allocated through them
+        let header_pages = self.get_header_pages(tx)?;
         header_pages.mark_page(pid, false);
         header_pages.release_latches();
+        Ok(())
     }
 
     /// Balancing two internal pages according the situation:
     ///
-    /// 1. Merge the two pages if the count of entries in the two
-    /// pages is less than the maximum capacity of a single page.
+    /// 1. Merge the two pages if the entries of the two pages fit in a
+    /// single page.
     ///
     /// 2. Otherwise, steal entries from the sibling and copy them to
     /// the given page so that both pages are at least half full.
@@ -418,14 +447,13 @@ impl BTreeTable {
 
         let left_children = left_rc.rl().children_count();
         let right_children = right_rc.rl().children_count();
-        if left_children + right_children <= left_rc.rl().get_children_capacity() {
+        if left_rc.rl().can_merge(&right_rc.rl(), self.get_fan_out()) {
             // if the two pages can be merged, merge them
             return self.merge_internal_page(tx, left_rc, right_rc, parent_rc, &parent_entry);
         }
 
         // if there aren't any entries to move, return immediately
-        let move_count =
-            (left_children + right_children) / 2 - cmp::min(left_children, right_children);
+        let move_count = balance_move_count(left_children, right_children);
         if move_count == 0 {
             return Ok(());
         }
@@ -438,6 +466,12 @@ impl BTreeTable {
             let mut right = right_rc.wl();
 
             if left_children < right_children {
+                check_move_count(
+                    &right.get_pid(),
+                    move_count,
+                    BTreeInternalPageIterator::new(&right).count(),
+                )?;
+
                 // The edge child of the destination page.
                 let edge_child_pid = left.get_last_child_pid();
 
@@ -459,6 +493,12 @@ impl BTreeTable {
                     right.delete_key_and_left_child(i);
                 }
             } else {
+                check_move_count(
+                    &left.get_pid(),
+                    move_count,
+                    BTreeInternalPageIterator::new(&left).count(),
+                )?;
+
                 // The edge child of the destination page.
                 let edge_child_pid = right.get_first_child_pid();
 
@@ -528,6 +568,12 @@ impl BTreeTable {
         let mut moved_records = Vec::new();
 
         for e in src_iter.take(move_count) {
+            // the keys of the dest page may run out of room before the
+            // slots (for prefix compressed pages)
+            if dest.empty_slots_count() == 0 {
+                break;
+            }
+
             // 1. delete the entry from the src page
             moved_records.push(e.get_record_id());
 
@@ -581,12 +627,12 @@ impl BTreeTable {
 
         let left_tuples = left_rc.rl().tuples_count();
         let right_tuples = right_rc.rl().tuples_count();
-        if left_tuples + right_tuples <= left_rc.rl().get_slots_count() {
+        if left_rc.rl().can_merge_with(&right_rc.rl()) {
             // if the two pages can be merged, merge them
             return self.merge_leaf_page(tx, left_rc, right_rc, parent_rc, &entry);
         }
 
-        let move_count = (left_tuples + right_tuples) / 2 - cmp::min(left_tuples, right_tuples);
+        let move_count = balance_move_count(left_tuples, right_tuples);
         if move_count == 0 {
             return self.merge_leaf_page(tx, left_rc, right_rc, parent_rc, &entry);
         }
@@ -599,10 +645,15 @@ impl BTreeTable {
             let mut right = right_rc.wl();
 
             if left_tuples < right_tuples {
+                check_move_count(
+                    &right.get_pid(),
+                    move_count,
+                    BTreeLeafPageIterator::new(&right).count(),
+                )?;
                 let iter = BTreeLeafPageIterator::new(&right);
                 let mut deleted_indexes = Vec::new();
                 for tuple in iter.take(move_count) {
-                    left.insert_tuple(&tuple)?;
+                    left.insert_tuple_with(&tuple, self.comparator.as_ref(), self.secondary_field)?;
                     deleted_indexes.push(tuple.get_slot_number());
                     key = tuple.get_cell(self.key_field);
                 }
@@ -610,10 +661,19 @@ impl BTreeTable {
                     right.delete_tuple(i);
                 }
             } else {
+                check_move_count(
+                    &left.get_pid(),
+                    move_count,
+                    BTreeLeafPageIterator::new(&left).count(),
+                )?;
                 let iter = BTreeLeafPageIterator::new(&left);
                 let mut deleted_indexes = Vec::new();
                 for tuple in iter.rev().take(move_count) {
-                    right.insert_tuple(&tuple)?;
+                    right.insert_tuple_with(
+                        &tuple,
+                        self.comparator.as_ref(),
+                        self.secondary_field,
+                    )?;
                     deleted_indexes.push(tuple.get_slot_number());
                     key = tuple.get_cell(self.key_field);
                 }
@@ -631,24 +691,64 @@ impl BTreeTable {
     }
 
     /// Delete all invisible tuples from the table.
+    ///
+    /// Nothing is deleted if the table retains tombstones, they are
+    /// reclaimed by `purge_tombstones` instead.
     pub fn delete_invisible_tuples(&self) -> SmallResult {
-        let tx = Transaction::new();
+        if self.retain_tombstones {
+            return Ok(());
+        }
 
-        let xlatch = self.tree_latch.wl();
+        self.vacuum(TransactionID::MAX)
+    }
+
+    /// Remove the tombstones of the tuples deleted by transactions older
+    /// than `before`. Tombstones which may still be visible to an active
+    /// transaction are kept.
+    pub fn purge_tombstones(&self, before: TransactionID) -> SmallResult {
+        self.vacuum(before)
+    }
 
-        // There is at least one active transaction since we just started one.
-        let min_action = Database::concurrent_status().min_active_tx().unwrap();
+    /// Get the tuples deleted from the view of `tx` which are still retained
+    /// as tombstones, in key order. Fails if a leaf page can't be read.
+    pub fn tombstones(&self, tx: &Transaction) -> Result<Vec<WrappedTuple>, SmallError> {
+        let mut tombstones = Vec::new();
 
-        let mut page_rc: Arc<RwLock<BTreeLeafPage>> =
-            self.get_first_page(&tx, Permission::ReadWrite);
+        let mut page_rc = self.get_first_page(tx, Permission::ReadOnly)?;
         loop {
-            page_rc.wl().delete_invisible_tuples(&min_action);
+            let right = {
+                let page = page_rc.rl();
+                let it = BTreeLeafPageIterator::new(&page);
+                tombstones.extend(it.filter(|t| t.is_tombstone_to(tx.get_id())));
+                page.get_right_pid()
+            };
+
+            match right {
+                Some(right) => {
+                    page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &right)?;
+                }
+                None => break,
+            }
+        }
 
-            self.handle_unstable_leaf_page(&tx, page_rc.clone())?;
+        Ok(tombstones)
+    }
+
+    /// Merge or redistribute the leaf pages which are less than half
+    /// full, used to tidy up the table after deleting with auto merge
+    /// disabled.
+    pub fn rebalance(&self, tx: &Transaction) -> SmallResult {
+        tx.check_active()?;
+
+        let xlatch = self.tree_latch.wl();
+
+        let mut page_rc = self.get_first_page(tx, Permission::ReadWrite)?;
+        loop {
+            self.handle_unstable_leaf_page(tx, page_rc.clone())?;
 
             let right = page_rc.rl().get_right_pid();
             if let Some(right) = right {
-                page_rc = BufferPool::get_leaf_page(&tx, Permission::ReadWrite, &right)?;
+                page_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, &right)?;
             } else {
                 break;
             }
@@ -656,8 +756,67 @@ impl BTreeTable {
 
         drop(xlatch);
 
-        tx.commit().unwrap();
-
         Ok(())
     }
+
+    /// Physically remove the tuples deleted by transactions older than
+    /// `before` and invisible to all active transactions and snapshots.
+    ///
+    /// The work is done by a transaction of its own, which is aborted if
+    /// anything fails.
+    fn vacuum(&self, before: TransactionID) -> SmallResult {
+        let tx = Transaction::new();
+
+        let result = {
+            let _xlatch = self.tree_latch.wl();
+
+            // There is at least one active transaction since we just started one.
+            let min_action = Database::concurrent_status().vacuum_horizon().unwrap();
+            let threshold = cmp::min(min_action, before);
+
+            self.vacuum_pages(&tx, &threshold)
+        };
+
+        if let Err(e) = result {
+            tx.abort()?;
+            return Err(e);
+        }
+        tx.commit()
+    }
+
+    fn vacuum_pages(&self, tx: &Transaction, threshold: &TransactionID) -> SmallResult {
+        let mut page_rc: Arc<RwLock<BTreeLeafPage>> =
+            self.get_first_page(tx, Permission::ReadWrite)?;
+        loop {
+            // the tuples are gone for all transactions, so are their keys
+            for key in page_rc.wl().delete_invisible_tuples(threshold) {
+                self.remove_from_bloom_filter(&key);
+            }
+
+            if self.auto_merge {
+                self.handle_unstable_leaf_page(tx, page_rc.clone())?;
+            }
+
+            let right = page_rc.rl().get_right_pid();
+            if let Some(right) = right {
+                page_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, &right)?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Fail with a `Corruption` error if the source page `pid` of a
+/// redistribution yields fewer than `move_count` items, the counts the move
+/// count is computed from don't match the content of the page then.
+fn check_move_count(pid: &BTreePageID, move_count: usize, available: usize) -> SmallResult {
+    if move_count > available {
+        let err_msg = format!(
+            "can't move {} items out of page {:?}, it holds {}",
+            move_count, pid, available,
+        );
+        return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+    }
+    Ok(())
 }
@@ -0,0 +1,133 @@
+use std::{cmp::Ordering, mem};
+
+use super::BTreeTableIterator;
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::{Cell, Tuple},
+    transaction::Transaction,
+    BTreeTable,
+};
+
+/// The row-level differences between two tables, see `BTreeTable::diff`.
+#[derive(Debug, Default, PartialEq)]
+pub struct TableDiff {
+    /// Tuples of the first table missing from the second one.
+    pub only_in_self: Vec<Tuple>,
+
+    /// Tuples of the second table missing from the first one.
+    pub only_in_other: Vec<Tuple>,
+
+    /// Tuples with the same key but different cells, as (self, other).
+    pub differing: Vec<(Tuple, Tuple)>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.differing.is_empty()
+    }
+}
+
+impl BTreeTable {
+    /// Compare the tuples of the two tables visible to `tx`, e.g. a table and
+    /// its replica.
+    ///
+    /// Both tables are scanned once in key order and the scans are merged on
+    /// the key field. When several tuples share a key, the identical ones are
+    /// matched first and the remaining ones are paired in scan order.
+    ///
+    /// Returns a `SchemaMismatch` error if the tables have different fields,
+    /// and the error of a scan which fails partway.
+    pub fn diff(&self, tx: &Transaction, other: &BTreeTable) -> Result<TableDiff, SmallError> {
+        if self.get_schema().get_fields() != other.get_schema().get_fields()
+            || self.key_field != other.key_field
+        {
+            return Err(SmallError::with_kind(
+                SmallErrorKind::SchemaMismatch,
+                &format!(
+                    "tables {} and {} have different schemas",
+                    self.name, other.name
+                ),
+            ));
+        }
+
+        let mut diff = TableDiff::default();
+        let mut left = DiffCursor::new(BTreeTableIterator::new(tx, self)?)?;
+        let mut right = DiffCursor::new(BTreeTableIterator::new(tx, other)?)?;
+        loop {
+            let order = match (&left.head, &right.head) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(l), Some(r)) => self
+                    .comparator
+                    .compare(&l.get_cell(self.key_field), &r.get_cell(self.key_field)),
+            };
+
+            match order {
+                Ordering::Less => {
+                    let key = left.head.as_ref().unwrap().get_cell(self.key_field);
+                    diff.only_in_self.extend(self.next_run(&mut left, &key)?);
+                }
+                Ordering::Greater => {
+                    let key = right.head.as_ref().unwrap().get_cell(self.key_field);
+                    diff.only_in_other.extend(self.next_run(&mut right, &key)?);
+                }
+                Ordering::Equal => {
+                    let key = left.head.as_ref().unwrap().get_cell(self.key_field);
+                    let mut lefts = self.next_run(&mut left, &key)?;
+                    let mut rights = self.next_run(&mut right, &key)?;
+
+                    lefts.retain(|t| match rights.iter().position(|r| r == t) {
+                        Some(i) => {
+                            rights.remove(i);
+                            false
+                        }
+                        None => true,
+                    });
+
+                    let paired = lefts.len().min(rights.len());
+                    diff.only_in_self.extend(lefts.drain(paired..));
+                    diff.only_in_other.extend(rights.drain(paired..));
+                    diff.differing.extend(lefts.into_iter().zip(rights));
+                }
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Take the tuples with the key `key` from the front of the scan.
+    fn next_run(&self, cursor: &mut DiffCursor, key: &Cell) -> Result<Vec<Tuple>, SmallError> {
+        let mut run = Vec::new();
+        while let Some(tuple) = &cursor.head {
+            if self
+                .comparator
+                .compare(&tuple.get_cell(self.key_field), key)
+                != Ordering::Equal
+            {
+                break;
+            }
+            run.push(cursor.advance()?);
+        }
+        Ok(run)
+    }
+}
+
+/// A scan of one side of the diff, with the tuple at its front read ahead.
+struct DiffCursor {
+    iter: BTreeTableIterator,
+    head: Option<Tuple>,
+}
+
+impl DiffCursor {
+    fn new(mut iter: BTreeTableIterator) -> Result<Self, SmallError> {
+        let head = iter.try_next()?.map(|t| t.get_tuple().clone());
+        Ok(Self { iter, head })
+    }
+
+    /// Take the tuple at the front, the scan must not be exhausted.
+    fn advance(&mut self) -> Result<Tuple, SmallError> {
+        let next = self.iter.try_next()?.map(|t| t.get_tuple().clone());
+        Ok(mem::replace(&mut self.head, next).unwrap())
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    io::{Cursor, ErrorKind, Read, Write},
+    sync::{Arc, RwLock},
+};
+
+use super::BTreeTableIterator;
+use crate::{
+    common::Catalog,
+    error::SmallError,
+    io::{read_into, Serializeable, SmallWriter},
+    storage::{
+        table_schema::{Field, TableSchema, Type},
+        tuple::Tuple,
+    },
+    transaction::Transaction,
+    types::SmallResult,
+    utils::HandyRwLock,
+    BTreeTable,
+};
+
+/// # Format of the stream
+///
+/// - header:
+///     - 2 bytes: number of fields
+///     - for each field: name, type, is_primary, is_varint
+/// - for each tuple, in key order:
+///     - 4 bytes: size of the encoded tuple
+///     - n bytes: the encoded tuple
+///
+/// The stream ends at the end of the last tuple.
+impl BTreeTable {
+    /// Write the schema and all tuples visible to `tx` to `writer`, the
+    /// output can be turned back into a table by `BTreeTable::load_from`.
+    pub fn stream_to<W: Write>(&self, tx: &Transaction, mut writer: W) -> SmallResult {
+        let fields = self.schema.get_fields();
+
+        let mut header = SmallWriter::new();
+        (fields.len() as u16).encode(&mut header, &());
+        for field in fields {
+            field.name.encode(&mut header, &());
+            field.get_type().encode(&mut header, &());
+            field.is_primary.encode(&mut header, &());
+            field.is_varint().encode(&mut header, &());
+        }
+        writer.write_all(&header.to_bytes())?;
+
+        let mut it = BTreeTableIterator::new(tx, self)?;
+        while let Some(tuple) = it.try_next()? {
+            let bytes = tuple.get_tuple().to_bytes(&self.schema);
+            writer.write_all(&(bytes.len() as u32).to_bytes(&()))?;
+            writer.write_all(&bytes)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Create the table `table_name` from a stream written by
+    /// `BTreeTable::stream_to`, the table is added to the catalog.
+    ///
+    /// Tuples are inserted by `tx` in the order of the stream, which is the
+    /// key order of the source table, so every insertion lands on the
+    /// rightmost leaf.
+    pub fn load_from<R: Read>(
+        table_name: &str,
+        tx: &Transaction,
+        mut reader: R,
+    ) -> Result<Arc<RwLock<BTreeTable>>, SmallError> {
+        let fields_count: u16 = read_exact_or_err(&mut reader, 2)
+            .map(|bytes| read_into(&mut Cursor::new(bytes), &()))?;
+
+        let mut fields = Vec::new();
+        for _ in 0..fields_count {
+            let name_size: u16 = read_exact_or_err(&mut reader, 2)
+                .map(|bytes| read_into(&mut Cursor::new(bytes), &()))?;
+            let name = String::from_utf8(read_exact_or_err(&mut reader, name_size as usize)?)
+                .or(Err(SmallError::new("invalid field name")))?;
+            let field_type: Type = read_exact_or_err(&mut reader, 2)
+                .map(|bytes| read_into(&mut Cursor::new(bytes), &()))?;
+            let is_primary: bool = read_exact_or_err(&mut reader, 1)
+                .map(|bytes| read_into(&mut Cursor::new(bytes), &()))?;
+            let is_varint: bool = read_exact_or_err(&mut reader, 1)
+                .map(|bytes| read_into(&mut Cursor::new(bytes), &()))?;
+            if is_varint {
+                fields.push(Field::new_varint(&name, is_primary));
+            } else {
+                fields.push(Field::new(&name, field_type, is_primary));
+            }
+        }
+
+        let schema = TableSchema::new(fields);
+        let table_rc = Arc::new(RwLock::new(BTreeTable::new(table_name, None, &schema)));
+        Catalog::add_table(Arc::clone(&table_rc), true)?;
+
+        let table = table_rc.rl();
+
+        loop {
+            let mut size_bytes = [0u8; 4];
+            match reader.read_exact(&mut size_bytes) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let size: u32 = read_into(&mut Cursor::new(size_bytes), &());
+            let size = size as usize;
+            let bytes = read_exact_or_err(&mut reader, size)?;
+            let tuple: Tuple = read_into(&mut Cursor::new(bytes), &schema);
+
+            table.insert_tuple(tx, &Tuple::new(&tuple.get_cells(), tx.get_id()))?;
+        }
+
+        drop(table);
+        Ok(table_rc)
+    }
+}
+
+fn read_exact_or_err<R: Read>(reader: &mut R, bytes_count: usize) -> Result<Vec<u8>, SmallError> {
+    let mut buffer = vec![0u8; bytes_count];
+    reader
+        .read_exact(&mut buffer)
+        .or(Err(SmallError::new("unexpected end of stream")))?;
+    Ok(buffer)
+}
@@ -0,0 +1,136 @@
+use std::io::BufRead;
+
+use log::debug;
+
+use crate::{
+    error::SmallError,
+    storage::tuple::{Cell, Tuple},
+    transaction::Transaction,
+    BTreeTable,
+};
+
+/// A field of the CSV file which cannot be converted to the type of its
+/// column.
+#[derive(Debug, PartialEq)]
+pub struct ImportError {
+    /// Line number in the file, start from 1.
+    pub line: usize,
+
+    /// Column index in the schema, start from 0.
+    pub column: usize,
+
+    /// The offending value.
+    pub value: String,
+
+    pub message: String,
+}
+
+/// The outcome of `BTreeTable::import_csv`.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Number of rows inserted into the table.
+    pub imported_rows: usize,
+
+    /// Errors of all rejected rows, rows with any error are not inserted.
+    pub errors: Vec<ImportError>,
+}
+
+impl BTreeTable {
+    /// Import rows from a CSV file, each line is a row and fields are
+    /// separated by ",".
+    ///
+    /// Every field is parsed with the type of its column. A row with a
+    /// malformed field (or a wrong number of fields) is skipped and the
+    /// problem is recorded in the returned report together with its line
+    /// number, so nothing is silently converted.
+    ///
+    /// Return an error only when the reader or the insertion fails.
+    pub fn import_csv<R: BufRead>(
+        &self,
+        tx: &Transaction,
+        reader: R,
+        has_header: bool,
+    ) -> Result<ImportReport, SmallError> {
+        let mut report = ImportReport::default();
+        let fields = self.schema.get_fields();
+        let mut tuples = Vec::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.map_err(|e| SmallError::new(&format!("read csv failed: {}", e)))?;
+
+            if (has_header && line_number == 1) || line.trim().is_empty() {
+                continue;
+            }
+
+            let values: Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+            if values.len() != fields.len() {
+                report.errors.push(ImportError {
+                    line: line_number,
+                    column: values.len().min(fields.len()),
+                    value: line.clone(),
+                    message: format!("expect {} fields, got {}", fields.len(), values.len()),
+                });
+                continue;
+            }
+
+            let mut cells = Vec::new();
+            let mut row_valid = true;
+            for (column, (value, field)) in values.iter().zip(fields.iter()).enumerate() {
+                match Cell::parse(value, &field.get_type()) {
+                    Ok(cell) => cells.push(cell),
+                    Err(e) => {
+                        row_valid = false;
+                        report.errors.push(ImportError {
+                            line: line_number,
+                            column,
+                            value: value.to_string(),
+                            message: e.to_string(),
+                        });
+                    }
+                }
+            }
+
+            if !row_valid {
+                continue;
+            }
+
+            tuples.push(Tuple::new(&cells, tx.get_id()));
+        }
+
+        report.imported_rows = self.import_tuples(tx, tuples.into_iter().map(Ok))?;
+
+        debug!(
+            "import csv into {}, {} rows imported, {} errors",
+            self.name,
+            report.imported_rows,
+            report.errors.len(),
+        );
+
+        Ok(report)
+    }
+
+    /// Insert `tuples` by `tx` one by one, the path shared by the imports,
+    /// the copies and the rebuilds. Return the number of tuples inserted,
+    /// the first error of `tuples` ends the import.
+    ///
+    /// There is no bottom-up bulk load, but tuples sorted by the key are
+    /// appended to the rightmost leaf, which is found through the leaf
+    /// cache instead of a descent per tuple.
+    ///
+    /// The Bloom filter (if any) is built again afterwards, so the keys of
+    /// the tuples the import replaced (e.g. by a rebuild) are dropped from
+    /// it.
+    pub(super) fn import_tuples<I>(&self, tx: &Transaction, tuples: I) -> Result<usize, SmallError>
+    where
+        I: IntoIterator<Item = Result<Tuple, SmallError>>,
+    {
+        let mut count = 0;
+        for tuple in tuples {
+            self.insert_tuple(tx, &tuple?)?;
+            count += 1;
+        }
+        self.rebuild_bloom_filter(tx)?;
+        Ok(count)
+    }
+}
@@ -1,9 +1,11 @@
 use std::{
-    sync::{Arc, RwLock},
+    cmp::Ordering,
+    collections::HashSet,
+    sync::{atomic::Ordering as AtomicOrdering, Arc, RwLock},
     usize,
 };
 
-use super::SearchFor;
+use super::{OnConflict, SearchFor};
 use crate::{
     btree::{
         buffer_pool::BufferPool,
@@ -12,10 +14,13 @@ use crate::{
             BTreePage, BTreePageID, Entry, PageCategory,
         },
     },
-    error::SmallError,
-    storage::tuple::{Cell, Tuple},
+    error::{SmallError, SmallErrorKind},
+    storage::{
+        table_schema::Type,
+        tuple::{Cell, Tuple},
+    },
     transaction::{Permission, Transaction},
-    types::ResultPod,
+    types::{ResultPod, SmallResult},
     utils::HandyRwLock,
     BTreeTable, Database,
 };
@@ -26,6 +31,10 @@ impl BTreeTable {
     /// sorted order. May cause pages to split if the page where
     /// tuple belongs is full.
     pub fn insert_tuple(&self, tx: &Transaction, tuple: &Tuple) -> Result<(), SmallError> {
+        tx.check_active()?;
+        tx.check_writable()?;
+        self.validate_tuple(tuple)?;
+
         let new_tuple = tuple.clone();
 
         // Request an X-latch on the tree.
@@ -44,36 +53,221 @@ impl BTreeTable {
         drop(x_latch);
 
         // Insert the tuple into the leaf page.
-        leaf_rc.wl().insert_tuple(&new_tuple)?;
+        leaf_rc.wl().insert_tuple_with(
+            &new_tuple,
+            self.comparator.as_ref(),
+            self.secondary_field,
+        )?;
+
+        // after the insertion into the leaf, so a filter built concurrently
+        // misses no key
+        self.add_to_bloom_filter(&new_tuple.get_cell(self.key_field));
+        self.record_change(tx, None, Some(&new_tuple));
 
         let leaf_pid = leaf_rc.rl().get_pid();
         Database::mut_concurrent_status().release_latch(tx, &leaf_pid)?;
 
+        self.insert_index_entries(tx, &new_tuple)?;
+
         return Ok(());
     }
 
+    /// Insert a tuple in a transaction of its own, which is committed on
+    /// success and aborted on error. For callers which don't manage
+    /// transactions.
+    pub fn insert_auto(&self, tuple: &Tuple) -> SmallResult {
+        self.auto_commit(|tx| self.insert_tuple(tx, &Tuple::new(&tuple.get_cells(), tx.get_id())))
+    }
+
+    /// Insert a batch of tuples, `on_conflict` decides what happens to a
+    /// tuple whose key is already in the table (or appears earlier in the
+    /// same batch).
+    ///
+    /// With `OnConflict::Error` all keys are checked before the first
+    /// insertion, so a rejected batch leaves the table unchanged.
+    pub fn insert_tuples(
+        &self,
+        tx: &Transaction,
+        tuples: &[Tuple],
+        on_conflict: OnConflict,
+    ) -> SmallResult {
+        tx.check_active()?;
+        tx.check_writable()?;
+        for tuple in tuples {
+            self.validate_tuple(tuple)?;
+        }
+
+        if on_conflict == OnConflict::Error {
+            let mut batch_keys = HashSet::new();
+            for tuple in tuples {
+                let key = tuple.get_cell(self.key_field);
+                if !batch_keys.insert(key.clone()) || self.get_by_key(tx, &key)?.is_some() {
+                    return Err(self.duplicate_key_error(&key));
+                }
+            }
+        }
+
+        for tuple in tuples {
+            let key = tuple.get_cell(self.key_field);
+            if let Some(existing) = self.get_by_key(tx, &key)? {
+                match on_conflict {
+                    OnConflict::Error => return Err(self.duplicate_key_error(&key)),
+                    OnConflict::Skip => continue,
+                    OnConflict::Replace => self.delete_tuple(tx, &existing)?,
+                }
+            }
+            self.insert_tuple(tx, tuple)?;
+        }
+
+        Ok(())
+    }
+
+    fn duplicate_key_error(&self, key: &Cell) -> SmallError {
+        let err_msg = format!(
+            "duplicate key {:?} in batch insert, table: {}",
+            key, self.name,
+        );
+        SmallError::with_kind(SmallErrorKind::DuplicateKey, &err_msg)
+    }
+
+    /// Check that the tuple can be stored in this table, so a malformed
+    /// tuple is rejected before any page is touched.
+    fn validate_tuple(&self, tuple: &Tuple) -> SmallResult {
+        let fields_count = self.schema.get_fields().len();
+        if tuple.cells_count() != fields_count {
+            let err_msg = format!(
+                "tuple arity mismatch, table: {}, expect {} cells, got {}",
+                self.name,
+                fields_count,
+                tuple.cells_count(),
+            );
+            return Err(SmallError::with_kind(
+                SmallErrorKind::SchemaMismatch,
+                &err_msg,
+            ));
+        }
+
+        // a cell of another type (or a NULL, which has no encoding) would be
+        // written with the width of its own type and shift the tuples after
+        // it, a varint field only holds integers as well
+        for (i, field) in self.schema.get_fields().iter().enumerate() {
+            let cell = tuple.get_cell(i);
+            let matches = match (field.get_type(), &cell) {
+                (Type::Bool, Cell::Bool(_)) => true,
+                (Type::Int64, Cell::Int64(_)) => true,
+                (Type::Float64, Cell::Float64(_)) => true,
+                (Type::Bytes(size), Cell::Bytes(v)) => v.len() <= size as usize,
+                _ => false,
+            };
+            if !matches {
+                let err_msg = format!(
+                    "field {} of table {} has type {:?}, got {:?}",
+                    field.name,
+                    self.name,
+                    field.get_type(),
+                    cell,
+                );
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::SchemaMismatch,
+                    &err_msg,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn get_available_leaf(
         &self,
         tx: &Transaction,
         tuple: &Tuple,
     ) -> Result<Arc<RwLock<BTreeLeafPage>>, SmallError> {
-        let root_pid = self.get_root_pid(tx);
-
-        // Find and lock the left-most leaf page corresponding to the key field.
         let field = tuple.get_cell(self.key_field);
-        let mut leaf_rc = self.find_leaf_page(
-            tx,
-            Permission::ReadWrite,
-            root_pid,
-            &SearchFor::Target(field),
-        );
 
-        if leaf_rc.rl().empty_slots_count() == 0 {
-            // Split the leaf page if there are no more slots available.
+        // Find and lock the left-most leaf page corresponding to the key field,
+        // the leaf of the previous insert is reused if it covers the key.
+        let mut leaf_rc = match self.get_cached_leaf(tx, &field)? {
+            Some(leaf_rc) => leaf_rc,
+            None => {
+                let root_pid = self.get_root_pid(tx);
+                let (leaf_rc, lower, upper) = self.find_leaf_page_with_bounds(
+                    tx,
+                    Permission::ReadWrite,
+                    root_pid,
+                    &SearchFor::Target(field),
+                )?;
+                let pid = leaf_rc.rl().get_pid();
+                self.cache_leaf(tx, pid, lower, upper);
+                leaf_rc
+            }
+        };
+
+        loop {
+            leaf_rc = self.seek_secondary_position(tx, leaf_rc, tuple)?;
+            if !leaf_rc.rl().is_full() {
+                return Ok(leaf_rc);
+            }
+
+            // Split the leaf page if there is no more room for the tuple.
             leaf_rc = self.split_leaf_page(tx, leaf_rc, tuple.get_cell(self.key_field))?;
+
+            // the tuples with the same key may be split apart, the tuple may
+            // belong to a page on the right
+            if self.secondary_field.is_none() {
+                return Ok(leaf_rc);
+            }
         }
+    }
 
-        Ok(leaf_rc)
+    /// With a secondary sort, the tuples with the key of `tuple` may span
+    /// several leaves. Move right from the left-most leaf of the key to the
+    /// leaf `tuple` belongs to, the latch of the leaves passed by is released.
+    fn seek_secondary_position(
+        &self,
+        tx: &Transaction,
+        mut leaf_rc: Arc<RwLock<BTreeLeafPage>>,
+        tuple: &Tuple,
+    ) -> ResultPod<BTreeLeafPage> {
+        let secondary_field = match self.secondary_field {
+            Some(field) => field,
+            None => return Ok(leaf_rc),
+        };
+
+        loop {
+            let right = leaf_rc.rl().get_right_pid();
+            let right_pid = match right {
+                Some(pid) => pid,
+                None => return Ok(leaf_rc),
+            };
+            let right_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, &right_pid)?;
+
+            let first = BTreeLeafPageIterator::new(&right_rc.rl()).next();
+            let goes_right = match first {
+                Some(first) => {
+                    self.comparator
+                        .compare(
+                            &first.get_cell(self.key_field),
+                            &tuple.get_cell(self.key_field),
+                        )
+                        .then_with(|| {
+                            first
+                                .get_cell(secondary_field)
+                                .cmp(&tuple.get_cell(secondary_field))
+                        })
+                        == Ordering::Less
+                }
+                None => false,
+            };
+
+            if !goes_right {
+                Database::mut_concurrent_status().release_latch(tx, &right_pid)?;
+                return Ok(leaf_rc);
+            }
+
+            let pid = leaf_rc.rl().get_pid();
+            Database::mut_concurrent_status().release_latch(tx, &pid)?;
+            leaf_rc = right_rc;
+        }
     }
 
     /// Split a leaf page to make room for new tuples and
@@ -98,7 +292,9 @@ impl BTreeTable {
         page_rc: Arc<RwLock<BTreeLeafPage>>,
         field: Cell,
     ) -> ResultPod<BTreeLeafPage> {
-        let new_sibling_rc = self.get_empty_leaf_page(tx);
+        self.invalidate_leaf_cache();
+
+        let new_sibling_rc = self.get_empty_leaf_page(tx)?;
         let parent_pid: BTreePageID;
         let key: Cell;
 
@@ -108,15 +304,19 @@ impl BTreeTable {
             let mut new_sibling = new_sibling_rc.wl();
             let mut page = page_rc.wl();
             // 1. adding a new page on the right of the existing
-            // page and moving half of the tuples to the new page
-            let tuple_count = page.tuples_count();
-            let move_tuple_count = tuple_count / 2;
+            // page and moving the tuples beyond the split ratio to the
+            // new page, both pages keep at least one tuple
+            let move_tuple_count = page.split_move_count(self.split_ratio);
 
             let mut it = BTreeLeafPageIterator::new(&page);
             let mut delete_indexes: Vec<usize> = Vec::new();
             for tuple in it.by_ref().rev().take(move_tuple_count) {
                 delete_indexes.push(tuple.get_slot_number());
-                new_sibling.insert_tuple(&tuple)?;
+                new_sibling.insert_tuple_with(
+                    &tuple,
+                    self.comparator.as_ref(),
+                    self.secondary_field,
+                )?;
             }
 
             for i in delete_indexes {
@@ -139,7 +339,14 @@ impl BTreeTable {
         // We put this method outside all the borrow blocks since
         // once the parent page is split, a lot of children will
         // been borrowed. (may including the current leaf page)
-        let parent_rc = self.get_parent_with_empty_slots(tx, parent_pid, &field);
+        let parent_rc = self.get_parent_with_empty_slots(tx, parent_pid, &field)?;
+
+        // 3. Link the new sibling into the leaf chain and the parent.
+        //
+        // All pages touched by the split (the parent, both halves and the
+        // old right sibling) stay X-latched until the links are complete, so
+        // a concurrent descent or scan never sees a half-linked sibling.
+        let old_right_pid: Option<BTreePageID>;
 
         // borrow of parent_rc start here
         // borrow of page_rc start here
@@ -152,21 +359,19 @@ impl BTreeTable {
 
             parent.insert_entry(&mut entry)?;
 
+            // set sibling id
+            old_right_pid = page.get_right_pid();
+            new_sibling.set_right_pid(old_right_pid);
+            new_sibling.set_left_pid(Some(page.get_pid()));
+            page.set_right_pid(Some(new_sibling.get_pid()));
+
             // set left pointer for the old right sibling
-            if let Some(old_right_pid) = page.get_right_pid() {
+            if let Some(old_right_pid) = old_right_pid {
                 let old_right_rc =
                     BufferPool::get_leaf_page(tx, Permission::ReadWrite, &old_right_pid).unwrap();
                 old_right_rc.wl().set_left_pid(Some(new_sibling.get_pid()));
-
-                // release the latch on "old_right_rc"
-                Database::mut_concurrent_status().release_latch(tx, &old_right_pid)?;
             }
 
-            // set sibling id
-            new_sibling.set_right_pid(page.get_right_pid());
-            new_sibling.set_left_pid(Some(page.get_pid()));
-            page.set_right_pid(Some(new_sibling.get_pid()));
-
             // set parent id
             page.set_parent_pid(&parent.get_pid());
             new_sibling.set_parent_pid(&parent.get_pid());
@@ -175,7 +380,13 @@ impl BTreeTable {
         // borrow of page_rc end here
         // borrow of new_sibling_rc end here
 
-        if field > key {
+        // 4. The split is complete, release the latches of the leaf pages
+        // the new tuple doesn't go to.
+        if let Some(old_right_pid) = old_right_pid {
+            Database::mut_concurrent_status().release_latch(tx, &old_right_pid)?;
+        }
+
+        if self.comparator.compare(&field, &key) == Ordering::Greater {
             // release all page latches except the new sibling page
             //  - the original filled page (page_rc)
             let pid = page_rc.rl().get_pid();
@@ -192,11 +403,15 @@ impl BTreeTable {
         }
     }
 
-    pub(crate) fn get_empty_page_index(&self, tx: &Transaction) -> u32 {
-        let header_pages = self.get_header_pages(tx);
+    pub(crate) fn get_empty_page_index(&self, tx: &Transaction) -> Result<u32, SmallError> {
+        let header_pages = self.get_header_pages(tx)?;
         let empty_page_index = header_pages.get_empty_page_index();
         header_pages.release_latches();
-        empty_page_index as u32
+
+        let empty_page_index = empty_page_index?;
+        self.page_index
+            .fetch_max(empty_page_index, AtomicOrdering::Relaxed);
+        Ok(empty_page_index)
     }
 
     /// Method to encapsulate the process of getting a parent page
@@ -218,31 +433,33 @@ impl BTreeTable {
         tx: &Transaction,
         parent_id: BTreePageID,
         field: &Cell,
-    ) -> Arc<RwLock<BTreeInternalPage>> {
+    ) -> ResultPod<BTreeInternalPage> {
         // create a parent page if necessary
         // this will be the new root of the tree
         match parent_id.category {
             PageCategory::RootPointer => {
-                let new_parent_rc = self.get_empty_interanl_page(tx);
+                let new_parent_rc = self.get_empty_interanl_page(tx)?;
 
                 // update the root pointer
                 self.set_root_pid(tx, &new_parent_rc.wl().get_pid());
 
-                new_parent_rc
+                Ok(new_parent_rc)
             }
             PageCategory::Internal => {
                 let parent_rc =
                     BufferPool::get_internal_page(tx, Permission::ReadWrite, &parent_id).unwrap();
-                let empty_slots_count: usize;
+                let is_full: bool;
 
                 // borrow of parent_rc start here
                 {
-                    empty_slots_count = parent_rc.rl().empty_slots_count();
+                    let parent = parent_rc.rl();
+                    is_full = parent.empty_slots_count() == 0
+                        || parent.entries_count() + 1 >= self.get_fan_out();
                 }
                 // borrow of parent_rc end here
 
-                if empty_slots_count > 0 {
-                    return parent_rc;
+                if !is_full {
+                    return Ok(parent_rc);
                 } else {
                     // split upper parent
                     return self.split_internal_page(tx, parent_rc, field);
@@ -278,8 +495,10 @@ impl BTreeTable {
         tx: &Transaction,
         page_rc: Arc<RwLock<BTreeInternalPage>>,
         field: &Cell,
-    ) -> Arc<RwLock<BTreeInternalPage>> {
-        let sibling_rc = self.get_empty_interanl_page(tx);
+    ) -> ResultPod<BTreeInternalPage> {
+        self.invalidate_leaf_cache();
+
+        let sibling_rc = self.get_empty_interanl_page(tx)?;
         let key: Cell;
         let mut parent_pid: BTreePageID;
         let mut new_entry: Entry;
@@ -295,7 +514,7 @@ impl BTreeTable {
             if parent_pid.category == PageCategory::RootPointer {
                 // create new parent page if the parent page is root
                 // pointer page.
-                let parent_rc = self.get_empty_interanl_page(tx);
+                let parent_rc = self.get_empty_interanl_page(tx)?;
                 parent_pid = parent_rc.rl().get_pid();
 
                 // update the root pointer
@@ -313,7 +532,7 @@ impl BTreeTable {
 
                 // set parent id for the right child
                 let right_pid = e.get_right_child();
-                Self::set_parent(tx, &right_pid, &sibling.get_pid());
+                Self::set_parent(tx, &right_pid, &sibling.get_pid())?;
             }
 
             let middle_entry = it.next_back().unwrap();
@@ -325,7 +544,7 @@ impl BTreeTable {
             }
 
             // set parent id for right child to the middle entry
-            Self::set_parent(tx, &middle_entry.get_right_child(), &sibling.get_pid());
+            Self::set_parent(tx, &middle_entry.get_right_child(), &sibling.get_pid())?;
 
             key = middle_entry.get_key();
             new_entry = Entry::new(&key, &page.get_pid(), &sibling.get_pid());
@@ -333,7 +552,7 @@ impl BTreeTable {
         // borrow of sibling_rc end here
         // borrow of page_rc end here
 
-        let parent_rc = self.get_parent_with_empty_slots(tx, parent_pid, field);
+        let parent_rc = self.get_parent_with_empty_slots(tx, parent_pid, field)?;
         parent_pid = parent_rc.rl().get_pid();
         page_rc.wl().set_parent_pid(&parent_pid);
         sibling_rc.wl().set_parent_pid(&parent_pid);
@@ -345,10 +564,10 @@ impl BTreeTable {
         }
         // borrow of parent_rc end here
 
-        if *field > key {
-            sibling_rc
+        if self.comparator.compare(field, &key) == Ordering::Greater {
+            Ok(sibling_rc)
         } else {
-            page_rc
+            Ok(page_rc)
         }
     }
 }
@@ -0,0 +1,201 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+use super::SearchFor;
+use crate::{
+    btree::page::{BTreeLeafPage, BTreeLeafPageIterator, BTreePage, BTreePageID, PageGuard},
+    error::SmallError,
+    storage::tuple::{Cell, WrappedTuple},
+    transaction::{Permission, Transaction},
+    utils::HandyRwLock,
+    BTreeTable,
+};
+
+impl BTreeTable {
+    /// Scan the table in key order without holding a latch on the leaf pages
+    /// between two reads, so the writers are not blocked by the scan.
+    ///
+    /// The tuples of a leaf page are copied out under a short read latch. When
+    /// moving to the right sibling, the sibling is validated (its left pointer
+    /// must still point back to the page just read); if a committed writer
+    /// split or merged the pages in the meantime, the scan re-resolves its
+    /// position with a descent from the root on the last key it returned.
+    ///
+    /// Every tuple present for the whole scan is returned exactly once, the
+    /// tuples inserted during the scan may or may not be returned. The scan
+    /// must not be used by a transaction writing to the table, since it
+    /// releases the latches of the pages it reads.
+    pub fn latch_free_scan<'t>(&'t self, tx: &Transaction) -> LatchFreeScan<'t> {
+        LatchFreeScan {
+            tx: tx.clone(),
+            table: self,
+            tuples: VecDeque::new(),
+            current_pid: None,
+            right_pid: None,
+            started: false,
+            last_key: None,
+            last_key_count: 0,
+            resume: None,
+            finished: false,
+        }
+    }
+}
+
+/// A scan created by `BTreeTable::latch_free_scan`.
+pub struct LatchFreeScan<'t> {
+    tx: Transaction,
+    table: &'t BTreeTable,
+
+    /// The visible tuples of the current leaf page not returned yet.
+    tuples: VecDeque<WrappedTuple>,
+
+    /// The leaf page the tuples were copied from, and its right sibling at
+    /// that time.
+    current_pid: Option<BTreePageID>,
+    right_pid: Option<BTreePageID>,
+
+    started: bool,
+
+    /// The key of the last returned tuple, and how many tuples with this key
+    /// have been returned.
+    last_key: Option<Cell>,
+    last_key_count: usize,
+
+    /// Set when a page is loaded: the tuples before (last key, count) have
+    /// been returned already and are skipped. The count is 0 when moving to
+    /// the right sibling, since the tuples with the last key left on the
+    /// sibling have not been returned.
+    resume: Option<(Cell, usize)>,
+
+    finished: bool,
+}
+
+impl<'t> LatchFreeScan<'t> {
+    /// Same as `next`, but reports the failure of reading a page (e.g.
+    /// `LockTimeout`) as an error.
+    pub fn try_next(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        loop {
+            if self.finished {
+                return Ok(None);
+            }
+
+            while let Some(tuple) = self.tuples.pop_front() {
+                let key = tuple.get_cell(self.table.key_field);
+                if self.already_returned(&key) {
+                    continue;
+                }
+
+                if self.last_key.as_ref() == Some(&key) {
+                    self.last_key_count += 1;
+                } else {
+                    self.last_key = Some(key);
+                    self.last_key_count = 1;
+                }
+                return Ok(Some(tuple));
+            }
+
+            self.advance()?;
+        }
+    }
+
+    /// Whether the tuple with `key` has been returned before the current
+    /// page was loaded.
+    fn already_returned(&mut self, key: &Cell) -> bool {
+        let (last_key, remaining) = match &mut self.resume {
+            Some(resume) => resume,
+            None => return false,
+        };
+
+        match self.table.comparator.compare(key, last_key) {
+            Ordering::Less => true,
+            Ordering::Equal if *remaining > 0 => {
+                *remaining -= 1;
+                true
+            }
+            _ => {
+                self.resume = None;
+                false
+            }
+        }
+    }
+
+    /// Copy the tuples of the next leaf page.
+    fn advance(&mut self) -> Result<(), SmallError> {
+        if !self.started {
+            self.started = true;
+            let root_pid = self.table.get_root_pid(&self.tx);
+            let page_rc = self.table.find_leaf_page(
+                &self.tx,
+                Permission::ReadOnly,
+                root_pid,
+                &SearchFor::LeftMost,
+            )?;
+            self.load(&PageGuard::new(&self.tx, page_rc));
+            return Ok(());
+        }
+
+        let right_pid = match self.right_pid {
+            Some(pid) => pid,
+            None => {
+                self.finished = true;
+                return Ok(());
+            }
+        };
+
+        let guard = self
+            .table
+            .pin_leaf(&self.tx, &right_pid, Permission::ReadOnly)?;
+        if guard.rl().get_left_pid() == self.current_pid {
+            self.load(&guard);
+
+            // the tuples moved to the sibling (e.g. by a redistribution)
+            // since the current page was read have a lower key
+            if self.resume.is_none() {
+                self.resume = self.last_key.clone().map(|key| (key, 0));
+            }
+            return Ok(());
+        }
+        drop(guard);
+
+        // The sibling pointer is stale, the pages have been split or merged
+        // since the current page was read. Find the page holding the last
+        // returned key again.
+        self.resolve()
+    }
+
+    fn resolve(&mut self) -> Result<(), SmallError> {
+        let search = match &self.last_key {
+            Some(key) => SearchFor::Target(key.clone()),
+            None => SearchFor::LeftMost,
+        };
+
+        let root_pid = self.table.get_root_pid(&self.tx);
+        let page_rc =
+            self.table
+                .find_leaf_page(&self.tx, Permission::ReadOnly, root_pid, &search)?;
+        self.load(&PageGuard::new(&self.tx, page_rc));
+
+        self.resume = self.last_key.clone().map(|key| (key, self.last_key_count));
+        Ok(())
+    }
+
+    /// Copy the visible tuples of the latched page, the latch is released
+    /// by the caller right after.
+    fn load(&mut self, guard: &PageGuard<BTreeLeafPage>) {
+        let page = guard.rl();
+        self.tuples = BTreeLeafPageIterator::new(&page)
+            .filter(|t| t.visible_to(self.tx.get_id()))
+            .collect();
+        self.current_pid = Some(page.get_pid());
+        self.right_pid = page.get_right_pid();
+    }
+}
+
+impl<'t> Iterator for LatchFreeScan<'t> {
+    type Item = WrappedTuple;
+
+    /// Panics if a page can't be read, use `try_next` to handle the error.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("failed to scan the table: {}", e))
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    cmp::Ordering,
+    sync::{atomic, Arc, RwLock},
+};
+
+use crate::{
+    btree::{
+        buffer_pool::BufferPool,
+        page::{BTreeLeafPage, BTreePageID},
+    },
+    storage::tuple::Cell,
+    transaction::{Permission, Transaction, TransactionID},
+    BTreeTable, SmallError,
+};
+
+/// The leaf page reached by the last insert of a transaction, with the key
+/// range it covers according to the internal pages on the path to it.
+pub(super) struct CachedLeaf {
+    tx_id: TransactionID,
+    pid: BTreePageID,
+
+    /// Exclusive, None means unbounded.
+    lower: Option<Cell>,
+
+    /// Inclusive, None means unbounded.
+    upper: Option<Cell>,
+}
+
+/// The leaf cache of the inserts.
+///
+/// Consecutive inserts of a transaction often go to the same leaf page (e.g.
+/// a sorted run), the leaf is reused as long as the key falls within its
+/// range, without descending from the root again. The cache only holds the
+/// leaf of one transaction, and it is cleared whenever the structure of the
+/// tree changes (split, merge, redistribution, new root).
+impl BTreeTable {
+    /// Get the cached leaf of `tx` if it covers `key`.
+    pub(super) fn get_cached_leaf(
+        &self,
+        tx: &Transaction,
+        key: &Cell,
+    ) -> Result<Option<Arc<RwLock<BTreeLeafPage>>>, SmallError> {
+        // with a secondary sort the tuples of a key may span several leaves
+        if self.secondary_field.is_some() {
+            return Ok(None);
+        }
+
+        let pid = {
+            let cache = self.leaf_cache.lock().unwrap();
+            let cached = match cache.as_ref() {
+                Some(cached) if cached.tx_id == tx.get_id() => cached,
+                _ => return Ok(None),
+            };
+
+            let above_lower = match &cached.lower {
+                Some(lower) => self.comparator.compare(key, lower) == Ordering::Greater,
+                None => true,
+            };
+            let below_upper = match &cached.upper {
+                Some(upper) => self.comparator.compare(key, upper) != Ordering::Greater,
+                None => true,
+            };
+            if !above_lower || !below_upper {
+                return Ok(None);
+            }
+            cached.pid
+        };
+
+        let leaf_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, &pid)?;
+        Ok(Some(leaf_rc))
+    }
+
+    pub(super) fn cache_leaf(
+        &self,
+        tx: &Transaction,
+        pid: BTreePageID,
+        lower: Option<Cell>,
+        upper: Option<Cell>,
+    ) {
+        *self.leaf_cache.lock().unwrap() = Some(CachedLeaf {
+            tx_id: tx.get_id(),
+            pid,
+            lower,
+            upper,
+        });
+    }
+
+    /// Forget the cached leaf, called when the structure of the tree
+    /// changes.
+    pub(crate) fn invalidate_leaf_cache(&self) {
+        *self.leaf_cache.lock().unwrap() = None;
+    }
+
+    /// Number of descents from the root (or from a given page) to a leaf
+    /// page made on this table so far.
+    pub fn descent_count(&self) -> usize {
+        self.descents.load(atomic::Ordering::Relaxed)
+    }
+}
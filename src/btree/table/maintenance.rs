@@ -0,0 +1,176 @@
+use std::cmp::Ordering;
+
+use log::info;
+
+use crate::{
+    btree::{buffer_pool::BufferPool, page::BTreeLeafPageIterator},
+    error::SmallError,
+    transaction::{Permission, Transaction},
+    types::SmallResult,
+    utils::HandyRwLock,
+    BTreeTable, Database,
+};
+
+/// Statistics of a table collected by `BTreeTable::analyze`, the planner
+/// uses them to estimate the selectivity of the access paths of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableAnalysis {
+    /// Number of tuples visible to the analyzing transaction.
+    pub tuples_count: usize,
+
+    /// Number of distinct values of the key field.
+    pub distinct_keys: usize,
+
+    pub leaf_pages: usize,
+
+    /// Fraction of the leaf slots holding a visible tuple.
+    pub leaf_occupancy: f64,
+}
+
+impl TableAnalysis {
+    /// Estimate the number of tuples returned by seeking `keys_count` keys
+    /// of the key field.
+    pub fn estimate_seek_rows(&self, keys_count: usize) -> usize {
+        if self.distinct_keys == 0 {
+            return 0;
+        }
+        (keys_count * self.tuples_count).div_ceil(self.distinct_keys)
+    }
+}
+
+/// The steps run by `BTreeTable::maintenance`, all enabled by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceOptions {
+    /// Reclaim the slots of the deleted tuples.
+    pub compact: bool,
+
+    /// Merge or redistribute the leaf pages which are less than half full.
+    pub rebalance: bool,
+
+    /// Refresh the statistics of the table.
+    pub analyze: bool,
+
+    /// Take a checkpoint and truncate the log.
+    pub checkpoint: bool,
+}
+
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        Self {
+            compact: true,
+            rebalance: true,
+            analyze: true,
+            checkpoint: true,
+        }
+    }
+}
+
+impl BTreeTable {
+    /// Collect the statistics of the table from the tuples visible to `tx`,
+    /// they are kept in the table until the next call (see
+    /// `get_analysis`).
+    ///
+    /// The leaf pages are read in key order, so the distinct keys are
+    /// counted by comparing each key with the previous one.
+    pub fn analyze(&self, tx: &Transaction) -> Result<TableAnalysis, SmallError> {
+        let mut tuples_count = 0;
+        let mut distinct_keys = 0;
+        let mut last_key = None;
+        let mut leaf_pages = 0;
+        let mut slots_count = 0;
+
+        let mut page_rc = self.get_first_page(tx, Permission::ReadOnly)?;
+        loop {
+            let right = {
+                let page = page_rc.rl();
+                leaf_pages += 1;
+                slots_count += page.get_slots_count();
+                for tuple in BTreeLeafPageIterator::new(&page) {
+                    if tuple.visible_to(tx.get_id()) {
+                        tuples_count += 1;
+
+                        let key = tuple.get_cell(self.key_field);
+                        let is_new = match &last_key {
+                            Some(last_key) => self.compare_keys(&key, last_key) != Ordering::Equal,
+                            None => true,
+                        };
+                        if is_new {
+                            distinct_keys += 1;
+                        }
+                        last_key = Some(key);
+                    }
+                }
+                page.get_right_pid()
+            };
+
+            match right {
+                Some(right) => {
+                    page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &right)?;
+                }
+                None => break,
+            }
+        }
+
+        let analysis = TableAnalysis {
+            tuples_count,
+            distinct_keys,
+            leaf_pages,
+            leaf_occupancy: tuples_count as f64 / slots_count as f64,
+        };
+        *self.analysis.wl() = Some(analysis.clone());
+        Ok(analysis)
+    }
+
+    /// Get the statistics collected by the last `analyze`, None if the table
+    /// has never been analyzed.
+    pub fn get_analysis(&self) -> Option<TableAnalysis> {
+        self.analysis.rl().clone()
+    }
+
+    /// Get the statistics collected by the last `analyze`, the table is
+    /// analyzed first if it never was. They are not refreshed by the later
+    /// writes, run `analyze` (or `maintenance`) for that.
+    pub fn get_or_analyze(&self, tx: &Transaction) -> Result<TableAnalysis, SmallError> {
+        match self.get_analysis() {
+            Some(analysis) => Ok(analysis),
+            None => self.analyze(tx),
+        }
+    }
+
+    /// Housekeeping of the table, runs the steps enabled in `options` in
+    /// order:
+    ///
+    /// - compact: reclaim the slots of the deleted tuples, in a transaction
+    ///   of its own.
+    /// - rebalance: merge or redistribute the under-full leaf pages.
+    /// - analyze: refresh the statistics, so they describe the tidied table.
+    /// - checkpoint: take a checkpoint and truncate the log, it's kept from
+    ///   the first record of the oldest running transaction (`tx` at the
+    ///   latest).
+    ///
+    /// Should be called before `tx` reads or writes the table, the
+    /// compaction waits for the latch on the tree.
+    pub fn maintenance(&self, tx: &Transaction, options: &MaintenanceOptions) -> SmallResult {
+        tx.check_active()?;
+
+        if options.compact {
+            self.delete_invisible_tuples()?;
+        }
+
+        if options.rebalance {
+            self.rebalance(tx)?;
+        }
+
+        if options.analyze {
+            self.analyze(tx)?;
+        }
+
+        if options.checkpoint {
+            Database::checkpoint()?;
+            let truncated = Database::mut_log_manager().truncate()?;
+            info!("maintenance of {}: log truncated: {}", self.name, truncated);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::{cmp::Ordering, collections::BinaryHeap, sync::Arc};
+
+use super::{BTreeTableIterator, KeyComparator};
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::{Cell, WrappedTuple},
+    transaction::Transaction,
+    BTreeTable,
+};
+
+/// A scan over several tables sharing the same schema (e.g. the shards of a
+/// partitioned table), yielding the tuples of all of them in the order of the
+/// key field.
+///
+/// The tables are scanned in parallel and merged through a binary heap
+/// holding the next tuple of each scan, so every tuple costs O(log k) for k
+/// tables. The keys are ordered by the comparator of the first table, tuples
+/// with equal keys come in the order of the tables.
+pub struct MergedScan {
+    iters: Vec<BTreeTableIterator>,
+    heap: BinaryHeap<HeapItem>,
+    key_field: usize,
+    comparator: Arc<dyn KeyComparator>,
+}
+
+struct HeapItem {
+    key: Cell,
+    table_index: usize,
+    tuple: WrappedTuple,
+    comparator: Arc<dyn KeyComparator>,
+}
+
+/// Reversed so the `BinaryHeap` (a max-heap) pops the smallest key first.
+impl Ord for HeapItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&other.key, &self.key)
+            .then_with(|| other.table_index.cmp(&self.table_index))
+    }
+}
+
+impl PartialOrd for HeapItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapItem {}
+
+impl MergedScan {
+    /// Fails with a `SchemaMismatch` error if the tables don't share the
+    /// same schema, and if the scan of a table can't start.
+    pub fn new(tx: &Transaction, tables: &[&BTreeTable]) -> Result<Self, SmallError> {
+        let first = tables
+            .first()
+            .ok_or(SmallError::new("a merged scan needs at least one table"))?;
+        for table in tables {
+            if table.get_schema().get_fields() != first.get_schema().get_fields()
+                || table.key_field != first.key_field
+            {
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::SchemaMismatch,
+                    &format!(
+                        "tables {} and {} have different schemas",
+                        first.name, table.name
+                    ),
+                ));
+            }
+        }
+
+        let mut iters = Vec::new();
+        for table in tables {
+            iters.push(BTreeTableIterator::new(tx, table)?);
+        }
+
+        let mut instance = Self {
+            iters,
+            heap: BinaryHeap::new(),
+            key_field: first.key_field,
+            comparator: Arc::clone(&first.comparator),
+        };
+        for i in 0..instance.iters.len() {
+            instance.advance(i)?;
+        }
+        Ok(instance)
+    }
+
+    /// Same as `next`, but the error of a scan is reported instead of
+    /// panicking.
+    pub fn try_next(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        let item = match self.heap.pop() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        self.advance(item.table_index)?;
+        Ok(Some(item.tuple))
+    }
+
+    /// Push the next tuple of the `i`-th table into the heap.
+    fn advance(&mut self, i: usize) -> Result<(), SmallError> {
+        if let Some(tuple) = self.iters[i].try_next()? {
+            self.heap.push(HeapItem {
+                key: tuple.get_cell(self.key_field),
+                table_index: i,
+                tuple,
+                comparator: Arc::clone(&self.comparator),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for MergedScan {
+    type Item = WrappedTuple;
+
+    /// Panics if the scan of a table fails, use `try_next` to handle the
+    /// error.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("failed to scan the tables: {}", e))
+    }
+}
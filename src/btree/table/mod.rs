@@ -4,6 +4,39 @@ pub use table::*;
 mod table_iter;
 pub use table_iter::*;
 
+mod import;
+pub use import::*;
+
+mod merged_scan;
+pub use merged_scan::*;
+
+mod comparator;
+pub use comparator::*;
+
+mod shared_scan;
+pub use shared_scan::*;
+
+mod latch_free_scan;
+pub use latch_free_scan::*;
+
+mod diff;
+pub use diff::*;
+
+mod bench;
+pub use bench::*;
+
+mod bloom;
+pub use bloom::*;
+
+mod maintenance;
+pub use maintenance::*;
+
+mod copy;
+mod dump;
+mod leaf_cache;
+mod rebuild;
+mod secondary_index;
+
 mod delete;
 mod insert;
 mod search;
@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{
+    btree::{
+        buffer_pool::BufferPool,
+        page::{
+            BTreeBasePage, BTreeInternalPage, BTreeInternalPageIterator, BTreeLeafPage, BTreePage,
+            BTreePageID, BTreeRootPointerPage, PageCategory,
+        },
+    },
+    error::{SmallError, SmallErrorKind},
+    io::Serializeable,
+    storage::tuple::Tuple,
+    transaction::Transaction,
+    BTreeTable, Database,
+};
+
+impl BTreeTable {
+    /// Rebuild the tree from its chain of leaf pages found in the table file,
+    /// discarding the internal pages, the root pointer page and the header
+    /// pages. This salvages the data when the internal structure is damaged
+    /// but the leaves are intact.
+    ///
+    /// The chain starts from the left-most leaf, reached from the root when
+    /// the left-most path of the internal pages is still readable, or else
+    /// the only leaf page without a left sibling. It is followed through the
+    /// right sibling pointers, every page must point back to its left
+    /// sibling. The leaf pages freed by a merge keep their content on disk,
+    /// they are not linked into the chain and their stale tuples are not
+    /// recovered.
+    ///
+    /// The tuples of the chain visible to `tx` (duplicates included) are
+    /// inserted by `tx` into a fresh tree, in key order.
+    ///
+    /// Pages carry no checksum, so any page tagged as a leaf is trusted
+    /// (after inflating it, for the pages of a compressed table).
+    ///
+    /// The table must not be used by any other transaction during the
+    /// rebuild. Uncommitted changes in the buffer pool are discarded.
+    ///
+    /// Return the number of tuples recovered.
+    pub fn rebuild_from_leaves(&self, tx: &Transaction) -> Result<usize, SmallError> {
+        self.invalidate_leaf_cache();
+
+        let pages = self.read_raw_pages()?;
+
+        let leaf_tag = PageCategory::Leaf.to_bytes(&());
+        let mut leaves: HashMap<u32, BTreeLeafPage> = HashMap::new();
+        for (page_index, bytes) in pages.iter().enumerate() {
+            if bytes[0..4] != leaf_tag[..] {
+                continue;
+            }
+            let pid = BTreePageID::new(PageCategory::Leaf, self.get_id(), page_index as u32);
+            let page = <BTreeLeafPage as BTreePage>::new(&pid, bytes, &self.schema);
+            leaves.insert(page_index as u32, page);
+        }
+
+        let head = match self.leftmost_leaf_from_root(&pages) {
+            Some(head) if leaves.contains_key(&head) => head,
+            _ => {
+                let heads: Vec<u32> = leaves
+                    .iter()
+                    .filter(|(_, page)| page.get_left_pid().is_none())
+                    .map(|(index, _)| *index)
+                    .collect();
+                match heads.as_slice() {
+                    [head] => *head,
+                    _ => {
+                        let err_msg = format!(
+                            "the left-most leaf of table {} is ambiguous, candidates: {:?}",
+                            self.name, heads,
+                        );
+                        return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+                    }
+                }
+            }
+        };
+
+        let mut tuples = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = Some(head);
+        let mut previous: Option<u32> = None;
+        while let Some(index) = current {
+            let page = match leaves.get(&index) {
+                Some(page) if visited.insert(index) => page,
+                _ => {
+                    let err_msg = format!("leaf page {} is not a valid link of the chain", index);
+                    return Err(SmallError::with_kind(
+                        SmallErrorKind::CorruptSiblingChain,
+                        &err_msg,
+                    ));
+                }
+            };
+            if page.get_left_pid().map(|pid| pid.page_index) != previous {
+                let err_msg = format!(
+                    "leaf page {} doesn't point back to its left sibling {:?}",
+                    index, previous,
+                );
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::CorruptSiblingChain,
+                    &err_msg,
+                ));
+            }
+
+            for tuple in page.iter() {
+                let tuple = tuple.get_tuple();
+                if tuple.visible_to(tx.get_id()) {
+                    tuples.push(Tuple::new(&tuple.get_cells(), tx.get_id()));
+                }
+            }
+
+            previous = Some(index);
+            current = page.get_right_pid().map(|pid| pid.page_index);
+        }
+
+        // start over from an empty tree
+        Database::mut_buffer_pool().discard_table_pages(self.get_id());
+        {
+            let mut file = self.get_file();
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        self.file_init();
+        self.set_page_index(self.pages_count() as u32);
+
+        // the entries are added back with the tuples
+        self.clear_index_entries(tx)?;
+        self.import_tuples(tx, tuples.into_iter().map(Ok))
+    }
+
+    /// Read all pages of the table file, the compressed pages are inflated.
+    /// A page which can't be inflated is not salvageable, it's left as is.
+    fn read_raw_pages(&self) -> Result<Vec<Vec<u8>>, SmallError> {
+        let page_size = BufferPool::get_page_size();
+
+        let mut file = self.get_file();
+        let file_size = file.metadata()?.len() as usize;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut pages = Vec::new();
+        for _ in 0..file_size / page_size {
+            let mut bytes = vec![0; page_size];
+            file.read_exact(&mut bytes)?;
+
+            // only the leaf pages are compressed
+            if BTreeBasePage::is_compressed_page(&bytes) {
+                if let Ok(data) = BTreeBasePage::decompress_page_data(&bytes) {
+                    bytes = data;
+                }
+            }
+            pages.push(bytes);
+        }
+        Ok(pages)
+    }
+
+    /// Follow the left-most path from the root to a leaf in the raw pages.
+    /// Return None if a page on the path is damaged.
+    fn leftmost_leaf_from_root(&self, pages: &[Vec<u8>]) -> Option<u32> {
+        let leaf_tag = PageCategory::Leaf.to_bytes(&());
+        let internal_tag = PageCategory::Internal.to_bytes(&());
+        let root_ptr_tag = PageCategory::RootPointer.to_bytes(&());
+
+        // the root pointer page is the first page, the category of the root
+        // is stored after the index of the root
+        let root_ptr = pages.first()?;
+        if root_ptr[0..4] != root_ptr_tag[..]
+            || (root_ptr[8..12] != leaf_tag[..] && root_ptr[8..12] != internal_tag[..])
+        {
+            return None;
+        }
+        let root_ptr_pid = BTreePageID::get_root_ptr_page_id(self.get_id());
+        let mut pid =
+            <BTreeRootPointerPage as BTreePage>::new(&root_ptr_pid, root_ptr, &self.schema)
+                .get_root_pid();
+
+        // a path can't visit more pages than the file has
+        for _ in 0..pages.len() {
+            let bytes = pages.get(pid.page_index as usize)?;
+            if bytes[0..4] == leaf_tag[..] {
+                return Some(pid.page_index);
+            }
+            if bytes[0..4] != internal_tag[..] {
+                return None;
+            }
+
+            let page = <BTreeInternalPage as BTreePage>::new(&pid, bytes, &self.schema);
+            pid = BTreeInternalPageIterator::new(&page)
+                .next()?
+                .get_left_child();
+        }
+        None
+    }
+}
@@ -1,36 +1,221 @@
-use crate::BTreeTable;
+use std::{cmp::Ordering, collections::VecDeque, iter};
+
+use super::{OnMissing, SearchFor};
+use crate::{
+    btree::{
+        buffer_pool::BufferPool,
+        page::{BTreePage, BTreePageID, PageGuard},
+    },
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::{Cell, WrappedTuple},
+    transaction::{Permission, Transaction},
+    utils::HandyRwLock,
+    BTreeTable,
+};
 
 impl BTreeTable {
-    // pub(super) fn get_pages(
-    //     &self,
-    //     tx: &Transaction,
-    //     predicate: &Predicate,
-    // ) -> Result<Vec<Arc<RwLock<BTreeLeafPage>>>, SmallError> {
-    //     if predicate.field_index == self.key_field {
-    //         match predicate.op {
-    //             Op::Equals | Op::GreaterThan | Op::GreaterThanOrEq => {
-    //                 start_page_rc = self.find_leaf_page(
-    //                     tx,
-    //                     Permission::ReadOnly,
-    //                     root_pid,
-    //                     &SearchFor::Target(predicate.cell.clone()),
-    //                 )
-    //             }
-    //             Op::LessThan | Op::LessThanOrEq => {
-    //                 start_page_rc = table.find_leaf_page(
-    //                     &tx,
-    //                     Permission::ReadOnly,
-    //                     root_pid,
-    //                     &SearchFor::LeftMost,
-    //                 )
-    //             }
-    //             Op::Like => todo!(),
-    //             Op::NotEquals => todo!(),
-    //         }
-    //     } else {
-    //         log::error!("Not implemented yet");
-    //         todo!()
-    //     }
-    //     todo!()
-    // }
+    /// Get the tuple with the given key through a single descent from the
+    /// root.
+    ///
+    /// If there are several tuples with the same key, the first one (in the
+    /// scan order) is returned. Return None if no tuple (visible to `tx`)
+    /// has the key. Keys are compared with the comparator of the table.
+    /// Fails if the leaf page of the key can't be found or read.
+    ///
+    /// The Bloom filter of the table (if any) is checked first, so the
+    /// lookups of the absent keys mostly don't touch any page.
+    pub fn get_by_key(
+        &self,
+        tx: &Transaction,
+        key: &Cell,
+    ) -> Result<Option<WrappedTuple>, SmallError> {
+        if !self.may_contain_key(key) {
+            return Ok(None);
+        }
+
+        // a point lookup, the scans of the transaction read ahead less
+        tx.record_point_lookup();
+
+        let root_pid = self.get_root_pid(tx);
+        let page_rc = self.find_leaf_page(
+            tx,
+            Permission::ReadOnly,
+            root_pid,
+            &SearchFor::Target(key.clone()),
+        )?;
+
+        // the latch on the page is released when the guard is dropped
+        let mut page_guard = PageGuard::new(tx, page_rc);
+
+        loop {
+            {
+                let page = page_guard.rl();
+                if let Some(start) = page.lower_bound_by(key, self.comparator.as_ref()) {
+                    // tuples with the same key may be invisible to `tx`, check
+                    // them one by one
+                    for slot in start..page.get_slots_count() {
+                        let tuple = match page.get_tuple(slot) {
+                            Some(tuple) => tuple,
+                            None => continue,
+                        };
+                        let current = tuple.get_cell(self.key_field);
+                        if self.comparator.compare(&current, key) != Ordering::Equal {
+                            return Ok(None);
+                        }
+                        if tuple.visible_to(tx.get_id()) {
+                            return Ok(Some(WrappedTuple::new(tuple, slot, page.get_pid())));
+                        }
+                    }
+                }
+            }
+
+            // All tuples in the page are smaller than the key (or invisible),
+            // the key may still be in the right sibling. (e.g. the page is the left-most
+            // page which may contain the key, but the key is deleted from it)
+            let right = page_guard.rl().get_right_pid();
+            match right {
+                Some(right_pid) => {
+                    let right_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &right_pid)?;
+                    page_guard = PageGuard::new(tx, right_rc);
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Same as `get_by_key`, but a missing key is an error when `on_missing`
+    /// is `OnMissing::Error`, for the callers which require the key.
+    pub fn get_by_key_with(
+        &self,
+        tx: &Transaction,
+        key: &Cell,
+        on_missing: OnMissing,
+    ) -> Result<Option<WrappedTuple>, SmallError> {
+        match self.get_by_key(tx, key)? {
+            Some(tuple) => Ok(Some(tuple)),
+            None => match on_missing {
+                OnMissing::Empty => Ok(None),
+                OnMissing::Error => {
+                    let err_msg = format!("key {:?} not found, table: {}", key, self.name);
+                    Err(SmallError::with_kind(SmallErrorKind::NotFound, &err_msg))
+                }
+            },
+        }
+    }
+
+    /// Get the smallest key visible to `tx`, None if the table has no
+    /// visible tuple. Fails if a leaf page can't be read.
+    ///
+    /// Only the left-most leaf pages are read (more than one if the first
+    /// ones hold no visible tuple), and only the key cell is copied.
+    pub fn min_key(&self, tx: &Transaction) -> Result<Option<Cell>, SmallError> {
+        let mut page_guard = PageGuard::new(tx, self.get_first_page(tx, Permission::ReadOnly)?);
+        loop {
+            let left_to_right = {
+                let page = page_guard.rl();
+                let found = (0..page.get_slots_count())
+                    .filter_map(|slot| page.get_tuple(slot))
+                    .find(|tuple| tuple.visible_to(tx.get_id()));
+                if let Some(tuple) = found {
+                    return Ok(Some(tuple.get_cell(self.key_field)));
+                }
+                page.get_right_pid()
+            };
+
+            let pid = match left_to_right {
+                Some(pid) => pid,
+                None => return Ok(None),
+            };
+            let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &pid)?;
+            page_guard = PageGuard::new(tx, page_rc);
+        }
+    }
+
+    /// Get the largest key visible to `tx`, None if the table has no
+    /// visible tuple. The mirror of `min_key`.
+    pub fn max_key(&self, tx: &Transaction) -> Result<Option<Cell>, SmallError> {
+        let mut page_guard = PageGuard::new(tx, self.get_last_page(tx, Permission::ReadOnly)?);
+        loop {
+            let right_to_left = {
+                let page = page_guard.rl();
+                let found = (0..page.get_slots_count())
+                    .rev()
+                    .filter_map(|slot| page.get_tuple(slot))
+                    .find(|tuple| tuple.visible_to(tx.get_id()));
+                if let Some(tuple) = found {
+                    return Ok(Some(tuple.get_cell(self.key_field)));
+                }
+                page.get_left_pid()
+            };
+
+            let pid = match right_to_left {
+                Some(pid) => pid,
+                None => return Ok(None),
+            };
+            let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &pid)?;
+            page_guard = PageGuard::new(tx, page_rc);
+        }
+    }
+
+    /// Return true if a tuple with the given key is visible to `tx`.
+    pub fn contains_key(&self, tx: &Transaction, key: &Cell) -> Result<bool, SmallError> {
+        Ok(self.get_by_key(tx, key)?.is_some())
+    }
+
+    /// Iterate over the keys visible to `tx`, in the scan order.
+    ///
+    /// Only the key cell of each tuple is decoded, for the callers which
+    /// need the key set (e.g. to find the gaps, or to build an external
+    /// index). See `scan_cells` for how the pages are read.
+    ///
+    /// A leaf page which can't be read is yielded as an error, which ends
+    /// the iteration.
+    pub fn keys<'a>(
+        &'a self,
+        tx: &'a Transaction,
+    ) -> impl Iterator<Item = Result<Cell, SmallError>> + 'a {
+        self.scan_cells(tx, self.key_field)
+    }
+
+    /// Iterate over the cells of field `field_index` of the tuples visible
+    /// to `tx`, in the scan order, only that cell of each tuple is decoded.
+    ///
+    /// The leaf pages are read one at a time, the latch on a page is
+    /// released once its cells are collected. The pages which aren't in the
+    /// buffer pool are not loaded into it, see
+    /// `BufferPool::get_leaf_page_cells`.
+    pub(crate) fn scan_cells<'a>(
+        &'a self,
+        tx: &'a Transaction,
+        field_index: usize,
+    ) -> impl Iterator<Item = Result<Cell, SmallError>> + 'a {
+        let mut first = Some(self.get_first_page(tx, Permission::ReadOnly));
+        let mut next_pid: Option<BTreePageID> = None;
+        let mut cells = VecDeque::new();
+
+        iter::from_fn(move || loop {
+            if let Some(cell) = cells.pop_front() {
+                return Some(Ok(cell));
+            }
+
+            let (page_cells, right_pid) = match first.take() {
+                Some(Ok(page_rc)) => {
+                    // the first page is loaded and latched by the descent
+                    let page_guard = PageGuard::new(tx, page_rc);
+                    let page = page_guard.rl();
+                    (
+                        page.visible_cells(tx.get_id(), field_index),
+                        page.get_right_pid(),
+                    )
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => match BufferPool::get_leaf_page_cells(tx, &next_pid.take()?, field_index) {
+                    Ok(result) => result,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            cells.extend(page_cells);
+            next_pid = right_pid;
+        })
+    }
 }
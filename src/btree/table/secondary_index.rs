@@ -0,0 +1,84 @@
+use std::ops::Bound;
+
+use crate::{
+    btree::table::BTreeTableIterator, common::IndexMeta, storage::tuple::Tuple,
+    transaction::Transaction, types::SmallResult, utils::HandyRwLock, BTreeTable, Database,
+};
+
+/// Maintenance of the secondary indexes of a table (see `IndexMeta`), every
+/// insertion and deletion of a tuple goes through here, whatever the path
+/// (SQL, the table API, an import or a rebuild).
+impl BTreeTable {
+    /// Add the entry of `tuple` to the index: the indexed value, followed by
+    /// the key of the tuple.
+    pub(crate) fn insert_index_entry(
+        &self,
+        tx: &Transaction,
+        index: &IndexMeta,
+        tuple: &Tuple,
+    ) -> SmallResult {
+        let cells = vec![
+            tuple.get_cell(index.field_index),
+            tuple.get_cell(self.key_field),
+        ];
+        index
+            .index_table
+            .rl()
+            .insert_tuple(tx, &Tuple::new(&cells, tx.get_id()))
+    }
+
+    /// Add the entries of a tuple inserted into the table to all its
+    /// secondary indexes.
+    pub(super) fn insert_index_entries(&self, tx: &Transaction, tuple: &Tuple) -> SmallResult {
+        // the catalog must not be locked while the index tables load their
+        // pages
+        let indexes = Database::catalog().table_indexes(&self.name);
+        for index in indexes {
+            self.insert_index_entry(tx, &index, tuple)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the entries of a tuple deleted from the table from all its
+    /// secondary indexes, one entry per index.
+    pub(super) fn delete_index_entries(&self, tx: &Transaction, tuple: &Tuple) -> SmallResult {
+        let indexes = Database::catalog().table_indexes(&self.name);
+        for index in indexes {
+            let value = tuple.get_cell(index.field_index);
+            let key = tuple.get_cell(self.key_field);
+
+            let index_table = index.index_table.rl();
+            let mut it =
+                index_table.range(tx, Bound::Included(value.clone()), Bound::Included(value))?;
+            let mut found = None;
+            while let Some(entry) = it.try_next()? {
+                if entry.get_cell(1) == key {
+                    found = Some(entry);
+                    break;
+                }
+            }
+            if let Some(entry) = found {
+                index_table.delete_tuple(tx, &entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all entries of the secondary indexes of the table, before the
+    /// tuples are inserted again by a rebuild.
+    pub(super) fn clear_index_entries(&self, tx: &Transaction) -> SmallResult {
+        let indexes = Database::catalog().table_indexes(&self.name);
+        for index in indexes {
+            let index_table = index.index_table.rl();
+            let mut it = BTreeTableIterator::new(tx, &index_table)?;
+            let mut entries = Vec::new();
+            while let Some(entry) = it.try_next()? {
+                entries.push(entry);
+            }
+            for entry in &entries {
+                index_table.delete_tuple(tx, entry)?;
+            }
+        }
+        Ok(())
+    }
+}
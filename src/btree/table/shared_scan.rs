@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    btree::{
+        buffer_pool::BufferPool,
+        page::{BTreeLeafPage, BTreeLeafPageIterator, BTreePage, BTreePageID},
+    },
+    error::SmallError,
+    storage::tuple::WrappedTuple,
+    transaction::{Permission, Transaction},
+    utils::HandyRwLock,
+    BTreeTable, Database,
+};
+
+impl BTreeTable {
+    /// Scan the table in key order like `iter`, but share the physical pass
+    /// over the leaf pages with the other shared scans of the table that are
+    /// running at the same time.
+    ///
+    /// The leaf pages are read once by the pass (on behalf of whichever scan
+    /// needs them first, which is charged for the read) and every scan gets
+    /// the tuples visible to its own transaction.
+    ///
+    /// The pass streams the leaf pages: it only keeps the tuples of the
+    /// pages some scan hasn't reached the end of, and only latches (with its
+    /// own transaction) the last page it read, to reach its right sibling.
+    /// A scan joins the running pass if the pass still has the first page,
+    /// otherwise it starts a new pass. A scan must not be used by a
+    /// transaction writing to the table.
+    ///
+    /// Fails if a new pass can't find the first leaf page.
+    pub fn shared_scan(&self, tx: &Transaction) -> Result<SharedScan, SmallError> {
+        let mut current = self.shared_pass.lock().unwrap();
+        let joined = current
+            .upgrade()
+            .and_then(|pass| pass.join().map(|id| (pass, id)));
+        let (pass, id) = match joined {
+            Some(joined) => joined,
+            None => {
+                let pass = Arc::new(ScanPass::new(tx, self)?);
+                *current = Arc::downgrade(&pass);
+                let id = pass.join().unwrap();
+                (pass, id)
+            }
+        };
+
+        Ok(SharedScan {
+            tx: tx.clone(),
+            pass,
+            id,
+            page_index: 0,
+            tuples: Arc::new(Vec::new()),
+            cursor: 0,
+        })
+    }
+}
+
+/// A physical pass over the leaf pages of a table, see
+/// `BTreeTable::shared_scan`.
+pub(super) struct ScanPass {
+    /// The transaction latching the last page read by the pass, committed
+    /// when the pass is dropped.
+    tx: Transaction,
+
+    state: Mutex<PassState>,
+}
+
+struct PassState {
+    /// The tuples of the leaf pages read by the pass which some scan hasn't
+    /// finished, in key order. Including the tuples invisible to some of the
+    /// scans.
+    pages: VecDeque<Arc<Vec<WrappedTuple>>>,
+
+    /// The index in the pass of the first page of `pages`.
+    first_index: usize,
+
+    /// The index of the next page of each scan sharing the pass.
+    scans: HashMap<usize, usize>,
+    next_scan_id: usize,
+
+    /// The last leaf page read, latched until its right sibling is.
+    last_pid: BTreePageID,
+
+    /// The next leaf page to read, `None` once the pass is finished.
+    next_pid: Option<BTreePageID>,
+}
+
+impl ScanPass {
+    fn new(tx: &Transaction, table: &BTreeTable) -> Result<Self, SmallError> {
+        let pass_tx = Transaction::new();
+
+        let pages_read = pass_tx.stats().pages_read;
+        let first_rc = match table.get_first_page(&pass_tx, Permission::ReadOnly) {
+            Ok(page_rc) => page_rc,
+            Err(e) => {
+                // release the latches taken by the failed descent
+                if let Err(commit_err) = pass_tx.commit() {
+                    log::error!("failed to end the shared scan pass: {:?}", commit_err);
+                }
+                return Err(e);
+            }
+        };
+        tx.count_pages_read(pass_tx.stats().pages_read - pages_read);
+
+        let first_pid = first_rc.rl().get_pid();
+        let (tuples, next_pid) = read_tuples(&first_rc.rl());
+
+        Ok(Self {
+            tx: pass_tx,
+            state: Mutex::new(PassState {
+                pages: VecDeque::from([Arc::new(tuples)]),
+                first_index: 0,
+                scans: HashMap::new(),
+                next_scan_id: 0,
+                last_pid: first_pid,
+                next_pid,
+            }),
+        })
+    }
+
+    /// Register a scan starting at the first page, return its id. Returns
+    /// `None` if the first page has been dropped already.
+    fn join(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.first_index > 0 {
+            return None;
+        }
+
+        let id = state.next_scan_id;
+        state.next_scan_id += 1;
+        state.scans.insert(id, 0);
+        Some(id)
+    }
+
+    /// Unregister the scan `id`, the pages it was the last to need are
+    /// dropped.
+    fn leave(&self, id: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.scans.remove(&id);
+        state.drop_finished_pages();
+    }
+
+    /// Get the tuples of the `index`-th leaf page for the scan `id`, which
+    /// is done with the pages before. The page is read if no scan has asked
+    /// for it yet. Returns `None` past the last page.
+    fn page(
+        &self,
+        tx: &Transaction,
+        id: usize,
+        index: usize,
+    ) -> Result<Option<Arc<Vec<WrappedTuple>>>, SmallError> {
+        let mut state = self.state.lock().unwrap();
+        state.scans.insert(id, index);
+        state.drop_finished_pages();
+
+        while state.first_index + state.pages.len() <= index {
+            let pid = match state.next_pid {
+                Some(pid) => pid,
+                None => return Ok(None),
+            };
+
+            let page_rc = BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &pid)?;
+            // the page read made by the pass is charged to the scan that
+            // needed the page
+            tx.count_pages_read(1);
+
+            // the previous page is released once its right sibling is
+            // latched, the tuples handed out to the scans are copies
+            Database::mut_concurrent_status().release_latch(&self.tx, &state.last_pid)?;
+
+            let (tuples, next_pid) = read_tuples(&page_rc.rl());
+            state.pages.push_back(Arc::new(tuples));
+            state.last_pid = pid;
+            state.next_pid = next_pid;
+        }
+        Ok(Some(state.pages[index - state.first_index].clone()))
+    }
+}
+
+impl PassState {
+    /// Drop the pages every scan is done with.
+    fn drop_finished_pages(&mut self) {
+        let needed = self.scans.values().min().cloned().unwrap_or(usize::MAX);
+        while self.first_index < needed && !self.pages.is_empty() {
+            self.pages.pop_front();
+            self.first_index += 1;
+        }
+    }
+}
+
+impl Drop for ScanPass {
+    fn drop(&mut self) {
+        // release the latches taken by the pass
+        if let Err(e) = self.tx.commit() {
+            log::error!("failed to end the shared scan pass: {:?}", e);
+        }
+    }
+}
+
+fn read_tuples(page: &BTreeLeafPage) -> (Vec<WrappedTuple>, Option<BTreePageID>) {
+    (
+        BTreeLeafPageIterator::new(page).collect(),
+        page.get_right_pid(),
+    )
+}
+
+/// A scan created by `BTreeTable::shared_scan`.
+pub struct SharedScan {
+    tx: Transaction,
+    pass: Arc<ScanPass>,
+
+    /// The id of the scan in the pass.
+    id: usize,
+
+    /// Index of the next leaf page in the pass.
+    page_index: usize,
+
+    tuples: Arc<Vec<WrappedTuple>>,
+    cursor: usize,
+}
+
+impl SharedScan {
+    /// Same as `next`, but a leaf page which can't be read is reported
+    /// instead of panicking.
+    pub fn try_next(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        loop {
+            while self.cursor < self.tuples.len() {
+                let tuple = &self.tuples[self.cursor];
+                self.cursor += 1;
+                if tuple.visible_to(self.tx.get_id()) {
+                    return Ok(Some(WrappedTuple::new(
+                        tuple.get_tuple(),
+                        tuple.get_slot_number(),
+                        tuple.get_pid(),
+                    )));
+                }
+            }
+
+            self.tuples = match self.pass.page(&self.tx, self.id, self.page_index)? {
+                Some(tuples) => tuples,
+                None => return Ok(None),
+            };
+            self.page_index += 1;
+            self.cursor = 0;
+        }
+    }
+}
+
+impl Drop for SharedScan {
+    fn drop(&mut self) {
+        self.pass.leave(self.id);
+    }
+}
+
+impl Iterator for SharedScan {
+    type Item = WrappedTuple;
+
+    /// Panics if a leaf page can't be read, see `try_next`.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("shared scan failed: {}", e))
+    }
+}
@@ -1,12 +1,12 @@
 use core::fmt;
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashSet},
     fs::{File, OpenOptions},
     hash::{Hash, Hasher},
-    io::{Seek, SeekFrom, Write},
+    io::{Read, Seek, SeekFrom, Write},
     sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc, Mutex, MutexGuard, RwLock,
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, Weak,
     },
     time::SystemTime,
     usize,
@@ -14,27 +14,41 @@ use std::{
 
 use log::debug;
 
-use super::BTreeTableIterator;
+use super::{
+    leaf_cache::CachedLeaf, BloomFilter, KeyComparator, NaturalOrder, ScanPass, TableAnalysis,
+};
 use crate::{
     btree::{
         buffer_pool::BufferPool,
         page::{
-            BTreeBasePage, BTreeInternalPage, BTreeInternalPageIterator, BTreeLeafPage,
-            BTreeLeafPageIterator, BTreeLeafPageIteratorRc, BTreePage, BTreePageID,
-            BTreeRootPointerPage, Entry, HeaderPages, PageCategory, TableIndex,
+            page_index_exhausted, BTreeBasePage, BTreeInternalPage, BTreeInternalPageIterator,
+            BTreeLeafPage, BTreeLeafPageIterator, BTreeLeafPageIteratorRc, BTreePage, BTreePageID,
+            BTreeRootPointerPage, Entry, HeaderPages, PageCategory, PageGuard, TableIndex,
+            LEAF_FORMAT_VERSION,
         },
     },
-    error::{get_caller, SmallError},
+    common::{Catalog, TableOptions, TupleChange},
+    error::{get_caller, SmallError, SmallErrorKind},
+    io::punch_hole,
     storage::{
         table_schema::TableSchema,
         tuple::{Cell, Tuple, WrappedTuple},
     },
     transaction::{Permission, Transaction},
-    types::SmallResult,
+    types::{ResultPod, SmallResult},
     utils::{lock_state, HandyRwLock},
     Database,
 };
 
+/// The levels a path from the root may have on top of the height of the
+/// most sparse tree with the pages of the table, see
+/// `BTreeTable::check_depth`.
+const DEPTH_SLACK: usize = 2;
+
+/// A leaf page with the (exclusive lower, inclusive upper) bounds of its
+/// keys, see `BTreeTable::find_leaf_page_with_bounds`.
+type LeafWithBounds = (Arc<RwLock<BTreeLeafPage>>, Option<Cell>, Option<Cell>);
+
 pub enum SearchFor {
     Target(Cell),
     LeftMost,
@@ -74,6 +88,62 @@ pub struct BTreeTable {
     /// The page index start from 0 and increase monotonically by 1,
     /// the page index of "root pointer" page is always 0.
     pub(crate) page_index: AtomicU32,
+
+    /// Fraction of the tuples kept in the original page when a leaf page
+    /// is split, the rest are moved to the new right sibling.
+    pub(super) split_ratio: f64,
+
+    /// The ordering of the keys.
+    pub(super) comparator: Arc<dyn KeyComparator>,
+
+    /// The field ordering the tuples with equal keys, None means they are
+    /// kept in insertion-dependent order.
+    pub(super) secondary_field: Option<usize>,
+
+    /// The Bloom filter over the keys, see `enable_bloom_filter`.
+    pub(super) bloom_filter: RwLock<Option<BloomFilter>>,
+
+    /// Keep the deleted tuples as tombstones until `purge_tombstones` is
+    /// called, instead of removing them in `delete_invisible_tuples`.
+    pub(crate) retain_tombstones: bool,
+
+    /// Merge or redistribute the leaf pages which become less than half
+    /// full on delete. When disabled, the pages are only tidied up by
+    /// `rebalance`.
+    pub(super) auto_merge: bool,
+
+    /// Store the leaf pages deflated on disk, see `set_compressed`.
+    pub(crate) compressed: bool,
+
+    /// The maximum number of children of an internal page, None means as
+    /// many as the page can hold.
+    pub(crate) max_fan_out: Option<usize>,
+
+    /// The physical pass of the running shared scans, see `shared_scan`.
+    pub(super) shared_pass: Mutex<Weak<ScanPass>>,
+
+    /// The leaf reached by the last insert, see `get_cached_leaf`.
+    pub(super) leaf_cache: Mutex<Option<CachedLeaf>>,
+
+    /// Number of calls of `find_leaf_page`.
+    pub(super) descents: AtomicUsize,
+
+    /// The statistics collected by the last `analyze`.
+    pub(super) analysis: RwLock<Option<TableAnalysis>>,
+}
+
+/// A page whose status in the header pages doesn't match the tree, found
+/// by `BTreeTable::verify_free_space`.
+#[derive(Debug, PartialEq)]
+pub struct FreeSpaceDiscrepancy {
+    pub page_index: u32,
+
+    /// The page is marked as occupied in the header pages.
+    pub marked_used: bool,
+
+    /// The page is a live page of the table (the root pointer page, a header
+    /// page, or a page reachable from the root).
+    pub in_tree: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -82,6 +152,31 @@ pub enum WriteScene {
     Sequential,
 }
 
+/// What `BTreeTable::insert_tuples` does when a tuple of the batch has the
+/// same key as a tuple visible in the table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Reject the whole batch, nothing is inserted.
+    Error,
+
+    /// Leave the existing tuple untouched and drop the new one.
+    Skip,
+
+    /// Delete the existing tuple and insert the new one.
+    Replace,
+}
+
+/// What `BTreeTable::get_by_key_with` returns when no tuple visible to the
+/// transaction has the key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnMissing {
+    /// Return `Ok(None)`.
+    Empty,
+
+    /// Return an error of kind `SmallErrorKind::NotFound`.
+    Error,
+}
+
 impl fmt::Display for BTreeTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<BTreeFile, file: {:?}, id: {}>", "empty", self.table_id)
@@ -137,11 +232,58 @@ impl BTreeTable {
             page_index: AtomicU32::new(1),
 
             key_field: schema.get_key_pos(),
+
+            split_ratio: 0.5,
+
+            comparator: Arc::new(NaturalOrder),
+
+            secondary_field: None,
+
+            bloom_filter: RwLock::new(None),
+
+            retain_tombstones: false,
+
+            auto_merge: true,
+
+            compressed: false,
+
+            max_fan_out: None,
+
+            shared_pass: Mutex::new(Weak::new()),
+
+            leaf_cache: Mutex::new(None),
+
+            descents: AtomicUsize::new(0),
+
+            analysis: RwLock::new(None),
         };
 
         instance.file_init();
         instance
     }
+
+    /// Open the table `table_name` of the database, created earlier (e.g.
+    /// before a restart), with its persisted schema. See
+    /// `Catalog::open_table`.
+    pub fn open(table_name: &str) -> Result<Arc<RwLock<BTreeTable>>, SmallError> {
+        Catalog::open_table(table_name)
+    }
+
+    /// Same as `new`, but the keys are ordered by `comparator` instead of
+    /// the natural ordering of the cells.
+    ///
+    /// The comparator is not persisted, the table has to be created with
+    /// the same comparator every time it is opened.
+    pub fn new_with_comparator(
+        table_name: &str,
+        table_id: Option<u32>,
+        schema: &TableSchema,
+        comparator: Arc<dyn KeyComparator>,
+    ) -> Self {
+        let mut instance = Self::new(table_name, table_id, schema);
+        instance.comparator = comparator;
+        instance
+    }
 }
 
 // normal read-only functions
@@ -158,7 +300,7 @@ impl BTreeTable {
     /// all pages.
     pub fn tuples_count(&self) -> usize {
         let tx = Transaction::new();
-        let count = BTreeTableIterator::new(&tx, self).count();
+        let count = self.iter(&tx).count();
         tx.commit().unwrap();
         count
     }
@@ -166,13 +308,52 @@ impl BTreeTable {
     pub fn get_random_tuple(&self, _tx: &Transaction) -> Tuple {
         unimplemented!()
     }
+
+    /// Run `op` in a new transaction, commit it if `op` succeeds, otherwise
+    /// abort it and return the error of `op`.
+    pub(super) fn auto_commit<F>(&self, op: F) -> SmallResult
+    where
+        F: FnOnce(&Transaction) -> SmallResult,
+    {
+        let tx = Transaction::new();
+        match op(&tx) {
+            Ok(()) => tx.commit(),
+            Err(e) => {
+                if let Err(abort_err) = tx.abort() {
+                    log::error!("failed to abort {:?}: {}", tx, abort_err);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Record a tuple inserted (`before` is `None`) or deleted (`after` is
+    /// `None`) by the transaction, for the commit hooks. Nothing is recorded
+    /// when no hook is registered.
+    pub(super) fn record_change(
+        &self,
+        tx: &Transaction,
+        before: Option<&Tuple>,
+        after: Option<&Tuple>,
+    ) {
+        if !Database::has_commit_hooks() {
+            return;
+        }
+
+        let change = TupleChange {
+            table: self.name.clone(),
+            before: before.cloned(),
+            after: after.cloned(),
+        };
+        Database::mut_concurrent_status().record_change(tx, change);
+    }
 }
 
 // api which interacting with disk directly
 impl BTreeTable {
-    pub(crate) fn get_empty_leaf_page(&self, tx: &Transaction) -> Arc<RwLock<BTreeLeafPage>> {
+    pub(crate) fn get_empty_leaf_page(&self, tx: &Transaction) -> ResultPod<BTreeLeafPage> {
         // create the new page
-        let page_index = self.get_empty_page_index(tx);
+        let page_index = self.get_empty_page_index(tx)?;
         let page_id = BTreePageID::new(PageCategory::Leaf, self.table_id, page_index);
         let page = BTreeLeafPage::new(&page_id, &BTreeBasePage::empty_page_data(), &self.schema);
 
@@ -181,18 +362,17 @@ impl BTreeTable {
         let page_rc = Arc::new(RwLock::new(page));
         // insert to buffer pool because it's a dirty page at this
         // time
-        Database::mut_buffer_pool()
-            .leaf_buffer
-            .insert(page_id, page_rc.clone());
-        page_rc
+        //
+        // (the page may be a free page reused, its cached copy is stale)
+        let buffer_pool = Database::buffer_pool();
+        buffer_pool.leaf_buffer.remove(&page_id);
+        buffer_pool.leaf_buffer.insert(page_id, page_rc.clone())?;
+        Ok(page_rc)
     }
 
-    pub(crate) fn get_empty_interanl_page(
-        &self,
-        tx: &Transaction,
-    ) -> Arc<RwLock<BTreeInternalPage>> {
+    pub(crate) fn get_empty_interanl_page(&self, tx: &Transaction) -> ResultPod<BTreeInternalPage> {
         // create the new page
-        let page_index = self.get_empty_page_index(tx);
+        let page_index = self.get_empty_page_index(tx)?;
         let page_id = BTreePageID::new(PageCategory::Internal, self.table_id, page_index);
         let page =
             BTreeInternalPage::new(&page_id, &BTreeBasePage::empty_page_data(), &self.schema);
@@ -202,10 +382,14 @@ impl BTreeTable {
         let page_rc = Arc::new(RwLock::new(page));
         // insert to buffer pool because it's a dirty page at this
         // time
-        Database::mut_buffer_pool()
+        //
+        // (the page may be a free page reused, its cached copy is stale)
+        let buffer_pool = Database::buffer_pool();
+        buffer_pool.internal_buffer.remove(&page_id);
+        buffer_pool
             .internal_buffer
-            .insert(page_id, page_rc.clone());
-        page_rc
+            .insert(page_id, page_rc.clone())?;
+        Ok(page_rc)
     }
 
     pub fn write_empty_page_to_disk(&self, page_id: &BTreePageID) {
@@ -213,35 +397,83 @@ impl BTreeTable {
     }
 
     pub(crate) fn write_page_to_disk(&self, page_id: &BTreePageID, data: &Vec<u8>) {
-        let start_pos: usize = page_id.page_index as usize * BufferPool::get_page_size();
-        self.get_file()
-            .seek(SeekFrom::Start(start_pos as u64))
+        self.try_write_page_to_disk(page_id, data)
             .expect("io error");
-        self.get_file().write(&data).expect("io error");
-        self.get_file().flush().expect("io error");
+    }
+
+    /// Same as `write_page_to_disk`, but reports the io error to the caller.
+    pub(crate) fn try_write_page_to_disk(
+        &self,
+        page_id: &BTreePageID,
+        data: &Vec<u8>,
+    ) -> SmallResult {
+        let page_size = BufferPool::get_page_size();
+        let start_pos: usize = page_id.page_index as usize * page_size;
+
+        // the leaf pages of a compressed table only take the bytes they need,
+        // the rest of the page is a hole in the file
+        let compressed = if !self.compressed || page_id.category != PageCategory::Leaf {
+            None
+        } else if BTreeBasePage::is_empty_page(data) {
+            Some(data[0..4].to_vec())
+        } else {
+            BTreeBasePage::compress_page_data(data)
+        };
+
+        let mut file = self.get_file();
+        file.seek(SeekFrom::Start(start_pos as u64))?;
+        file.write_all(compressed.as_ref().unwrap_or(data))?;
+        if let Some(compressed) = &compressed {
+            let page_end = (start_pos + page_size) as u64;
+            let file_len = file.metadata()?.len();
+            if file_len < page_end {
+                file.set_len(page_end)?;
+            }
+
+            // free the blocks still holding a larger previous version of
+            // the page
+            let data_end = (start_pos + compressed.len()) as u64;
+            punch_hole(&file, data_end, page_end - data_end)?;
+        }
+        file.flush()?;
+        Ok(())
     }
 }
 
 impl BTreeTable {
     pub fn set_root_pid(&self, tx: &Transaction, root_pid: &BTreePageID) {
-        let root_pointer_rc = self.get_root_ptr_page(tx, Permission::ReadWrite);
-        root_pointer_rc.wl().set_root_pid(root_pid);
+        self.invalidate_leaf_cache();
+        // the latch on the root pointer page is released when the guard is
+        // dropped
+        let root_pointer = PageGuard::new(tx, self.get_root_ptr_page(tx, Permission::ReadWrite));
+        root_pointer.wl().set_root_pid(root_pid);
+    }
 
-        // release the latch on the root pointer page
-        let root_pointer_pid = root_pointer_rc.rl().get_pid();
-        Database::mut_concurrent_status()
-            .release_latch(tx, &root_pointer_pid)
-            .unwrap();
+    /// Get the leaf page with the given permission, the latch on the page is
+    /// released when the returned guard is dropped.
+    pub fn pin_leaf(
+        &self,
+        tx: &Transaction,
+        pid: &BTreePageID,
+        perm: Permission,
+    ) -> Result<PageGuard<BTreeLeafPage>, SmallError> {
+        let page_rc = BufferPool::get_leaf_page(tx, perm, pid)?;
+        Ok(PageGuard::new(tx, page_rc))
     }
 
-    pub(crate) fn set_parent(tx: &Transaction, child_pid: &BTreePageID, parent_pid: &BTreePageID) {
+    /// Point the parent of the internal or leaf page `child_pid` to
+    /// `parent_pid`, root pointer and header pages have no parent.
+    pub(crate) fn set_parent(
+        tx: &Transaction,
+        child_pid: &BTreePageID,
+        parent_pid: &BTreePageID,
+    ) -> SmallResult {
         match child_pid.category {
-            PageCategory::RootPointer => todo!(),
             PageCategory::Internal => {
                 log::info!("{} set parent: {:?} -> {:?}", tx, child_pid, parent_pid,);
 
                 let child_rc =
-                    BufferPool::get_internal_page(tx, Permission::ReadWrite, &child_pid).unwrap();
+                    BufferPool::get_internal_page(tx, Permission::ReadWrite, &child_pid)?;
 
                 // borrow of left_rc start here
                 {
@@ -250,13 +482,10 @@ impl BTreeTable {
                 }
                 // borrow of left_rc end here
 
-                Database::mut_concurrent_status()
-                    .release_latch(tx, &child_pid)
-                    .unwrap();
+                Database::mut_concurrent_status().release_latch(tx, &child_pid)?;
             }
             PageCategory::Leaf => {
-                let child_rc =
-                    BufferPool::get_leaf_page(tx, Permission::ReadWrite, &child_pid).unwrap();
+                let child_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, &child_pid)?;
 
                 // borrow of left_rc start here
                 {
@@ -265,12 +494,19 @@ impl BTreeTable {
                 }
                 // borrow of left_rc end here
 
-                Database::mut_concurrent_status()
-                    .release_latch(tx, &child_pid)
-                    .unwrap();
+                Database::mut_concurrent_status().release_latch(tx, &child_pid)?;
+            }
+            PageCategory::RootPointer | PageCategory::Header => {
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::Corruption,
+                    &format!(
+                        "page {:?} can't be the child of {:?}",
+                        child_pid, parent_pid
+                    ),
+                ));
             }
-            PageCategory::Header => todo!(),
         }
+        Ok(())
     }
 
     /// Finds and locks the leaf page in the B+ tree based on the search
@@ -298,7 +534,37 @@ impl BTreeTable {
         perm: Permission,
         pid: BTreePageID,
         search: &SearchFor,
-    ) -> Arc<RwLock<BTreeLeafPage>> {
+    ) -> ResultPod<BTreeLeafPage> {
+        Ok(self.find_leaf_page_with_bounds(tx, perm, pid, search)?.0)
+    }
+
+    /// Same as `find_leaf_page`, also return the range of the keys covered by
+    /// the leaf page according to the internal pages on the path: (exclusive
+    /// lower bound, inclusive upper bound), None means unbounded. The range
+    /// is only meaningful for `SearchFor::Target`.
+    pub(super) fn find_leaf_page_with_bounds(
+        &self,
+        tx: &Transaction,
+        perm: Permission,
+        pid: BTreePageID,
+        search: &SearchFor,
+    ) -> Result<LeafWithBounds, SmallError> {
+        self.descents.fetch_add(1, Ordering::Relaxed);
+
+        let mut bounds = (None, None);
+        let leaf_rc = self.descend(tx, perm, pid, search, &mut bounds, 0)?;
+        Ok((leaf_rc, bounds.0, bounds.1))
+    }
+
+    fn descend(
+        &self,
+        tx: &Transaction,
+        perm: Permission,
+        pid: BTreePageID,
+        search: &SearchFor,
+        bounds: &mut (Option<Cell>, Option<Cell>),
+        depth: usize,
+    ) -> Result<Arc<RwLock<BTreeLeafPage>>, SmallError> {
         match pid.category {
             PageCategory::Leaf => {
                 // return directly
@@ -306,25 +572,39 @@ impl BTreeTable {
                 if v.is_err() {
                     log::error!("error: {:?}", v);
                 }
-                return v.unwrap();
+                return v;
             }
             PageCategory::Internal => {
-                let page_rc =
-                    BufferPool::get_internal_page(tx, Permission::ReadOnly, &pid).unwrap();
+                self.check_depth(&pid, depth)?;
+
                 let mut child_pid: Option<BTreePageID> = None;
 
-                // borrow of page_rc start here
+                // the latch on the internal page is released at the end of
+                // this block
                 {
-                    let page = page_rc.rl();
+                    let page_guard = PageGuard::new(
+                        tx,
+                        BufferPool::get_internal_page(tx, Permission::ReadOnly, &pid)?,
+                    );
+                    let page = page_guard.rl();
                     let it = BTreeInternalPageIterator::new(&page);
                     let mut entry: Option<Entry> = None;
                     let mut found = false;
                     for e in it {
                         match search {
                             SearchFor::Target(cell) => {
-                                if &e.get_key() >= cell {
+                                if self.comparator.compare(&e.get_key(), cell)
+                                    != std::cmp::Ordering::Less
+                                {
                                     child_pid = Some(e.get_left_child());
                                     found = true;
+
+                                    // the keys of the child are in
+                                    // (previous key, key]
+                                    bounds.1 = Some(e.get_key());
+                                    if let Some(previous) = &entry {
+                                        bounds.0 = Some(previous.get_key());
+                                    }
                                     break;
                                 }
                             }
@@ -351,28 +631,23 @@ impl BTreeTable {
                         match entry {
                             Some(e) => {
                                 child_pid = Some(e.get_right_child());
+                                bounds.0 = Some(e.get_key());
                             }
-                            None => todo!(),
+                            None => return Err(empty_internal_page_error(&pid)),
                         }
                     }
                 }
-                // borrow of page_rc end here
-
-                Database::mut_concurrent_status()
-                    .release_latch(tx, &pid)
-                    .unwrap();
 
                 // search child page recursively
                 match child_pid {
                     Some(child_pid) => {
-                        return self.find_leaf_page(tx, perm, child_pid, search);
+                        return self.descend(tx, perm, child_pid, search, bounds, depth + 1);
                     }
-                    None => todo!(),
+                    // a child is always chosen once the page has an entry
+                    None => unreachable!(),
                 }
             }
-            _ => {
-                todo!()
-            }
+            PageCategory::RootPointer | PageCategory::Header => Err(unexpected_child_error(&pid)),
         }
     }
 
@@ -380,9 +655,15 @@ impl BTreeTable {
         self.file.lock().unwrap()
     }
 
+    /// Make the pages written to the table file durable.
+    pub(crate) fn sync_file(&self) -> SmallResult {
+        self.get_file().sync_all()?;
+        Ok(())
+    }
+
     /// Initialize the data file when the file is empty. Specifically, it
     /// writes the root pointer page and the an empty leaf page to the file.
-    fn file_init(&self) {
+    pub(super) fn file_init(&self) {
         let mut file = self.get_file();
         let table_index = self.get_id();
 
@@ -405,28 +686,54 @@ impl BTreeTable {
         }
     }
 
-    pub fn get_first_page(&self, tx: &Transaction, perm: Permission) -> Arc<RwLock<BTreeLeafPage>> {
+    /// Check that the leaf pages of the file are in the format this version
+    /// reads (see `LEAF_FORMAT_VERSION`), a file with varint fields written
+    /// before the format changed is rejected instead of being misread. The
+    /// layout of the fixed-width pages didn't change, any version is fine.
+    pub(crate) fn check_leaf_format(&self) -> SmallResult {
+        if self.schema.is_fixed_width() {
+            return Ok(());
+        }
+
+        let mut bytes = vec![0; BufferPool::get_page_size()];
+        {
+            let mut file = self.get_file();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut bytes)?;
+        }
+
+        let pid = BTreePageID::get_root_ptr_page_id(self.table_id);
+        let root_ptr = <BTreeRootPointerPage as BTreePage>::new(&pid, &bytes, &self.schema);
+        if root_ptr.get_leaf_format() != LEAF_FORMAT_VERSION {
+            let err_msg = format!(
+                "table {} has leaf pages of format {}, expect format {}",
+                self.name,
+                root_ptr.get_leaf_format(),
+                LEAF_FORMAT_VERSION,
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+        }
+        Ok(())
+    }
+
+    pub fn get_first_page(&self, tx: &Transaction, perm: Permission) -> ResultPod<BTreeLeafPage> {
         let page_id = self.get_root_pid(tx);
-        return self.find_leaf_page(tx, perm, page_id, &SearchFor::LeftMost);
+        self.find_leaf_page(tx, perm, page_id, &SearchFor::LeftMost)
     }
 
-    pub fn get_last_page(&self, tx: &Transaction, perm: Permission) -> Arc<RwLock<BTreeLeafPage>> {
+    pub fn get_last_page(&self, tx: &Transaction, perm: Permission) -> ResultPod<BTreeLeafPage> {
         let page_id = self.get_root_pid(tx);
-        return self.find_leaf_page(tx, perm, page_id, &SearchFor::RightMost);
+        self.find_leaf_page(tx, perm, page_id, &SearchFor::RightMost)
     }
 
     /// Get the root page pid.
     pub fn get_root_pid(&self, tx: &Transaction) -> BTreePageID {
-        let root_ptr_rc = self.get_root_ptr_page(tx, Permission::ReadOnly);
-        let mut root_pid = root_ptr_rc.rl().get_root_pid();
+        // the latch on the root pointer page is released when the guard is
+        // dropped
+        let root_ptr = PageGuard::new(tx, self.get_root_ptr_page(tx, Permission::ReadOnly));
+        let mut root_pid = root_ptr.rl().get_root_pid();
         root_pid.table_id = self.get_id();
 
-        // release the latch on the root pointer page
-        let root_pointer_pid = root_ptr_rc.rl().get_pid();
-        Database::mut_concurrent_status()
-            .release_latch(tx, &root_pointer_pid)
-            .unwrap();
-
         root_pid
     }
 
@@ -439,7 +746,7 @@ impl BTreeTable {
         BufferPool::get_root_ptr_page(tx, perm, &root_ptr_pid).unwrap()
     }
 
-    pub(crate) fn get_header_pages(&self, tx: &Transaction) -> HeaderPages {
+    pub(crate) fn get_header_pages(&self, tx: &Transaction) -> Result<HeaderPages, SmallError> {
         HeaderPages::new(self, tx)
     }
 
@@ -451,47 +758,312 @@ impl BTreeTable {
         file_size / BufferPool::get_page_size() - 1
     }
 
-    // get the first tuple under the internal/leaf page
-    pub fn get_first_tuple(&self, _pid: &BTreePageID) -> Option<Tuple> {
-        todo!()
+    /// Get the first tuple under the internal/leaf page `pid`, None if the
+    /// left-most leaf page under it is empty.
+    pub fn get_first_tuple(
+        &self,
+        tx: &Transaction,
+        pid: &BTreePageID,
+    ) -> Result<Option<Tuple>, SmallError> {
+        let page_rc = self.find_leaf_page(tx, Permission::ReadOnly, *pid, &SearchFor::LeftMost)?;
+        let page = PageGuard::new(tx, page_rc);
+        let tuple = BTreeLeafPageIterator::new(&page.rl()).next();
+        Ok(tuple.map(|t| t.get_tuple().clone()))
+    }
+
+    /// Drop all cached state of the table and read it from disk again, used
+    /// after the table file is modified externally (e.g. by another process
+    /// or handle).
+    ///
+    /// All pages of the table are discarded from the buffer pool, so the root
+    /// pointer page is read from disk on the next access. The table should
+    /// not be used by any active transaction, otherwise its uncommitted
+    /// changes are lost.
+    pub fn reload(&self) {
+        Database::mut_buffer_pool().discard_table_pages(self.table_id);
+        self.invalidate_leaf_cache();
+
+        // the last page of the file may be changed as well
+        self.set_page_index(self.pages_count() as u32);
+
+        let tx = Transaction::new();
+        if let Err(e) = self.rebuild_bloom_filter(&tx) {
+            log::error!("failed to rebuild the bloom filter: {}", e);
+        }
+        tx.commit().unwrap();
+    }
+
+    /// Set the fraction of the tuples kept in the left page when a leaf page
+    /// is split, e.g. 0.9 keeps the left pages nearly full under ascending
+    /// inserts.
+    ///
+    /// Panics if `ratio` is not in the range (0, 1).
+    pub fn set_split_ratio(&mut self, ratio: f64) {
+        if !(ratio > 0.0 && ratio < 1.0) {
+            panic!("split ratio must be in (0, 1), got {}", ratio);
+        }
+        self.split_ratio = ratio;
+    }
+
+    pub fn get_split_ratio(&self) -> f64 {
+        self.split_ratio
+    }
+
+    /// Order the tuples with equal keys by the natural ordering of `field`,
+    /// so the scans return them deterministically.
+    ///
+    /// Like the comparator, the setting is not persisted, and only the
+    /// tuples inserted (or moved by splits and merges) after the call are
+    /// placed by it.
+    ///
+    /// Panics if `field` is the key field or is out of the schema.
+    pub fn set_secondary_sort(&mut self, field: Option<usize>) {
+        if let Some(field) = field {
+            if field == self.key_field || field >= self.schema.get_fields().len() {
+                panic!("invalid secondary sort field: {}", field);
+            }
+        }
+        self.secondary_field = field;
+    }
+
+    pub fn get_secondary_sort(&self) -> Option<usize> {
+        self.secondary_field
+    }
+
+    /// Retain the deleted tuples as tombstones (for change-data-capture and
+    /// time-travel reads), they are only reclaimed by `purge_tombstones`.
+    /// The setting is persisted in the catalog.
+    pub fn set_retain_tombstones(&mut self, retain: bool) -> SmallResult {
+        self.retain_tombstones = retain;
+        Catalog::write_table_options(self)
+    }
+
+    pub fn get_retain_tombstones(&self) -> bool {
+        self.retain_tombstones
+    }
+
+    /// Enable or disable the merging of the pages which become less than
+    /// half full on delete, see `rebalance`.
+    pub fn set_auto_merge(&mut self, auto_merge: bool) {
+        self.auto_merge = auto_merge;
+    }
+
+    pub fn get_auto_merge(&self) -> bool {
+        self.auto_merge
+    }
+
+    /// Store the leaf pages deflated on disk, for cold or archival tables,
+    /// trading CPU on every page read and write for disk space.
+    ///
+    /// The pages are kept uncompressed in the buffer pool, so the number of
+    /// tuples a leaf page holds is the same as in an uncompressed table. A
+    /// page keeps its place in the file (at `page_index * page_size`), only
+    /// the deflated bytes are written and the rest of the page is left as
+    /// a hole, which saves the disk blocks the page doesn't fill.
+    ///
+    /// The setting is persisted in the catalog. The compressed pages are
+    /// recognized on read either way, only the pages written after the call
+    /// are compressed.
+    pub fn set_compressed(&mut self, compressed: bool) -> SmallResult {
+        self.compressed = compressed;
+        Catalog::write_table_options(self)
+    }
+
+    pub fn get_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Get the settings of the table which are persisted in the catalog.
+    pub(crate) fn get_options(&self) -> TableOptions {
+        TableOptions {
+            bloom_filter: self.bloom_filter_params(),
+            max_fan_out: self.max_fan_out,
+            compressed: self.compressed,
+            retain_tombstones: self.retain_tombstones,
+            // the indexes are known by the catalog, see
+            // `Catalog::write_table_options`
+            index_of: None,
+        }
+    }
+
+    /// Cap the number of children of the internal pages below what a page
+    /// can hold, e.g. to study the height of the tree against the size of
+    /// the nodes. Only the internal pages split after the call honor the cap.
+    /// The cap is persisted in the catalog.
+    ///
+    /// Panics if `fan_out` is less than 3.
+    pub fn set_max_fan_out(&mut self, fan_out: Option<usize>) -> SmallResult {
+        if let Some(fan_out) = fan_out {
+            if fan_out < 3 {
+                panic!("fan-out must be at least 3, got {}", fan_out);
+            }
+        }
+        self.max_fan_out = fan_out;
+        Catalog::write_table_options(self)
+    }
+
+    /// Get the maximum number of children of an internal page, which is the
+    /// capacity of the page unless it's capped by `set_max_fan_out`.
+    pub fn get_fan_out(&self) -> usize {
+        let cap = BTreeInternalPage::get_children_cap(&self.schema);
+        match self.max_fan_out {
+            Some(fan_out) => fan_out.min(cap),
+            None => cap,
+        }
+    }
+
+    /// Whether a LIKE pattern on the key with a fixed prefix is served by a
+    /// seek to the prefix, see `KeyComparator::orders_bytes_naturally`.
+    pub fn seeks_prefixes(&self) -> bool {
+        self.comparator.orders_bytes_naturally()
+    }
+
+    /// Compare two keys by the ordering of the table, see `KeyComparator`.
+    pub fn compare_keys(&self, a: &Cell, b: &Cell) -> std::cmp::Ordering {
+        self.comparator.compare(a, b)
+    }
+
+    /// Replace the schema of the table. The tuples are not rewritten, so the
+    /// table must be empty and its cached pages discarded.
+    pub(crate) fn set_schema(&mut self, schema: &TableSchema) {
+        self.schema = schema.clone();
+        self.key_field = schema.get_key_pos();
     }
 
     pub fn set_page_index(&self, i: u32) {
         self.page_index.store(i, Ordering::Relaxed);
     }
 
+    /// Take the page index following the last page of the file, fails with
+    /// `PageIndexExhausted` instead of wrapping around when the last page
+    /// has the largest page index.
+    pub(crate) fn next_page_index(&self) -> Result<u32, SmallError> {
+        self.page_index
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |i| i.checked_add(1))
+            .map(|i| i + 1)
+            .or(Err(page_index_exhausted()))
+    }
+
+    /// Return a `TreeTooDeep` error if a path from the root reaches an
+    /// internal page at `depth`, while a tree with the pages of the table is
+    /// not that high. Keeps a corrupt tree (e.g. a cycle of internal pages)
+    /// from recursing until the stack overflows.
+    ///
+    /// The internal pages have about half of the fan-out children at least,
+    /// so the height is about the logarithm of the number of pages in this
+    /// base.
+    pub(super) fn check_depth(&self, pid: &BTreePageID, depth: usize) -> SmallResult {
+        // the index of the last page allocated is cheaper to get than the
+        // size of the file, but it's not restored when the table is opened
+        let pages = self.page_index.load(Ordering::Relaxed) as usize;
+        if depth <= self.max_depth(pages) {
+            return Ok(());
+        }
+        let pages = pages.max(self.pages_count());
+        if depth <= self.max_depth(pages) {
+            return Ok(());
+        }
+
+        let err_msg = format!(
+            "page {:?} is at depth {}, the table {} has only {} pages",
+            pid, depth, self.name, pages,
+        );
+        Err(SmallError::with_kind(SmallErrorKind::TreeTooDeep, &err_msg))
+    }
+
+    fn max_depth(&self, pages: usize) -> usize {
+        // a split leaves at least half of the children in each page, rounded
+        // down on the left, but a fan-out of 3 may leave a single child there
+        // and the levels don't have to shrink, the pages are the only bound
+        let min_children = (self.get_fan_out() - 1).div_ceil(2);
+        if min_children < 2 {
+            return pages + DEPTH_SLACK;
+        }
+
+        let mut depth = DEPTH_SLACK;
+        let mut capacity: usize = 1;
+        while capacity < pages {
+            capacity = capacity.saturating_mul(min_children) + 1;
+            depth += 1;
+        }
+        depth
+    }
+
     // get the last tuple under the internal/leaf page
-    pub fn get_last_tuple(&self, tx: &Transaction, pid: &BTreePageID) -> Option<WrappedTuple> {
+    pub fn get_last_tuple(
+        &self,
+        tx: &Transaction,
+        pid: &BTreePageID,
+    ) -> Result<Option<WrappedTuple>, SmallError> {
+        self.get_last_tuple_at(tx, pid, 0)
+    }
+
+    fn get_last_tuple_at(
+        &self,
+        tx: &Transaction,
+        pid: &BTreePageID,
+        depth: usize,
+    ) -> Result<Option<WrappedTuple>, SmallError> {
         match pid.category {
-            PageCategory::RootPointer => todo!(),
             PageCategory::Internal => {
-                let page_rc = BufferPool::get_internal_page(tx, Permission::ReadOnly, pid).unwrap();
+                self.check_depth(pid, depth)?;
+
+                let page_rc = BufferPool::get_internal_page(tx, Permission::ReadOnly, pid)?;
 
                 // borrow of page_rc start here
                 let child_pid: BTreePageID;
                 {
                     let page = page_rc.rl();
                     let mut it = BTreeInternalPageIterator::new(&page);
-                    child_pid = it.next_back().unwrap().get_right_child();
+                    child_pid = match it.next_back() {
+                        Some(e) => e.get_right_child(),
+                        None => return Err(empty_internal_page_error(pid)),
+                    };
                 }
                 // borrow of page_rc end here
-                self.get_last_tuple(tx, &child_pid)
+                self.get_last_tuple_at(tx, &child_pid, depth + 1)
             }
             PageCategory::Leaf => {
-                let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadWrite, pid).unwrap();
+                let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, pid)?;
 
                 let page = page_rc.rl();
                 let mut it = BTreeLeafPageIterator::new(&page);
-                it.next_back()
+                Ok(it.next_back())
             }
-            PageCategory::Header => todo!(),
+            PageCategory::RootPointer | PageCategory::Header => Err(unexpected_child_error(pid)),
         }
     }
 }
 
+/// An internal page without any entry doesn't lead to any child, it only
+/// appears in a corrupt tree.
+fn empty_internal_page_error(pid: &BTreePageID) -> SmallError {
+    let err_msg = format!("internal page {:?} has no entry", pid);
+    SmallError::with_kind(SmallErrorKind::Corruption, &err_msg)
+}
+
+/// Only internal and leaf pages can be reached from the root.
+fn unexpected_child_error(pid: &BTreePageID) -> SmallError {
+    let err_msg = format!("page {:?} is reached as a node of the tree", pid);
+    SmallError::with_kind(SmallErrorKind::Corruption, &err_msg)
+}
+
 /// debug methods
 impl BTreeTable {
-    /// Print the BTreeFile structure.
+    /// Print the BTreeFile structure to the debug log, see
+    /// `BTreeTable::render_tree` for the arguments.
+    pub fn draw_tree(&self, max_level: i64) {
+        let depiction = self.render_tree(max_level);
+        debug!(
+            "[{}] tree_structure, level {}: {}",
+            get_caller(),
+            max_level,
+            depiction
+        );
+    }
+
+    /// Render the BTreeFile structure as a string, one line per page, no
+    /// matter what the log level is.
     ///
     /// # Arguments
     ///
@@ -501,7 +1073,7 @@ impl BTreeTable {
     ///       leaf)
     ///     - ...
     ///     - -1: print all pages
-    pub fn draw_tree(&self, max_level: i64) {
+    pub fn render_tree(&self, max_level: i64) -> String {
         Database::mut_concurrent_status().clear();
 
         let tx = Transaction::new();
@@ -523,13 +1095,8 @@ impl BTreeTable {
 
         depiction.push_str(&format!("\n\n----- PRINT TREE STRUCTURE END   -----\n\n"));
 
-        debug!(
-            "[{}] tree_structure, level {}: {}",
-            get_caller(),
-            max_level,
-            depiction
-        );
         tx.commit().unwrap();
+        depiction
     }
 
     fn draw_subtree(
@@ -656,7 +1223,11 @@ impl BTreeTable {
                 max_level,
             ));
         }
-        depiction.push_str(&format!("{}├── key: {:?}\n", prefix, entry.get_key()));
+        depiction.push_str(&format!(
+            "{}├── key: {}\n",
+            prefix,
+            entry.get_key().to_display_string_with(true)
+        ));
         depiction.push_str(&self.draw_subtree(tx, &entry.get_right_child(), level + 1, max_level));
 
         return depiction;
@@ -683,6 +1254,13 @@ impl BTreeTable {
         }
     }
 
+    /// Same as `check_integrity`, but the first error found is returned
+    /// instead of panicking, e.g. a `TreeTooDeep` or a `Corruption` error
+    /// for a malformed tree.
+    pub fn try_check_integrity(&self) -> SmallResult {
+        self.check_integrity_inner(true)
+    }
+
     fn check_integrity_inner(&self, check_occupancy: bool) -> SmallResult {
         Database::mut_concurrent_status().clear();
 
@@ -703,7 +1281,7 @@ impl BTreeTable {
             // the "root_summary" stands for the whole tree, so the left pointer should be
             // none.
             let err_msg = format!("root left pointer is not none: {:?}", root_summary.left_ptr);
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         }
         if root_summary.right_ptr.is_some() {
             // the "root_summary" stands for the whole tree, so the right pointer should be
@@ -712,7 +1290,7 @@ impl BTreeTable {
                 "root right pointer is not none: {:?}",
                 root_summary.right_ptr
             );
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         }
 
         tx.commit().unwrap();
@@ -720,6 +1298,202 @@ impl BTreeTable {
         Ok(())
     }
 
+    /// Cross-check the free space bookkeeping in the header pages against
+    /// the pages actually used by the tree.
+    ///
+    /// Return all pages whose header slot is marked as occupied while the
+    /// page is not in the tree, and vice versa. An empty result means the
+    /// header pages are consistent with the tree. Fails if the header pages
+    /// can't be read.
+    pub fn verify_free_space(
+        &self,
+        tx: &Transaction,
+    ) -> Result<Vec<FreeSpaceDiscrepancy>, SmallError> {
+        // step 1: collect all live pages
+        let mut live_pages = HashSet::new();
+
+        // the root pointer page
+        live_pages.insert(0);
+
+        let root_pid = self.get_root_pid(tx);
+        for pid in self.collect_tree_pids(tx, &root_pid)? {
+            live_pages.insert(pid.page_index);
+        }
+
+        let header_pages = self.get_header_pages(tx)?;
+        for pid in header_pages.get_pids() {
+            live_pages.insert(pid.page_index);
+        }
+        let slots_status = header_pages.get_slots_status();
+        header_pages.release_latches();
+
+        // step 2: compare the header slots with the live pages
+        let mut discrepancies = Vec::new();
+        for (i, marked_used) in slots_status.iter().enumerate() {
+            let page_index = i as u32;
+            let in_tree = live_pages.contains(&page_index);
+            if *marked_used != in_tree {
+                discrepancies.push(FreeSpaceDiscrepancy {
+                    page_index,
+                    marked_used: *marked_used,
+                    in_tree,
+                });
+            }
+        }
+
+        // live pages which are not covered by the header pages at all
+        for page_index in live_pages {
+            if page_index as usize >= slots_status.len() {
+                discrepancies.push(FreeSpaceDiscrepancy {
+                    page_index,
+                    marked_used: false,
+                    in_tree: true,
+                });
+            }
+        }
+
+        Ok(discrepancies)
+    }
+
+    /// Walk the leaf chain right from the first leaf and back left from the
+    /// last leaf, checking that the sibling pointers of the neighbour leaves
+    /// point to each other and that each walk visits every leaf of the tree
+    /// exactly once, in key order.
+    ///
+    /// Return a `Corruption` error describing the first inconsistency found.
+    pub fn verify_leaf_chain(&self, tx: &Transaction) -> SmallResult {
+        let root_pid = self.get_root_pid(tx);
+        let leaves: Vec<BTreePageID> = self
+            .collect_tree_pids(tx, &root_pid)?
+            .into_iter()
+            .filter(|pid| pid.category == PageCategory::Leaf)
+            .collect();
+
+        let corruption = |msg: String| SmallError::with_kind(SmallErrorKind::Corruption, &msg);
+
+        // get the (left, right) pointers of a leaf
+        let get_siblings = |pid: &BTreePageID| -> Result<_, SmallError> {
+            let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, pid)?;
+            let siblings = {
+                let page = page_rc.rl();
+                (page.get_left_pid(), page.get_right_pid())
+            };
+            Database::mut_concurrent_status().release_latch(tx, pid)?;
+            Ok(siblings)
+        };
+
+        for (forward, expected) in [
+            (true, leaves.clone()),
+            (false, leaves.iter().rev().cloned().collect()),
+        ]
+        .iter()
+        {
+            let direction = if *forward { "right" } else { "left" };
+
+            let mut walked: Vec<BTreePageID> = Vec::new();
+            let mut previous: Option<BTreePageID> = None;
+            let mut current = expected.first().cloned();
+            while let Some(pid) = current {
+                // a walk longer than the leaves is stuck in a cycle
+                if walked.len() >= expected.len() || walked.contains(&pid) {
+                    return Err(corruption(format!(
+                        "the {} walk of the leaf chain visits {:?} twice or more than {} leaves",
+                        direction,
+                        pid,
+                        expected.len(),
+                    )));
+                }
+
+                let (left, right) = get_siblings(&pid)?;
+                let (back, next) = if *forward {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                if back != previous {
+                    return Err(corruption(format!(
+                        "the {} walk of the leaf chain reaches {:?} from {:?}, but it points back to {:?}",
+                        direction, pid, previous, back,
+                    )));
+                }
+
+                walked.push(pid);
+                previous = Some(pid);
+                current = next;
+            }
+
+            if &walked != expected {
+                return Err(corruption(format!(
+                    "the {} walk of the leaf chain visits {:?}, expect {:?}",
+                    direction, walked, expected,
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the ids of all live pages of the table: the root pointer page,
+    /// the header pages, and the internal and leaf pages of the tree. Free
+    /// pages (released by merges) are not included.
+    ///
+    /// The header pages are not created if the table has none yet. Fails if
+    /// a page can't be read.
+    pub fn page_ids(&self, tx: &Transaction) -> Result<Vec<BTreePageID>, SmallError> {
+        let root_ptr_pid = BTreePageID::get_root_ptr_page_id(self.table_id);
+        let mut pids = vec![root_ptr_pid];
+
+        let root_ptr_rc = self.get_root_ptr_page(tx, Permission::ReadOnly);
+        let mut header_pid = root_ptr_rc.rl().get_header_pid();
+        Database::mut_concurrent_status().release_latch(tx, &root_ptr_pid)?;
+
+        while let Some(pid) = header_pid {
+            let page_rc = BufferPool::get_header_page(tx, Permission::ReadOnly, &pid)?;
+            header_pid = page_rc.rl().get_next_pid();
+            Database::mut_concurrent_status().release_latch(tx, &pid)?;
+            pids.push(pid);
+        }
+
+        let root_pid = self.get_root_pid(tx);
+        pids.extend(self.collect_tree_pids(tx, &root_pid)?);
+        Ok(pids)
+    }
+
+    /// Collect the ids of all internal and leaf pages under the given page
+    /// (the page itself included).
+    pub(crate) fn collect_tree_pids(
+        &self,
+        tx: &Transaction,
+        pid: &BTreePageID,
+    ) -> Result<Vec<BTreePageID>, SmallError> {
+        let mut pids = vec![*pid];
+
+        if pid.category == PageCategory::Internal {
+            let mut children = Vec::new();
+
+            let page_rc = BufferPool::get_internal_page(tx, Permission::ReadOnly, pid)?;
+            // borrow of page_rc start here
+            {
+                let page = page_rc.rl();
+                for (i, entry) in BTreeInternalPageIterator::new(&page).enumerate() {
+                    if i == 0 {
+                        children.push(entry.get_left_child());
+                    }
+                    children.push(entry.get_right_child());
+                }
+            }
+            // borrow of page_rc end here
+
+            Database::mut_concurrent_status().release_latch(tx, pid)?;
+
+            for child_pid in children {
+                pids.extend(self.collect_tree_pids(tx, &child_pid)?);
+            }
+        }
+
+        Ok(pids)
+    }
+
     /// panic on any error found.
     fn check_sub_tree(
         &self,
@@ -733,9 +1507,16 @@ impl BTreeTable {
     ) -> Result<SubtreeSummary, SmallError> {
         match pid.category {
             PageCategory::Leaf => {
-                let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &pid).unwrap();
+                let page_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &pid)?;
                 let page = page_rc.rl();
-                page.check_integrity(parent_pid, lower_bound, upper_bound, check_occupancy, depth)?;
+                page.check_integrity(
+                    parent_pid,
+                    lower_bound,
+                    upper_bound,
+                    check_occupancy,
+                    depth,
+                    self.comparator.as_ref(),
+                )?;
 
                 let summary = SubtreeSummary {
                     left_ptr: page.get_left_pid(),
@@ -750,15 +1531,18 @@ impl BTreeTable {
             }
 
             PageCategory::Internal => {
-                let page_rc =
-                    BufferPool::get_internal_page(tx, Permission::ReadOnly, &pid).unwrap();
+                self.check_depth(pid, depth)?;
+
+                let page_rc = BufferPool::get_internal_page(tx, Permission::ReadOnly, &pid)?;
                 let page = page_rc.rl();
+
                 page.check_integrity(
                     parent_pid,
                     &lower_bound,
                     &upper_bound,
-                    check_occupancy,
+                    check_occupancy.then(|| self.get_fan_out()),
                     depth,
+                    self.comparator.as_ref(),
                 )?;
 
                 let it = BTreeInternalPageIterator::new(&page);
@@ -791,9 +1575,16 @@ impl BTreeTable {
                     last_entry = Some(entry);
                 }
 
+                let last_entry = match last_entry {
+                    Some(entry) => entry,
+                    None => {
+                        let err_msg = format!("internal page {:?} has no entry", pid);
+                        return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+                    }
+                };
                 let last_right_summary = self.check_sub_tree(
                     tx,
-                    &last_entry.unwrap().get_right_child(),
+                    &last_entry.get_right_child(),
                     pid,
                     &child_lower_bound,
                     upper_bound,
@@ -813,7 +1604,10 @@ impl BTreeTable {
             }
 
             // no other page types allowed inside the tree.
-            _ => panic!("invalid page category"),
+            _ => {
+                let err_msg = format!("page {:?} can't be inside the tree", pid);
+                Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg))
+            }
         }
     }
 }
@@ -834,7 +1628,7 @@ impl SubtreeSummary {
         if self.depth != right.depth {
             // the depth of the two subtrees should be the same
             let err_msg = format!("depth mismatch: {} != {}", self.depth, right.depth,);
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         }
 
         assert_eq!(self.depth, right.depth);
@@ -846,7 +1640,7 @@ impl SubtreeSummary {
                 "right pointer mismatch: {:?} != {:?}",
                 self.right_ptr, right.left_most_pid,
             );
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         }
 
         if self.right_most_pid != right.left_ptr {
@@ -856,7 +1650,7 @@ impl SubtreeSummary {
                 "rightmost page mismatch: {:?} != {:?}",
                 self.right_most_pid, right.left_ptr,
             );
-            return Err(SmallError::new(&err_msg));
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
         }
 
         // merge the two summaries
@@ -1,20 +1,120 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
 
-use super::SearchFor;
+use super::{KeyComparator, SearchFor};
 use crate::{
     btree::{
         buffer_pool::BufferPool,
-        page::{BTreeLeafPage, BTreeLeafPageIteratorRc, BTreePage},
+        page::{
+            BTreeLeafPage, BTreeLeafPageIterator, BTreeLeafPageIteratorRc, BTreePage, BTreePageID,
+        },
     },
-    storage::tuple::WrappedTuple,
-    transaction::{Permission, Transaction},
-    utils::HandyRwLock,
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::{Cell, FromCell, Tuple, WrappedTuple},
+    transaction::{Permission, Snapshot, Transaction},
+    types::SmallResult,
+    utils::{CancellationToken, HandyRwLock},
     BTreeTable, Database, Op, Predicate,
 };
 
+/// How many tuples a scan yields between two checks of its cancellation
+/// token.
+const CANCEL_CHECK_INTERVAL: usize = 64;
+
 impl BTreeTable {
+    /// Panics if the first or the last leaf page can't be found, see
+    /// `BTreeTableIterator::new`.
     pub fn iter(&self, tx: &Transaction) -> BTreeTableIterator {
         BTreeTableIterator::new(tx, self)
+            .unwrap_or_else(|e| panic!("failed to scan the table: {}", e))
+    }
+
+    /// Scan the table in key order and yield the tuples in batches of
+    /// `batch_size`, for consumers processing tuples in a vectorized way. The
+    /// last batch may be partial.
+    pub fn scan_batches(
+        &self,
+        tx: &Transaction,
+        batch_size: usize,
+    ) -> impl Iterator<Item = Vec<WrappedTuple>> {
+        if batch_size == 0 {
+            panic!("batch size must be positive");
+        }
+
+        let mut it = self.iter(tx);
+        std::iter::from_fn(move || {
+            let batch: Vec<WrappedTuple> = it.by_ref().take(batch_size).collect();
+            if batch.is_empty() {
+                return None;
+            }
+            Some(batch)
+        })
+    }
+
+    /// Scan the table in key order and yield the tuples accepted by `f`.
+    ///
+    /// `f` is evaluated on the tuples in place, the rejected ones are never
+    /// copied out of the pages.
+    ///
+    /// Panics if the scan fails, like the `Iterator` interface of
+    /// `BTreeTableIterator`.
+    pub fn scan_filter<F: FnMut(&Tuple) -> bool>(
+        &self,
+        tx: &Transaction,
+        mut f: F,
+    ) -> impl Iterator<Item = WrappedTuple> {
+        let mut it = self.iter(tx);
+        std::iter::from_fn(move || {
+            it.next_matching_inner(&mut f)
+                .unwrap_or_else(|e| panic!("failed to scan the table: {}", e))
+        })
+    }
+
+    /// Iterate over the tuples whose key is in the range `(lower, upper)`,
+    /// the iterator can be consumed from both ends. Fails if the leaf pages
+    /// of the bounds can't be found.
+    pub fn range(
+        &self,
+        tx: &Transaction,
+        lower: Bound<Cell>,
+        upper: Bound<Cell>,
+    ) -> Result<BTreeTableRangeIterator, SmallError> {
+        BTreeTableRangeIterator::new(tx, self, lower, upper)
+    }
+
+    /// Scan the table in key order and yield the value of column `col`
+    /// converted to `T`. Only the cells of the column are decoded from the
+    /// leaf pages, see `scan_cells`.
+    ///
+    /// Panics if `col` is out of range, if a cell cannot be converted to
+    /// `T`, or if the scan fails.
+    pub fn scan_column<'a, T: FromCell>(
+        &'a self,
+        tx: &'a Transaction,
+        col: usize,
+    ) -> impl Iterator<Item = T> + 'a {
+        let fields_count = self.schema.get_fields().len();
+        if col >= fields_count {
+            panic!(
+                "column index out of range, table: {}, column: {}, fields count: {}",
+                self.name, col, fields_count,
+            );
+        }
+
+        self.scan_cells(tx, col).map(move |cell| {
+            let cell = cell.unwrap_or_else(|e| panic!("failed to scan the table: {}", e));
+            match T::from_cell(&cell) {
+                Ok(v) => v,
+                Err(e) => panic!(
+                    "type mismatch on column {} of table {}: {}, cell: {:?}",
+                    col, self.name, e, cell,
+                ),
+            }
+        })
     }
 }
 
@@ -26,14 +126,32 @@ pub struct BTreeTableIterator {
 
     last_page_rc: Arc<RwLock<BTreeLeafPage>>,
     last_page_it: BTreeLeafPageIteratorRc,
+
+    cancel_token: Option<CancellationToken>,
+
+    // number of tuples yielded so far, used to decide when to check the
+    // cancellation token
+    yielded: usize,
+
+    // leaf pages entered by each end, a page entered twice means the sibling
+    // pointers of a corrupt tree form a cycle
+    visited: HashSet<BTreePageID>,
+    visited_back: HashSet<BTreePageID>,
 }
 
 impl BTreeTableIterator {
-    pub fn new(tx: &Transaction, table: &BTreeTable) -> Self {
-        let page_rc = table.get_first_page(&tx, Permission::ReadOnly);
-        let last_page_rc = table.get_last_page(&tx, Permission::ReadOnly);
+    /// Fails if the first or the last leaf page can't be found (e.g. a
+    /// malformed internal page on the way).
+    pub fn new(tx: &Transaction, table: &BTreeTable) -> Result<Self, SmallError> {
+        let page_rc = table.get_first_page(tx, Permission::ReadOnly)?;
+        let last_page_rc = table.get_last_page(tx, Permission::ReadOnly)?;
 
-        Self {
+        let mut visited = HashSet::new();
+        visited.insert(page_rc.rl().get_pid());
+        let mut visited_back = HashSet::new();
+        visited_back.insert(last_page_rc.rl().get_pid());
+
+        Ok(Self {
             tx: tx.clone(),
 
             page_rc: Arc::clone(&page_rc),
@@ -41,60 +159,427 @@ impl BTreeTableIterator {
 
             last_page_rc: Arc::clone(&last_page_rc),
             last_page_it: BTreeLeafPageIteratorRc::new(tx, Arc::clone(&last_page_rc)),
+
+            cancel_token: None,
+            yielded: 0,
+
+            visited,
+            visited_back,
+        })
+    }
+
+    /// Make the scan stop once `token` is tripped.
+    ///
+    /// `try_next` returns a `Cancelled` error after that, while the
+    /// `Iterator` interface panics.
+    pub fn with_cancel_token(mut self, token: &CancellationToken) -> Self {
+        self.cancel_token = Some(token.clone());
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Same as `next`, but reports the cancellation and a cycle in the
+    /// sibling pointers (`CorruptSiblingChain`) as errors.
+    pub fn try_next(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        self.check_cancel()?;
+        self.next_inner()
+    }
+
+    /// Same as `next_back`, but reports the cancellation and a cycle in the
+    /// sibling pointers (`CorruptSiblingChain`) as errors.
+    pub fn try_next_back(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        self.check_cancel()?;
+        self.next_back_inner()
+    }
+
+    fn check_cancel(&mut self) -> SmallResult {
+        if let Some(token) = &self.cancel_token {
+            if self.yielded.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+                token.check()?;
+            }
         }
+        self.yielded += 1;
+        Ok(())
     }
-}
 
-impl Iterator for BTreeTableIterator {
-    type Item = WrappedTuple;
+    fn next_inner(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        self.next_matching_inner(&mut |_| true)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn next_matching_inner<F: FnMut(&Tuple) -> bool>(
+        &mut self,
+        f: &mut F,
+    ) -> Result<Option<WrappedTuple>, SmallError> {
         loop {
-            let v = self.page_it.next();
+            let v = self.page_it.next_matching(f);
             if !v.is_none() {
-                return v;
+                return Ok(v);
             }
 
             // The current page is exhausted, move to the its right sibling.
             let right = self.page_rc.rl().get_right_pid();
             if let Some(right) = right {
-                let sibling_rc =
-                    BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &right).unwrap();
+                if !self.visited.insert(right) {
+                    return Err(sibling_cycle_error(&right));
+                }
+
+                let window = self.tx.record_sequential_read(&right);
+
+                let sibling_rc = BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &right)?;
                 let page_it = BTreeLeafPageIteratorRc::new(&self.tx, Arc::clone(&sibling_rc));
 
+                // load the next pages of the scan ahead
+                let next = sibling_rc.rl().get_right_pid();
+                if let Some(next) = next {
+                    BufferPool::prefetch_leaf_pages(&self.tx, &next, window)?;
+                }
+
                 self.page_rc = Arc::clone(&sibling_rc);
                 self.page_it = page_it;
                 continue;
             } else {
-                return None;
+                return Ok(None);
             }
         }
     }
-}
 
-impl DoubleEndedIterator for BTreeTableIterator {
-    fn next_back(&mut self) -> Option<Self::Item> {
+    fn next_back_inner(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
         let v = self.last_page_it.next_back();
         if !v.is_none() {
-            return v;
+            return Ok(v);
         }
 
         let left = self.last_page_rc.rl().get_left_pid();
         match left {
             Some(left) => {
-                let sibling_rc =
-                    BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &left).unwrap();
+                if !self.visited_back.insert(left) {
+                    return Err(sibling_cycle_error(&left));
+                }
+
+                let sibling_rc = BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &left)?;
                 let page_it = BTreeLeafPageIteratorRc::new(&self.tx, Arc::clone(&sibling_rc));
 
                 self.last_page_rc = Arc::clone(&sibling_rc);
                 self.last_page_it = page_it;
-                return self.last_page_it.next_back();
+                return Ok(self.last_page_it.next_back());
             }
             None => {
-                return None;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+fn sibling_cycle_error(pid: &BTreePageID) -> SmallError {
+    let err_msg = format!(
+        "leaf page {:?} is reached twice through the sibling pointers",
+        pid
+    );
+    SmallError::with_kind(SmallErrorKind::CorruptSiblingChain, &err_msg)
+}
+
+impl Iterator for BTreeTableIterator {
+    type Item = WrappedTuple;
+
+    /// Panics on the errors reported by `try_next` (e.g. the cancellation),
+    /// use `try_next` to handle them.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("failed to scan the table: {}", e))
+    }
+}
+
+impl DoubleEndedIterator for BTreeTableIterator {
+    /// Panics on the errors reported by `try_next_back`.
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.try_next_back()
+            .unwrap_or_else(|e| panic!("failed to scan the table: {}", e))
+    }
+}
+
+/// An iterator over the tuples whose key is in a range, in key order.
+///
+/// Both ends respect both bounds, so the range can be consumed from either
+/// end (e.g. `key BETWEEN a AND b ORDER BY key DESC`). The two cursors never
+/// cross, every tuple is returned at most once.
+pub struct BTreeTableRangeIterator {
+    tx: Transaction,
+    key_field: usize,
+    comparator: Arc<dyn KeyComparator>,
+
+    lower: Bound<Cell>,
+    upper: Bound<Cell>,
+
+    page_rc: Arc<RwLock<BTreeLeafPage>>,
+    page_it: BTreeLeafPageIteratorRc,
+
+    last_page_rc: Arc<RwLock<BTreeLeafPage>>,
+    last_page_it: BTreeLeafPageIteratorRc,
+
+    snapshot: Option<Arc<Snapshot>>,
+
+    // position (pid, slot number) of the last tuple returned from each end,
+    // used to detect the meeting of the two cursors
+    front_pos: Option<(BTreePageID, usize)>,
+    back_pos: Option<(BTreePageID, usize)>,
+
+    finished: bool,
+}
+
+impl BTreeTableRangeIterator {
+    pub fn new(
+        tx: &Transaction,
+        table: &BTreeTable,
+        lower: Bound<Cell>,
+        upper: Bound<Cell>,
+    ) -> Result<Self, SmallError> {
+        let root_pid = table.get_root_pid(tx);
+
+        let page_rc = match &lower {
+            Bound::Included(key) | Bound::Excluded(key) => table.find_leaf_page(
+                tx,
+                Permission::ReadOnly,
+                root_pid,
+                &SearchFor::Target(key.clone()),
+            )?,
+            Bound::Unbounded => {
+                table.find_leaf_page(tx, Permission::ReadOnly, root_pid, &SearchFor::LeftMost)?
+            }
+        };
+
+        let last_page_rc = match &upper {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                Self::find_last_leaf(tx, table, root_pid, key)?
+            }
+            Bound::Unbounded => {
+                table.find_leaf_page(tx, Permission::ReadOnly, root_pid, &SearchFor::RightMost)?
+            }
+        };
+
+        Ok(Self {
+            tx: tx.clone(),
+            key_field: table.key_field,
+            comparator: Arc::clone(&table.comparator),
+
+            lower,
+            upper,
+
+            page_rc: Arc::clone(&page_rc),
+            page_it: BTreeLeafPageIteratorRc::new(tx, Arc::clone(&page_rc)),
+
+            last_page_rc: Arc::clone(&last_page_rc),
+            last_page_it: BTreeLeafPageIteratorRc::new(tx, Arc::clone(&last_page_rc)),
+
+            snapshot: None,
+
+            front_pos: None,
+            back_pos: None,
+
+            finished: false,
+        })
+    }
+
+    /// See the tuples under `snapshot` instead of the current status of the
+    /// transactions, the iterator returns the same tuples however long it
+    /// lives.
+    pub fn with_snapshot(mut self, snapshot: Arc<Snapshot>) -> Self {
+        self.snapshot = Some(snapshot);
+        self.page_it = self.page_iter(Arc::clone(&self.page_rc));
+        self.last_page_it = self.page_iter(Arc::clone(&self.last_page_rc));
+        self
+    }
+
+    fn page_iter(&self, page_rc: Arc<RwLock<BTreeLeafPage>>) -> BTreeLeafPageIteratorRc {
+        BTreeLeafPageIteratorRc::with_snapshot(&self.tx, page_rc, self.snapshot.clone())
+    }
+
+    /// Find the right-most leaf page which may contain `key`.
+    ///
+    /// `find_leaf_page` returns the left-most one, tuples with the same key
+    /// may continue in the right siblings.
+    fn find_last_leaf(
+        tx: &Transaction,
+        table: &BTreeTable,
+        root_pid: BTreePageID,
+        key: &Cell,
+    ) -> Result<Arc<RwLock<BTreeLeafPage>>, SmallError> {
+        let mut page_rc = table.find_leaf_page(
+            tx,
+            Permission::ReadOnly,
+            root_pid,
+            &SearchFor::Target(key.clone()),
+        )?;
+
+        loop {
+            let right = page_rc.rl().get_right_pid();
+            let right_pid = match right {
+                Some(pid) => pid,
+                None => return Ok(page_rc),
+            };
+
+            let right_rc = BufferPool::get_leaf_page(tx, Permission::ReadOnly, &right_pid)?;
+            let first_key = BTreeLeafPageIterator::new(&right_rc.rl())
+                .next()
+                .map(|t| t.get_cell(table.key_field));
+            match first_key {
+                Some(first_key)
+                    if table.comparator.compare(&first_key, key) != Ordering::Greater =>
+                {
+                    page_rc = right_rc
+                }
+                _ => return Ok(page_rc),
             }
         }
     }
+
+    fn below_lower(&self, key: &Cell) -> bool {
+        match &self.lower {
+            Bound::Included(lower) => self.comparator.compare(key, lower) == Ordering::Less,
+            Bound::Excluded(lower) => self.comparator.compare(key, lower) != Ordering::Greater,
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn above_upper(&self, key: &Cell) -> bool {
+        match &self.upper {
+            Bound::Included(upper) => self.comparator.compare(key, upper) == Ordering::Greater,
+            Bound::Excluded(upper) => self.comparator.compare(key, upper) != Ordering::Less,
+            Bound::Unbounded => false,
+        }
+    }
+}
+
+impl BTreeTableRangeIterator {
+    /// Same as `next`, but a leaf page which can't be read is reported
+    /// instead of panicking.
+    pub fn try_next(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        loop {
+            if self.finished {
+                return Ok(None);
+            }
+
+            let tuple = match self.page_it.next() {
+                Some(t) => t,
+                None => {
+                    // The current page is exhausted, move to the its right sibling.
+                    let right = self.page_rc.rl().get_right_pid();
+                    match right {
+                        Some(right) => {
+                            let sibling_rc =
+                                BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &right)?;
+                            self.page_it = self.page_iter(Arc::clone(&sibling_rc));
+                            self.page_rc = sibling_rc;
+                            continue;
+                        }
+                        None => {
+                            self.finished = true;
+                            return Ok(None);
+                        }
+                    }
+                }
+            };
+
+            // the two cursors meet, all tuples have been returned
+            let pos = (tuple.get_pid(), tuple.get_slot_number());
+            if Some(pos) == self.back_pos {
+                self.finished = true;
+                return Ok(None);
+            }
+
+            let key = tuple.get_cell(self.key_field);
+            if self.below_lower(&key) {
+                continue;
+            }
+            if self.above_upper(&key) {
+                self.finished = true;
+                return Ok(None);
+            }
+
+            self.front_pos = Some(pos);
+            return Ok(Some(tuple));
+        }
+    }
+
+    /// Same as `next_back`, but a leaf page which can't be read is reported
+    /// instead of panicking.
+    pub fn try_next_back(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        loop {
+            if self.finished {
+                return Ok(None);
+            }
+
+            let tuple = match self.last_page_it.next_back() {
+                Some(t) => t,
+                None => {
+                    // The current page is exhausted, move to the its left sibling.
+                    let left = self.last_page_rc.rl().get_left_pid();
+                    match left {
+                        Some(left) => {
+                            let sibling_rc =
+                                BufferPool::get_leaf_page(&self.tx, Permission::ReadOnly, &left)?;
+                            self.last_page_it = self.page_iter(Arc::clone(&sibling_rc));
+                            self.last_page_rc = sibling_rc;
+                            continue;
+                        }
+                        None => {
+                            self.finished = true;
+                            return Ok(None);
+                        }
+                    }
+                }
+            };
+
+            // the two cursors meet, all tuples have been returned
+            let pos = (tuple.get_pid(), tuple.get_slot_number());
+            if Some(pos) == self.front_pos {
+                self.finished = true;
+                return Ok(None);
+            }
+
+            // the reverse iterator of the leaf page doesn't filter invisible
+            // tuples
+            if !self.last_page_it.is_visible(&tuple) {
+                continue;
+            }
+
+            let key = tuple.get_cell(self.key_field);
+            if self.above_upper(&key) {
+                continue;
+            }
+            if self.below_lower(&key) {
+                self.finished = true;
+                return Ok(None);
+            }
+
+            self.back_pos = Some(pos);
+            return Ok(Some(tuple));
+        }
+    }
+}
+
+impl Iterator for BTreeTableRangeIterator {
+    type Item = WrappedTuple;
+
+    /// Panics if a leaf page can't be read, use `try_next` to handle the
+    /// error.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next()
+            .unwrap_or_else(|e| panic!("failed to scan the range: {}", e))
+    }
+}
+
+impl DoubleEndedIterator for BTreeTableRangeIterator {
+    /// Panics if a leaf page can't be read, use `try_next_back` to handle
+    /// the error.
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.try_next_back()
+            .unwrap_or_else(|e| panic!("failed to scan the range: {}", e))
+    }
 }
 
 pub struct BTreeTableSearchIterator<'t> {
@@ -105,114 +590,216 @@ pub struct BTreeTableSearchIterator<'t> {
     predicate: Predicate,
     search_field: usize,
     is_key_search: bool,
+
+    /// The ordering of the keys, a search on the key compares by it so it
+    /// stops where the tree order says the matching keys end.
+    comparator: Arc<dyn KeyComparator>,
+
+    /// The fixed prefix of a LIKE pattern on the key, when the search
+    /// started from it (see `BTreeTable::seeks_prefixes`).
+    like_prefix: Option<Vec<u8>>,
 }
 
 impl<'t> BTreeTableSearchIterator<'t> {
-    pub fn new(tx: &'t Transaction, table: &BTreeTable, predicate: &Predicate) -> Self {
+    /// Fails if the leaf page to start from can't be found.
+    pub fn new(
+        tx: &'t Transaction,
+        table: &BTreeTable,
+        predicate: &Predicate,
+    ) -> Result<Self, SmallError> {
         let start_page_rc: Arc<RwLock<BTreeLeafPage>>;
         let root_pid = table.get_root_pid(tx);
 
         if predicate.field_index == table.key_field {
-            match predicate.op {
+            match &predicate.op {
                 Op::Equals | Op::GreaterThan | Op::GreaterThanOrEq => {
                     start_page_rc = table.find_leaf_page(
-                        &tx,
+                        tx,
                         Permission::ReadOnly,
                         root_pid,
                         &SearchFor::Target(predicate.cell.clone()),
-                    )
+                    )?
+                }
+                Op::LessThan | Op::LessThanOrEq | Op::NotEquals => {
+                    start_page_rc = table.find_leaf_page(
+                        tx,
+                        Permission::ReadOnly,
+                        root_pid,
+                        &SearchFor::LeftMost,
+                    )?
                 }
-                Op::LessThan | Op::LessThanOrEq => {
+                Op::In(values) if values.is_empty() => {
                     start_page_rc = table.find_leaf_page(
-                        &tx,
+                        tx,
                         Permission::ReadOnly,
                         root_pid,
                         &SearchFor::LeftMost,
-                    )
+                    )?
+                }
+                Op::In(values) => {
+                    // the values are sorted by `Cell` order, start from the
+                    // smallest one in the order of the table
+                    let first = values
+                        .iter()
+                        .min_by(|a, b| table.comparator.compare(a, b))
+                        .unwrap();
+                    start_page_rc = table.find_leaf_page(
+                        tx,
+                        Permission::ReadOnly,
+                        root_pid,
+                        &SearchFor::Target(first.clone()),
+                    )?
+                }
+                Op::Like => {
+                    // seek to the fixed prefix of the pattern, the matching
+                    // keys follow it
+                    let search = match predicate.like_prefix() {
+                        Some(prefix) if table.seeks_prefixes() => {
+                            SearchFor::Target(Cell::Bytes(prefix))
+                        }
+                        _ => SearchFor::LeftMost,
+                    };
+                    start_page_rc =
+                        table.find_leaf_page(tx, Permission::ReadOnly, root_pid, &search)?
                 }
-                Op::Like => todo!(),
-                Op::NotEquals => todo!(),
             }
         } else {
             start_page_rc =
-                table.find_leaf_page(&tx, Permission::ReadOnly, root_pid, &SearchFor::LeftMost)
+                table.find_leaf_page(tx, Permission::ReadOnly, root_pid, &SearchFor::LeftMost)?
         }
 
-        Self {
+        Ok(Self {
             tx,
             current_page_rc: Arc::clone(&start_page_rc),
             page_it: BTreeLeafPageIteratorRc::new(tx, Arc::clone(&start_page_rc)),
             predicate: predicate.clone(),
             search_field: predicate.field_index,
             is_key_search: predicate.field_index == table.key_field,
+            comparator: Arc::clone(&table.comparator),
+            like_prefix: if predicate.field_index == table.key_field && table.seeks_prefixes() {
+                predicate.like_prefix()
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Same as `next`, but a leaf page which can't be read (or released) is
+    /// reported instead of panicking.
+    pub fn try_next(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
+        if let Some(t) = self.next_inner()? {
+            return Ok(Some(t));
+        }
+
+        // release the latch on the last page
+        let pid = self.current_page_rc.rl().get_pid();
+        Database::mut_concurrent_status().release_latch(self.tx, &pid)?;
+        Ok(None)
+    }
+
+    /// Compare a cell of the searched field to `other`, by the ordering of
+    /// the table for a search on the key.
+    fn compare(&self, field: &Cell, other: &Cell) -> Ordering {
+        if self.is_key_search {
+            self.comparator.compare(field, other)
+        } else {
+            field.cmp(other)
         }
     }
 
     // TODO: Short circuit on some conditions.
-    fn next_inner(&mut self) -> Option<WrappedTuple> {
+    fn next_inner(&mut self) -> Result<Option<WrappedTuple>, SmallError> {
         loop {
             let tuple = self.page_it.next();
 
             match tuple {
-                Some(t) => match self.predicate.op {
-                    Op::Equals => {
-                        let field = t.get_cell(self.search_field);
-                        if field == self.predicate.cell {
-                            return Some(t);
-                        } else if self.is_key_search && field > self.predicate.cell {
-                            return None;
+                Some(t) => {
+                    let field = t.get_cell(self.search_field);
+                    let order = self.compare(&field, &self.predicate.cell);
+                    match &self.predicate.op {
+                        Op::Equals => {
+                            if order == Ordering::Equal {
+                                return Ok(Some(t));
+                            } else if self.is_key_search && order == Ordering::Greater {
+                                return Ok(None);
+                            }
                         }
-                    }
-                    Op::GreaterThan => {
-                        let field = t.get_cell(self.search_field);
-                        if field > self.predicate.cell {
-                            return Some(t);
+                        Op::GreaterThan => {
+                            if order == Ordering::Greater {
+                                return Ok(Some(t));
+                            }
                         }
-                    }
-                    Op::GreaterThanOrEq => {
-                        let field = t.get_cell(self.search_field);
-                        if field >= self.predicate.cell {
-                            return Some(t);
+                        Op::GreaterThanOrEq => {
+                            if order != Ordering::Less {
+                                return Ok(Some(t));
+                            }
                         }
-                    }
-                    Op::LessThan => {
-                        let field = t.get_cell(self.search_field);
-                        if field < self.predicate.cell {
-                            return Some(t);
-                        } else if self.is_key_search && field >= self.predicate.cell {
-                            return None;
+                        Op::LessThan => {
+                            if order == Ordering::Less {
+                                return Ok(Some(t));
+                            } else if self.is_key_search {
+                                return Ok(None);
+                            }
                         }
-                    }
-                    Op::LessThanOrEq => {
-                        let field = t.get_cell(self.search_field);
-                        if field <= self.predicate.cell {
-                            return Some(t);
-                        } else if self.is_key_search && field > self.predicate.cell {
-                            return None;
+                        Op::LessThanOrEq => {
+                            if order != Ordering::Greater {
+                                return Ok(Some(t));
+                            } else if self.is_key_search {
+                                return Ok(None);
+                            }
+                        }
+                        Op::In(values) => {
+                            if values
+                                .iter()
+                                .any(|v| self.compare(&field, v) == Ordering::Equal)
+                            {
+                                return Ok(Some(t));
+                            } else if self.is_key_search
+                                && values
+                                    .iter()
+                                    .all(|v| self.compare(&field, v) == Ordering::Greater)
+                            {
+                                return Ok(None);
+                            }
+                        }
+                        Op::Like => {
+                            if self.predicate.matches(&field) {
+                                return Ok(Some(t));
+                            } else if let (Some(prefix), Cell::Bytes(bytes)) =
+                                (&self.like_prefix, &field)
+                            {
+                                // the keys starting with the prefix are passed
+                                let prefix_cell = Cell::Bytes(prefix.clone());
+                                if !bytes.starts_with(prefix)
+                                    && self.compare(&field, &prefix_cell) == Ordering::Greater
+                                {
+                                    return Ok(None);
+                                }
+                            }
+                        }
+                        Op::NotEquals => {
+                            if order != Ordering::Equal {
+                                return Ok(Some(t));
+                            }
                         }
                     }
-                    Op::Like => todo!(),
-                    Op::NotEquals => todo!(),
-                },
+                }
                 None => {
                     let right = self.current_page_rc.rl().get_right_pid();
 
                     // don't need the previous page anymore, release the latch on it
                     let pid = self.current_page_rc.rl().get_pid();
-                    Database::mut_concurrent_status()
-                        .release_latch(self.tx, &pid)
-                        .unwrap();
+                    Database::mut_concurrent_status().release_latch(self.tx, &pid)?;
 
                     // init iterator on next page and continue search
                     if let Some(right_pid) = right {
                         let rc =
-                            BufferPool::get_leaf_page(self.tx, Permission::ReadOnly, &right_pid)
-                                .unwrap();
+                            BufferPool::get_leaf_page(self.tx, Permission::ReadOnly, &right_pid)?;
                         self.current_page_rc = Arc::clone(&rc);
                         self.page_it = BTreeLeafPageIteratorRc::new(self.tx, Arc::clone(&rc));
                         continue;
                     } else {
-                        return None;
+                        return Ok(None);
                     }
                 }
             }
@@ -223,17 +810,10 @@ impl<'t> BTreeTableSearchIterator<'t> {
 impl Iterator for BTreeTableSearchIterator<'_> {
     type Item = WrappedTuple;
 
+    /// Panics if a leaf page can't be read, use `try_next` to handle the
+    /// error.
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(t) = self.next_inner() {
-            return Some(t);
-        } else {
-            // release the latch on the last page
-            let pid = self.current_page_rc.rl().get_pid();
-            Database::mut_concurrent_status()
-                .release_latch(self.tx, &pid)
-                .unwrap();
-
-            return None;
-        }
+        self.try_next()
+            .unwrap_or_else(|e| panic!("failed to search the table: {}", e))
     }
 }
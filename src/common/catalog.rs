@@ -1,18 +1,27 @@
 use std::{
     collections::HashMap,
-    io::Cursor,
+    fs,
     sync::{Arc, RwLock},
 };
 
-use super::schema::Schema;
+use log::error;
+
+use super::{
+    catalog_file::{CatalogEntry, CatalogFile, TableOptions},
+    schema::Schema,
+};
 use crate::{
-    btree::table::BTreeTableSearchIterator,
-    io::{read_into, Serializeable},
+    btree::{
+        buffer_pool::BufferPool,
+        page::PageCategory,
+        table::{BTreeTableIterator, BTreeTableSearchIterator},
+    },
+    error::{SmallError, SmallErrorKind},
     storage::{
-        table_schema::{Field, TableSchema, Type},
+        table_schema::{Field, TableSchema},
         tuple::{Cell, Tuple},
     },
-    transaction::Transaction,
+    transaction::{Permission, Transaction},
     types::SmallResult,
     utils::HandyRwLock,
     BTreeTable, Database, Op, Predicate,
@@ -26,16 +35,40 @@ const TABLE_SCHEMA_ID: u32 = 123;
 const SCHEMA_NAME: &str = "schemas";
 const SCHEMA_ID: u32 = 124;
 
+// The id for the table "pg_database"
+const PG_DATABASE_ID: u32 = 0;
+
 type TableID = u32;
 type TableRC = Arc<RwLock<BTreeTable>>;
 
 type SchemaID = u32;
 type SchemaRC = Arc<RwLock<Schema>>;
 
+/// A secondary index, stored as a B+ tree table keyed on the indexed column
+/// whose tuples are (indexed value, primary key) pairs.
+///
+/// The metadata of an index is persisted with the entry of its table in the
+/// catalog file (see `TableOptions::index_of`), the indexes are registered
+/// again by `load_tables`.
+#[derive(Clone)]
+pub struct IndexMeta {
+    pub name: String,
+
+    /// Name of the indexed table.
+    pub table_name: String,
+
+    /// Position of the indexed column in the schema of the indexed table.
+    pub field_index: usize,
+
+    pub index_table: TableRC,
+}
+
 pub struct Catalog {
     tables: HashMap<TableID, TableRC>,
 
     schemas: HashMap<SchemaID, SchemaRC>,
+
+    indexes: HashMap<String, IndexMeta>,
 }
 
 impl Catalog {
@@ -44,11 +77,19 @@ impl Catalog {
             tables: HashMap::new(),
 
             schemas: HashMap::new(),
+
+            indexes: HashMap::new(),
         }
     }
 
     /// Load tables from disk.
     ///
+    /// The tables are listed by the catalog file (see `CatalogFile`), which
+    /// is read before the WAL is replayed, so the recovery finds all the
+    /// tables it has to restore. A database without a catalog file (created
+    /// before the file existed) gets its tables from the "tables" table,
+    /// and the file is written from them.
+    ///
     /// We cannot pass the `self` reference to this function, since "catalog" is
     /// used inside the table write/read api, and it will cause permanent
     /// blocking.
@@ -60,23 +101,80 @@ impl Catalog {
         //
         // All "add_table" calls in this function should not persist the table,
         // because we are loading the tables from disk.
-        Catalog::add_table(tables.clone(), false);
+        Catalog::add_table(tables.clone(), false)?;
+
+        let catalog_file = CatalogFile::current();
+        if catalog_file.exists() {
+            let entries = catalog_file.read()?;
+            for entry in &entries {
+                let table = Self::table_from_entry(entry);
+                Catalog::add_table(Arc::new(RwLock::new(table)), false)?;
+            }
+            Self::load_indexes(&entries);
+        } else {
+            Self::load_tables_from_schema_table(&tables)?;
+        }
 
+        {
+            // Insert table "pg_database" if it does not exist.
+            let mut catalog = Database::mut_catalog();
+            catalog.search_table("pg_database").unwrap_or_else(|| {
+                let schema = TableSchema::for_pg_database();
+                let table = BTreeTable::new("pg_database", Some(PG_DATABASE_ID), &schema);
+                let table_rc = Arc::new(RwLock::new(table));
+                catalog.add_table_to_memory(table_rc.clone());
+                table_rc
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Register the secondary indexes listed by the catalog file, once all
+    /// the tables are loaded. An index whose table is gone is left as a
+    /// plain table.
+    fn load_indexes(entries: &[CatalogEntry]) {
+        let mut catalog = Database::mut_catalog();
+        for entry in entries {
+            let (table_id, field_index) = match entry.options.index_of {
+                Some(index_of) => index_of,
+                None => continue,
+            };
+            let table_name = match catalog.get_table(&table_id) {
+                Some(table_rc) => table_rc.rl().name.clone(),
+                None => continue,
+            };
+            let index_table = match catalog.get_table(&entry.table_id) {
+                Some(index_table) => index_table,
+                None => continue,
+            };
+            catalog.add_index(IndexMeta {
+                name: entry.name.clone(),
+                table_name,
+                field_index,
+                index_table,
+            });
+        }
+    }
+
+    /// Load the tables described by the "tables" table, and list them in the
+    /// catalog file.
+    fn load_tables_from_schema_table(tables: &TableRC) -> SmallResult {
         // scan the catalog table and load all the tables
         let mut schemas = HashMap::new();
         let mut table_names = HashMap::new();
 
         let tx = Transaction::new();
         let schema_table = tables.rl();
-        let mut iter = schema_table.iter(&tx);
-        while let Some(tuple) = iter.next() {
+        let mut iter = BTreeTableIterator::new(&tx, &schema_table)?;
+        while let Some(tuple) = iter.try_next()? {
             let table_id = tuple.get_cell(0).get_int64()?;
-            let table_name = String::from_utf8(tuple.get_cell(1).get_bytes()?).unwrap();
-            let field_name = String::from_utf8(tuple.get_cell(2).get_bytes()?).unwrap();
-            let field_type = Type::decode(&mut Cursor::new(tuple.get_cell(3).get_bytes()?), &());
+            let table_name = decode_name(tuple.get_cell(1).get_bytes()?)?;
+            let field_name = decode_name(tuple.get_cell(2).get_bytes()?)?;
+            let field_type = tuple.get_cell(3).get_bytes()?;
             let is_primary = tuple.get_cell(4).get_bool()?;
 
-            let field = Field::new(&field_name, field_type, is_primary);
+            let field = Field::from_type_bytes(&field_name, &field_type, is_primary);
 
             // insert the field into the schema, if "table_id" is not
             // in the map, then insert a new vector
@@ -84,6 +182,7 @@ impl Catalog {
             table_names.insert(table_id, table_name);
         }
 
+        let mut entries = Vec::new();
         for (table_id, fields) in schemas {
             let schema = TableSchema::new(fields);
             let table_name = table_names.get(&table_id).unwrap();
@@ -92,24 +191,20 @@ impl Catalog {
 
             // All "add_table" calls in this function should not persist the table,
             // because we are loading the tables from disk.
-            Catalog::add_table(Arc::new(RwLock::new(table)), false);
-        }
+            Catalog::add_table(Arc::new(RwLock::new(table)), false)?;
 
-        tx.commit().unwrap();
-
-        {
-            // Insert table "pg_database" if it does not exist.
-            let mut catalog = Database::mut_catalog();
-            catalog.search_table("pg_database").unwrap_or_else(|| {
-                let schema = TableSchema::for_pg_database();
-                let table = BTreeTable::new("pg_database", Some(0), &schema);
-                let table_rc = Arc::new(RwLock::new(table));
-                catalog.add_table_to_memory(table_rc.clone());
-                table_rc
+            entries.push(CatalogEntry {
+                table_id: table_id as u32,
+                name: table_name.clone(),
+                schema,
+                options: TableOptions::default(),
             });
         }
 
-        Ok(())
+        tx.commit().unwrap();
+
+        entries.sort_by_key(|entry| entry.table_id);
+        CatalogFile::current().write(&entries)
     }
 
     /// Load tables from disk.
@@ -159,20 +254,17 @@ impl Catalog {
             Op::Equals,
             &Cell::Bytes(table_name.as_bytes().to_vec()),
         );
-        let iter = BTreeTableSearchIterator::new(&tx, &schema_table, &predicate);
+        let iter = BTreeTableSearchIterator::new(&tx, &schema_table, &predicate).unwrap();
         let mut fields = Vec::new();
         let mut table_id_option: Option<i64> = None;
         for tuple in iter {
             table_id_option = Some(tuple.get_cell(0).get_int64().unwrap());
 
-            let field_name = String::from_utf8(tuple.get_cell(2).get_bytes().unwrap()).unwrap();
-            let field_type = read_into(
-                &mut Cursor::new(tuple.get_cell(3).get_bytes().unwrap()),
-                &(),
-            );
+            let field_name = decode_name(tuple.get_cell(2).get_bytes().ok()?).ok()?;
+            let field_type = tuple.get_cell(3).get_bytes().unwrap();
             let is_primary = tuple.get_cell(4).get_bool().unwrap();
 
-            let field = Field::new(&field_name, field_type, is_primary);
+            let field = Field::from_type_bytes(&field_name, &field_type, is_primary);
             fields.push(field);
         }
 
@@ -229,7 +321,7 @@ impl Catalog {
         self.tables.insert(id, Arc::clone(&table_rc));
     }
 
-    fn add_table_to_disk(table_rc: TableRC) {
+    fn add_table_to_disk(table_rc: TableRC) -> SmallResult {
         let table = table_rc.rl();
 
         let schema_table_rc = Database::mut_catalog().get_table_schemas();
@@ -237,41 +329,375 @@ impl Catalog {
 
         let tx = Transaction::new();
 
+        for field in table.get_schema().get_fields() {
+            let tuple = Self::field_tuple(&schema_table, &table, field, &tx);
+            if let Err(e) = schema_table.insert_tuple(&tx, &tuple) {
+                tx.abort()?;
+                return Err(e);
+            }
+        }
+
+        // the rows are only committed once the table is listed in the
+        // catalog file, so a failure on either side leaves no trace of the
+        // table on the other
+        let table_id = table.get_id();
+        let entry = CatalogEntry {
+            table_id,
+            name: table.name.clone(),
+            schema: table.get_schema(),
+            options: table.get_options(),
+        };
+        if let Err(e) = CatalogFile::current().update(|entries| {
+            entries.retain(|e| e.table_id != entry.table_id);
+            entries.push(entry);
+        }) {
+            tx.abort()?;
+            return Err(e);
+        }
+
+        if let Err(e) = tx.commit() {
+            CatalogFile::current().update(|entries| entries.retain(|e| e.table_id != table_id))?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Build the row of the "tables" table describing `field` of `table`.
+    fn field_tuple(
+        schema_table: &BTreeTable,
+        table: &BTreeTable,
+        field: &Field,
+        tx: &Transaction,
+    ) -> Tuple {
         let schema_fields = schema_table.schema.get_fields();
         let table_name_type = schema_fields[1].get_type();
         let field_name_type = schema_fields[2].get_type();
         let field_type_type = schema_fields[3].get_type();
 
-        for field in table.get_schema().get_fields() {
-            // let t = field.get_type();
-            let cells = vec![
-                // table id
-                Cell::new_int64(table.get_id() as i64),
-                // table name
-                Cell::new_bytes(&table.name.as_bytes(), &table_name_type),
-                // field name
-                Cell::new_bytes(&field.name.as_bytes(), &field_name_type),
-                // field type
-                Cell::new_bytes(&field.get_type().to_bytes(&()), &field_type_type),
-                // is primary
-                Cell::new_bool(field.is_primary),
-            ];
-            let tuple = Tuple::new(&cells, tx.get_id());
-            schema_table.insert_tuple(&tx, &tuple).unwrap();
-        }
-
-        tx.commit().unwrap();
+        let cells = vec![
+            // table id
+            Cell::new_int64(table.get_id() as i64),
+            // table name
+            Cell::new_bytes(&table.name.as_bytes(), &table_name_type),
+            // field name
+            Cell::new_bytes(&field.name.as_bytes(), &field_name_type),
+            // field type
+            Cell::new_bytes(&field.type_to_bytes(), &field_type_type),
+            // is primary
+            Cell::new_bool(field.is_primary),
+        ];
+        Tuple::new(&cells, tx.get_id())
     }
 
-    pub fn add_table(table_rc: TableRC, persist: bool) {
+    /// Add the table to the catalog, with `persist` its schema is written
+    /// to the "tables" table and the catalog file as well.
+    ///
+    /// If the schema fails to be persisted, the table is removed from the
+    /// catalog and the error is returned. A table file with varint fields
+    /// whose leaf pages are in an older format is rejected (see
+    /// `LEAF_FORMAT_VERSION`).
+    pub fn add_table(table_rc: TableRC, persist: bool) -> SmallResult {
+        table_rc.rl().check_leaf_format()?;
+
         {
             let mut catalog = Database::mut_catalog();
             catalog.add_table_to_memory(table_rc.clone());
         }
 
         if persist {
-            Self::add_table_to_disk(table_rc);
+            let table_id = table_rc.rl().get_id();
+            if let Err(e) = Self::add_table_to_disk(table_rc) {
+                Database::mut_catalog().tables.remove(&table_id);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the table named `table_name`, or create it with `schema` if it
+    /// doesn't exist.
+    ///
+    /// Concurrent callers asking for the same table all get the same table,
+    /// the file of the table is only initialized once. Returns a
+    /// `SchemaMismatch` error if the existing table has a different schema.
+    pub fn get_or_create_table(
+        table_name: &str,
+        schema: &TableSchema,
+        persist: bool,
+    ) -> Result<TableRC, SmallError> {
+        let _latch = Database::create_table_latch();
+
+        let existing = Database::catalog().search_table(table_name);
+        if let Some(table_rc) = existing {
+            if table_rc.rl().get_schema().get_fields() != schema.get_fields() {
+                let err_msg = format!(
+                    "table {} already exists with a different schema",
+                    table_name
+                );
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::SchemaMismatch,
+                    &err_msg,
+                ));
+            }
+            return Ok(table_rc);
+        }
+
+        let table_rc = Arc::new(RwLock::new(BTreeTable::new(table_name, None, schema)));
+        Self::add_table(table_rc.clone(), persist)?;
+        Ok(table_rc)
+    }
+
+    /// Get the table named `table_name`, with the schema persisted when it
+    /// was created (the catalog file keeps the name, the type and the key
+    /// flag of every column).
+    ///
+    /// All the persisted tables are loaded when the database starts (see
+    /// `load_tables`), so they are found in memory. Return a `NotFound`
+    /// error if the table has never been created, or was created without
+    /// persisting it.
+    pub fn open_table(table_name: &str) -> Result<TableRC, SmallError> {
+        Database::catalog()
+            .search_table(table_name)
+            .ok_or(SmallError::with_kind(
+                SmallErrorKind::NotFound,
+                &format!("table {} not found", table_name),
+            ))
+    }
+
+    /// Build the table listed by `entry`, with the options which shape its
+    /// pages. The Bloom filter is built from the pages, see `apply_options`.
+    fn table_from_entry(entry: &CatalogEntry) -> BTreeTable {
+        let mut table = BTreeTable::new(&entry.name, Some(entry.table_id), &entry.schema);
+        table.max_fan_out = entry.options.max_fan_out;
+        table.compressed = entry.options.compressed;
+        table.retain_tombstones = entry.options.retain_tombstones;
+        table
+    }
+
+    /// Apply the options persisted in the catalog file to the tables loaded
+    /// by `load_tables`, called once the log is recovered since the Bloom
+    /// filters are built from the pages.
+    pub(crate) fn apply_table_options() -> SmallResult {
+        for entry in CatalogFile::current().read()? {
+            let table_rc = Database::mut_catalog().get_table(&entry.table_id);
+            if let Some(table_rc) = table_rc {
+                Self::apply_options(&table_rc.rl(), &entry.options)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_options(table: &BTreeTable, options: &TableOptions) -> SmallResult {
+        if let Some((counters_count, hashes_count)) = options.bloom_filter {
+            let tx = Transaction::new();
+            if let Err(e) = table.build_bloom_filter(&tx, counters_count, hashes_count) {
+                tx.abort()?;
+                return Err(e);
+            }
+            tx.commit()?;
         }
+        Ok(())
+    }
+
+    /// Write the options of the table (see `TableOptions`) to the catalog
+    /// file, nothing is written for a table which isn't listed there.
+    pub(crate) fn write_table_options(table: &BTreeTable) -> SmallResult {
+        let table_id = table.get_id();
+        let options = table.get_options();
+        CatalogFile::current().update(|entries| {
+            for entry in entries.iter_mut().filter(|e| e.table_id == table_id) {
+                entry.options = TableOptions {
+                    index_of: entry.options.index_of,
+                    ..options.clone()
+                };
+            }
+        })
+    }
+
+    /// Record in the catalog file that the table of `index` is a secondary
+    /// index, so it's registered again when the database is loaded.
+    pub(crate) fn write_index(index: &IndexMeta) -> SmallResult {
+        let index_table_id = index.index_table.rl().get_id();
+        let table_id = Database::catalog()
+            .search_table(&index.table_name)
+            .ok_or(SmallError::with_kind(
+                SmallErrorKind::NotFound,
+                &format!("table {} not found", index.table_name),
+            ))?
+            .rl()
+            .get_id();
+        CatalogFile::current().update(|entries| {
+            for entry in entries.iter_mut().filter(|e| e.table_id == index_table_id) {
+                entry.options.index_of = Some((table_id, index.field_index));
+            }
+        })
+    }
+
+    /// Remove the table from the catalog and delete its persisted schema and
+    /// its file.
+    ///
+    /// Like `add_table`, this function doesn't take the `self` reference
+    /// since it writes the "tables" table.
+    pub fn drop_table(table_name: &str) -> SmallResult {
+        let table_rc =
+            Database::catalog()
+                .search_table(table_name)
+                .ok_or(SmallError::with_kind(
+                    SmallErrorKind::NotFound,
+                    &format!("table {} not found", table_name),
+                ))?;
+        let table_id = table_rc.rl().get_id();
+
+        // delete the persisted schema
+        let schema_table_rc = Database::mut_catalog().get_table_schemas();
+        let tx = Transaction::new();
+        let predicate = Predicate::new(0, Op::Equals, &Cell::Int64(table_id as i64));
+        schema_table_rc.rl().delete_tuples(&tx, &predicate)?;
+        tx.commit()?;
+        CatalogFile::current().update(|entries| entries.retain(|e| e.table_id != table_id))?;
+
+        Database::mut_catalog().tables.remove(&table_id);
+        Database::mut_buffer_pool().discard_table_pages(table_id);
+
+        let table_path = Database::global()
+            .get_path()
+            .join(table_name.to_owned() + ".table");
+        fs::remove_file(table_path)?;
+
+        Ok(())
+    }
+
+    /// Append the column `field` to the table, as part of `tx`.
+    ///
+    /// The change is logged in the WAL, so the previous schema is restored
+    /// if `tx` aborts or doesn't finish before a crash. The tuples are not
+    /// rewritten, hence the table must not have any tuple.
+    pub fn add_column(tx: &Transaction, table_name: &str, field: &Field) -> SmallResult {
+        let table_rc =
+            Database::catalog()
+                .search_table(table_name)
+                .ok_or(SmallError::with_kind(
+                    SmallErrorKind::NotFound,
+                    &format!("table {} not found", table_name),
+                ))?;
+
+        let before = table_rc.rl().get_schema();
+        if field.is_primary {
+            let err_msg = format!("table {} already has a key", table_name);
+            return Err(SmallError::with_kind(
+                SmallErrorKind::SchemaMismatch,
+                &err_msg,
+            ));
+        }
+        if before.get_fields().iter().any(|f| f.name == field.name) {
+            let err_msg = format!("column {} already exists in {}", field.name, table_name);
+            return Err(SmallError::with_kind(
+                SmallErrorKind::SchemaMismatch,
+                &err_msg,
+            ));
+        }
+
+        // the tuples would have to be rewritten in the new format, deleted
+        // tuples kept on the pages count as well
+        let is_empty = {
+            let table = table_rc.rl();
+            let root_pid = table.get_root_pid(tx);
+            matches!(root_pid.category, PageCategory::Leaf)
+                && table
+                    .get_first_page(tx, Permission::ReadOnly)?
+                    .rl()
+                    .tuples_count()
+                    == 0
+        };
+        if !is_empty {
+            return Err(SmallError::new(&format!(
+                "cannot add a column to {}, the table is not empty",
+                table_name
+            )));
+        }
+
+        let table_id = table_rc.rl().get_id();
+        Database::mut_log_manager().log_schema_change(tx, table_id, &before)?;
+
+        let schema_table_rc = Database::mut_catalog().get_table_schemas();
+        {
+            let schema_table = schema_table_rc.rl();
+            let tuple = Self::field_tuple(&schema_table, &table_rc.rl(), field, tx);
+            schema_table.insert_tuple(tx, &tuple)?;
+        }
+
+        // the catalog file is only updated when `tx` commits
+        let mut fields = before.get_fields().clone();
+        fields.push(field.clone());
+        Self::set_schema(
+            table_id,
+            &TableSchema::new(fields),
+            &Database::buffer_pool(),
+        );
+        Database::mut_concurrent_status().record_schema_change(tx, table_id);
+        Ok(())
+    }
+
+    /// Set the schema of the table `table_id` in memory, the cached pages of
+    /// the table are discarded since they were decoded with the old schema.
+    ///
+    /// Used by the DDL statements and to undo them, the persisted schema
+    /// (the "tables" table) is handled by the caller. The schema listed in
+    /// the catalog file is updated here.
+    pub(crate) fn restore_schema(table_id: u32, schema: &TableSchema, buffer_pool: &BufferPool) {
+        if !Self::set_schema(table_id, schema, buffer_pool) {
+            return;
+        }
+
+        let result = CatalogFile::current().update(|entries| {
+            for entry in entries.iter_mut().filter(|e| e.table_id == table_id) {
+                entry.schema = schema.clone();
+            }
+        });
+        if let Err(e) = result {
+            error!(
+                "failed to update the catalog file, table: {}, error: {}",
+                table_id, e
+            );
+        }
+    }
+
+    /// Set the schema of the table `table_id` in memory only, return false
+    /// if the table is not in the catalog.
+    fn set_schema(table_id: u32, schema: &TableSchema, buffer_pool: &BufferPool) -> bool {
+        let table_rc = match Database::mut_catalog().get_table(&table_id) {
+            Some(table_rc) => table_rc,
+            None => return false,
+        };
+
+        buffer_pool.discard_table_pages(table_id);
+        table_rc.wl().set_schema(schema);
+        true
+    }
+
+    /// List the schemas changed by `tx` in the catalog file, called when
+    /// `tx` commits, before its "COMMIT" record is written. If the commit
+    /// doesn't go through, the undo of the schema changes restores the
+    /// previous schemas in the file.
+    pub(crate) fn write_schema_changes(tx: &Transaction) -> SmallResult {
+        let table_ids = Database::mut_concurrent_status().take_schema_changes(tx);
+        if table_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut schemas = HashMap::new();
+        for table_id in table_ids {
+            if let Some(table_rc) = Database::mut_catalog().get_table(&table_id) {
+                schemas.insert(table_id, table_rc.rl().get_schema());
+            }
+        }
+        CatalogFile::current().update(|entries| {
+            for entry in entries.iter_mut() {
+                if let Some(schema) = schemas.remove(&entry.table_id) {
+                    entry.schema = schema;
+                }
+            }
+        })
     }
 
     pub fn search_schema(&self, schema_name: &str) -> Option<SchemaRC> {
@@ -285,6 +711,32 @@ impl Catalog {
         None
     }
 
+    /// Get all tables in the catalog, including the system tables.
+    pub(crate) fn get_tables(&self) -> Vec<TableRC> {
+        self.tables.values().cloned().collect()
+    }
+
+    /// Get the tables created by the users, the system tables ("tables",
+    /// "schemas" and "pg_database") excluded.
+    pub(crate) fn get_user_tables(&self) -> Vec<TableRC> {
+        self.tables
+            .iter()
+            .filter(|(id, _)| ![TABLE_SCHEMA_ID, SCHEMA_ID, PG_DATABASE_ID].contains(id))
+            .map(|(_, table_rc)| table_rc.clone())
+            .collect()
+    }
+
+    /// Get the names of all tables in the catalog, in alphabetical order.
+    pub fn list_tables(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .tables
+            .values()
+            .map(|table_rc| table_rc.rl().name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
     pub fn search_table(&self, table_name: &str) -> Option<TableRC> {
         for table_rc in self.tables.values() {
             let table = table_rc.rl();
@@ -295,4 +747,38 @@ impl Catalog {
 
         None
     }
+
+    pub fn add_index(&mut self, index: IndexMeta) {
+        self.indexes.insert(index.name.clone(), index);
+    }
+
+    pub fn remove_index(&mut self, index_name: &str) -> Option<IndexMeta> {
+        self.indexes.remove(index_name)
+    }
+
+    pub fn search_index(&self, index_name: &str) -> Option<IndexMeta> {
+        self.indexes.get(index_name).cloned()
+    }
+
+    /// Get the secondary indexes of the table, in alphabetical order.
+    pub fn table_indexes(&self, table_name: &str) -> Vec<IndexMeta> {
+        let mut indexes: Vec<IndexMeta> = self
+            .indexes
+            .values()
+            .filter(|index| index.table_name == table_name)
+            .cloned()
+            .collect();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        indexes
+    }
+}
+
+/// Decode a name stored in the "tables" table.
+fn decode_name(bytes: Vec<u8>) -> Result<String, SmallError> {
+    String::from_utf8(bytes).map_err(|_| {
+        SmallError::with_kind(
+            SmallErrorKind::Corruption,
+            "the \"tables\" table holds a name which is not valid utf-8",
+        )
+    })
 }
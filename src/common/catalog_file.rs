@@ -0,0 +1,289 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    storage::table_schema::{Field, TableSchema},
+    types::SmallResult,
+    Database,
+};
+
+/// Name of the catalog file in the directory of the database.
+pub(crate) const CATALOG_FILE_NAME: &str = "catalog";
+
+/// A table listed in the catalog file.
+#[derive(Debug, Clone)]
+pub(crate) struct CatalogEntry {
+    pub(crate) table_id: u32,
+    pub(crate) name: String,
+    pub(crate) schema: TableSchema,
+    pub(crate) options: TableOptions,
+}
+
+/// The settings of a table which are persisted with its schema, so they
+/// are applied again when the table is loaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TableOptions {
+    /// The number of counters and the number of hashes of the Bloom filter,
+    /// see `BTreeTable::enable_bloom_filter`.
+    pub(crate) bloom_filter: Option<(usize, usize)>,
+
+    /// The cap on the children of the internal pages, see
+    /// `BTreeTable::set_max_fan_out`.
+    pub(crate) max_fan_out: Option<usize>,
+
+    /// Store the leaf pages deflated on disk, see
+    /// `BTreeTable::set_compressed`.
+    pub(crate) compressed: bool,
+
+    /// Keep the deleted tuples as tombstones, see
+    /// `BTreeTable::set_retain_tombstones`.
+    pub(crate) retain_tombstones: bool,
+
+    /// The id of the indexed table and the position of the indexed column,
+    /// for the table of a secondary index (see `IndexMeta`).
+    pub(crate) index_of: Option<(u32, usize)>,
+}
+
+impl TableOptions {
+    fn encode(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some((counters_count, hashes_count)) = self.bloom_filter {
+            options.push(format!("bloom_filter={}/{}", counters_count, hashes_count));
+        }
+        if let Some(fan_out) = self.max_fan_out {
+            options.push(format!("max_fan_out={}", fan_out));
+        }
+        if self.compressed {
+            options.push("compressed=1".to_string());
+        }
+        if self.retain_tombstones {
+            options.push("retain_tombstones=1".to_string());
+        }
+        if let Some((table_id, field_index)) = self.index_of {
+            options.push(format!("index_of={}/{}", table_id, field_index));
+        }
+        options
+    }
+
+    /// Parse the options column, None if it's malformed. Unknown options
+    /// are rejected as well, they may change how the table is read.
+    fn decode(column: &str) -> Option<Self> {
+        let mut options = Self::default();
+        for option in column.split(',').filter(|option| !option.is_empty()) {
+            let (name, value) = option.split_once('=')?;
+            match name {
+                "bloom_filter" => {
+                    let (counters_count, hashes_count) = value.split_once('/')?;
+                    options.bloom_filter =
+                        Some((counters_count.parse().ok()?, hashes_count.parse().ok()?));
+                }
+                "max_fan_out" => {
+                    let fan_out: usize = value.parse().ok()?;
+                    if fan_out < 3 {
+                        return None;
+                    }
+                    options.max_fan_out = Some(fan_out);
+                }
+                "compressed" => {
+                    options.compressed = match value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return None,
+                    };
+                }
+                "retain_tombstones" => {
+                    options.retain_tombstones = match value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return None,
+                    };
+                }
+                "index_of" => {
+                    let (table_id, field_index) = value.split_once('/')?;
+                    options.index_of = Some((table_id.parse().ok()?, field_index.parse().ok()?));
+                }
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+}
+
+/// The list of the tables of a database, so the database knows which
+/// tables to open before anything else is read.
+///
+/// # Format
+///
+/// A text file, one line per table, the columns are separated by tabs:
+///
+/// - table id
+/// - table name
+/// - the fields of the schema separated by commas, each field is
+///   `name:type:key`, where the type is the hex of `Field::type_to_bytes`
+///   and key is 1 for the key field, 0 otherwise.
+/// - (optional) the options of the table (see `TableOptions`) separated by
+///   commas, each option is `name=value`. The column is left out when the
+///   table has no option.
+///
+/// The names are percent-encoded (see `escape`), so they can hold any of
+/// the separators.
+///
+/// The file is rewritten as a whole (into a temporary file which is synced
+/// and then renamed over it) on every change, so it's never seen half
+/// written.
+pub(crate) struct CatalogFile {
+    path: PathBuf,
+}
+
+impl CatalogFile {
+    pub(crate) fn new(db_path: &Path) -> Self {
+        Self {
+            path: db_path.join(CATALOG_FILE_NAME),
+        }
+    }
+
+    /// The catalog file of the current database.
+    pub(crate) fn current() -> Self {
+        Self::new(&Database::global().get_path())
+    }
+
+    pub(crate) fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Read the tables listed in the file, an absent file lists no table.
+    pub(crate) fn read(&self) -> Result<Vec<CatalogEntry>, SmallError> {
+        if !self.exists() {
+            return Ok(Vec::new());
+        }
+
+        let text = fs::read_to_string(&self.path)?;
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(Self::parse_line)
+            .collect()
+    }
+
+    /// Replace the content of the file with `entries`.
+    pub(crate) fn write(&self, entries: &[CatalogEntry]) -> SmallResult {
+        let mut text = String::new();
+        for entry in entries {
+            let fields: Vec<String> = entry
+                .schema
+                .get_fields()
+                .iter()
+                .map(|f| {
+                    format!(
+                        "{}:{}:{}",
+                        escape(&f.name),
+                        hex::encode(f.type_to_bytes()),
+                        f.is_primary as u8
+                    )
+                })
+                .collect();
+            text.push_str(&format!(
+                "{}\t{}\t{}",
+                entry.table_id,
+                escape(&entry.name),
+                fields.join(",")
+            ));
+            let options = entry.options.encode();
+            if !options.is_empty() {
+                text.push_str(&format!("\t{}", options.join(",")));
+            }
+            text.push('\n');
+        }
+
+        // the content must be on disk before the rename makes it the catalog
+        // file, and the rename itself before the change is relied upon
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir).and_then(|dir| dir.sync_all())?;
+        }
+        Ok(())
+    }
+
+    /// Read the file, apply `f` to the entries and write them back.
+    pub(crate) fn update<F: FnOnce(&mut Vec<CatalogEntry>)>(&self, f: F) -> SmallResult {
+        let _latch = Database::catalog_file_latch();
+        let mut entries = self.read()?;
+        f(&mut entries);
+        self.write(&entries)
+    }
+
+    fn parse_line(line: &str) -> Result<CatalogEntry, SmallError> {
+        let corrupted = || {
+            SmallError::with_kind(
+                SmallErrorKind::Corruption,
+                &format!("invalid line in the catalog file: {}", line),
+            )
+        };
+
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() != 3 && columns.len() != 4 {
+            return Err(corrupted());
+        }
+        let table_id = columns[0].parse().or(Err(corrupted()))?;
+
+        let mut fields = Vec::new();
+        for field in columns[2].split(',') {
+            let parts: Vec<&str> = field.split(':').collect();
+            if parts.len() != 3 {
+                return Err(corrupted());
+            }
+            let name = unescape(parts[0]).ok_or_else(corrupted)?;
+            let type_bytes = hex::decode(parts[1]).or(Err(corrupted()))?;
+            fields.push(Field::from_type_bytes(&name, &type_bytes, parts[2] == "1"));
+        }
+
+        let options = match columns.get(3) {
+            Some(column) => TableOptions::decode(column).ok_or_else(corrupted)?,
+            None => TableOptions::default(),
+        };
+
+        Ok(CatalogEntry {
+            table_id,
+            name: unescape(columns[1]).ok_or_else(corrupted)?,
+            schema: TableSchema::new(fields),
+            options,
+        })
+    }
+}
+
+/// Percent-encode the separators of the catalog file (tab, comma, colon and
+/// the line breaks) and the percent sign itself.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '%' | '\t' | ',' | ':' | '\n' | '\r' => escaped.push_str(&format!("%{:02X}", c as u8)),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse `escape`, None if `name` is not a valid output of it.
+fn unescape(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail.get(..2)?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -1,14 +1,27 @@
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     mem,
-    path::PathBuf,
-    sync::{Arc, Once, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, MutexGuard, Once, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
+use lazy_static::lazy_static;
+use log::error;
+
 use super::Catalog;
 use crate::{
     btree::buffer_pool::BufferPool,
-    transaction::{ConcurrentStatus, LogManager},
-    types::Pod,
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::Tuple,
+    transaction::{ConcurrentStatus, LogManager, TransactionID},
+    types::{Pod, SmallResult},
     utils::HandyRwLock,
 };
 
@@ -25,7 +38,8 @@ use crate::{
 ///
 /// TODO: update this comment
 ///
-/// TODO: support multiple databases
+/// Besides the global database, independent databases can be opened by
+/// `Database::open` and entered by `Database::enter`.
 pub struct Database {
     path: PathBuf,
 
@@ -33,19 +47,188 @@ pub struct Database {
     catalog: Pod<Catalog>,
     concurrent_status: Pod<ConcurrentStatus>,
     log_manager: Pod<LogManager>,
+
+    /// Taken in write mode by DDL statements and in read mode by DML
+    /// statements, so no statement runs against a table being created,
+    /// dropped or altered.
+    ddl_latch: RwLock<()>,
+
+    /// Serializes `Catalog::get_or_create_table`, so a table is never
+    /// created twice by concurrent callers.
+    create_table_latch: Mutex<()>,
+
+    /// Serializes the updates of the catalog file (see `CatalogFile`).
+    catalog_file_latch: Mutex<()>,
+
+    /// The background thread started by `start_checkpointer`.
+    checkpointer: Mutex<Option<Checkpointer>>,
+
+    /// The hooks registered by `on_commit`, in registration order, with
+    /// the id of their handle.
+    commit_hooks: Arc<RwLock<Vec<(u64, CommitHook)>>>,
+}
+
+struct Checkpointer {
+    /// Dropping the sender stops the thread.
+    stop: mpsc::Sender<()>,
+    handle: JoinHandle<()>,
 }
 
 static mut SINGLETON: *mut Database = 0 as *mut Database;
 
+lazy_static! {
+    /// The databases created by `Database::open` and its variants, by the
+    /// canonical path of their directory, so a directory is never opened
+    /// twice. The entry is removed when the database is closed or dropped.
+    static ref OPENED: Mutex<HashMap<PathBuf, Weak<Database>>> = Mutex::new(HashMap::new());
+}
+
+/// The name of the WAL file in the directory of the database.
+const WAL_FILE_NAME: &str = "wal.log";
+
+thread_local! {
+    /// Databases entered by the current thread through `Database::enter`,
+    /// the last one is returned by `Database::global`.
+    static ENTERED: RefCell<Vec<Arc<Database>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returned by `Database::enter`, the database stays the current database
+/// of the thread until the guard is dropped.
+pub struct DatabaseGuard {
+    // not Send, the guard must be dropped by the thread that entered
+    _marker: std::marker::PhantomData<*const ()>,
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+impl Drop for DatabaseGuard {
+    fn drop(&mut self) {
+        ENTERED.with(|entered| {
+            entered.borrow_mut().pop();
+        });
+    }
+}
+
+/// A snapshot of the database status, returned by `Database::health`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// Number of tables in the catalog, system tables excluded.
+    pub table_count: usize,
+
+    /// Number of pages in the files of all tables (root pointer pages
+    /// excluded).
+    pub total_pages: usize,
+
+    /// Number of cached pages modified by running transactions.
+    pub dirty_pages: usize,
+
+    /// Size of the WAL file in bytes.
+    pub wal_size: u64,
+
+    /// Offset of the last checkpoint record in the WAL, `None` if no
+    /// checkpoint has been written.
+    pub last_checkpoint_offset: Option<u64>,
+}
+
+impl HealthReport {
+    /// Get the report as (name, value) pairs, in the order of the fields.
+    pub fn entries(&self) -> Vec<(&'static str, Option<u64>)> {
+        vec![
+            ("table_count", Some(self.table_count as u64)),
+            ("total_pages", Some(self.total_pages as u64)),
+            ("dirty_pages", Some(self.dirty_pages as u64)),
+            ("wal_size", Some(self.wal_size)),
+            ("last_checkpoint_offset", self.last_checkpoint_offset),
+        ]
+    }
+}
+
+/// What `Database::open_with` does when the directory holds tables but the
+/// WAL is missing (or empty).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnMissingWal {
+    /// Start with a new WAL, there is nothing to recover. This is what
+    /// `open` does.
+    CreateFresh,
+
+    /// Refuse to open the directory, the WAL may have been lost or the
+    /// directory may not be the one of the database.
+    Error,
+}
+
+/// The options of `Database::open_with`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenOptions {
+    pub on_missing_wal: OnMissingWal,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            on_missing_wal: OnMissingWal::CreateFresh,
+        }
+    }
+}
+
+/// A tuple modified by a transaction, `before` is `None` for an insertion
+/// and `after` is `None` for a deletion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TupleChange {
+    pub table: String,
+    pub before: Option<Tuple>,
+    pub after: Option<Tuple>,
+}
+
+/// The changes of a committed transaction, passed to the hooks registered by
+/// `Database::on_commit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitEvent {
+    pub tx_id: TransactionID,
+
+    /// The changes in the order they were made.
+    pub changes: Vec<TupleChange>,
+}
+
+pub type CommitHook = Arc<dyn Fn(&CommitEvent) + Send + Sync>;
+
+/// Returned by `Database::on_commit`, the hook is unregistered when the
+/// handle is dropped.
+#[must_use = "the hook is unregistered when the handle is dropped"]
+pub struct CommitHookHandle {
+    hooks: Weak<RwLock<Vec<(u64, CommitHook)>>>,
+    id: u64,
+}
+
+impl Drop for CommitHookHandle {
+    fn drop(&mut self) {
+        // the database may be gone already
+        if let Some(hooks) = self.hooks.upgrade() {
+            hooks.wl().retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
 impl Database {
     fn new() -> Self {
+        Self::new_at(Self::default_path())
+    }
+
+    /// The directory of the global database when no path is given.
+    pub fn default_path() -> PathBuf {
         let db_name = "default_db";
-        let db_path = PathBuf::from("data").join(db_name);
+        PathBuf::from("data").join(db_name)
+    }
+
+    fn new_at(db_path: PathBuf) -> Self {
         if !db_path.exists() {
             std::fs::create_dir_all(&db_path).unwrap();
         }
 
-        let log_path = db_path.join("wal.log");
+        let log_path = db_path.join(WAL_FILE_NAME);
 
         let instance = Self {
             path: db_path,
@@ -54,6 +237,16 @@ impl Database {
             concurrent_status: Arc::new(RwLock::new(ConcurrentStatus::new())),
             catalog: Arc::new(RwLock::new(Catalog::new())),
             log_manager: Arc::new(RwLock::new(LogManager::new(log_path))),
+
+            ddl_latch: RwLock::new(()),
+
+            create_table_latch: Mutex::new(()),
+
+            catalog_file_latch: Mutex::new(()),
+
+            checkpointer: Mutex::new(None),
+
+            commit_hooks: Arc::new(RwLock::new(Vec::new())),
         };
 
         return instance;
@@ -68,8 +261,18 @@ impl Database {
     /// - Status of `log_manager` will be reset, but the log file
     ///  itself will keep unchanged.
     pub fn reset() {
+        Self::reset_at(Self::default_path());
+    }
+
+    /// Same as `reset`, but the global database is stored in the given
+    /// directory.
+    pub fn reset_at<P: AsRef<Path>>(path: P) {
         // Initialize the new db instance.
-        let singleton = Self::new();
+        let singleton = Self::new_at(path.as_ref().to_path_buf());
+
+        // the checkpointer of the previous instance would keep running
+        // against the new one
+        Self::stop_checkpointer();
 
         unsafe {
             if !SINGLETON.is_null() {
@@ -82,11 +285,341 @@ impl Database {
             SINGLETON = mem::transmute(Box::new(singleton));
         }
 
-        Catalog::load_tables().unwrap();
-        Catalog::load_schemas().unwrap();
+        Self::init();
+    }
+
+    /// Open the database stored in the given directory, independent of the
+    /// global database and of the other opened databases: it has its own
+    /// catalog, buffer pool and WAL.
+    ///
+    /// The tables, pages and transactions of the database are only
+    /// accessible from a thread which has entered it through
+    /// `Database::enter`. The database is freed when the last handle is
+    /// dropped (an entered database is held by the thread until the guard
+    /// is dropped).
+    ///
+    /// Fails with `SmallErrorKind::AlreadyOpen` if the directory is already
+    /// opened by the process, until that database is closed or dropped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Arc<Database>, SmallError> {
+        let db = Self::register(path.as_ref())?;
+
+        let _guard = Database::enter(&db);
+        Self::init();
+
+        Ok(db.clone())
+    }
+
+    /// Same as `open`, but the failures of the startup are returned instead
+    /// of panicking. This is the startup path for embedders after a crash:
+    /// the catalog is loaded and the log is recovered (the changes of the
+    /// committed transactions are redone, the ones of the unfinished
+    /// transactions are undone) before the database is returned.
+    pub fn open_and_recover<P: AsRef<Path>>(path: P) -> Result<Arc<Database>, SmallError> {
+        let db = Self::register(path.as_ref())?;
+
+        let _guard = Database::enter(&db);
+        Self::try_init()?;
+
+        Ok(db.clone())
+    }
+
+    /// Create the database stored in `path` and register it in `OPENED`,
+    /// unless the directory is already opened.
+    fn register(path: &Path) -> Result<Arc<Database>, SmallError> {
+        std::fs::create_dir_all(path).or(Err(SmallError::with_kind(
+            SmallErrorKind::Io,
+            &format!("failed to create {:?}", path),
+        )))?;
+        let key = Self::canonical_path(path)?;
+
+        let mut opened = OPENED.lock().unwrap();
+        let global_path = unsafe { SINGLETON.as_ref() }.map(|db| db.get_path());
+        let in_use = opened.get(&key).is_some_and(|db| db.strong_count() > 0)
+            || global_path
+                .is_some_and(|global| Self::canonical_path(&global).ok() == Some(key.clone()));
+        if in_use {
+            let err_msg = format!("the database {:?} is already opened", path);
+            return Err(SmallError::with_kind(SmallErrorKind::AlreadyOpen, &err_msg));
+        }
+
+        let db = Arc::new(Self::new_at(path.to_path_buf()));
+        opened.insert(key, Arc::downgrade(&db));
+        Ok(db)
+    }
+
+    fn canonical_path(path: &Path) -> Result<PathBuf, SmallError> {
+        path.canonicalize().or(Err(SmallError::with_kind(
+            SmallErrorKind::Io,
+            &format!("failed to resolve {:?}", path),
+        )))
+    }
+
+    /// Same as `open_and_recover`, with the behavior on a missing WAL given
+    /// by `options`.
+    ///
+    /// A directory without any table is a new database, it's opened
+    /// whatever the options.
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        options: &OpenOptions,
+    ) -> Result<Arc<Database>, SmallError> {
+        let path = path.as_ref();
 
-        Database::mut_log_manager().recover().unwrap();
+        if options.on_missing_wal == OnMissingWal::Error
+            && Self::wal_missing(path)
+            && Self::has_table_files(path)?
+        {
+            let err_msg = format!(
+                "the directory {:?} holds tables but no WAL ({})",
+                path, WAL_FILE_NAME
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::NotFound, &err_msg));
+        }
+
+        Self::open_and_recover(path)
+    }
+
+    /// The WAL of the directory is absent or empty.
+    fn wal_missing(path: &Path) -> bool {
+        match std::fs::metadata(path.join(WAL_FILE_NAME)) {
+            Ok(metadata) => metadata.len() == 0,
+            Err(_) => true,
+        }
+    }
+
+    fn has_table_files(path: &Path) -> Result<bool, SmallError> {
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            // the directory is created on open
+            Err(_) => return Ok(false),
+        };
+
+        for entry in entries {
+            let entry = entry.or(Err(SmallError::with_kind(
+                SmallErrorKind::Io,
+                &format!("failed to list {:?}", path),
+            )))?;
+            if entry.path().extension().is_some_and(|ext| ext == "table") {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Make `db` the current database of the thread, all accessors
+    /// (`Database::catalog`, `Database::mut_buffer_pool`, ...) refer to it
+    /// until the returned guard is dropped.
+    ///
+    /// Entering is thread-local, threads spawned by the caller have to enter
+    /// the database themselves.
+    pub fn enter(db: &Arc<Database>) -> DatabaseGuard {
+        ENTERED.with(|entered| entered.borrow_mut().push(db.clone()));
+        DatabaseGuard {
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Load the catalog and recover from the log.
+    fn init() {
+        Self::try_init().unwrap();
+    }
+
+    fn try_init() -> SmallResult {
+        Catalog::load_tables()?;
+        Catalog::load_schemas()?;
+
+        Database::mut_log_manager().recover()?;
         Database::mut_concurrent_status().clear();
+
+        Catalog::apply_table_options()?;
+        Ok(())
+    }
+
+    /// Register a hook called with the changes of every transaction
+    /// committed from now on, after its "COMMIT" record is durable. For
+    /// streaming the changes to downstream systems (change data capture).
+    ///
+    /// The hooks run on the committing thread, after the latches of the
+    /// transaction are released. Changes are only recorded while a hook is
+    /// registered, so a transaction running across the registration reports
+    /// the changes made after it.
+    ///
+    /// The hook stays registered until the returned handle is dropped.
+    pub fn on_commit(hook: CommitHook) -> CommitHookHandle {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        let hooks = &Self::global().commit_hooks;
+        hooks.wl().push((id, hook));
+        CommitHookHandle {
+            hooks: Arc::downgrade(hooks),
+            id,
+        }
+    }
+
+    pub(crate) fn has_commit_hooks() -> bool {
+        !Self::global().commit_hooks.rl().is_empty()
+    }
+
+    pub(crate) fn emit_commit(event: &CommitEvent) {
+        let hooks = Self::global().commit_hooks.rl().clone();
+        for (_, hook) in hooks {
+            hook(event);
+        }
+    }
+
+    /// Shut down the database cleanly.
+    ///
+    /// This is the clean shutdown path: all pages in the buffer pool are
+    /// written to disk and a final checkpoint is appended to the log, so
+    /// nothing is left for the recovery on the next start. (Dropping the
+    /// database without calling this method is treated as a crash.)
+    ///
+    /// All transactions must be finished (committed or aborted) before the
+    /// call, otherwise an error is returned and nothing is written.
+    ///
+    /// A page which can't be written fails the call, the database stays
+    /// open then.
+    pub fn close() -> SmallResult {
+        if let Some(tx_id) = Database::concurrent_status().min_active_tx() {
+            let err_msg = format!("cannot close database, transaction {} is active", tx_id);
+            return Err(SmallError::new(&err_msg));
+        }
+
+        // step 1: write all pages to disk
+        Database::mut_buffer_pool().write_all_pages()?;
+
+        // step 2: write the final checkpoint
+        Database::mut_log_manager().log_checkpoint()?;
+
+        // step 3: clear the page cache, pages are read from disk from now on
+        Database::mut_buffer_pool().clear();
+
+        // step 4: the directory can be opened again
+        Self::global().unregister();
+
+        Ok(())
+    }
+
+    /// Remove the database from `OPENED` if it was opened by `open`.
+    fn unregister(&self) {
+        let key = match Self::canonical_path(&self.path) {
+            Ok(key) => key,
+            Err(_) => return,
+        };
+
+        let mut opened = OPENED.lock().unwrap();
+        let is_self = opened
+            .get(&key)
+            .is_some_and(|db| std::ptr::eq(db.as_ptr(), self));
+        if is_self {
+            opened.remove(&key);
+        }
+    }
+
+    /// Shared access to the buffer pool, enough to get and insert pages
+    /// since the page caches lock their shards internally.
+    pub fn buffer_pool() -> RwLockReadGuard<'static, BufferPool> {
+        Self::global().buffer_pool.rl()
+    }
+
+    /// Make everything written so far durable, without writing a
+    /// checkpoint.
+    ///
+    /// The dirty pages of the buffer pool are flushed (following the same
+    /// steal policy as the checkpoint), then the WAL and the files of all
+    /// tables are synced to the disk. The call returns once all of them are
+    /// durable.
+    pub fn sync() -> SmallResult {
+        // step 1: flush the dirty pages
+        Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager())?;
+
+        // step 2: sync the log first, the pages on disk must never be ahead
+        // of the log
+        Database::mut_log_manager().sync()?;
+
+        // step 3: sync the table files
+        let tables = Database::catalog().get_tables();
+        for table_rc in tables {
+            table_rc.rl().sync_file()?;
+        }
+
+        Ok(())
+    }
+
+    /// Collect a report of the database status, for monitoring.
+    pub fn health() -> Result<HealthReport, SmallError> {
+        let tables = Database::catalog().get_tables();
+        let table_count = Database::catalog().get_user_tables().len();
+        let total_pages = tables
+            .iter()
+            .map(|table_rc| table_rc.rl().pages_count())
+            .sum();
+
+        let dirty_pages = Database::concurrent_status().dirty_pages_count();
+
+        let mut log_manager = Database::mut_log_manager();
+        let wal_size = log_manager.size()?;
+        let last_checkpoint_offset = log_manager.last_checkpoint_offset()?;
+
+        Ok(HealthReport {
+            table_count,
+            total_pages,
+            dirty_pages,
+            wal_size,
+            last_checkpoint_offset,
+        })
+    }
+
+    /// Take a checkpoint.
+    ///
+    /// The buffer pool is taken before the log, the same order as the
+    /// commit, so it's safe to call while other transactions are running.
+    pub fn checkpoint() -> SmallResult {
+        let buffer_pool = Database::buffer_pool();
+        let mut log_manager = Database::mut_log_manager();
+        log_manager.log_checkpoint_with(&buffer_pool)
+    }
+
+    /// Start a background thread taking a checkpoint every `interval`.
+    ///
+    /// Return an error if the checkpointer is already running. It runs until
+    /// `stop_checkpointer` is called.
+    pub fn start_checkpointer(interval: Duration) -> SmallResult {
+        let mut checkpointer = Self::global().checkpointer.lock().unwrap();
+        if checkpointer.is_some() {
+            return Err(SmallError::new("checkpointer is already running"));
+        }
+
+        // the thread works on the current database of the caller
+        let db = ENTERED.with(|entered| entered.borrow().last().cloned());
+
+        let (stop, stop_receiver) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let _guard = db.as_ref().map(Database::enter);
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                if let Err(e) = Database::checkpoint() {
+                    error!("checkpoint failed: {}", e);
+                }
+            }
+        });
+
+        *checkpointer = Some(Checkpointer { stop, handle });
+        Ok(())
+    }
+
+    /// Stop the checkpointer and wait for its thread to finish, do nothing if
+    /// it's not running.
+    pub fn stop_checkpointer() {
+        let checkpointer = match Self::try_global() {
+            Some(db) => db.checkpointer.lock().unwrap().take(),
+            None => None,
+        };
+
+        if let Some(checkpointer) = checkpointer {
+            drop(checkpointer.stop);
+            checkpointer.handle.join().unwrap();
+        }
     }
 
     pub fn mut_buffer_pool() -> RwLockWriteGuard<'static, BufferPool> {
@@ -117,7 +650,36 @@ impl Database {
         Self::global().log_manager.wl()
     }
 
+    /// Held by DDL statements, waits for the running DML statements and
+    /// blocks new ones.
+    pub fn ddl_latch() -> RwLockWriteGuard<'static, ()> {
+        Self::global().ddl_latch.wl()
+    }
+
+    /// Held by DML statements, blocks DDL statements until the guard is
+    /// dropped.
+    pub fn dml_latch() -> RwLockReadGuard<'static, ()> {
+        Self::global().ddl_latch.rl()
+    }
+
+    pub(crate) fn create_table_latch() -> MutexGuard<'static, ()> {
+        Self::global().create_table_latch.lock().unwrap()
+    }
+
+    pub(crate) fn catalog_file_latch() -> MutexGuard<'static, ()> {
+        Self::global().catalog_file_latch.lock().unwrap()
+    }
+
+    /// Get the current database: the database most recently entered by the
+    /// thread, or the global database if there is none.
     pub fn global() -> &'static Self {
+        let entered = ENTERED.with(|entered| entered.borrow().last().map(Arc::as_ptr));
+        if let Some(db) = entered {
+            // an entered database is kept alive by the thread until the
+            // guard is dropped, the references must not outlive the guard
+            return unsafe { &*db };
+        }
+
         // Initialize it to a null value
         // static mut SINGLETON: *mut Database = 0 as *mut Database;
         static ONCE: Once = Once::new();
@@ -139,6 +701,17 @@ impl Database {
         }
     }
 
+    /// Same as `global`, but return `None` instead of creating the global
+    /// database if it doesn't exist yet.
+    fn try_global() -> Option<&'static Self> {
+        let entered = ENTERED.with(|entered| entered.borrow().last().is_some());
+        if entered {
+            return Some(Self::global());
+        }
+
+        unsafe { SINGLETON.as_ref() }
+    }
+
     pub fn get_path(&self) -> PathBuf {
         self.path.clone()
     }
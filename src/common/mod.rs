@@ -4,4 +4,7 @@ pub use database::*;
 mod catalog;
 pub use catalog::*;
 
+mod catalog_file;
+pub(crate) use catalog_file::TableOptions;
+
 pub mod schema;
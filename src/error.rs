@@ -2,21 +2,89 @@ use std::{error::Error, fmt};
 
 use backtrace::Backtrace;
 
+/// The category of a `SmallError`, so callers can react to specific
+/// failures without matching on the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmallErrorKind {
+    /// The operation was cancelled through a `CancellationToken`.
+    Cancelled,
+
+    /// The data doesn't match the schema of the table.
+    SchemaMismatch,
+
+    /// The transaction has already been committed or aborted.
+    InactiveTransaction,
+
+    /// The data of a page is shorter than the page size, e.g. the table
+    /// file is truncated in the middle of a page.
+    ShortPage,
+
+    /// A tuple with the same key already exists in the table.
+    DuplicateKey,
+
+    /// The sibling pointers of the leaf pages form a cycle.
+    CorruptSiblingChain,
+
+    /// A path from the root is longer than the pages of the table allow,
+    /// e.g. the internal pages form a cycle.
+    TreeTooDeep,
+
+    /// Reading or writing a file failed.
+    Io,
+
+    /// The data on disk (pages or log records) is inconsistent.
+    Corruption,
+
+    /// A latch could not be acquired before the timeout.
+    LockTimeout,
+
+    /// Waiting for a latch would cause a deadlock.
+    Deadlock,
+
+    /// The requested table, column or index doesn't exist.
+    NotFound,
+
+    /// The query returns more rows than the limit of the session.
+    ResultTooLarge,
+
+    /// A read-only transaction attempted to modify the data.
+    ReadOnlyTransaction,
+
+    /// All page indexes of the table are in use, no page can be added.
+    PageIndexExhausted,
+
+    /// The directory of the database is already opened by the process.
+    AlreadyOpen,
+
+    /// Errors that don't belong to any specific category.
+    Other,
+}
+
 #[derive(Debug)]
 pub struct SmallError {
+    kind: SmallErrorKind,
     msg: String,
     backtrace: String,
 }
 
 impl SmallError {
     pub(crate) fn new(msg: &str) -> SmallError {
+        Self::with_kind(SmallErrorKind::Other, msg)
+    }
+
+    pub(crate) fn with_kind(kind: SmallErrorKind, msg: &str) -> SmallError {
         let bt = Backtrace::new();
         SmallError {
+            kind,
             msg: msg.to_string(),
             backtrace: format!("error backtrace:\n{:?}", bt),
         }
     }
 
+    pub fn kind(&self) -> SmallErrorKind {
+        self.kind
+    }
+
     pub fn show_backtrace(&self) {
         println!("error: {}\n{}", self.msg, self.backtrace);
     }
@@ -34,6 +102,12 @@ impl Error for SmallError {
     }
 }
 
+impl From<std::io::Error> for SmallError {
+    fn from(e: std::io::Error) -> Self {
+        Self::with_kind(SmallErrorKind::Io, &format!("io error: {}", e))
+    }
+}
+
 /// Get the line number and file name of the caller
 pub(crate) fn get_caller() -> String {
     let bt = Backtrace::new();
@@ -8,7 +8,10 @@ use std::{
 
 use bit_vec::BitVec;
 
-use crate::{error::SmallError, types::SmallResult};
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    types::SmallResult,
+};
 
 pub struct SmallFile {
     file: File,
@@ -38,35 +41,72 @@ impl SmallFile {
     }
 
     pub fn get_size(&self) -> Result<u64, SmallError> {
-        let metadata = self.file.metadata().or(Err(SmallError::new("io error")))?;
+        let metadata = self.file.metadata()?;
         Ok(metadata.len())
     }
 
     pub fn get_current_position(&mut self) -> Result<u64, SmallError> {
-        let offset = self
-            .file
-            .seek(std::io::SeekFrom::Current(0))
-            .or(Err(SmallError::new("io error")))?;
+        let offset = self.file.seek(std::io::SeekFrom::Current(0))?;
         Ok(offset)
     }
 
     pub fn set_len(&self, len: u64) -> SmallResult {
-        self.file
-            .set_len(len)
-            .or(Err(SmallError::new("io error")))?;
+        self.file.set_len(len)?;
         Ok(())
     }
 
     pub fn seek(&mut self, pos: SeekFrom) -> Result<u64, SmallError> {
-        self.file.seek(pos).or(Err(SmallError::new("io error")))
+        Ok(self.file.seek(pos)?)
     }
 
     pub fn flush(&mut self) -> SmallResult {
-        self.file.flush().or(Err(SmallError::new("io error")))?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Make the content of the file durable, i.e. wait until the operating
+    /// system has written it to the disk.
+    pub fn sync(&mut self) -> SmallResult {
+        self.flush()?;
+        self.file.sync_all()?;
         Ok(())
     }
 }
 
+/// Deallocate the disk blocks of the `len` bytes of `file` starting at
+/// `offset`, they read back as zeros. The length of the file is unchanged.
+///
+/// Only the blocks entirely inside the range are freed, the rest of the
+/// range is zeroed. It's a no-op on the platforms and the file systems
+/// without hole punching, the bytes are left as they are then.
+pub fn punch_hole(file: &File, offset: u64, len: u64) -> SmallResult {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let ret = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => {}
+                _ => return Err(SmallError::with_kind(SmallErrorKind::Io, &err.to_string())),
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, offset, len);
+
+    Ok(())
+}
+
 impl std::io::Read for SmallFile {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         self.file.read(buf)
@@ -95,6 +135,22 @@ pub fn read_exact<R: std::io::Read>(reader: &mut R, bytes_count: usize) -> Vec<u
     buffer
 }
 
+/// Same as `read_exact`, but a truncated input is reported as a
+/// `Corruption` error instead of panicking.
+pub fn try_read_exact<R: std::io::Read>(
+    reader: &mut R,
+    bytes_count: usize,
+) -> Result<Vec<u8>, SmallError> {
+    let mut buffer = vec![0u8; bytes_count];
+    reader
+        .read_exact(&mut buffer)
+        .or(Err(SmallError::with_kind(
+            SmallErrorKind::Corruption,
+            &format!("truncated data, expect {} more bytes", bytes_count),
+        )))?;
+    Ok(buffer)
+}
+
 pub struct SmallWriter {
     buf: Vec<u8>,
 }
@@ -146,6 +202,13 @@ impl SmallWriter {
     }
 }
 
+/// The encoding of the objects written to the table files, the WAL and the
+/// dump streams.
+///
+/// All integers (and floats) are written in little-endian byte order, every
+/// encoder writing raw integer bytes should go through the implementations
+/// here instead of calling `to_le_bytes`/`to_be_bytes` itself, so a value
+/// written by one module is read back the same by another.
 pub(crate) trait Serializeable {
     type Reference;
 
@@ -272,3 +335,53 @@ macro_rules! impl_serialization {
 }
 
 impl_serialization!(for u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, isize, usize, f32, f64);
+
+/// The maximum size of an i64 encoded by `write_varint`.
+pub const MAX_VARINT_SIZE: usize = 10;
+
+/// Write `v` as a zigzag LEB128 varint: 7 bits per byte, least significant
+/// group first, the high bit of each byte tells whether more bytes follow.
+///
+/// Values in the range [-64, 63] take 1 byte, the widest values take
+/// `MAX_VARINT_SIZE` bytes.
+pub fn write_varint(writer: &mut SmallWriter, v: i64) {
+    let mut n = ((v << 1) ^ (v >> 63)) as u64;
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            writer.write_bytes(&[byte]);
+            return;
+        }
+        writer.write_bytes(&[byte | 0x80]);
+    }
+}
+
+/// Read a varint written by `write_varint`. A truncated varint or one
+/// longer than `MAX_VARINT_SIZE` is reported as a `Corruption` error.
+pub fn try_read_varint<R: std::io::Read>(reader: &mut R) -> Result<i64, SmallError> {
+    let mut n: u64 = 0;
+    let mut shift = 0;
+    for _ in 0..MAX_VARINT_SIZE {
+        let byte = try_read_exact(reader, 1)?[0];
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((n >> 1) as i64) ^ -((n & 1) as i64));
+        }
+        shift += 7;
+    }
+
+    let err_msg = format!("varint longer than {} bytes", MAX_VARINT_SIZE);
+    Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg))
+}
+
+/// Get the size of `v` encoded by `write_varint`.
+pub fn varint_size(v: i64) -> usize {
+    let mut n = ((v << 1) ^ (v >> 63)) as u64;
+    let mut size = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        size += 1;
+    }
+    size
+}
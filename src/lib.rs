@@ -14,6 +14,7 @@ mod predicate;
 
 pub use btree::table::BTreeTable;
 pub use common::Database;
+pub use error::{SmallError, SmallErrorKind};
 pub use operator::Op;
 pub use predicate::Predicate;
 pub use storage::table_schema::TableSchema;
@@ -1,54 +1,42 @@
-use std::sync::{Arc, Mutex};
-
-use log::info;
-use pgwire::{
-    api::{
-        auth::noop::NoopStartupHandler, query::PlaceholderExtendedQueryHandler, MakeHandler,
-        StatelessMakeHandler,
-    },
-    tokio::process_socket,
-};
+use log::{info, warn};
 use small_db::{
-    server::pg_handler::PostgresHandler, sql::session::Session, utils::init_log, Database,
+    server::{serve, AuthMethod, ServerConfig},
+    utils::init_log,
+    Database,
 };
-use tokio::net::TcpListener;
 
 /// Connect to the server with
-/// `psql -h localhost -p 5433 -d default_db -U xiaochen`
+/// `psql -h localhost -p 5432 -d default_db -U xiaochen`
+///
+/// The server is configured by the environment variables `SMALL_DB_HOST`,
+/// `SMALL_DB_PORT` and `SMALL_DB_PATH` (see `ServerConfig`), e.g. to run
+/// it next to a real Postgres or in a container.
+///
+/// The clients are authenticated by password, the users are given by
+/// `SMALL_DB_USERS`, e.g. `SMALL_DB_USERS=xiaochen:secret`. For local
+/// development, `SMALL_DB_DEV=1 SMALL_DB_AUTH=trust` accepts every
+/// connection.
 #[tokio::main]
 pub async fn main() {
     init_log();
 
-    Database::reset();
-
-    let session = Arc::new(Mutex::new(Session::new()));
-    let pg_handler = PostgresHandler::new(session);
+    let config = ServerConfig::from_env().unwrap();
+    let auth = match &config.auth {
+        AuthMethod::Trust => "trust".to_string(),
+        AuthMethod::Md5 { users } => {
+            if users.is_empty() {
+                warn!("no user is given by SMALL_DB_USERS, every connection is rejected");
+            }
+            format!("md5, {} users", users.len())
+        }
+    };
+    info!(
+        "host: {}, port: {}, database path: {:?}, auth: {}",
+        config.host, config.port, config.db_path, auth
+    );
 
-    let processor = Arc::new(StatelessMakeHandler::new(Arc::new(pg_handler)));
-    // We have not implemented extended query in this server, use placeholder
-    // instead
-    let placeholder = Arc::new(StatelessMakeHandler::new(Arc::new(
-        PlaceholderExtendedQueryHandler,
-    )));
-    let authenticator = Arc::new(StatelessMakeHandler::new(Arc::new(NoopStartupHandler)));
+    Database::reset_at(&config.db_path);
 
-    let server_addr = "127.0.0.1:5432";
-    let listener = TcpListener::bind(server_addr).await.unwrap();
-    info!("Listening to {}", server_addr);
-    loop {
-        let incoming_socket = listener.accept().await.unwrap();
-        let authenticator_ref = authenticator.make();
-        let processor_ref = processor.make();
-        let placeholder_ref = placeholder.make();
-        tokio::spawn(async move {
-            process_socket(
-                incoming_socket.0,
-                None,
-                authenticator_ref,
-                processor_ref,
-                placeholder_ref,
-            )
-            .await
-        });
-    }
+    let listener = config.bind().await.unwrap();
+    serve(listener, config.auth).await;
 }
@@ -1,3 +1,5 @@
+use crate::storage::tuple::Cell;
+
 #[derive(Clone, Debug)]
 pub enum Op {
     Equals,
@@ -7,4 +9,7 @@ pub enum Op {
     LessThanOrEq,
     Like,
     NotEquals,
+
+    /// Equals any of the values.
+    In(Vec<Cell>),
 }
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{cmp::Ordering, fmt};
 
 use crate::{storage::tuple::Cell, Op};
 
@@ -18,15 +18,68 @@ impl Predicate {
         }
     }
 
+    /// Create a predicate matching the cells equal to any of `cells`.
+    ///
+    /// The values are kept sorted and deduplicated, `cell` holds the
+    /// smallest one (or `Cell::Null` for an empty list) so a search on the
+    /// key can start from it.
+    pub fn in_list(field_index: usize, cells: &[Cell]) -> Self {
+        let mut values = cells.to_vec();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        values.dedup();
+
+        Self {
+            field_index,
+            cell: values.first().cloned().unwrap_or(Cell::Null),
+            op: Op::In(values),
+        }
+    }
+
+    /// Create a predicate matching the byte strings of the SQL `LIKE`
+    /// pattern `pattern`: `%` matches any sequence of characters, `_` a
+    /// single character. There is no escape character.
+    pub fn like(field_index: usize, pattern: &str) -> Self {
+        Self::new(
+            field_index,
+            Op::Like,
+            &Cell::Bytes(pattern.as_bytes().to_vec()),
+        )
+    }
+
+    /// The fixed beginning of a LIKE pattern (before its first wildcard),
+    /// every matching string starts with it. `None` for the other
+    /// operators and for the patterns starting with a wildcard.
+    pub fn like_prefix(&self) -> Option<Vec<u8>> {
+        match (&self.op, &self.cell) {
+            (Op::Like, Cell::Bytes(pattern)) => {
+                let prefix: Vec<u8> = pattern
+                    .iter()
+                    .take_while(|b| **b != b'%' && **b != b'_')
+                    .cloned()
+                    .collect();
+                if prefix.is_empty() {
+                    None
+                } else {
+                    Some(prefix)
+                }
+            }
+            _ => None,
+        }
+    }
+
     pub(crate) fn matches(&self, cell: &Cell) -> bool {
-        match self.op {
+        match &self.op {
             Op::Equals => cell == &self.cell,
             Op::GreaterThan => cell > &self.cell,
             Op::GreaterThanOrEq => cell >= &self.cell,
             Op::LessThan => cell < &self.cell,
             Op::LessThanOrEq => cell <= &self.cell,
-            Op::Like => todo!(),
+            Op::Like => match (cell, &self.cell) {
+                (Cell::Bytes(text), Cell::Bytes(pattern)) => like_matches(pattern, text),
+                _ => false,
+            },
             Op::NotEquals => cell != &self.cell,
+            Op::In(values) => values.contains(cell),
         }
     }
 }
@@ -40,3 +93,50 @@ impl fmt::Display for Predicate {
         )
     }
 }
+
+/// Returns true if `text` matches the LIKE pattern `pattern`, see
+/// `Predicate::like`.
+///
+/// The strings are compared character by character (as UTF-8), so `_`
+/// matches a multi-byte character as a whole.
+fn like_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let pattern: Vec<char> = String::from_utf8_lossy(pattern).chars().collect();
+    let text: Vec<char> = String::from_utf8_lossy(text).chars().collect();
+
+    // greedy matching, backtracking to the last `%` on a mismatch
+    let (mut p, mut t) = (0, 0);
+    let mut last_percent: Option<(usize, usize)> = None;
+    while t < text.len() {
+        // `%` is tested first, it's a wildcard even if the text has a `%`
+        if p < pattern.len() && pattern[p] == '%' {
+            last_percent = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '_' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((percent_p, percent_t)) = last_percent {
+            // let the `%` take one more character
+            p = percent_p + 1;
+            t = percent_t + 1;
+            last_percent = Some((percent_p, percent_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+/// The smallest byte string greater than all the strings starting with
+/// `prefix`, `None` if there is no such string (the prefix is made of 0xff
+/// bytes only).
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use pgwire::{
+    api::auth::{md5pass::hash_md5_password, AuthSource, LoginInfo, Password},
+    error::PgWireResult,
+};
+use rand::Rng;
+
+/// How the clients are authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// Accept every connection, for local development only (see
+    /// `ServerConfig::from_vars`).
+    Trust,
+
+    /// Require the password of the user, the password is sent hashed with
+    /// MD5 (the "md5" method of Postgres). The users are given as (name,
+    /// password) pairs.
+    Md5 { users: HashMap<String, String> },
+}
+
+/// The passwords of the users, looked up by the MD5 startup handler.
+pub struct UserPasswords {
+    users: HashMap<String, String>,
+}
+
+impl UserPasswords {
+    pub fn new(users: HashMap<String, String>) -> Self {
+        Self { users }
+    }
+}
+
+#[async_trait]
+impl AuthSource for UserPasswords {
+    async fn get_password(&self, login: &LoginInfo) -> PgWireResult<Password> {
+        let user = login
+            .user()
+            .as_ref()
+            .map(|user| user.to_string())
+            .unwrap_or_default();
+
+        let salt = rand::thread_rng().gen::<[u8; 4]>().to_vec();
+
+        // an unknown user goes through the challenge as well, against a
+        // random password, so it's rejected exactly like a wrong password
+        // and the response doesn't tell which users exist
+        let hashed = match self.users.get(&user) {
+            Some(password) => hash_md5_password(&user, password, &salt),
+            None => {
+                let password = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+                hash_md5_password(&user, &password, &salt)
+            }
+        };
+        Ok(Password::new(Some(salt), hashed.as_bytes().to_vec()))
+    }
+}
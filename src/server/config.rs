@@ -0,0 +1,118 @@
+use std::{collections::HashMap, env, path::PathBuf};
+
+use tokio::net::TcpListener;
+
+use super::AuthMethod;
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    Database,
+};
+
+/// Where the server listens and which database it serves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// The address to bind, e.g. "0.0.0.0" to accept remote connections
+    /// in a container.
+    pub host: String,
+
+    pub port: u16,
+
+    /// The directory of the database.
+    pub db_path: PathBuf,
+
+    pub auth: AuthMethod,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 5432,
+            db_path: Database::default_path(),
+            auth: AuthMethod::Md5 {
+                users: HashMap::new(),
+            },
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read the configuration from the environment variables
+    /// `SMALL_DB_HOST`, `SMALL_DB_PORT` and `SMALL_DB_PATH`, the unset ones
+    /// take the default value.
+    ///
+    /// The clients are authenticated with the MD5 method against the users
+    /// of `SMALL_DB_USERS` ("name:password" pairs separated by commas), no
+    /// client gets in when no user is given. For local development, every
+    /// client is trusted when `SMALL_DB_AUTH` is "trust", which is only
+    /// accepted along with the dev flag `SMALL_DB_DEV=1`.
+    pub fn from_env() -> Result<Self, SmallError> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Same as `from_env`, the variables are looked up by `get`.
+    pub fn from_vars<F>(get: F) -> Result<Self, SmallError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = Self::default();
+
+        if let Some(host) = get("SMALL_DB_HOST") {
+            config.host = host;
+        }
+        if let Some(port) = get("SMALL_DB_PORT") {
+            config.port = port.parse().or(Err(SmallError::with_kind(
+                SmallErrorKind::Other,
+                &format!("SMALL_DB_PORT is not a valid port: {}", port),
+            )))?;
+        }
+        if let Some(path) = get("SMALL_DB_PATH") {
+            config.db_path = PathBuf::from(path);
+        }
+
+        let mut users = HashMap::new();
+        for pair in get("SMALL_DB_USERS").unwrap_or_default().split(',') {
+            if pair.trim().is_empty() {
+                continue;
+            }
+            let (name, password) = pair.split_once(':').ok_or(SmallError::with_kind(
+                SmallErrorKind::Other,
+                &format!("SMALL_DB_USERS expects name:password pairs, got: {}", pair),
+            ))?;
+            users.insert(name.trim().to_string(), password.to_string());
+        }
+        config.auth = match get("SMALL_DB_AUTH").as_deref() {
+            None | Some("md5") => AuthMethod::Md5 { users },
+            Some("trust") if get("SMALL_DB_DEV").as_deref() == Some("1") => AuthMethod::Trust,
+            Some("trust") => {
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::Other,
+                    "SMALL_DB_AUTH=trust is only accepted for development, with SMALL_DB_DEV=1",
+                ))
+            }
+            Some(method) => {
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::Other,
+                    &format!("unknown SMALL_DB_AUTH: {}", method),
+                ))
+            }
+        };
+
+        Ok(config)
+    }
+
+    /// The address to bind, "host:port".
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Bind the listener of the server.
+    pub async fn bind(&self) -> Result<TcpListener, SmallError> {
+        TcpListener::bind(self.addr())
+            .await
+            .or(Err(SmallError::with_kind(
+                SmallErrorKind::Io,
+                &format!("failed to bind {}", self.addr()),
+            )))
+    }
+}
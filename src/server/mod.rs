@@ -1 +1,92 @@
+mod auth;
+pub use auth::*;
+
+mod config;
+pub use config::*;
+
 pub mod pg_handler;
+
+use std::{sync::Arc, time::Duration};
+
+use log::{error, info};
+use pgwire::{
+    api::{
+        auth::{
+            md5pass::MakeMd5PasswordAuthStartupHandler, noop::NoopStartupHandler,
+            DefaultServerParameterProvider, StartupHandler,
+        },
+        query::PlaceholderExtendedQueryHandler,
+        MakeHandler, StatelessMakeHandler,
+    },
+    tokio::process_socket,
+};
+use tokio::net::TcpListener;
+
+use self::pg_handler::MakePostgresHandler;
+
+/// How long the server waits before accepting again after an accept error.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Serve the Postgres wire protocol on `listener` until the task is
+/// dropped, each connection is handled by a task of its own. The clients
+/// are authenticated by `auth`, a rejected client gets the error of
+/// Postgres ("28P01", password authentication failed).
+///
+/// The listener is bound by the caller, so the address and port are up to
+/// it (e.g. port 0 for an ephemeral port in tests).
+pub async fn serve(listener: TcpListener, auth: AuthMethod) {
+    match auth {
+        AuthMethod::Trust => accept(listener, || Arc::new(NoopStartupHandler)).await,
+        AuthMethod::Md5 { users } => {
+            // the handler keeps the salt of the connection, one per
+            // connection
+            let authenticator = MakeMd5PasswordAuthStartupHandler::new(
+                Arc::new(UserPasswords::new(users)),
+                Arc::new(DefaultServerParameterProvider),
+            );
+            accept(listener, || authenticator.make()).await
+        }
+    }
+}
+
+async fn accept<A, F>(listener: TcpListener, make_authenticator: F)
+where
+    A: StartupHandler + 'static,
+    F: Fn() -> Arc<A>,
+{
+    // a session per connection
+    let processor = MakePostgresHandler;
+    // We have not implemented extended query in this server, use placeholder
+    // instead
+    let placeholder = Arc::new(StatelessMakeHandler::new(Arc::new(
+        PlaceholderExtendedQueryHandler,
+    )));
+
+    info!("Listening to {:?}", listener.local_addr());
+    loop {
+        // an accept error (e.g. EMFILE, out of file descriptors) only
+        // concerns the connection being accepted, the server goes on after
+        // a pause so a persistent error doesn't spin the loop
+        let incoming_socket = match listener.accept().await {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let authenticator_ref = make_authenticator();
+        let processor_ref = processor.make();
+        let placeholder_ref = placeholder.make();
+        tokio::spawn(async move {
+            process_socket(
+                incoming_socket.0,
+                None,
+                authenticator_ref,
+                processor_ref,
+                placeholder_ref,
+            )
+            .await
+        });
+    }
+}
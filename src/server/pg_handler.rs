@@ -4,13 +4,13 @@ use async_trait::async_trait;
 use pgwire::{
     api::{
         query::SimpleQueryHandler,
-        results::{QueryResponse, Response},
-        ClientInfo,
+        results::{FieldFormat, FieldInfo, QueryResponse, Response, Tag},
+        ClientInfo, MakeHandler, Type,
     },
-    error::PgWireResult,
+    error::{PgWireError, PgWireResult},
 };
 
-use crate::{sql::session::Session, transaction::Transaction};
+use crate::sql::session::{command_name, ExecResult, QueryResult, Session};
 
 pub struct PostgresHandler {
     pub session: Arc<Mutex<Session>>,
@@ -22,25 +22,70 @@ impl PostgresHandler {
     }
 }
 
+/// Make the handler of a connection. Every connection gets a `Session` of
+/// its own, which is dropped with the handler when the connection is closed
+/// (so a transaction left open by the client is rolled back).
+pub struct MakePostgresHandler;
+
+impl MakeHandler for MakePostgresHandler {
+    type Handler = Arc<PostgresHandler>;
+
+    fn make(&self) -> Self::Handler {
+        Arc::new(PostgresHandler::new(Arc::new(Mutex::new(Session::new()))))
+    }
+}
+
 #[async_trait]
 impl SimpleQueryHandler for PostgresHandler {
     async fn do_query<'b, C>(&self, _client: &C, query: &'b str) -> PgWireResult<Vec<Response<'b>>>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        let mut session = self.session.lock().unwrap();
+        // the query runs in the transaction opened by BEGIN, or in a
+        // transaction of its own
+        //
+        // it may block on the latches held by other sessions, so it runs on
+        // the blocking pool instead of a worker thread of the runtime
+        let session = Arc::clone(&self.session);
+        let sql = query.to_string();
+        let result = tokio::task::spawn_blocking(move || session.lock().unwrap().run(&sql))
+            .await
+            .map_err(|e| PgWireError::ApiError(Box::new(e)))?
+            .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
 
-        let tx = Transaction::new();
+        let response = match result {
+            ExecResult::Rows(rows) => {
+                let field_defs = field_infos(&rows);
+                Response::Query(QueryResponse::new(Arc::new(field_defs), rows))
+            }
+            ExecResult::Affected(count) => Response::Execution(command_tag(query, Some(count))),
+            ExecResult::Ddl | ExecResult::Control => Response::Execution(command_tag(query, None)),
+        };
 
-        let result = session
-            .execute(&tx, query)
-            .map_err(|e| pgwire::error::PgWireError::ApiError(Box::new(e)))?;
+        Ok(vec![response])
+    }
+}
 
-        let field_defs = Vec::new();
+/// Describe the columns of the rows, all of them are sent as text. Columns
+/// without a name get the name Postgres gives them, "?column?".
+fn field_infos(rows: &QueryResult) -> Vec<FieldInfo> {
+    let cells_count = rows.data.first().map_or(0, |row| row.get_cells().len());
+    let count = std::cmp::max(rows.columns.len(), cells_count);
 
-        let query_response = QueryResponse::new(Arc::new(field_defs), result);
-        let response = Response::Query(query_response);
+    (0..count)
+        .map(|i| {
+            let name = rows
+                .columns
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| "?column?".to_string());
+            FieldInfo::new(name, None, None, Type::VARCHAR, FieldFormat::Text)
+        })
+        .collect()
+}
 
-        Ok(vec![response])
-    }
+/// Build the command completion tag of a statement, e.g. "INSERT 0 3" or
+/// "CREATE TABLE".
+fn command_tag(query: &str, rows: Option<usize>) -> Tag {
+    Tag::new_for_execution(&command_name(query), rows)
 }
@@ -0,0 +1,129 @@
+use std::sync::{Arc, RwLock};
+
+use sqlparser::ast::{Expr, FunctionArg, FunctionArgExpr, Select, SelectItem};
+
+use super::stream::{Batch, Stream, TableStream};
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    sql::{executor::from::handle_from, session::QueryResult},
+    storage::tuple::{Cell, Tuple},
+    transaction::Transaction,
+    utils::{CancellationToken, HandyRwLock},
+    BTreeTable,
+};
+
+enum Aggregate {
+    Min(usize),
+    Max(usize),
+    Count,
+}
+
+/// Handle a SELECT whose only projection is `MIN(column)`, `MAX(column)` or
+/// `COUNT(*)` and which has no WHERE clause.
+///
+/// `MIN` and `MAX` on the key field never scan: they read the first/last
+/// visible key of the tree (see `BTreeTable::min_key`), which costs a
+/// descent to each end. Other columns and `COUNT(*)` scan the table:
+/// the statistics of `BTreeTable::analyze` are not refreshed by the later
+/// writes and are counted for the analyzing transaction, so they can't
+/// answer an exact count.
+///
+/// Return `None` if the statement is not of this shape.
+pub fn handle_aggregate(
+    tx: &Transaction,
+    select: &Select,
+    cancel_token: &CancellationToken,
+) -> Result<Option<QueryResult>, SmallError> {
+    if select.selection.is_some() || select.projection.len() != 1 {
+        return Ok(None);
+    }
+
+    let func = match &select.projection[0] {
+        SelectItem::UnnamedExpr(Expr::Function(func)) => func,
+        _ => return Ok(None),
+    };
+    if func.args.len() != 1 {
+        return Ok(None);
+    }
+
+    let table_rc = handle_from(&select.from)?;
+    let func_name = func.name.to_string().to_lowercase();
+
+    let aggregate = {
+        let table = table_rc.rl();
+        match (func_name.as_str(), &func.args[0]) {
+            ("count", FunctionArg::Unnamed(FunctionArgExpr::Wildcard)) => Aggregate::Count,
+            ("min", FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(column)))) => {
+                Aggregate::Min(field_index(&table, &column.value)?)
+            }
+            ("max", FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(column)))) => {
+                Aggregate::Max(field_index(&table, &column.value)?)
+            }
+            _ => return Ok(None),
+        }
+    };
+
+    let key_field = table_rc.rl().key_field;
+    let cell = match aggregate {
+        Aggregate::Min(i) if i == key_field => table_rc.rl().min_key(tx)?,
+        Aggregate::Max(i) if i == key_field => table_rc.rl().max_key(tx)?,
+        Aggregate::Min(i) => {
+            let mut min: Option<Cell> = None;
+            scan(tx, &table_rc, cancel_token, |t| {
+                let cell = t.get_cell(i);
+                if min.as_ref().is_none_or(|m| &cell < m) {
+                    min = Some(cell);
+                }
+            })?;
+            min
+        }
+        Aggregate::Max(i) => {
+            let mut max: Option<Cell> = None;
+            scan(tx, &table_rc, cancel_token, |t| {
+                let cell = t.get_cell(i);
+                if max.as_ref().is_none_or(|m| &cell > m) {
+                    max = Some(cell);
+                }
+            })?;
+            max
+        }
+        Aggregate::Count => {
+            let mut count: i64 = 0;
+            scan(tx, &table_rc, cancel_token, |_| count += 1)?;
+            Some(Cell::Int64(count))
+        }
+    };
+
+    let mut result = QueryResult::new();
+    result.columns = vec![func_name];
+    let row = Tuple::new(&vec![cell.unwrap_or(Cell::Null)], tx.get_id());
+    result.push_batch(&Batch::new(vec![row]));
+    Ok(Some(result))
+}
+
+fn field_index(table: &BTreeTable, column: &str) -> Result<usize, SmallError> {
+    table
+        .get_schema()
+        .get_fields()
+        .iter()
+        .position(|f| f.name == column)
+        .ok_or(SmallError::with_kind(
+            SmallErrorKind::NotFound,
+            &format!("column {} not found", column),
+        ))
+}
+
+fn scan<F: FnMut(&Tuple)>(
+    tx: &Transaction,
+    table_rc: &Arc<RwLock<BTreeTable>>,
+    cancel_token: &CancellationToken,
+    mut f: F,
+) -> Result<(), SmallError> {
+    let mut stream = TableStream::new(tx, table_rc.clone(), cancel_token)?;
+    while let Some(batch) = stream.next_batch()? {
+        for tuple in &batch.rows {
+            f(tuple);
+        }
+    }
+    Ok(())
+}
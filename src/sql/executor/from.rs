@@ -1,15 +1,16 @@
+use std::sync::{Arc, RwLock};
+
 use log::info;
 use sqlparser::ast::TableWithJoins;
 
 use crate::{
-    error::SmallError, sql::executor::stream::TableStream, transaction::Transaction,
-    utils::HandyRwLock, Database,
+    error::{SmallError, SmallErrorKind},
+    utils::HandyRwLock,
+    BTreeTable, Database,
 };
 
-pub fn handle_from(
-    tx: &Transaction,
-    from: &Vec<TableWithJoins>,
-) -> Result<Box<TableStream>, SmallError> {
+/// Resolve the table referenced by the FROM clause.
+pub fn handle_from(from: &Vec<TableWithJoins>) -> Result<Arc<RwLock<BTreeTable>>, SmallError> {
     let first_from = &from[0];
 
     if first_from.joins.len() == 0 {
@@ -21,6 +22,17 @@ pub fn handle_from(
                 info!("=====");
 
                 let idents = &name.0;
+                if idents.len() == 1 {
+                    let table_name = &idents[0].value;
+                    let table = Database::catalog().search_table(table_name).ok_or(
+                        SmallError::with_kind(
+                            SmallErrorKind::NotFound,
+                            &format!("table {} not found", table_name),
+                        ),
+                    )?;
+                    return Ok(table);
+                }
+
                 if idents.len() == 2 {
                     // find the schema
                     let schema_name = &idents[0].value;
@@ -28,13 +40,19 @@ pub fn handle_from(
 
                     // find the table
                     let table_name = &idents[1].value;
-                    let table = schema.rl().search_table(table_name).unwrap();
+                    let table =
+                        schema
+                            .rl()
+                            .search_table(table_name)
+                            .ok_or(SmallError::with_kind(
+                                SmallErrorKind::NotFound,
+                                &format!("table {} not found", table_name),
+                            ))?;
 
                     info!("schema_name: {:?}", schema.rl().name);
                     info!("table_name: {:?}", table.rl().name);
 
-                    let stream = TableStream::new(tx, table);
-                    return Ok(Box::new(stream));
+                    return Ok(table);
                 }
             }
             _ => {
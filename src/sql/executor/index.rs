@@ -0,0 +1,102 @@
+use std::sync::{Arc, RwLock};
+
+use super::stream::{IndexSeekStream, Stream};
+use crate::{
+    btree::table::BTreeTableIterator,
+    common::{Catalog, IndexMeta},
+    error::{SmallError, SmallErrorKind},
+    storage::{table_schema::Field, tuple::Cell},
+    transaction::Transaction,
+    types::SmallResult,
+    utils::HandyRwLock,
+    BTreeTable, Database, TableSchema,
+};
+
+/// Create the secondary index `index_name` on a column of the table, the
+/// index is filled by scanning the table.
+pub fn create_index(
+    tx: &Transaction,
+    index_name: &str,
+    table_name: &str,
+    column: &str,
+) -> SmallResult {
+    if Database::catalog().search_index(index_name).is_some()
+        || Database::catalog().search_table(index_name).is_some()
+    {
+        return Err(SmallError::new(&format!(
+            "relation {} already exists",
+            index_name
+        )));
+    }
+
+    let table_rc = Database::catalog()
+        .search_table(table_name)
+        .ok_or(SmallError::with_kind(
+            SmallErrorKind::NotFound,
+            &format!("table {} not found", table_name),
+        ))?;
+    let table = table_rc.rl();
+
+    let fields = table.get_schema().get_fields().clone();
+    let field_index = fields
+        .iter()
+        .position(|f| f.name == column)
+        .ok_or(SmallError::with_kind(
+            SmallErrorKind::NotFound,
+            &format!("column {} not found", column),
+        ))?;
+
+    // the indexed value is the key of the index, the primary key of the
+    // table follows it
+    let key_field = &fields[table.key_field];
+    let schema = TableSchema::new(vec![
+        Field::new(column, fields[field_index].get_type(), true),
+        Field::new(&key_field.name, key_field.get_type(), false),
+    ]);
+    let index_table_rc = Arc::new(RwLock::new(BTreeTable::new(index_name, None, &schema)));
+    Catalog::add_table(index_table_rc.clone(), true)?;
+
+    let index = IndexMeta {
+        name: index_name.to_string(),
+        table_name: table_name.to_string(),
+        field_index,
+        index_table: index_table_rc,
+    };
+
+    let mut it = BTreeTableIterator::new(tx, &table)?;
+    while let Some(tuple) = it.try_next()? {
+        table.insert_index_entry(tx, &index, &tuple)?;
+    }
+
+    // from now on the index is maintained by the insertions and deletions
+    // of the table
+    Catalog::write_index(&index)?;
+    Database::mut_catalog().add_index(index);
+    Ok(())
+}
+
+/// Remove the secondary index and its B+ tree table.
+pub fn drop_index(index_name: &str) -> SmallResult {
+    let index = Database::mut_catalog()
+        .remove_index(index_name)
+        .ok_or(SmallError::with_kind(
+            SmallErrorKind::NotFound,
+            &format!("index {} not found", index_name),
+        ))?;
+    Catalog::drop_table(&index.name)
+}
+
+/// Get the primary keys of the tuples whose indexed column equals one of
+/// the given values.
+pub fn lookup_primary_keys(
+    tx: &Transaction,
+    index: &IndexMeta,
+    values: &[Cell],
+) -> Result<Vec<Cell>, SmallError> {
+    let mut keys = Vec::new();
+    let mut stream = IndexSeekStream::new(tx, index.index_table.clone(), values);
+    while let Some(batch) = stream.next_batch()? {
+        keys.extend(batch.rows.iter().map(|t| t.get_cell(1)));
+    }
+    Ok(keys)
+}
@@ -1,6 +1,8 @@
 pub mod sql_handler;
 pub mod stream;
 
+mod aggregate;
 mod from;
+mod index;
 mod join;
-mod select;
+pub(crate) mod select;
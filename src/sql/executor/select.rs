@@ -1,9 +1,639 @@
-use sqlparser::ast::Select;
+use std::{
+    cmp::Ordering,
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
 
-use super::stream::Stream;
-use crate::{error::SmallError, sql::executor::from::handle_from, transaction::Transaction};
+use sqlparser::ast::{BinaryOperator, Expr, Select, Value};
 
-pub fn handle_select(tx: &Transaction, select: &Select) -> Result<Box<dyn Stream>, SmallError> {
-    let stream = handle_from(tx, &select.from)?;
-    return Ok(stream);
+use super::{
+    index::lookup_primary_keys,
+    stream::{Conjunction, Disjunction, FilterStream, IndexSeekStream, Stream, TableStream},
+};
+use crate::{
+    btree::table::OnMissing,
+    common::IndexMeta,
+    error::{SmallError, SmallErrorKind},
+    predicate::prefix_upper_bound,
+    sql::executor::from::handle_from,
+    storage::{table_schema::Type, tuple::Cell},
+    transaction::Transaction,
+    utils::{CancellationToken, HandyRwLock},
+    BTreeTable, Database, Op, Predicate,
+};
+
+/// How the tuples of the table are fetched.
+pub enum ScanPlan {
+    /// Scan all the tuples of the table.
+    SeqScan,
+
+    /// Descend the B+ tree to the tuples whose key equals one of the given
+    /// cells, once per cell.
+    IndexSeek(Vec<Cell>),
+
+    /// Find the primary keys of the tuples whose indexed column equals one
+    /// of the given cells in the secondary index, then seek them in the
+    /// table.
+    SecondaryIndexSeek(IndexMeta, Vec<Cell>),
+
+    /// Descend the B+ tree to the keys starting with the given bytes, the
+    /// fixed prefix of a LIKE pattern on the key.
+    PrefixSeek(Vec<u8>),
+}
+
+/// Optimizer hints given in a `/*+ ... */` comment of the statement.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PlanHints {
+    /// Name of the index the plan has to use, from `index(name)`.
+    pub index: Option<String>,
+
+    /// A point select on the key field fails with `SmallErrorKind::NotFound`
+    /// instead of returning no rows when the key is missing, from `strict`.
+    pub strict: bool,
+}
+
+impl PlanHints {
+    /// Extract the hints of a statement, unknown hints are ignored.
+    pub fn parse(sql: &str) -> Self {
+        let mut hints = Self::default();
+
+        let body = match sql.find("/*+") {
+            Some(start) => &sql[start + 3..],
+            None => return hints,
+        };
+        let body = &body[..body.find("*/").unwrap_or(body.len())];
+
+        // every piece but the last one ends with a hint taking an argument,
+        // e.g. "strict index(name", after the hints without one
+        for piece in body.split(')') {
+            let (words, argument) = match piece.find('(') {
+                Some(open) => (&piece[..open], Some(piece[open + 1..].trim())),
+                None => (piece, None),
+            };
+            let mut words: Vec<&str> = words.split_whitespace().collect();
+            if let Some(argument) = argument {
+                if words.pop() == Some("index") {
+                    hints.index = Some(argument.to_string());
+                }
+            }
+            if words.contains(&"strict") {
+                hints.strict = true;
+            }
+        }
+        hints
+    }
+}
+
+/// Name of the index built on the key field of the table.
+pub fn key_index_name(table: &BTreeTable) -> String {
+    format!("{}_pkey", table.name)
+}
+
+pub struct SelectPlan {
+    table: Arc<RwLock<BTreeTable>>,
+    scan: ScanPlan,
+
+    /// Predicates applied in memory to the fetched tuples.
+    filters: Vec<Predicate>,
+
+    /// Disjunctions applied in memory to the fetched tuples, a tuple is kept
+    /// if it matches at least one term of each of them.
+    disjunctions: Vec<Disjunction>,
+}
+
+impl SelectPlan {
+    /// Describe the plan, one line per operator, from the outermost one.
+    pub fn explain(&self) -> Vec<String> {
+        let table = self.table.rl();
+        let fields = table.get_schema().get_fields().clone();
+
+        let show = |cells: &[Cell]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .map(|cell| cell.to_display_string_with(true))
+                .collect();
+            format!("[{}]", cells.join(", "))
+        };
+
+        let describe = |p: &Predicate| match &p.op {
+            Op::In(values) => format!("{} In {}", fields[p.field_index].name, show(values)),
+            op => format!(
+                "{} {:?} {}",
+                fields[p.field_index].name,
+                op,
+                p.cell.to_display_string_with(true)
+            ),
+        };
+
+        let mut lines = Vec::new();
+        if !self.filters.is_empty() || !self.disjunctions.is_empty() {
+            let conditions = describe_conditions(&self.filters, &self.disjunctions, &describe);
+            lines.push(format!("Filter: {}", conditions.join(" AND ")));
+        }
+
+        match &self.scan {
+            ScanPlan::SeqScan => lines.push(format!("SeqScan on {}", table.name)),
+            ScanPlan::IndexSeek(keys) if keys.len() == 1 => lines.push(format!(
+                "IndexSeek on {}: {} Equals {}",
+                table.name,
+                fields[table.key_field].name,
+                keys[0].to_display_string_with(true)
+            )),
+            ScanPlan::IndexSeek(keys) => lines.push(format!(
+                "IndexSeek on {}: {} In {}",
+                table.name,
+                fields[table.key_field].name,
+                show(keys)
+            )),
+            ScanPlan::SecondaryIndexSeek(index, values) => lines.push(format!(
+                "IndexSeek on {} using {}: {} In {}",
+                table.name,
+                index.name,
+                fields[index.field_index].name,
+                show(values)
+            )),
+            ScanPlan::PrefixSeek(prefix) => lines.push(format!(
+                "PrefixSeek on {}: {} starts with {}",
+                table.name,
+                fields[table.key_field].name,
+                Cell::Bytes(prefix.clone()).to_display_string_with(true)
+            )),
+        }
+
+        lines
+    }
+}
+
+/// Sort the values of an IN list by the ordering of `table` and remove the
+/// duplicates, like `IndexSeekStream` does with its keys.
+fn dedup_values(table: &BTreeTable, mut values: Vec<Cell>) -> Vec<Cell> {
+    values.sort_by(|a, b| table.compare_keys(a, b));
+    values.dedup_by(|a, b| table.compare_keys(a, b) == Ordering::Equal);
+    values
+}
+
+pub fn handle_select(
+    tx: &Transaction,
+    select: &Select,
+    hints: &PlanHints,
+    cancel_token: &CancellationToken,
+) -> Result<Box<dyn Stream>, SmallError> {
+    let plan = plan_select(tx, select, hints)?;
+
+    let stream: Box<dyn Stream> = match &plan.scan {
+        ScanPlan::SeqScan => Box::new(TableStream::new(tx, plan.table.clone(), cancel_token)?),
+        ScanPlan::IndexSeek(keys) => {
+            let mut stream =
+                IndexSeekStream::new(tx, plan.table.clone(), keys).with_cancel_token(cancel_token);
+            if hints.strict && keys.len() == 1 {
+                stream = stream.with_on_missing(OnMissing::Error);
+            }
+            Box::new(stream)
+        }
+        ScanPlan::SecondaryIndexSeek(index, values) => {
+            let keys = lookup_primary_keys(tx, index, values)?;
+            Box::new(
+                IndexSeekStream::new(tx, plan.table.clone(), &keys).with_cancel_token(cancel_token),
+            )
+        }
+        ScanPlan::PrefixSeek(prefix) => {
+            let upper = match prefix_upper_bound(prefix) {
+                Some(upper) => Bound::Excluded(Cell::Bytes(upper)),
+                None => Bound::Unbounded,
+            };
+            Box::new(
+                IndexSeekStream::range(
+                    tx,
+                    plan.table.clone(),
+                    Bound::Included(Cell::Bytes(prefix.clone())),
+                    upper,
+                )
+                .with_cancel_token(cancel_token),
+            )
+        }
+    };
+
+    if plan.filters.is_empty() && plan.disjunctions.is_empty() {
+        return Ok(stream);
+    }
+    Ok(Box::new(FilterStream::new(
+        stream,
+        plan.filters,
+        plan.disjunctions,
+    )))
+}
+
+/// Build the plan of a SELECT statement.
+///
+/// An equality or an IN-list on the key field in the WHERE clause turns the
+/// scan into an index seek, the remaining conditions are evaluated on the
+/// tuples returned by the seek. An equality or an IN-list on a column with a
+/// secondary index (created by `CREATE INDEX`) can also be served by that
+/// index. When several seeks are possible, the one returning the fewest
+/// tuples according to the statistics of the table and of the indexes (see
+/// `BTreeTable::analyze`) is used.
+///
+/// Otherwise, a LIKE pattern on the key with a fixed prefix (`key LIKE
+/// 'abc%'`) is served by a seek to the prefix if the ordering of the keys
+/// allows it (see `BTreeTable::seeks_prefixes`), the pattern is still
+/// evaluated on the tuples returned by the seek.
+///
+/// A disjunction of equalities on the same column (`key = 1 OR key = 2`) is
+/// planned as the IN-list of their values, so it can be served by a seek.
+/// Other disjunctions (including the ones with conjunctions among their
+/// terms) are evaluated on every tuple returned by the scan.
+///
+/// The `index(name)` hint makes the planner fail instead of falling back to
+/// a sequential scan when the named index can't be used.
+///
+/// The `strict` hint only matters when the statement is run, see
+/// `PlanHints::strict`.
+pub fn plan_select(
+    tx: &Transaction,
+    select: &Select,
+    hints: &PlanHints,
+) -> Result<SelectPlan, SmallError> {
+    let (table, mut predicates, disjunctions) = select_conditions(select)?;
+
+    let key_field = table.rl().key_field;
+    let key_index = key_index_name(&table.rl());
+    let indexes = Database::catalog().table_indexes(&table.rl().name);
+
+    let mut candidates: Vec<usize> = predicates
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.field_index == key_field && matches!(p.op, Op::Equals | Op::In(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut secondary_indexes = indexes.iter().collect::<Vec<_>>();
+    if let Some(index) = &hints.index {
+        if index == &key_index {
+            secondary_indexes.clear();
+        } else if indexes.iter().any(|i| &i.name == index) {
+            candidates.clear();
+            secondary_indexes.retain(|i| &i.name == index);
+        } else {
+            return Err(SmallError::with_kind(
+                SmallErrorKind::NotFound,
+                &format!("index {} not found", index),
+            ));
+        }
+    }
+
+    // the seeks the conditions can be served by, with the condition each
+    // one serves (the condition is dropped from the filters by a seek on
+    // the key, a secondary index keeps it since the seek on the primary
+    // keys may return other tuples sharing the same key)
+    let mut seeks: Vec<(ScanPlan, Option<usize>)> = Vec::new();
+    for i in candidates {
+        let keys = match &predicates[i].op {
+            Op::In(values) => dedup_values(&table.rl(), values.clone()),
+            _ => vec![predicates[i].cell.clone()],
+        };
+        seeks.push((ScanPlan::IndexSeek(keys), Some(i)));
+    }
+    for index in secondary_indexes {
+        for predicate in &predicates {
+            if predicate.field_index != index.field_index {
+                continue;
+            }
+            let values = match &predicate.op {
+                Op::Equals => vec![predicate.cell.clone()],
+                Op::In(values) => dedup_values(&index.index_table.rl(), values.clone()),
+                _ => continue,
+            };
+            seeks.push((ScanPlan::SecondaryIndexSeek(index.clone(), values), None));
+        }
+    }
+
+    // only read the statistics when there is a choice to make, the seek
+    // returning the fewest tuples is used, a seek on the key wins the ties
+    let chosen = if seeks.len() > 1 {
+        let mut estimates = Vec::new();
+        for (seek, _) in &seeks {
+            estimates.push(estimate_seek_rows(tx, &table.rl(), seek)?);
+        }
+        let best = (0..seeks.len()).min_by_key(|i| estimates[*i]).unwrap();
+        Some(seeks.swap_remove(best))
+    } else {
+        seeks.pop()
+    };
+
+    let mut scan = ScanPlan::SeqScan;
+    if let Some((seek, served)) = chosen {
+        if let Some(i) = served {
+            predicates.remove(i);
+        }
+        scan = seek;
+    }
+
+    let key_index_allowed = hints.index.as_ref().is_none_or(|index| index == &key_index);
+    if let (true, ScanPlan::SeqScan) = (key_index_allowed, &scan) {
+        let table = table.rl();
+        let prefix = predicates
+            .iter()
+            .filter(|p| p.field_index == key_field && table.seeks_prefixes())
+            .find_map(|p| p.like_prefix());
+        if let Some(prefix) = prefix {
+            scan = ScanPlan::PrefixSeek(prefix);
+        }
+    }
+
+    if let (Some(index), ScanPlan::SeqScan) = (&hints.index, &scan) {
+        return Err(SmallError::new(&format!(
+            "index {} can't serve the query",
+            index
+        )));
+    }
+
+    Ok(SelectPlan {
+        table,
+        scan,
+        filters: predicates,
+        disjunctions,
+    })
+}
+
+/// Describe each of the conditions a tuple has to match, the terms of a
+/// disjunction with several conditions are parenthesized.
+fn describe_conditions(
+    predicates: &[Predicate],
+    disjunctions: &[Disjunction],
+    describe: &dyn Fn(&Predicate) -> String,
+) -> Vec<String> {
+    let mut conditions: Vec<String> = predicates.iter().map(describe).collect();
+    for disjunction in disjunctions {
+        let terms: Vec<String> = disjunction
+            .iter()
+            .map(|term| {
+                let conditions =
+                    describe_conditions(&term.predicates, &term.disjunctions, describe);
+                match conditions.as_slice() {
+                    [condition] => condition.clone(),
+                    _ => format!("({})", conditions.join(" AND ")),
+                }
+            })
+            .collect();
+        conditions.push(format!("({})", terms.join(" OR ")));
+    }
+    conditions
+}
+
+/// Estimate the number of tuples returned by an index seek, from the
+/// statistics of the table or of the secondary index.
+fn estimate_seek_rows(
+    tx: &Transaction,
+    table: &BTreeTable,
+    seek: &ScanPlan,
+) -> Result<usize, SmallError> {
+    match seek {
+        ScanPlan::IndexSeek(keys) => Ok(table.get_or_analyze(tx)?.estimate_seek_rows(keys.len())),
+        ScanPlan::SecondaryIndexSeek(index, values) => Ok(index
+            .index_table
+            .rl()
+            .get_or_analyze(tx)?
+            .estimate_seek_rows(values.len())),
+        _ => Ok(usize::MAX),
+    }
+}
+
+/// The table of a SELECT statement, with the predicates and the
+/// disjunctions of its WHERE clause.
+pub type SelectConditions = (Arc<RwLock<BTreeTable>>, Vec<Predicate>, Vec<Disjunction>);
+
+/// Resolve the table of a SELECT statement and collect the conditions of
+/// its WHERE clause, as the predicates and the disjunctions every returned
+/// tuple has to match.
+pub fn select_conditions(select: &Select) -> Result<SelectConditions, SmallError> {
+    let table = handle_from(&select.from)?;
+
+    let mut predicates = Vec::new();
+    let mut disjunctions = Vec::new();
+    if let Some(selection) = &select.selection {
+        collect_predicates(&table.rl(), selection, &mut predicates, &mut disjunctions)?;
+    }
+    Ok((table, predicates, disjunctions))
+}
+
+/// Collect the conditions of a conjunction of `column <op> value`
+/// comparisons, `column IN (value, ...)` lists and `column LIKE 'pattern'`
+/// patterns, on any column of the table.
+///
+/// The terms of the conjunction may also be disjunctions of such
+/// conditions (whose terms may be conjunctions again), the ones that can't
+/// be turned into an IN-list are collected into `disjunctions`.
+fn collect_predicates(
+    table: &BTreeTable,
+    expr: &Expr,
+    predicates: &mut Vec<Predicate>,
+    disjunctions: &mut Vec<Disjunction>,
+) -> Result<(), SmallError> {
+    match expr {
+        Expr::Nested(inner) => collect_predicates(table, inner, predicates, disjunctions),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            collect_predicates(table, left, predicates, disjunctions)?;
+            collect_predicates(table, right, predicates, disjunctions)
+        }
+        Expr::BinaryOp {
+            op: BinaryOperator::Or,
+            ..
+        } => {
+            let mut terms = Vec::new();
+            collect_disjunction(table, expr, &mut terms)?;
+
+            // equalities on the same column are merged into an IN-list
+            let mut field_index = None;
+            let mut values = Vec::new();
+            for term in &terms {
+                let predicate = match (term.predicates.as_slice(), term.disjunctions.is_empty()) {
+                    ([predicate], true) => predicate,
+                    _ => {
+                        disjunctions.push(terms);
+                        return Ok(());
+                    }
+                };
+                let field_index = *field_index.get_or_insert(predicate.field_index);
+                match &predicate.op {
+                    Op::Equals if predicate.field_index == field_index => {
+                        values.push(predicate.cell.clone())
+                    }
+                    Op::In(cells) if predicate.field_index == field_index => {
+                        values.extend(cells.iter().cloned())
+                    }
+                    _ => {
+                        disjunctions.push(terms);
+                        return Ok(());
+                    }
+                }
+            }
+
+            predicates.push(Predicate::in_list(field_index.unwrap(), &values));
+            Ok(())
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let op = comparison_op(op)
+                .ok_or_else(|| SmallError::new(&format!("unsupported condition: {:?}", expr)))?;
+            let (column, value, op) = match (left.as_ref(), right.as_ref()) {
+                (Expr::Identifier(column), Expr::Value(value)) => (column, value, op),
+                (Expr::Value(value), Expr::Identifier(column)) => (column, value, reverse(op)),
+                _ => {
+                    return Err(SmallError::new(&format!(
+                        "unsupported condition: {:?}",
+                        expr
+                    )))
+                }
+            };
+
+            let field_index = field_index(table, &column.value)?;
+            let cell = parse_value(table, field_index, value)?;
+
+            predicates.push(Predicate::new(field_index, op, &cell));
+            Ok(())
+        }
+        Expr::Like {
+            negated: false,
+            expr: column,
+            pattern,
+            escape_char: None,
+        } => match (column.as_ref(), pattern.as_ref()) {
+            (Expr::Identifier(column), Expr::Value(Value::SingleQuotedString(pattern))) => {
+                let field_index = field_index(table, &column.value)?;
+                if !matches!(
+                    table.get_schema().get_fields()[field_index].get_type(),
+                    Type::Bytes(_)
+                ) {
+                    return Err(SmallError::new(&format!(
+                        "LIKE on the non-string column {}",
+                        column.value
+                    )));
+                }
+
+                predicates.push(Predicate::like(field_index, pattern));
+                Ok(())
+            }
+            _ => Err(SmallError::new(&format!(
+                "unsupported condition: {:?}",
+                expr
+            ))),
+        },
+        Expr::InList {
+            expr: column,
+            list,
+            negated: false,
+        } => match column.as_ref() {
+            Expr::Identifier(column) => {
+                let field_index = field_index(table, &column.value)?;
+
+                let mut cells = Vec::new();
+                for item in list {
+                    match item {
+                        Expr::Value(value) => cells.push(parse_value(table, field_index, value)?),
+                        _ => {
+                            return Err(SmallError::new(&format!("unsupported value: {:?}", item)))
+                        }
+                    }
+                }
+
+                predicates.push(Predicate::in_list(field_index, &cells));
+                Ok(())
+            }
+            _ => Err(SmallError::new(&format!(
+                "unsupported condition: {:?}",
+                expr
+            ))),
+        },
+        _ => Err(SmallError::new(&format!(
+            "unsupported condition: {:?}",
+            expr
+        ))),
+    }
+}
+
+/// Collect the terms of a disjunction, each of them is a conjunction of
+/// conditions.
+fn collect_disjunction(
+    table: &BTreeTable,
+    expr: &Expr,
+    terms: &mut Disjunction,
+) -> Result<(), SmallError> {
+    match expr {
+        Expr::Nested(inner) => collect_disjunction(table, inner, terms),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => {
+            collect_disjunction(table, left, terms)?;
+            collect_disjunction(table, right, terms)
+        }
+        _ => {
+            let mut predicates = Vec::new();
+            let mut disjunctions = Vec::new();
+            collect_predicates(table, expr, &mut predicates, &mut disjunctions)?;
+            terms.push(Conjunction {
+                predicates,
+                disjunctions,
+            });
+            Ok(())
+        }
+    }
+}
+
+/// Map a comparison operator of the SQL AST to the operator of the
+/// predicate.
+fn comparison_op(op: &BinaryOperator) -> Option<Op> {
+    match op {
+        BinaryOperator::Eq => Some(Op::Equals),
+        BinaryOperator::NotEq => Some(Op::NotEquals),
+        BinaryOperator::Gt => Some(Op::GreaterThan),
+        BinaryOperator::GtEq => Some(Op::GreaterThanOrEq),
+        BinaryOperator::Lt => Some(Op::LessThan),
+        BinaryOperator::LtEq => Some(Op::LessThanOrEq),
+        _ => None,
+    }
+}
+
+/// Get the operator of the same comparison with its operands swapped, e.g.
+/// `5 < column` is `column > 5`.
+fn reverse(op: Op) -> Op {
+    match op {
+        Op::GreaterThan => Op::LessThan,
+        Op::GreaterThanOrEq => Op::LessThanOrEq,
+        Op::LessThan => Op::GreaterThan,
+        Op::LessThanOrEq => Op::GreaterThanOrEq,
+        op => op,
+    }
+}
+
+fn field_index(table: &BTreeTable, column: &str) -> Result<usize, SmallError> {
+    table
+        .get_schema()
+        .get_fields()
+        .iter()
+        .position(|f| f.name == column)
+        .ok_or_else(|| {
+            SmallError::with_kind(
+                SmallErrorKind::NotFound,
+                &format!("column {} not found", column),
+            )
+        })
+}
+
+/// Parse a literal as a cell of the type of the field.
+fn parse_value(table: &BTreeTable, field_index: usize, value: &Value) -> Result<Cell, SmallError> {
+    let text = match value {
+        Value::Number(text, _) => text.clone(),
+        Value::SingleQuotedString(text) => text.clone(),
+        Value::Boolean(b) => b.to_string(),
+        _ => return Err(SmallError::new(&format!("unsupported value: {:?}", value))),
+    };
+    Cell::parse(
+        &text,
+        &table.get_schema().get_fields()[field_index].get_type(),
+    )
 }
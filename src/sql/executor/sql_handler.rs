@@ -1,92 +1,343 @@
 use log::info;
 use sqlparser::{
-    ast::{ColumnOption, Statement},
+    ast::{AlterTableOperation, ColumnDef, ColumnOption, Expr, ObjectType, Statement, Value},
     dialect::GenericDialect,
     parser::Parser,
 };
 
-use super::stream::Stream;
+use super::stream::{Batch, Stream};
 use crate::{
-    error::SmallError,
-    sql::{executor::select::handle_select, session::QueryResult},
-    storage::table_schema::{Field, Type},
+    common::Catalog,
+    error::{SmallError, SmallErrorKind},
+    sql::{
+        executor::{
+            aggregate::handle_aggregate,
+            from::handle_from,
+            index::{create_index, drop_index},
+            select::{handle_select, plan_select, PlanHints},
+        },
+        session::{ExecResult, QueryResult},
+    },
+    storage::{
+        table_schema::{Field, Type},
+        tuple::{Cell, Tuple},
+    },
     transaction::Transaction,
-    BTreeTable, TableSchema,
+    utils::{CancellationToken, HandyRwLock},
+    Database, TableSchema,
 };
 
-pub fn handle_sql(tx: &Transaction, sql: &str) -> Result<QueryResult, SmallError> {
-    info!("Query: {}", sql);
+pub fn handle_sql(
+    tx: &Transaction,
+    sql: &str,
+    cancel_token: &CancellationToken,
+    max_rows: Option<usize>,
+) -> Result<ExecResult, SmallError> {
+    let ast = parse_sql(sql)?;
+    handle_statements(tx, &ast, sql, cancel_token, max_rows)
+}
 
+pub fn parse_sql(sql: &str) -> Result<Vec<Statement>, SmallError> {
     let dialect = GenericDialect {}; // or AnsiDialect, or your own dialect ...
 
-    let ast = Parser::parse_sql(&dialect, sql).unwrap();
+    Parser::parse_sql(&dialect, sql).map_err(|e| SmallError::new(&format!("parse error: {}", e)))
+}
+
+/// Same as `handle_sql`, for the statements parsed from `sql` by
+/// `parse_sql`. Only a single statement is supported, a text with several
+/// statements is rejected before any of them runs.
+pub fn handle_statements(
+    tx: &Transaction,
+    ast: &[Statement],
+    sql: &str,
+    cancel_token: &CancellationToken,
+    max_rows: Option<usize>,
+) -> Result<ExecResult, SmallError> {
+    info!("Query: {}", sql);
+
+    if ast.is_empty() {
+        return Ok(ExecResult::Rows(QueryResult::new()));
+    }
 
     info!("AST: {:?}", ast);
 
-    let statement = &ast[0];
+    let statement = match ast {
+        [statement] => statement,
+        _ => {
+            return Err(SmallError::new(&format!(
+                "several statements can't be run at once, got {}",
+                ast.len()
+            )))
+        }
+    };
+    let hints = PlanHints::parse(sql);
+
+    // DDL statements wait for the running DML statements and block new ones,
+    // the guard is held until the statement finishes
+    let (_ddl_guard, _dml_guard) = match statement {
+        Statement::CreateTable { .. }
+        | Statement::CreateIndex { .. }
+        | Statement::Drop { .. }
+        | Statement::AlterTable { .. } => (Some(Database::ddl_latch()), None),
+        _ => (None, Some(Database::dml_latch())),
+    };
 
     match statement {
-        Statement::CreateTable { name, columns, .. } => {
+        Statement::CreateTable {
+            name,
+            columns,
+            if_not_exists,
+            ..
+        } => {
             info!("name: {:?}", name);
             info!("columns: {:?}", columns);
 
             let table_name = name.to_string();
             info!("name: {:?}", table_name);
 
-            let mut fields: Vec<Field> = Vec::new();
-            for column in columns {
-                let is_pkey = column.options.iter().any(|c| match c.option {
-                    ColumnOption::Unique { is_primary: true } => true,
-                    _ => false,
-                });
+            let fields: Vec<Field> = columns.iter().map(column_field).collect();
 
-                let field_type = match &column.data_type {
-                    sqlparser::ast::DataType::Integer(_) => Type::Int64,
-                    sqlparser::ast::DataType::Varchar(_) => Type::Bytes(20),
-                    _ => Type::Int64,
-                };
+            let schema = TableSchema::new(fields);
+
+            // the table is created once even if several sessions race to
+            // create it, see `Catalog::get_or_create_table`
+            if !if_not_exists && Database::catalog().search_table(&table_name).is_some() {
+                return Err(SmallError::new(&format!(
+                    "table {} already exists",
+                    table_name
+                )));
+            }
+            Catalog::get_or_create_table(&table_name, &schema, true)?;
+            return Ok(ExecResult::Ddl);
+        }
+        Statement::Drop {
+            object_type: ObjectType::Table,
+            names,
+            if_exists,
+            ..
+        } => {
+            for name in names {
+                let table_name = &name.0.last().unwrap().value;
+                if *if_exists && Database::catalog().search_table(table_name).is_none() {
+                    continue;
+                }
+                Catalog::drop_table(table_name)?;
+            }
 
-                let field = Field::new(&column.name.to_string(), field_type, is_pkey);
+            return Ok(ExecResult::Ddl);
+        }
+        Statement::CreateIndex {
+            name,
+            table_name,
+            columns,
+            ..
+        } => {
+            let column = match columns.as_slice() {
+                [column] => match &column.expr {
+                    Expr::Identifier(ident) => ident.value.clone(),
+                    expr => {
+                        return Err(SmallError::new(&format!(
+                            "unsupported index column: {:?}",
+                            expr
+                        )))
+                    }
+                },
+                _ => return Err(SmallError::new("only single-column indexes are supported")),
+            };
 
-                fields.push(field);
+            let index_name = &name.0.last().unwrap().value;
+            let table_name = &table_name.0.last().unwrap().value;
+            create_index(tx, index_name, table_name, &column)?;
+            return Ok(ExecResult::Ddl);
+        }
+        Statement::Drop {
+            object_type: ObjectType::Index,
+            names,
+            if_exists,
+            ..
+        } => {
+            for name in names {
+                let index_name = &name.0.last().unwrap().value;
+                if *if_exists && Database::catalog().search_index(index_name).is_none() {
+                    continue;
+                }
+                drop_index(index_name)?;
             }
 
-            let schema = TableSchema::new(fields);
+            return Ok(ExecResult::Ddl);
+        }
+        Statement::AlterTable {
+            name,
+            operation: AlterTableOperation::AddColumn { column_def, .. },
+        } => {
+            let table_name = &name.0.last().unwrap().value;
+            Catalog::add_column(tx, table_name, &column_field(column_def))?;
+            return Ok(ExecResult::Ddl);
+        }
+        Statement::Insert {
+            table_name, source, ..
+        } => {
+            // the schema part of the name is ignored, table names are unique
+            // in the catalog
+            let name = &table_name.0.last().unwrap().value;
+            let table_rc = Database::catalog()
+                .search_table(name)
+                .ok_or(SmallError::with_kind(
+                    SmallErrorKind::NotFound,
+                    &format!("table {} not found", table_name),
+                ))?;
+            let table = table_rc.rl();
+            let fields = table.get_schema().get_fields().clone();
+
+            let rows = match source.body.as_ref() {
+                sqlparser::ast::SetExpr::Values(values) => &values.rows,
+                _ => return Err(SmallError::new("only INSERT ... VALUES is supported")),
+            };
+
+            for row in rows {
+                let mut cells = Vec::new();
+                for (i, expr) in row.iter().enumerate() {
+                    let field = fields
+                        .get(i)
+                        .ok_or(SmallError::new("too many values in the row"))?;
+                    let text = match expr {
+                        Expr::Value(Value::Number(text, _)) => text.clone(),
+                        Expr::Value(Value::SingleQuotedString(text)) => text.clone(),
+                        Expr::Value(Value::Boolean(b)) => b.to_string(),
+                        _ => {
+                            return Err(SmallError::new(&format!("unsupported value: {:?}", expr)))
+                        }
+                    };
+                    cells.push(Cell::parse(&text, &field.get_type())?);
+                }
 
-            let _table = BTreeTable::new(&table_name, None, &schema);
+                let tuple = Tuple::new(&cells, tx.get_id());
+                table.insert_tuple(tx, &tuple)?;
+            }
+
+            return Ok(ExecResult::Affected(rows.len()));
         }
-        Statement::Query(query) => {
-            match query.body.as_ref() {
-                sqlparser::ast::SetExpr::Select(select) => {
-                    info!("projection: {:?}", select.projection);
-                    info!("from: {:?}", select.from);
-                    let stream = handle_select(tx, select)?;
-                    return collect_result(stream);
+        Statement::Query(query) => match query.body.as_ref() {
+            sqlparser::ast::SetExpr::Select(select) => {
+                info!("projection: {:?}", select.projection);
+                info!("from: {:?}", select.from);
+                if let Some(result) = handle_aggregate(tx, select, cancel_token)? {
+                    return Ok(ExecResult::Rows(result));
                 }
-                _ => {
-                    todo!()
+
+                let stream = handle_select(tx, select, &hints, cancel_token)?;
+                let mut result = collect_result(stream, cancel_token, max_rows)?;
+                result.columns = handle_from(&select.from)?
+                    .rl()
+                    .get_schema()
+                    .get_fields()
+                    .iter()
+                    .map(|f| f.name.clone())
+                    .collect();
+                return Ok(ExecResult::Rows(result));
+            }
+            _ => {
+                return Err(SmallError::new(&format!(
+                    "unsupported statement: {}",
+                    statement
+                )));
+            }
+        },
+        Statement::Explain { statement, .. } => {
+            if let Statement::Query(query) = statement.as_ref() {
+                if let sqlparser::ast::SetExpr::Select(select) = query.body.as_ref() {
+                    let plan = plan_select(tx, select, &hints)?;
+
+                    let mut result = QueryResult::new();
+                    result.columns = vec!["QUERY PLAN".to_string()];
+                    let rows = plan
+                        .explain()
+                        .into_iter()
+                        .map(|line| Tuple::new(&vec![Cell::Bytes(line.into_bytes())], tx.get_id()))
+                        .collect();
+                    result.push_batch(&Batch::new(rows));
+                    return Ok(ExecResult::Rows(result));
                 }
             }
 
-            todo!()
+            return Err(SmallError::new(&format!(
+                "unsupported statement in EXPLAIN: {}",
+                statement
+            )));
+        }
+        Statement::ShowVariable { variable }
+            if variable.len() == 1 && variable[0].value.eq_ignore_ascii_case("status") =>
+        {
+            let mut result = QueryResult::new();
+            result.columns = vec!["name".to_string(), "value".to_string()];
+            let rows = Database::health()?
+                .entries()
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        Some(v) => Cell::Int64(v as i64),
+                        None => Cell::Null,
+                    };
+                    Tuple::new(
+                        &vec![Cell::Bytes(name.as_bytes().to_vec()), value],
+                        tx.get_id(),
+                    )
+                })
+                .collect();
+            result.push_batch(&Batch::new(rows));
+            return Ok(ExecResult::Rows(result));
         }
         _ => {
-            todo!()
+            return Err(SmallError::new(&format!(
+                "unsupported statement: {}",
+                statement
+            )));
         }
     }
+}
+
+/// Build the field of a column of CREATE TABLE or ALTER TABLE.
+fn column_field(column: &ColumnDef) -> Field {
+    let is_pkey = column.options.iter().any(|c| match c.option {
+        ColumnOption::Unique { is_primary: true } => true,
+        _ => false,
+    });
+
+    let field_type = match &column.data_type {
+        sqlparser::ast::DataType::Integer(_) => Type::Int64,
+        sqlparser::ast::DataType::Varchar(_) => Type::Bytes(20),
+        _ => Type::Int64,
+    };
 
-    todo!()
+    Field::new(&column.name.to_string(), field_type, is_pkey)
 }
 
-fn collect_result(mut stream: Box<dyn Stream>) -> Result<QueryResult, SmallError> {
+/// Drain the stream into a result, fails with `ResultTooLarge` as soon as
+/// the result gets more than `max_rows` rows.
+fn collect_result(
+    mut stream: Box<dyn Stream>,
+    cancel_token: &CancellationToken,
+    max_rows: Option<usize>,
+) -> Result<QueryResult, SmallError> {
     let mut result = QueryResult::new();
 
     loop {
+        cancel_token.check()?;
+
         if let Some(batch) = stream.next_batch()? {
             result.push_batch(&batch);
         } else {
             break;
         }
+
+        if let Some(max_rows) = max_rows {
+            if result.data.len() > max_rows {
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::ResultTooLarge,
+                    &format!("the result has more than {} rows", max_rows),
+                ));
+            }
+        }
     }
 
     Ok(result)
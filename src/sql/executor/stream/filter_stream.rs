@@ -0,0 +1,70 @@
+use super::{Batch, Stream};
+use crate::{error::SmallError, storage::tuple::Tuple, Predicate};
+
+/// A term of a disjunction, the predicates and the nested disjunctions a
+/// tuple has to match all of, e.g. `a = 1 AND (b = 2 OR c = 3)`.
+#[derive(Clone)]
+pub struct Conjunction {
+    pub predicates: Vec<Predicate>,
+    pub disjunctions: Vec<Disjunction>,
+}
+
+/// The terms of a disjunction, a tuple matches it if it matches one of them.
+pub type Disjunction = Vec<Conjunction>;
+
+/// Stream that keeps only the tuples of its input matching all the
+/// predicates, and at least one term of each disjunction.
+pub struct FilterStream {
+    input: Box<dyn Stream>,
+    predicates: Vec<Predicate>,
+    disjunctions: Vec<Disjunction>,
+}
+
+impl FilterStream {
+    pub fn new(
+        input: Box<dyn Stream>,
+        predicates: Vec<Predicate>,
+        disjunctions: Vec<Disjunction>,
+    ) -> Self {
+        Self {
+            input,
+            predicates,
+            disjunctions,
+        }
+    }
+}
+
+impl Stream for FilterStream {
+    fn next_batch(&mut self) -> Result<Option<Batch>, SmallError> {
+        // skip the batches filtered out entirely, an empty batch would be
+        // taken as the end of the stream
+        while let Some(batch) = self.input.next_batch()? {
+            let rows: Vec<_> = batch
+                .rows
+                .into_iter()
+                .filter(|t| matches_conditions(t, &self.predicates, &self.disjunctions))
+                .collect();
+
+            if !rows.is_empty() {
+                return Ok(Some(Batch::new(rows)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Returns true if `tuple` matches all the predicates, and at least one
+/// term of each disjunction.
+pub fn matches_conditions(
+    tuple: &Tuple,
+    predicates: &[Predicate],
+    disjunctions: &[Disjunction],
+) -> bool {
+    let matches = |p: &Predicate| p.matches(&tuple.get_cell(p.field_index));
+    predicates.iter().all(matches)
+        && disjunctions.iter().all(|d| {
+            d.iter()
+                .any(|term| matches_conditions(tuple, &term.predicates, &term.disjunctions))
+        })
+}
@@ -0,0 +1,157 @@
+use std::{
+    cmp::Ordering,
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
+
+use log::info;
+
+use super::{Batch, Stream};
+use crate::{
+    btree::table::{BTreeTableRangeIterator, OnMissing},
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::Cell,
+    transaction::Transaction,
+    utils::{CancellationToken, HandyRwLock},
+    BTreeTable,
+};
+
+/// Stream of the tuples whose key equals one of the given values, or is in a
+/// range, found by descending the B+ tree instead of scanning the whole
+/// table.
+///
+/// The values are sorted and deduplicated by the comparator of the table,
+/// then sought one after another, so the tuples come out in key order and
+/// each of them only once.
+pub struct IndexSeekStream {
+    tx: Transaction,
+    table: Arc<RwLock<BTreeTable>>,
+
+    // ranges not sought yet, in descending order so the next one is popped
+    // from the end
+    ranges: Vec<(Bound<Cell>, Bound<Cell>)>,
+    iter: Option<BTreeTableRangeIterator>,
+
+    /// What to do when a key sought has no tuple, see `with_on_missing`.
+    on_missing: OnMissing,
+
+    /// The key of the current range when it's a single key, and whether a
+    /// tuple has been found for it.
+    key: Option<Cell>,
+    found: bool,
+
+    cancel_token: Option<CancellationToken>,
+}
+
+impl IndexSeekStream {
+    pub fn new(tx: &Transaction, table: Arc<RwLock<BTreeTable>>, keys: &[Cell]) -> Self {
+        let mut keys = keys.to_vec();
+        {
+            let table = table.rl();
+            keys.sort_by(|a, b| table.compare_keys(b, a));
+            keys.dedup_by(|a, b| table.compare_keys(a, b) == Ordering::Equal);
+        }
+
+        let ranges = keys
+            .into_iter()
+            .map(|key| (Bound::Included(key.clone()), Bound::Included(key)))
+            .collect();
+        Self {
+            tx: tx.clone(),
+            table,
+            ranges,
+            iter: None,
+            on_missing: OnMissing::Empty,
+            key: None,
+            found: false,
+            cancel_token: None,
+        }
+    }
+
+    /// With `OnMissing::Error`, a key sought without a tuple visible to the
+    /// transaction fails the stream with a `NotFound` error, found by the
+    /// same descent which reads the tuples.
+    pub fn with_on_missing(mut self, on_missing: OnMissing) -> Self {
+        self.on_missing = on_missing;
+        self
+    }
+
+    /// Make the stream fail with a `Cancelled` error once `token` is
+    /// tripped, it's checked before each batch.
+    pub fn with_cancel_token(mut self, token: &CancellationToken) -> Self {
+        self.cancel_token = Some(token.clone());
+        self
+    }
+
+    /// Stream of the tuples whose key is in the range.
+    pub fn range(
+        tx: &Transaction,
+        table: Arc<RwLock<BTreeTable>>,
+        lower: Bound<Cell>,
+        upper: Bound<Cell>,
+    ) -> Self {
+        Self {
+            tx: tx.clone(),
+            table,
+            ranges: vec![(lower, upper)],
+            iter: None,
+            on_missing: OnMissing::Empty,
+            key: None,
+            found: false,
+            cancel_token: None,
+        }
+    }
+}
+
+impl Stream for IndexSeekStream {
+    fn next_batch(&mut self) -> Result<Option<Batch>, SmallError> {
+        if let Some(token) = &self.cancel_token {
+            token.check()?;
+        }
+
+        let mut tuples = Vec::new();
+
+        while tuples.len() < 100 {
+            let tuple = match self.iter.as_mut() {
+                Some(it) => it.try_next()?,
+                None => None,
+            };
+            if let Some(tuple) = tuple {
+                self.found = true;
+                tuples.push(tuple.get_tuple().clone());
+                continue;
+            }
+
+            // the current range is exhausted, seek the next one
+            if let (Some(key), false, OnMissing::Error) = (&self.key, self.found, self.on_missing) {
+                let err_msg = format!("key {:?} not found, table: {}", key, self.table.rl().name);
+                return Err(SmallError::with_kind(SmallErrorKind::NotFound, &err_msg));
+            }
+            let (lower, upper) = match self.ranges.pop() {
+                Some(range) => range,
+                None => break,
+            };
+            self.key = match (&lower, &upper) {
+                (Bound::Included(lower), Bound::Included(upper))
+                    if self.table.rl().compare_keys(lower, upper) == Ordering::Equal =>
+                {
+                    Some(lower.clone())
+                }
+                _ => None,
+            };
+            self.found = false;
+            self.iter = Some(self.table.rl().range(&self.tx, lower, upper)?);
+        }
+
+        info!(
+            "IndexSeekStream::next_batch: tuples.len() = {}",
+            tuples.len()
+        );
+
+        if tuples.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Batch::new(tuples)))
+    }
+}
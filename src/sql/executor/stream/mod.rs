@@ -3,3 +3,9 @@ pub use stream::*;
 
 mod table_stream;
 pub use table_stream::*;
+
+mod index_seek_stream;
+pub use index_seek_stream::*;
+
+mod filter_stream;
+pub use filter_stream::*;
@@ -4,8 +4,11 @@ use log::info;
 
 use super::{Batch, Stream};
 use crate::{
-    btree::table::BTreeTableIterator, error::SmallError, transaction::Transaction,
-    utils::HandyRwLock, BTreeTable,
+    btree::table::BTreeTableIterator,
+    error::SmallError,
+    transaction::Transaction,
+    utils::{CancellationToken, HandyRwLock},
+    BTreeTable,
 };
 
 pub struct TableStream {
@@ -13,9 +16,13 @@ pub struct TableStream {
 }
 
 impl TableStream {
-    pub fn new(tx: &Transaction, table: Arc<RwLock<BTreeTable>>) -> Self {
-        let iter = BTreeTableIterator::new(tx, &table.rl());
-        Self { iter }
+    pub fn new(
+        tx: &Transaction,
+        table: Arc<RwLock<BTreeTable>>,
+        cancel_token: &CancellationToken,
+    ) -> Result<Self, SmallError> {
+        let iter = BTreeTableIterator::new(tx, &table.rl())?.with_cancel_token(cancel_token);
+        Ok(Self { iter })
     }
 }
 
@@ -24,7 +31,7 @@ impl Stream for TableStream {
         let mut tuples = Vec::new();
 
         for _ in 0..100 {
-            match self.iter.next() {
+            match self.iter.try_next()? {
                 Some(tuple) => tuples.push(tuple.get_tuple().clone()),
                 None => break,
             }
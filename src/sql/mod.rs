@@ -1,4 +1,5 @@
 pub mod executor;
 pub mod optimizer;
 pub mod planner;
+pub mod repl;
 pub mod session;
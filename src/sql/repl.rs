@@ -0,0 +1,152 @@
+use std::io::{BufRead, Write};
+
+use super::session::{ExecResult, QueryResult, Session};
+use crate::{
+    types::SmallResult,
+    utils::{render_grid, HandyRwLock},
+    Database,
+};
+
+const PROMPT: &str = "small-db=> ";
+const CONTINUATION_PROMPT: &str = "small-db-> ";
+
+/// Run an interactive shell, reading statements from `reader` and printing
+/// the results to `writer` until the input ends or `\q` is entered.
+///
+/// A statement may span multiple lines and ends with ";". Each statement
+/// runs in its own transaction. Lines starting with "\" are meta-commands:
+///
+/// - `\dt`: list all tables
+/// - `\d <name>`: describe the schema of a table
+/// - `\q`: quit
+pub fn run_repl<R: BufRead, W: Write>(reader: R, mut writer: W, prompt: bool) -> SmallResult {
+    let mut session = Session::new();
+    let mut statement = String::new();
+
+    let mut lines = reader.lines();
+    loop {
+        if prompt {
+            let p = if statement.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            write_output(&mut writer, p)?;
+            writer.flush()?;
+        }
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let line = line.trim();
+
+        if statement.is_empty() && line.starts_with('\\') {
+            if line == "\\q" {
+                break;
+            }
+            let output = handle_meta_command(line);
+            write_output(&mut writer, &output)?;
+            continue;
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        statement.push_str(line);
+        statement.push('\n');
+        if !line.ends_with(';') {
+            continue;
+        }
+
+        let output = execute_statement(&mut session, &statement);
+        write_output(&mut writer, &output)?;
+        statement.clear();
+    }
+
+    Ok(())
+}
+
+fn write_output<W: Write>(writer: &mut W, output: &str) -> SmallResult {
+    writer.write_all(output.as_bytes())?;
+    Ok(())
+}
+
+fn execute_statement(session: &mut Session, sql: &str) -> String {
+    match session.run(sql) {
+        Ok(ExecResult::Rows(rows)) => format_result(&rows),
+        Ok(ExecResult::Affected(count)) => format!("OK, {} rows affected\n", count),
+        Ok(ExecResult::Ddl) | Ok(ExecResult::Control) => "OK\n".to_string(),
+        Err(e) => format!("ERROR: {}\n", e),
+    }
+}
+
+fn format_result(result: &QueryResult) -> String {
+    if result.columns.is_empty() {
+        return "OK\n".to_string();
+    }
+
+    let rows: Vec<Vec<String>> = result
+        .data
+        .iter()
+        .map(|t| {
+            t.get_cells()
+                .iter()
+                .map(|cell| cell.to_display_string())
+                .collect()
+        })
+        .collect();
+
+    let mut output = render_grid(&result.columns, &rows);
+    if rows.len() == 1 {
+        output.push_str("(1 row)\n");
+    } else {
+        output.push_str(&format!("({} rows)\n", rows.len()));
+    }
+    output
+}
+
+fn handle_meta_command(line: &str) -> String {
+    let mut parts = line.split_whitespace();
+    let command = parts.next().unwrap_or("");
+    let argument = parts.next();
+
+    match (command, argument) {
+        ("\\dt", None) => {
+            let rows: Vec<Vec<String>> = Database::catalog()
+                .list_tables()
+                .into_iter()
+                .map(|name| vec![name])
+                .collect();
+            render_grid(&["name".to_string()], &rows)
+        }
+        ("\\d", Some(table_name)) => {
+            let table_rc = match Database::catalog().search_table(table_name) {
+                Some(table_rc) => table_rc,
+                None => return format!("ERROR: table {} not found\n", table_name),
+            };
+
+            let rows: Vec<Vec<String>> = table_rc
+                .rl()
+                .get_schema()
+                .columns()
+                .iter()
+                .map(|f| {
+                    vec![
+                        f.name().to_string(),
+                        format!("{:?}", f.cell_type()),
+                        f.is_key().to_string(),
+                    ]
+                })
+                .collect();
+            let header = vec![
+                "column".to_string(),
+                "type".to_string(),
+                "primary key".to_string(),
+            ];
+            render_grid(&header, &rows)
+        }
+        _ => format!("ERROR: unknown command {}\n", line),
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    cmp::Ordering,
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
+
+use sqlparser::ast::{Query, SelectItem, SetExpr};
+
+use super::QueryResult;
+use crate::{
+    btree::table::BTreeTableRangeIterator,
+    error::SmallError,
+    sql::executor::{
+        select::select_conditions,
+        stream::{matches_conditions, Disjunction},
+    },
+    storage::tuple::{Cell, Tuple},
+    transaction::{Snapshot, Transaction},
+    types::SmallResult,
+    utils::HandyRwLock,
+    BTreeTable, Database, Predicate,
+};
+
+/// The direction a FETCH moves the cursor in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchDirection {
+    Forward,
+    Backward,
+}
+
+/// Where a cursor stands between two FETCHes.
+enum Position {
+    BeforeFirst,
+
+    /// On the row with `key`, `rank` rows with the same key precede it.
+    On {
+        key: Cell,
+        rank: usize,
+    },
+
+    AfterLast,
+}
+
+/// A cursor declared by `DECLARE name CURSOR FOR SELECT ...`, the client
+/// pages through the rows of the query with FETCH instead of receiving
+/// them all at once.
+///
+/// The rows are not kept by the cursor, each FETCH reads them from a range
+/// iterator over the table starting at the position of the cursor, from its
+/// back end for FETCH BACKWARD. The iterators see the table under the
+/// snapshot taken when the cursor is declared, so the transactions
+/// committing in the meantime don't change the rows of the cursor. (The
+/// changes made by the transaction of the cursor itself are seen.)
+///
+/// Like in Postgres, the cursor has a position: before the first row, on a
+/// row, or after the last row. FETCH FORWARD returns the rows following the
+/// position in key order, FETCH BACKWARD the rows preceding it in reverse
+/// order, and the cursor is left on the last row returned (or past the end
+/// if the rows run out).
+pub struct Cursor {
+    tx: Transaction,
+    table: Arc<RwLock<BTreeTable>>,
+    snapshot: Arc<Snapshot>,
+
+    columns: Vec<String>,
+
+    /// The conditions of the WHERE clause, see `select_conditions`.
+    predicates: Vec<Predicate>,
+    disjunctions: Vec<Disjunction>,
+
+    position: Position,
+}
+
+impl Cursor {
+    /// Open a cursor over the rows of `query` for `tx`.
+    ///
+    /// `query` is a `SELECT *` from a single table with an optional WHERE
+    /// clause. The rows come in key order, ORDER BY and LIMIT are rejected.
+    pub fn declare(tx: &Transaction, query: &Query) -> Result<Self, SmallError> {
+        let select = match query.body.as_ref() {
+            SetExpr::Select(select) => select,
+            _ => {
+                return Err(SmallError::new(
+                    "a cursor can only be declared for a SELECT",
+                ))
+            }
+        };
+
+        if !matches!(select.projection.as_slice(), [SelectItem::Wildcard(_)]) {
+            return Err(SmallError::new("only SELECT * is supported in cursors"));
+        }
+        if !query.order_by.is_empty()
+            || query.limit.is_some()
+            || query.offset.is_some()
+            || query.fetch.is_some()
+        {
+            return Err(SmallError::new(
+                "ORDER BY and LIMIT are not supported in cursors, the rows come in key order",
+            ));
+        }
+
+        let _dml_guard = Database::dml_latch();
+        let (table_rc, predicates, disjunctions) = select_conditions(select)?;
+        let columns = table_rc
+            .rl()
+            .get_schema()
+            .get_fields()
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+
+        Ok(Self {
+            tx: tx.clone(),
+            table: table_rc,
+            snapshot: Arc::new(Snapshot::take(tx)),
+            columns,
+            predicates,
+            disjunctions,
+            position: Position::BeforeFirst,
+        })
+    }
+
+    /// Fetch up to `count` rows in `direction`, or all the remaining rows
+    /// if `count` is None. An empty result means there is no row left in
+    /// this direction.
+    pub fn fetch(
+        &mut self,
+        direction: FetchDirection,
+        count: Option<usize>,
+    ) -> Result<QueryResult, SmallError> {
+        let _dml_guard = Database::dml_latch();
+
+        let mut result = QueryResult::new();
+        result.columns = self.columns.clone();
+        if count == Some(0) {
+            return Ok(result);
+        }
+
+        let table_rc = Arc::clone(&self.table);
+        let table = table_rc.rl();
+        match direction {
+            FetchDirection::Forward => self.fetch_forward(&table, count, &mut result.data)?,
+            FetchDirection::Backward => self.fetch_backward(&table, count, &mut result.data)?,
+        }
+        Ok(result)
+    }
+
+    fn fetch_forward(
+        &mut self,
+        table: &BTreeTable,
+        count: Option<usize>,
+        rows: &mut Vec<Tuple>,
+    ) -> SmallResult {
+        // the rows with the key of the current row, up to the current row,
+        // are skipped
+        let (mut iter, skip) = match &self.position {
+            Position::BeforeFirst => (self.range(table, Bound::Unbounded, Bound::Unbounded)?, None),
+            Position::On { key, rank } => (
+                self.range(table, Bound::Included(key.clone()), Bound::Unbounded)?,
+                Some((key.clone(), *rank)),
+            ),
+            Position::AfterLast => return Ok(()),
+        };
+
+        // the key and the rank of the last row read
+        let mut last: Option<(Cell, usize)> = None;
+        while count.is_none_or(|count| rows.len() < count) {
+            let tuple = match iter.try_next()? {
+                Some(tuple) => tuple,
+                None => {
+                    self.position = Position::AfterLast;
+                    return Ok(());
+                }
+            };
+
+            let key = tuple.get_cell(table.key_field);
+            let rank = match &last {
+                Some((last_key, last_rank))
+                    if table.compare_keys(&key, last_key) == Ordering::Equal =>
+                {
+                    last_rank + 1
+                }
+                _ => 0,
+            };
+            last = Some((key.clone(), rank));
+
+            if let Some((skip_key, skip_rank)) = &skip {
+                if rank <= *skip_rank && table.compare_keys(&key, skip_key) == Ordering::Equal {
+                    continue;
+                }
+            }
+            if !matches_conditions(&tuple, &self.predicates, &self.disjunctions) {
+                continue;
+            }
+
+            rows.push(tuple.get_tuple().clone());
+            self.position = Position::On { key, rank };
+        }
+        Ok(())
+    }
+
+    fn fetch_backward(
+        &mut self,
+        table: &BTreeTable,
+        count: Option<usize>,
+        rows: &mut Vec<Tuple>,
+    ) -> SmallResult {
+        // the rows with the key of the current row, from the current row on,
+        // are skipped, they are counted from the back end
+        let (mut iter, skip) = match &self.position {
+            Position::BeforeFirst => return Ok(()),
+            Position::On { key, rank } => (
+                self.range(table, Bound::Unbounded, Bound::Included(key.clone()))?,
+                Some((
+                    key.clone(),
+                    self.count_key(table, key)?.saturating_sub(*rank),
+                )),
+            ),
+            Position::AfterLast => (self.range(table, Bound::Unbounded, Bound::Unbounded)?, None),
+        };
+
+        // the key of the last row read, with its rank counted from the back
+        // end
+        let mut last: Option<(Cell, usize)> = None;
+        let mut returned = None;
+        while count.is_none_or(|count| rows.len() < count) {
+            let tuple = match iter.try_next_back()? {
+                Some(tuple) => tuple,
+                None => {
+                    self.position = Position::BeforeFirst;
+                    return Ok(());
+                }
+            };
+
+            let key = tuple.get_cell(table.key_field);
+            let back_rank = match &last {
+                Some((last_key, last_rank))
+                    if table.compare_keys(&key, last_key) == Ordering::Equal =>
+                {
+                    last_rank + 1
+                }
+                _ => 0,
+            };
+            last = Some((key.clone(), back_rank));
+
+            if let Some((skip_key, skip_count)) = &skip {
+                if back_rank < *skip_count && table.compare_keys(&key, skip_key) == Ordering::Equal
+                {
+                    continue;
+                }
+            }
+            if !matches_conditions(&tuple, &self.predicates, &self.disjunctions) {
+                continue;
+            }
+
+            rows.push(tuple.get_tuple().clone());
+            returned = Some((key, back_rank));
+        }
+
+        if let Some((key, back_rank)) = returned {
+            let rank = self.count_key(table, &key)?.saturating_sub(back_rank + 1);
+            self.position = Position::On { key, rank };
+        }
+        Ok(())
+    }
+
+    fn range(
+        &self,
+        table: &BTreeTable,
+        lower: Bound<Cell>,
+        upper: Bound<Cell>,
+    ) -> Result<BTreeTableRangeIterator, SmallError> {
+        Ok(table
+            .range(&self.tx, lower, upper)?
+            .with_snapshot(Arc::clone(&self.snapshot)))
+    }
+
+    /// Count the rows with `key`, whether they match the query or not.
+    fn count_key(&self, table: &BTreeTable, key: &Cell) -> Result<usize, SmallError> {
+        let mut iter = self.range(
+            table,
+            Bound::Included(key.clone()),
+            Bound::Included(key.clone()),
+        )?;
+        let mut count = 0;
+        while iter.try_next()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -1,16 +1,314 @@
-use super::executor::{sql_handler::handle_sql, stream::Batch};
-use crate::{error::SmallError, storage::tuple::Tuple, transaction::Transaction};
+use std::collections::HashMap;
 
-pub struct Session {}
+use log::error;
 
+use sqlparser::ast::{self, CloseCursor, ObjectType, Query, Statement, Value};
+
+use super::executor::{
+    sql_handler::{handle_statements, parse_sql},
+    stream::Batch,
+};
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    storage::tuple::{Cell, Tuple},
+    transaction::{SavepointId, Transaction},
+    types::SmallResult,
+    utils::CancellationToken,
+};
+
+mod cursor;
+pub use cursor::*;
+
+pub struct Session {
+    /// Token used to cancel the running query of this session.
+    cancel_token: CancellationToken,
+
+    /// The maximum number of rows a query may return, `None` for no limit.
+    max_rows: Option<usize>,
+
+    /// The transaction opened by BEGIN, `None` outside an explicit
+    /// transaction.
+    current_tx: Option<Transaction>,
+
+    /// A statement failed in the explicit transaction, which is aborted. The
+    /// statements are rejected until COMMIT or ROLLBACK ends the
+    /// transaction block.
+    aborted: bool,
+
+    /// The savepoints of the current transaction by name, in the order they
+    /// were taken, with the number of cursors declared before each of them.
+    /// A name may be reused, the latest savepoint wins.
+    savepoints: Vec<(String, SavepointId, usize)>,
+
+    /// The cursors declared in the current transaction by name, with the
+    /// order they were declared in. They are closed when the transaction
+    /// ends, or when it's rolled back to a savepoint taken before them.
+    cursors: HashMap<String, (usize, Cursor)>,
+
+    /// The number of cursors declared by the session, gives the order of
+    /// the cursors.
+    cursors_declared: usize,
+}
+
+#[derive(Debug)]
 pub struct QueryResult {
+    /// Names of the columns, empty for statements returning no rows.
+    pub columns: Vec<String>,
+
     pub data: Vec<Tuple>,
     cursor: usize,
 }
 
+/// The outcome of a statement, tells the rows of a query from the effect of
+/// the other statements (like the command tag of Postgres).
+#[derive(Debug)]
+pub enum ExecResult {
+    /// The rows returned by a query (SELECT, EXPLAIN, SHOW).
+    Rows(QueryResult),
+
+    /// The number of rows changed by an INSERT, UPDATE or DELETE.
+    Affected(usize),
+
+    /// A statement changing the schema (CREATE, DROP).
+    Ddl,
+
+    /// A transaction or cursor control statement (BEGIN, COMMIT, ROLLBACK,
+    /// SAVEPOINT, DECLARE, CLOSE).
+    Control,
+}
+
+/// The transaction control statements handled by the session itself.
+#[derive(Debug, PartialEq, Eq)]
+enum TxControl {
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(String),
+    RollbackTo(String),
+    Release(String),
+}
+
+impl TxControl {
+    /// Recognize a transaction control statement, `None` for the other
+    /// statements.
+    fn parse(statement: &Statement) -> Option<Self> {
+        match statement {
+            Statement::StartTransaction { .. } => Some(TxControl::Begin),
+            Statement::Commit { chain: false } => Some(TxControl::Commit),
+            Statement::Rollback { chain: false } => Some(TxControl::Rollback),
+            // names of savepoints are case-insensitive unless quoted, like
+            // the other identifiers
+            Statement::Savepoint { name } if name.quote_style.is_none() => {
+                Some(TxControl::Savepoint(name.value.to_lowercase()))
+            }
+            Statement::Savepoint { name } => Some(TxControl::Savepoint(name.value.clone())),
+            _ => None,
+        }
+    }
+
+    /// Recognize the transaction control statements the SQL parser doesn't
+    /// know (END, ABORT, ROLLBACK TO and RELEASE) from their keywords.
+    fn parse_unsupported(sql: &str) -> Option<Self> {
+        let words: Vec<&str> = sql
+            .trim()
+            .trim_end_matches(';')
+            .split_whitespace()
+            .collect();
+        let keywords: Vec<String> = words.iter().map(|w| w.to_uppercase()).collect();
+        let keywords: Vec<&str> = keywords.iter().map(|w| w.as_str()).collect();
+
+        // names of savepoints are case-insensitive, like the other
+        // identifiers
+        let name = || words.last().unwrap().to_lowercase();
+
+        match keywords.as_slice() {
+            ["END"] | ["END", "TRANSACTION"] | ["END", "WORK"] => Some(TxControl::Commit),
+            ["ABORT"] | ["ABORT", "TRANSACTION"] | ["ABORT", "WORK"] => Some(TxControl::Rollback),
+            ["ROLLBACK", "TO", _] | ["ROLLBACK", "TO", "SAVEPOINT", _] => {
+                Some(TxControl::RollbackTo(name()))
+            }
+            ["RELEASE", _] | ["RELEASE", "SAVEPOINT", _] => Some(TxControl::Release(name())),
+            _ => None,
+        }
+    }
+
+    /// Get the command of the statement as Postgres names it in the
+    /// completion tag.
+    fn command(&self) -> &'static str {
+        match self {
+            TxControl::Begin => "BEGIN",
+            TxControl::Commit => "COMMIT",
+            TxControl::Rollback | TxControl::RollbackTo(_) => "ROLLBACK",
+            TxControl::Savepoint(_) => "SAVEPOINT",
+            TxControl::Release(_) => "RELEASE",
+        }
+    }
+}
+
+/// Get the command of the (last) statement of the text as Postgres names it
+/// in the completion tag, e.g. "INSERT 0" or "CREATE TABLE".
+pub(crate) fn command_name(sql_text: &str) -> String {
+    let statements = match parse_sql(sql_text) {
+        Ok(statements) => statements,
+        Err(_) => {
+            return TxControl::parse_unsupported(sql_text)
+                .map(|control| control.command().to_string())
+                .unwrap_or_default();
+        }
+    };
+    let statement = match statements.last() {
+        Some(statement) => statement,
+        None => return String::new(),
+    };
+    if let Some(control) = TxControl::parse(statement) {
+        return control.command().to_string();
+    }
+
+    match statement {
+        // the oid of the inserted row is always 0
+        Statement::Insert { .. } => "INSERT 0".to_string(),
+        Statement::Update { .. } => "UPDATE".to_string(),
+        Statement::Delete { .. } => "DELETE".to_string(),
+        Statement::CreateTable { .. } => "CREATE TABLE".to_string(),
+        Statement::CreateIndex { .. } => "CREATE INDEX".to_string(),
+        Statement::AlterTable { .. } => "ALTER TABLE".to_string(),
+        Statement::Drop { object_type, .. } => format!("DROP {}", object_type),
+        Statement::Declare { .. } => "DECLARE CURSOR".to_string(),
+        Statement::Fetch { .. } => "FETCH".to_string(),
+        Statement::Close { .. } => "CLOSE CURSOR".to_string(),
+        Statement::Analyze { .. } => "ANALYZE".to_string(),
+        Statement::Query(_) => "SELECT".to_string(),
+        Statement::Explain { .. } => "EXPLAIN".to_string(),
+        Statement::ShowVariable { .. } => "SHOW".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// The cursor statements handled by the session itself.
+#[derive(Debug, PartialEq, Eq)]
+enum CursorCommand {
+    Declare {
+        name: String,
+        query: Box<Query>,
+    },
+    Fetch {
+        name: String,
+        direction: FetchDirection,
+        count: Option<usize>,
+    },
+    /// None closes all the cursors.
+    Close(Option<String>),
+}
+
+impl CursorCommand {
+    /// Recognize a cursor statement, `None` for the other statements.
+    ///
+    /// - DECLARE name [SCROLL | NO SCROLL] CURSOR [WITHOUT HOLD] FOR query
+    /// - FETCH [NEXT | PRIOR | count | ALL | [FORWARD | BACKWARD] [count |
+    ///   ALL]] {FROM | IN} name
+    /// - CLOSE name | ALL
+    fn parse(statement: &Statement) -> Option<Result<Self, SmallError>> {
+        let unsupported = |what: &str| {
+            Some(Err(SmallError::new(&format!(
+                "{} is not supported: {}",
+                what, statement
+            ))))
+        };
+
+        match statement {
+            Statement::Declare {
+                name,
+                binary,
+                hold,
+                query,
+                ..
+            } => {
+                if *binary {
+                    return unsupported("BINARY cursor");
+                }
+                if *hold == Some(true) {
+                    return unsupported("cursor WITH HOLD");
+                }
+                Some(Ok(CursorCommand::Declare {
+                    name: name.value.to_lowercase(),
+                    query: query.clone(),
+                }))
+            }
+            Statement::Fetch {
+                name,
+                direction,
+                into,
+            } => {
+                if into.is_some() {
+                    return unsupported("FETCH INTO");
+                }
+
+                let count = |limit: &Value| match limit {
+                    Value::Number(n, _) => n.parse::<usize>().ok(),
+                    _ => None,
+                };
+                let (direction, limit) = match direction {
+                    ast::FetchDirection::Next | ast::FetchDirection::Forward { limit: None } => {
+                        (FetchDirection::Forward, Some(1))
+                    }
+                    ast::FetchDirection::Prior | ast::FetchDirection::Backward { limit: None } => {
+                        (FetchDirection::Backward, Some(1))
+                    }
+                    ast::FetchDirection::All | ast::FetchDirection::ForwardAll => {
+                        (FetchDirection::Forward, None)
+                    }
+                    ast::FetchDirection::BackwardAll => (FetchDirection::Backward, None),
+                    ast::FetchDirection::Count { limit }
+                    | ast::FetchDirection::Forward { limit: Some(limit) } => match count(limit) {
+                        Some(count) => (FetchDirection::Forward, Some(count)),
+                        None => return unsupported("FETCH count"),
+                    },
+                    ast::FetchDirection::Backward { limit: Some(limit) } => match count(limit) {
+                        Some(count) => (FetchDirection::Backward, Some(count)),
+                        None => return unsupported("FETCH count"),
+                    },
+                    _ => return unsupported("FETCH direction"),
+                };
+                Some(Ok(CursorCommand::Fetch {
+                    name: name.value.to_lowercase(),
+                    direction,
+                    count: limit,
+                }))
+            }
+            Statement::Close { cursor } => match cursor {
+                CloseCursor::All => Some(Ok(CursorCommand::Close(None))),
+                CloseCursor::Specific { name } => {
+                    Some(Ok(CursorCommand::Close(Some(name.value.to_lowercase()))))
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+impl ExecResult {
+    /// Get the rows of a query, `None` for the other statements.
+    pub fn into_rows(self) -> Option<QueryResult> {
+        match self {
+            ExecResult::Rows(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Get the number of rows changed by the statement, `None` for queries
+    /// and DDL statements.
+    pub fn affected(&self) -> Option<usize> {
+        match self {
+            ExecResult::Affected(count) => Some(*count),
+            _ => None,
+        }
+    }
+}
+
 impl QueryResult {
     pub fn new() -> Self {
         Self {
+            columns: Vec::new(),
             data: Vec::new(),
             cursor: 0,
         }
@@ -19,6 +317,18 @@ impl QueryResult {
     pub fn push_batch(&mut self, batch: &Batch) {
         self.data.extend(batch.rows.clone());
     }
+
+    /// Return up to `n` rows after the cursor and move the cursor past them,
+    /// an empty vector means all rows have been consumed.
+    ///
+    /// The cursor is shared with the `Stream` implementation, rows returned
+    /// by one of them are not returned again by the other.
+    pub fn fetch(&mut self, n: usize) -> Vec<Tuple> {
+        let end = std::cmp::min(self.cursor + n, self.data.len());
+        let rows = self.data[self.cursor..end].to_vec();
+        self.cursor = end;
+        rows
+    }
 }
 
 impl futures_core::stream::Stream for QueryResult {
@@ -26,33 +336,322 @@ impl futures_core::stream::Stream for QueryResult {
 
     fn poll_next(
         self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
+        _cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        if self.cursor >= self.data.len() {
-            return std::task::Poll::Ready(None);
-        }
+        let tuple = match self.get_mut().fetch(1).pop() {
+            Some(tuple) => tuple,
+            None => return std::task::Poll::Ready(None),
+        };
 
-        let tuple = &self.data[self.cursor];
-        let bytes_list: Vec<Option<bytes::Bytes>> = Vec::new();
+        // rows are sent in the text format, NULL is sent as a missing value
+        let mut bytes_list: Vec<Option<bytes::Bytes>> = Vec::new();
         for cell in tuple.get_cells() {
-            todo!();
-
-            // let bytes = cell.to_bytes();
-            // let v = bytes::Bytes::copy_from_slice(&bytes);
-            // bytes_list.push(Some(v));
+            let text = match cell {
+                Cell::Null => None,
+                _ => Some(cell.to_display_string()),
+            };
+            bytes_list.push(text.map(bytes::Bytes::from));
         }
 
         let data_row = pgwire::messages::data::DataRow::new(bytes_list);
-        return std::task::Poll::Ready(Some(Ok(data_row)));
+        std::task::Poll::Ready(Some(Ok(data_row)))
     }
 }
 
 impl Session {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            cancel_token: CancellationToken::new(),
+            max_rows: None,
+            current_tx: None,
+            aborted: false,
+            savepoints: Vec::new(),
+            cursors: HashMap::new(),
+            cursors_declared: 0,
+        }
+    }
+
+    /// Get the token of this session, calling `cancel` on it stops the query
+    /// currently running in `execute` with a `Cancelled` error.
+    ///
+    /// A tripped token is replaced after the query stops, so the token has to
+    /// be fetched again for the following queries.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel_token.clone()
+    }
+
+    /// Limit the number of rows a query may return, queries exceeding it
+    /// fail with a `ResultTooLarge` error instead of sending the rows.
+    pub fn set_max_rows(&mut self, max_rows: Option<usize>) {
+        self.max_rows = max_rows;
+    }
+
+    pub fn get_max_rows(&self) -> Option<usize> {
+        self.max_rows
+    }
+
+    pub fn execute(&mut self, tx: &Transaction, sql_text: &str) -> Result<ExecResult, SmallError> {
+        let statements = parse_sql(sql_text)?;
+        self.execute_statements(tx, &statements, sql_text)
+    }
+
+    fn execute_statements(
+        &mut self,
+        tx: &Transaction,
+        statements: &[Statement],
+        sql_text: &str,
+    ) -> Result<ExecResult, SmallError> {
+        let result = handle_statements(tx, statements, sql_text, &self.cancel_token, self.max_rows);
+
+        if self.cancel_token.is_cancelled() {
+            self.cancel_token = CancellationToken::new();
+        }
+
+        result
+    }
+
+    /// Run a statement in the transaction of the session.
+    ///
+    /// BEGIN opens a transaction which is used by the following statements
+    /// until COMMIT or ROLLBACK finalizes it. Inside it, SAVEPOINT name
+    /// marks a point the transaction can go back to with ROLLBACK TO
+    /// SAVEPOINT name, RELEASE SAVEPOINT name forgets it. Outside an explicit
+    /// transaction each statement runs in a transaction of its own, which
+    /// is committed if the statement succeeds.
+    ///
+    /// A failed statement aborts the transaction it runs in, including the
+    /// explicit one, since the changes it made so far can't be undone
+    /// alone. Like in Postgres, the following statements of the transaction
+    /// block are rejected until it is ended by ROLLBACK (or COMMIT, which
+    /// can only roll back at this point).
+    ///
+    /// DECLARE name CURSOR FOR query opens a cursor in the explicit
+    /// transaction, FETCH reads the rows of the query from it (see
+    /// `Cursor`) and CLOSE name closes it. The cursors are closed when the
+    /// transaction ends.
+    ///
+    /// DROP TABLE and DROP INDEX remove the table file right away and can't
+    /// be rolled back, so like VACUUM in Postgres they are rejected inside a
+    /// transaction block.
+    ///
+    /// The text holds a single statement, several statements sent together
+    /// are rejected (see `handle_statements`).
+    pub fn run(&mut self, sql_text: &str) -> Result<ExecResult, SmallError> {
+        let statements = parse_sql(sql_text);
+        let control = match statements.as_deref() {
+            Ok([statement]) => TxControl::parse(statement),
+            Ok(_) => None,
+            Err(_) => TxControl::parse_unsupported(sql_text),
+        };
+        if let Some(control) = control {
+            self.run_control(control)?;
+            return Ok(ExecResult::Control);
+        }
+
+        if self.aborted {
+            return Err(aborted_tx_error());
+        }
+
+        if let Ok([statement]) = statements.as_deref() {
+            if let Some(command) = CursorCommand::parse(statement) {
+                return self.run_cursor(command?);
+            }
+        }
+
+        let (tx, explicit) = match &self.current_tx {
+            Some(tx) => (tx.clone(), true),
+            None => (Transaction::new(), false),
+        };
+
+        let result = statements.and_then(|statements| {
+            if explicit && statements.iter().any(drops_table) {
+                return Err(SmallError::new(
+                    "DROP TABLE and DROP INDEX cannot run inside a transaction block",
+                ));
+            }
+            self.execute_statements(&tx, &statements, sql_text)
+        });
+        match result {
+            Ok(result) => {
+                if !explicit {
+                    tx.commit()?;
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                self.current_tx = None;
+                self.aborted = explicit;
+                self.savepoints.clear();
+                self.cursors.clear();
+                if let Err(abort_err) = tx.abort() {
+                    error!("failed to abort {:?}: {}", tx, abort_err);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Get the transaction opened by BEGIN, `None` outside an explicit
+    /// transaction.
+    pub fn current_tx(&self) -> Option<&Transaction> {
+        self.current_tx.as_ref()
+    }
+
+    fn run_control(&mut self, control: TxControl) -> SmallResult {
+        if self.aborted {
+            return match control {
+                TxControl::Begin => Err(SmallError::with_kind(
+                    SmallErrorKind::Other,
+                    "there is already a transaction in progress",
+                )),
+                // the transaction has been aborted when the statement
+                // failed, only the transaction block is left to end
+                TxControl::Commit | TxControl::Rollback => {
+                    self.aborted = false;
+                    Ok(())
+                }
+                _ => Err(aborted_tx_error()),
+            };
+        }
+
+        match control {
+            TxControl::Begin => {
+                if self.current_tx.is_some() {
+                    return Err(SmallError::with_kind(
+                        SmallErrorKind::Other,
+                        "there is already a transaction in progress",
+                    ));
+                }
+                self.current_tx = Some(Transaction::new());
+                Ok(())
+            }
+            TxControl::Commit | TxControl::Rollback => {
+                let tx = self.current_tx.take().ok_or(SmallError::with_kind(
+                    SmallErrorKind::Other,
+                    "there is no transaction in progress",
+                ))?;
+                self.savepoints.clear();
+                self.cursors.clear();
+                if control == TxControl::Commit {
+                    tx.commit()
+                } else {
+                    tx.abort()
+                }
+            }
+            TxControl::Savepoint(name) => {
+                let id = self.explicit_tx("SAVEPOINT")?.savepoint()?;
+                self.savepoints.push((name, id, self.cursors_declared));
+                Ok(())
+            }
+            TxControl::RollbackTo(name) => {
+                let i = self.find_savepoint(&name)?;
+                let (_, id, cursors_declared) = self.savepoints[i];
+                self.explicit_tx("ROLLBACK TO SAVEPOINT")?.rollback_to(id)?;
+                self.savepoints.truncate(i + 1);
+
+                // the cursors declared after the savepoint are closed
+                self.cursors
+                    .retain(|_, (order, _)| *order < cursors_declared);
+                Ok(())
+            }
+            TxControl::Release(name) => {
+                let i = self.find_savepoint(&name)?;
+                self.explicit_tx("RELEASE SAVEPOINT")?
+                    .release_savepoint(self.savepoints[i].1)?;
+                self.savepoints.truncate(i);
+                Ok(())
+            }
+        }
+    }
+
+    fn run_cursor(&mut self, command: CursorCommand) -> Result<ExecResult, SmallError> {
+        match command {
+            CursorCommand::Declare { name, query } => {
+                if self.cursors.contains_key(&name) {
+                    return Err(SmallError::new(&format!(
+                        "cursor \"{}\" already exists",
+                        name
+                    )));
+                }
+                let cursor = Cursor::declare(self.explicit_tx("DECLARE CURSOR")?, &query)?;
+                self.cursors.insert(name, (self.cursors_declared, cursor));
+                self.cursors_declared += 1;
+                Ok(ExecResult::Control)
+            }
+            CursorCommand::Fetch {
+                name,
+                direction,
+                count,
+            } => {
+                let (_, cursor) = self.cursors.get_mut(&name).ok_or(cursor_not_found(&name))?;
+                Ok(ExecResult::Rows(cursor.fetch(direction, count)?))
+            }
+            CursorCommand::Close(Some(name)) => {
+                self.cursors.remove(&name).ok_or(cursor_not_found(&name))?;
+                Ok(ExecResult::Control)
+            }
+            CursorCommand::Close(None) => {
+                self.cursors.clear();
+                Ok(ExecResult::Control)
+            }
+        }
     }
 
-    pub fn execute(&mut self, tx: &Transaction, sql_text: &str) -> Result<QueryResult, SmallError> {
-        handle_sql(tx, sql_text)
+    /// Get the transaction opened by BEGIN, `statement` can only be used
+    /// inside it.
+    fn explicit_tx(&self, statement: &str) -> Result<&Transaction, SmallError> {
+        self.current_tx.as_ref().ok_or(SmallError::with_kind(
+            SmallErrorKind::Other,
+            &format!("{} can only be used in transaction blocks", statement),
+        ))
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize, SmallError> {
+        self.savepoints
+            .iter()
+            .rposition(|(n, _, _)| n == name)
+            .ok_or(SmallError::with_kind(
+                SmallErrorKind::NotFound,
+                &format!("savepoint \"{}\" does not exist", name),
+            ))
+    }
+}
+
+/// Tell if the statement removes the file of a table (an index is stored as
+/// a table).
+fn drops_table(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Drop {
+            object_type: ObjectType::Table | ObjectType::Index,
+            ..
+        }
+    )
+}
+
+fn aborted_tx_error() -> SmallError {
+    SmallError::with_kind(
+        SmallErrorKind::InactiveTransaction,
+        "current transaction is aborted, commands ignored until end of transaction block",
+    )
+}
+
+fn cursor_not_found(name: &str) -> SmallError {
+    SmallError::with_kind(
+        SmallErrorKind::NotFound,
+        &format!("cursor \"{}\" does not exist", name),
+    )
+}
+
+impl Drop for Session {
+    /// A transaction left open when the session ends (e.g. the client
+    /// disconnects) is rolled back.
+    fn drop(&mut self) {
+        self.cursors.clear();
+        if let Some(tx) = self.current_tx.take() {
+            if let Err(e) = tx.abort() {
+                error!("failed to abort {:?}: {}", tx, e);
+            }
+        }
     }
 }
@@ -1,12 +1,17 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, io::Cursor};
 
 use super::Type;
+use crate::io::{read_into, Serializeable, MAX_VARINT_SIZE};
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Field {
     pub name: String,
     t: Type,
     pub is_primary: bool,
+
+    // encode the values as varints instead of fixed-width integers, only
+    // for `Type::Int64` fields
+    varint: bool,
 }
 
 impl Field {
@@ -15,10 +20,72 @@ impl Field {
             t: field_type,
             name: field_name.to_string(),
             is_primary,
+            varint: false,
+        }
+    }
+
+    /// Create an `Int64` field whose values are stored as varints, small
+    /// values take less space on the page but the tuples of the table are no
+    /// longer fixed-width.
+    pub fn new_varint(field_name: &str, is_primary: bool) -> Field {
+        Field {
+            varint: true,
+            ..Field::new(field_name, Type::Int64, is_primary)
         }
     }
 
     pub fn get_type(&self) -> Type {
         self.t
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn cell_type(&self) -> Type {
+        self.t
+    }
+
+    /// Whether the field is the key of the table.
+    pub fn is_key(&self) -> bool {
+        self.is_primary
+    }
+
+    /// Whether the field accepts NULL values. NULL cells cannot be stored
+    /// yet, so no field is nullable.
+    pub fn nullable(&self) -> bool {
+        false
+    }
+
+    pub fn is_varint(&self) -> bool {
+        self.varint
+    }
+
+    /// Get the size of the field in bytes, for a varint field this is the
+    /// size of the widest value.
+    pub(crate) fn get_max_disk_size(&self) -> usize {
+        if self.varint {
+            MAX_VARINT_SIZE
+        } else {
+            self.t.get_disk_size()
+        }
+    }
+
+    /// Encode the type of the field for the catalog: the encoded type,
+    /// followed by an extra byte 1 for varint fields.
+    pub(crate) fn type_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.t.to_bytes(&());
+        if self.varint {
+            bytes.push(1);
+        }
+        bytes
+    }
+
+    /// Create a field from the bytes written by `Field::type_to_bytes`.
+    pub(crate) fn from_type_bytes(field_name: &str, bytes: &[u8], is_primary: bool) -> Field {
+        let field_type: Type = read_into(&mut Cursor::new(bytes), &());
+        let mut field = Field::new(field_name, field_type, is_primary);
+        field.varint = bytes.len() > 2 && bytes[2] == 1;
+        field
+    }
 }
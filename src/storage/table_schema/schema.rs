@@ -1,5 +1,12 @@
+use std::io::Cursor;
+
 use super::{Field, Type};
-use crate::transaction::TRANSACTION_ID_BYTES;
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    io::{read_into, Serializeable, SmallWriter, MAX_VARINT_SIZE},
+    storage::tuple::{Cell, Tuple},
+    transaction::TRANSACTION_ID_BYTES,
+};
 
 #[derive(Debug, Clone)]
 pub struct TableSchema {
@@ -62,7 +69,8 @@ impl TableSchema {
 }
 
 impl TableSchema {
-    /// Get tuple size in bytes.
+    /// Get tuple size in bytes. For a schema with varint fields, this is the
+    /// size of the widest tuple.
     pub(crate) fn get_tuple_size(&self) -> usize {
         let mut size = 0;
 
@@ -73,11 +81,97 @@ impl TableSchema {
         size += TRANSACTION_ID_BYTES;
 
         for field in self.get_fields() {
-            size += field.get_type().get_disk_size();
+            size += field.get_max_disk_size();
         }
         size
     }
 
+    /// Get the size of the narrowest tuple in bytes, where every varint
+    /// field takes 1 byte.
+    pub(crate) fn get_min_tuple_size(&self) -> usize {
+        let varint_count = self.get_fields().iter().filter(|f| f.is_varint()).count();
+        self.get_tuple_size() - varint_count * (MAX_VARINT_SIZE - 1)
+    }
+
+    /// Get the size in bytes of a tuple in a leaf slot, including the
+    /// visibility information (xmin, xmax).
+    ///
+    /// The slots of a fixed-width schema all have this size, for a schema
+    /// with varint fields this is the size of the widest slot.
+    pub fn slot_size(&self) -> usize {
+        self.get_tuple_size()
+    }
+
+    /// Encode the tuple in the format of the leaf slots.
+    pub fn encode_tuple(&self, tuple: &Tuple) -> Result<Vec<u8>, SmallError> {
+        if tuple.cells_count() != self.fields.len() {
+            let err_msg = format!(
+                "tuple arity mismatch, expect {} cells, got {}",
+                self.fields.len(),
+                tuple.cells_count(),
+            );
+            return Err(SmallError::with_kind(
+                SmallErrorKind::SchemaMismatch,
+                &err_msg,
+            ));
+        }
+
+        for (field, cell) in self.fields.iter().zip(tuple.get_cells()) {
+            let valid = match (field.get_type(), &cell) {
+                (Type::Bool, Cell::Bool(_)) => true,
+                (Type::Int64, Cell::Int64(_)) => true,
+                (Type::Float64, Cell::Float64(_)) => true,
+                (Type::Bytes(size), Cell::Bytes(v)) => v.len() <= size as usize,
+                _ => false,
+            };
+            if !valid {
+                let err_msg = format!(
+                    "cell {:?} doesn't fit field {} of type {:?}",
+                    cell,
+                    field.name,
+                    field.get_type(),
+                );
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::SchemaMismatch,
+                    &err_msg,
+                ));
+            }
+        }
+
+        Ok(tuple.to_bytes(self))
+    }
+
+    /// Decode a tuple from the bytes of a leaf slot, the bytes must hold
+    /// exactly one tuple.
+    pub fn decode_tuple(&self, bytes: &[u8]) -> Result<Tuple, SmallError> {
+        if bytes.len() < self.get_min_tuple_size() || bytes.len() > self.get_tuple_size() {
+            let err_msg = format!(
+                "invalid slot size {}, expect {} to {} bytes",
+                bytes.len(),
+                self.get_min_tuple_size(),
+                self.get_tuple_size(),
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+        }
+
+        let mut reader = Cursor::new(bytes);
+        let tuple = Tuple::try_decode(&mut reader, self)?;
+        if reader.position() as usize != bytes.len() {
+            let err_msg = format!(
+                "{} trailing bytes after the tuple",
+                bytes.len() - reader.position() as usize,
+            );
+            return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+        }
+        Ok(tuple)
+    }
+
+    /// Whether all tuples of the schema have the same size, i.e. there is
+    /// no varint field.
+    pub(crate) fn is_fixed_width(&self) -> bool {
+        self.get_fields().iter().all(|f| !f.is_varint())
+    }
+
     /// Get the position of the key field.
     pub fn get_key_pos(&self) -> usize {
         for (i, field) in self.get_fields().iter().enumerate() {
@@ -101,6 +195,11 @@ impl TableSchema {
         &self.fields
     }
 
+    /// Get the columns of the table, in the order of the cells of a tuple.
+    pub fn columns(&self) -> &[Field] {
+        &self.fields
+    }
+
     pub fn get_pkey(&self) -> &Field {
         for field in self.get_fields() {
             if field.is_primary {
@@ -110,3 +209,35 @@ impl TableSchema {
         panic!("no key field found");
     }
 }
+
+/// # Format
+///
+/// - 2 bytes: number of fields
+/// - for each field:
+///   - field name (see `String`)
+///   - field type (see `Field::type_to_bytes`, encoded as `Vec<u8>`)
+///   - 1 byte: whether the field is the key
+impl Serializeable for TableSchema {
+    type Reference = ();
+
+    fn encode(&self, writer: &mut SmallWriter, _: &Self::Reference) {
+        (self.fields.len() as u16).encode(writer, &());
+        for field in &self.fields {
+            field.name.encode(writer, &());
+            field.type_to_bytes().encode(writer, &());
+            field.is_primary.encode(writer, &());
+        }
+    }
+
+    fn decode<R: std::io::Read>(reader: &mut R, _: &Self::Reference) -> Self {
+        let count: u16 = read_into(reader, &());
+        let mut fields = Vec::new();
+        for _ in 0..count {
+            let name: String = read_into(reader, &());
+            let type_bytes: Vec<u8> = read_into(reader, &());
+            let is_primary: bool = read_into(reader, &());
+            fields.push(Field::from_type_bytes(&name, &type_bytes, is_primary));
+        }
+        Self::new(fields)
+    }
+}
@@ -1,8 +1,13 @@
-use std::{fmt::Debug, io::Read};
+use std::{
+    convert::TryInto,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    io::Read,
+};
 
 use crate::{
-    error::SmallError,
-    io::{read_exact, Serializeable, SmallWriter},
+    error::{SmallError, SmallErrorKind},
+    io::{try_read_exact, Serializeable, SmallWriter},
     storage::table_schema::Type,
 };
 
@@ -68,6 +73,141 @@ impl Cell {
             _ => Err(SmallError::new("not bytes")),
         }
     }
+
+    /// Parse a cell of type `t` from its text representation (e.g. a field
+    /// of a CSV file).
+    pub fn parse(text: &str, t: &Type) -> Result<Cell, SmallError> {
+        match t {
+            Type::Bool => match text {
+                "true" | "t" | "1" => Ok(Cell::Bool(true)),
+                "false" | "f" | "0" => Ok(Cell::Bool(false)),
+                _ => Err(SmallError::new("not a bool")),
+            },
+            Type::Int64 => text
+                .parse::<i64>()
+                .map(Cell::Int64)
+                .map_err(|e| SmallError::new(&format!("not an int64: {}", e))),
+            Type::Float64 => text
+                .parse::<f64>()
+                .map(Cell::Float64)
+                .map_err(|e| SmallError::new(&format!("not a float64: {}", e))),
+            Type::Bytes(size) => {
+                if text.len() > *size as usize {
+                    let err_msg = format!("bytes too long: {} > {}", text.len(), size);
+                    return Err(SmallError::new(&err_msg));
+                }
+                Ok(Cell::Bytes(text.as_bytes().to_vec()))
+            }
+        }
+    }
+
+    /// Format the cell for display, the strings are not quoted. See
+    /// `to_display_string_with`.
+    pub fn to_display_string(&self) -> String {
+        self.to_display_string_with(false)
+    }
+
+    /// Format the cell for display, the same way in all the renderers (the
+    /// result grids, the plans, the tree drawings):
+    ///
+    /// - NULL as `NULL`
+    /// - floats with `FLOAT_DISPLAY_PRECISION` digits after the point
+    /// - byte strings as text (invalid UTF-8 is replaced), between single
+    ///   quotes if `quote_strings` is true, in which case the quotes inside
+    ///   the string are doubled as in SQL
+    pub fn to_display_string_with(&self, quote_strings: bool) -> String {
+        match self {
+            Cell::Null => "NULL".to_string(),
+            Cell::Bool(v) => v.to_string(),
+            Cell::Int64(v) => v.to_string(),
+            Cell::Float64(v) => format!("{:.*}", FLOAT_DISPLAY_PRECISION, v),
+            Cell::Bytes(v) => {
+                let text = String::from_utf8_lossy(v);
+                if quote_strings {
+                    format!("'{}'", text.replace('\'', "''"))
+                } else {
+                    text.to_string()
+                }
+            }
+        }
+    }
+}
+
+impl Cell {
+    /// Same as `decode`, but a truncated input or a payload size larger than
+    /// the field is reported as a `Corruption` error instead of panicking.
+    pub(crate) fn try_decode<R: Read>(reader: &mut R, t: &Type) -> Result<Self, SmallError> {
+        match t {
+            Type::Bool => Ok(Cell::Bool(try_read_exact(reader, 1)?[0] == 1)),
+            Type::Int64 => {
+                let bytes = try_read_exact(reader, 8)?;
+                Ok(Cell::Int64(i64::from_le_bytes(bytes.try_into().unwrap())))
+            }
+            Type::Float64 => {
+                let bytes = try_read_exact(reader, 8)?;
+                Ok(Cell::Float64(f64::from_le_bytes(bytes.try_into().unwrap())))
+            }
+            Type::Bytes(x) => {
+                // read size
+                let size_bytes = try_read_exact(reader, 2)?;
+                let size = u16::from_le_bytes(size_bytes.try_into().unwrap()) as usize;
+
+                // read payload
+                let mut payload = try_read_exact(reader, *x as usize)?;
+                if size > payload.len() {
+                    let err_msg = format!(
+                        "payload size {} exceeds the field size {}",
+                        size,
+                        payload.len()
+                    );
+                    return Err(SmallError::with_kind(SmallErrorKind::Corruption, &err_msg));
+                }
+
+                payload.truncate(size);
+                Ok(Cell::Bytes(payload))
+            }
+        }
+    }
+}
+
+/// Number of digits after the point of the floats formatted by
+/// `Cell::to_display_string`.
+pub const FLOAT_DISPLAY_PRECISION: usize = 6;
+
+/// Conversion from a `Cell` to a native rust type, used by the typed column
+/// adapters (e.g. `BTreeTable::scan_column`).
+pub trait FromCell: Sized {
+    fn from_cell(cell: &Cell) -> Result<Self, SmallError>;
+}
+
+impl FromCell for bool {
+    fn from_cell(cell: &Cell) -> Result<Self, SmallError> {
+        cell.get_bool()
+    }
+}
+
+impl FromCell for i64 {
+    fn from_cell(cell: &Cell) -> Result<Self, SmallError> {
+        cell.get_int64()
+    }
+}
+
+impl FromCell for f64 {
+    fn from_cell(cell: &Cell) -> Result<Self, SmallError> {
+        cell.get_float64()
+    }
+}
+
+impl FromCell for Vec<u8> {
+    fn from_cell(cell: &Cell) -> Result<Self, SmallError> {
+        cell.get_bytes()
+    }
+}
+
+impl FromCell for Cell {
+    fn from_cell(cell: &Cell) -> Result<Self, SmallError> {
+        Ok(cell.clone())
+    }
 }
 
 impl Serializeable for Cell {
@@ -107,50 +247,91 @@ impl Serializeable for Cell {
     }
 
     fn decode<R: Read>(reader: &mut R, reference: &Self::Reference) -> Self {
-        match reference {
-            Type::Bool => Cell::Bool(bool::decode(reader, &())),
-            Type::Int64 => Cell::Int64(i64::decode(reader, &())),
-            Type::Float64 => Cell::Float64(f64::decode(reader, &())),
-            Type::Bytes(x) => {
-                // read size
-                let size = u16::decode(reader, &());
-
-                // read payload
-                let payload = read_exact(reader, *x as usize);
-
-                let actual = payload[..size as usize].to_vec();
-
-                return Cell::Bytes(actual);
-            }
-        }
+        Self::try_decode(reader, reference).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
+/// Cells of different variants are never equal.
+///
+/// Floats are compared by value, except that NaN is equal to NaN (whatever
+/// its payload), so the equality is reflexive as required by `Eq`. `0.0`
+/// and `-0.0` are equal.
 impl PartialEq for Cell {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
+            (Cell::Null, Cell::Null) => true,
             (Cell::Bool(a), Cell::Bool(b)) => a == b,
             (Cell::Int64(a), Cell::Int64(b)) => a == b,
-            (Cell::Float64(a), Cell::Float64(b)) => a == b,
+            (Cell::Float64(a), Cell::Float64(b)) => (a.is_nan() && b.is_nan()) || a == b,
             (Cell::Bytes(a), Cell::Bytes(b)) => a == b,
-            _ => todo!(),
+            _ => false,
+        }
+    }
+}
+
+/// Consistent with `PartialEq`: floats are hashed by the bit pattern of a
+/// canonical value, all NaNs share one pattern and `-0.0` is hashed as
+/// `0.0`.
+impl Hash for Cell {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Cell::Null => {}
+            Cell::Bool(v) => v.hash(state),
+            Cell::Int64(v) => v.hash(state),
+            Cell::Float64(v) => {
+                let canonical = if v.is_nan() {
+                    f64::NAN
+                } else if *v == 0.0 {
+                    0.0
+                } else {
+                    *v
+                };
+                canonical.to_bits().hash(state);
+            }
+            Cell::Bytes(v) => v.hash(state),
         }
     }
 }
 
 impl PartialOrd for Cell {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self, other) {
-            (Cell::Int64(a), Cell::Int64(b)) => a.partial_cmp(b),
-            _ => todo!(),
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl Eq for Cell {}
 
+/// A total order consistent with `PartialEq`: NULL comes first, then the
+/// cells of different types are ordered by type (bool, int64, float64,
+/// bytes). Floats are ordered by value with all NaNs equal and after the
+/// other values, `-0.0` equals `0.0`. Bytes are ordered lexicographically.
 impl Ord for Cell {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        match (self, other) {
+            (Cell::Bool(a), Cell::Bool(b)) => a.cmp(b),
+            (Cell::Int64(a), Cell::Int64(b)) => a.cmp(b),
+            (Cell::Float64(a), Cell::Float64(b)) => match (a.is_nan(), b.is_nan()) {
+                (true, true) => std::cmp::Ordering::Equal,
+                (true, false) => std::cmp::Ordering::Greater,
+                (false, true) => std::cmp::Ordering::Less,
+                (false, false) => a.partial_cmp(b).unwrap(),
+            },
+            (Cell::Bytes(a), Cell::Bytes(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+}
+
+impl Cell {
+    /// The position of the type of the cell in the order of `Ord`.
+    fn type_rank(&self) -> u8 {
+        match self {
+            Cell::Null => 0,
+            Cell::Bool(_) => 1,
+            Cell::Int64(_) => 2,
+            Cell::Float64(_) => 3,
+            Cell::Bytes(_) => 4,
+        }
     }
 }
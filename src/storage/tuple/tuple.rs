@@ -1,13 +1,22 @@
 use std::{
+    collections::HashSet,
+    convert::TryInto,
     fmt::{self},
+    hash::{Hash, Hasher},
     usize,
 };
 
+use rand::{distributions::Alphanumeric, Rng};
+
 use crate::{
     btree::page::BTreePageID,
-    io::{Serializeable, SmallWriter},
-    storage::{table_schema::TableSchema, tuple::Cell},
-    transaction::{TransactionID, TransactionStatus},
+    error::SmallError,
+    io::{try_read_exact, try_read_varint, write_varint, Serializeable, SmallWriter},
+    storage::{
+        table_schema::{TableSchema, Type},
+        tuple::Cell,
+    },
+    transaction::{Snapshot, TransactionID, TransactionStatus, TRANSACTION_ID_BYTES},
     Database,
 };
 
@@ -49,6 +58,34 @@ impl Tuple {
     pub(crate) fn clone(&self) -> Self {
         Self::new_x(self.xmin, self.xmax, &self.cells.clone())
     }
+
+    /// Generate a random tuple valid for `schema`, for tests and benchmarks
+    /// not limited to integer tables.
+    ///
+    /// Bytes cells get up to the size of the field of random alphanumeric
+    /// characters. No cell is NULL, NULL cells can't be stored yet (see
+    /// `Field::nullable`).
+    pub fn random<R: Rng>(schema: &TableSchema, rng: &mut R, tx_id: TransactionID) -> Self {
+        let cells = schema
+            .get_fields()
+            .iter()
+            .map(|field| random_cell(&field.get_type(), rng))
+            .collect();
+        Self::new(&cells, tx_id)
+    }
+}
+
+fn random_cell<R: Rng>(t: &Type, rng: &mut R) -> Cell {
+    match t {
+        Type::Bool => Cell::Bool(rng.gen()),
+        Type::Int64 => Cell::Int64(rng.gen()),
+        Type::Float64 => Cell::Float64(rng.gen_range(-1e9, 1e9)),
+        Type::Bytes(size) => {
+            let len = rng.gen_range(0, *size as usize + 1);
+            let bytes = rng.sample_iter(Alphanumeric).take(len).map(|c| c as u8);
+            Cell::Bytes(bytes.collect())
+        }
+    }
 }
 
 impl Tuple {
@@ -60,10 +97,25 @@ impl Tuple {
         self.cells.clone()
     }
 
+    pub fn cells_count(&self) -> usize {
+        self.cells.len()
+    }
+
     /// Determines whether the tuple is visible to the transaction with the
     /// specified ID. This function is only relevant for isolation levels at
     /// or more strict than "Read Committed."
     pub(crate) fn visible_to(&self, tid: TransactionID) -> bool {
+        self.visible(tid, &HashSet::new())
+    }
+
+    /// Determines whether the tuple is visible under the snapshot, the
+    /// transactions in progress when it was taken are treated as such even
+    /// if they have finished since.
+    pub(crate) fn visible_in(&self, snapshot: &Snapshot) -> bool {
+        self.visible(snapshot.get_tx_id(), snapshot.get_in_progress())
+    }
+
+    fn visible(&self, tid: TransactionID, in_progress: &HashSet<TransactionID>) -> bool {
         // Invisible case 1:
         // The tuple is created by a transaction starts later than transaction "tid", so
         // it's not visible to "tid".
@@ -75,6 +127,9 @@ impl Tuple {
         // The transaction that created the tuple started earlier than "tid", but it has
         // not committed yet, so the tuple is not visible to the "tid".
         if self.xmin < tid {
+            if in_progress.contains(&self.xmin) {
+                return false;
+            }
             if let Some(status) = Database::concurrent_status().get_transaction_status(&self.xmin) {
                 if status != TransactionStatus::Committed {
                     return false;
@@ -101,7 +156,7 @@ impl Tuple {
         // Invisible case 4:
         // The tuple was deleted by a transaction that started earlier than "tid", and
         // the deleter has committed, so the tuple is not visible to "tid".
-        if self.xmax < tid {
+        if self.xmax < tid && !in_progress.contains(&self.xmax) {
             if let Some(status) = Database::concurrent_status().get_transaction_status(&self.xmax) {
                 if status == TransactionStatus::Committed {
                     return false;
@@ -116,6 +171,85 @@ impl Tuple {
         // In all other cases, the tuple is visible to "tid".
         return true;
     }
+
+    /// Determines whether the tuple is a tombstone to the transaction with
+    /// the specified ID: it would be visible if it had not been deleted.
+    pub(crate) fn is_tombstone_to(&self, tid: TransactionID) -> bool {
+        if self.xmax == TransactionID::MAX {
+            return false;
+        }
+
+        let live = Self::new_x(self.xmin, TransactionID::MAX, &self.cells);
+        live.visible_to(tid) && !self.visible_to(tid)
+    }
+}
+
+impl Tuple {
+    /// Same as `decode`, but a malformed input is reported as a
+    /// `Corruption` error instead of panicking.
+    pub(crate) fn try_decode<R: std::io::Read>(
+        reader: &mut R,
+        schema: &TableSchema,
+    ) -> Result<Self, SmallError> {
+        let xmin = TransactionID::from_le_bytes(
+            try_read_exact(reader, TRANSACTION_ID_BYTES)?
+                .try_into()
+                .unwrap(),
+        );
+        let xmax = TransactionID::from_le_bytes(
+            try_read_exact(reader, TRANSACTION_ID_BYTES)?
+                .try_into()
+                .unwrap(),
+        );
+
+        let mut cells: Vec<Cell> = Vec::new();
+        for field in schema.get_fields() {
+            let cell = if field.is_varint() {
+                Cell::Int64(try_read_varint(reader)?)
+            } else {
+                Cell::try_decode(reader, &field.get_type())?
+            };
+            cells.push(cell);
+        }
+        Ok(Self::new_x(xmin, xmax, &cells))
+    }
+
+    /// Same as `try_decode`, but only the cell of field `field_index` is
+    /// decoded, the bytes of the other cells are skipped. The returned tuple
+    /// holds that cell only, with the xmin and the xmax of the tuple to tell
+    /// its visibility.
+    pub(crate) fn try_decode_field<R: std::io::Read>(
+        reader: &mut R,
+        schema: &TableSchema,
+        field_index: usize,
+    ) -> Result<Self, SmallError> {
+        let xmin = TransactionID::from_le_bytes(
+            try_read_exact(reader, TRANSACTION_ID_BYTES)?
+                .try_into()
+                .unwrap(),
+        );
+        let xmax = TransactionID::from_le_bytes(
+            try_read_exact(reader, TRANSACTION_ID_BYTES)?
+                .try_into()
+                .unwrap(),
+        );
+
+        let mut cell = None;
+        for (i, field) in schema.get_fields().iter().enumerate() {
+            if i == field_index {
+                cell = Some(if field.is_varint() {
+                    Cell::Int64(try_read_varint(reader)?)
+                } else {
+                    Cell::try_decode(reader, &field.get_type())?
+                });
+            } else if field.is_varint() {
+                try_read_varint(reader)?;
+            } else {
+                try_read_exact(reader, field.get_max_disk_size())?;
+            }
+        }
+        Ok(Self::new_x(xmin, xmax, &vec![cell.unwrap()]))
+    }
 }
 
 /// Modifier
@@ -138,26 +272,28 @@ impl Serializeable for Tuple {
 
         for i in 0..self.cells.len() {
             let cell = &self.cells[i];
-            let t = reference.get_fields()[i].get_type();
-            cell.encode(writer, &t);
+            let field = &reference.get_fields()[i];
+            if field.is_varint() {
+                write_varint(writer, cell.get_int64().unwrap());
+            } else {
+                cell.encode(writer, &field.get_type());
+            }
         }
     }
 
     fn decode<R: std::io::Read>(reader: &mut R, reference: &Self::Reference) -> Self {
-        let xmin = TransactionID::decode(reader, &());
-        let xmax = TransactionID::decode(reader, &());
-
-        let mut cells: Vec<Cell> = Vec::new();
-        for field in reference.get_fields() {
-            let cell = Cell::decode(reader, &field.get_type());
-            cells.push(cell);
-        }
-        Self::new_x(xmin, xmax, &cells)
+        Self::try_decode(reader, reference).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
+/// Tuples are compared by their cells only, the visibility information
+/// (xmin, xmax) is ignored.
 impl PartialEq for Tuple {
     fn eq(&self, other: &Self) -> bool {
+        if self.cells.len() != other.cells.len() {
+            return false;
+        }
+
         for (i, field) in self.cells.iter().enumerate() {
             if field != &other.cells[i] {
                 return false;
@@ -170,6 +306,13 @@ impl PartialEq for Tuple {
 
 impl Eq for Tuple {}
 
+/// Consistent with `PartialEq`, only the cells are hashed.
+impl Hash for Tuple {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.cells.hash(state);
+    }
+}
+
 impl fmt::Display for Tuple {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut content: String = "{".to_owned();
@@ -182,7 +325,7 @@ impl fmt::Display for Tuple {
 
         // cells
         for cell in &self.cells {
-            let cell_str = format!("{:?}, ", cell);
+            let cell_str = format!("{}, ", cell.to_display_string_with(true));
             content.push_str(&cell_str);
         }
         content = content[..content.len() - 2].to_string();
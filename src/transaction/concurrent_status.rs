@@ -9,8 +9,9 @@ use std::{
 use super::wait_for_graph::WaitForGraph;
 use crate::{
     btree::page::BTreePageID,
-    error::SmallError,
-    transaction::{Transaction, TransactionID, TransactionStatus},
+    common::TupleChange,
+    error::{SmallError, SmallErrorKind},
+    transaction::{Savepoint, SavepointId, Transaction, TransactionID, TransactionStatus},
     types::SmallResult,
     Database,
 };
@@ -50,6 +51,12 @@ pub struct ConcurrentStatus {
 
     hold_pages: HashMap<Transaction, HashSet<BTreePageID>>,
 
+    // The page latched by the last latch request of each transaction, only
+    // kept if the transaction held no latch on the page before the request.
+    // A page guard created right after the request takes it, to know whether
+    // it may release the latch.
+    new_latches: HashMap<Transaction, BTreePageID>,
+
     dirty_pages: HashMap<Transaction, HashSet<BTreePageID>>,
 
     // Transaction status, used for transaction isolation, the idea comes from PostgreSQL.
@@ -58,6 +65,22 @@ pub struct ConcurrentStatus {
     // ID, it gives the transaction state (running, aborted, committed).
     transaction_status: HashMap<TransactionID, TransactionStatus>,
 
+    // Tuples modified by each transaction, only recorded when a commit hook
+    // is registered.
+    changes: HashMap<TransactionID, Vec<TupleChange>>,
+
+    // Savepoints of each transaction, in the order they were taken.
+    savepoints: HashMap<TransactionID, Vec<Savepoint>>,
+
+    // Tables whose schema was changed by each transaction, they are written
+    // to the catalog file on commit.
+    schema_changes: HashMap<TransactionID, HashSet<u32>>,
+
+    // The oldest transaction in progress of each live snapshot, with the
+    // number of snapshots sharing it. The vacuum keeps the tuples these
+    // transactions deleted.
+    snapshots: HashMap<TransactionID, usize>,
+
     wait_for_graph: WaitForGraph,
 }
 
@@ -67,11 +90,20 @@ impl ConcurrentStatus {
             s_latch_map: HashMap::new(),
             x_latch_map: HashMap::new(),
             hold_pages: HashMap::new(),
+            new_latches: HashMap::new(),
 
             dirty_pages: HashMap::new(),
 
             transaction_status: HashMap::new(),
 
+            changes: HashMap::new(),
+
+            savepoints: HashMap::new(),
+
+            schema_changes: HashMap::new(),
+
+            snapshots: HashMap::new(),
+
             wait_for_graph: WaitForGraph::new(),
         }
     }
@@ -116,7 +148,7 @@ impl ConcurrentStatus {
                     "\ndeadlock detected\nargs: {:?}, {:?}, {:?}\nconcurrent status: {:?}\ncycle: {:?}",
                     tx, lock, page_id, concurrent_status, cycle
                 );
-                let err = SmallError::new(&err_msg);
+                let err = SmallError::with_kind(SmallErrorKind::Deadlock, &err_msg);
                 err.show_backtrace();
 
                 return Err(err);
@@ -149,7 +181,7 @@ impl ConcurrentStatus {
             page_id,
             Database::concurrent_status(),
         );
-        let err = SmallError::new(&err_msg);
+        let err = SmallError::with_kind(SmallErrorKind::LockTimeout, &err_msg);
         err.show_backtrace();
         return Err(err);
     }
@@ -213,13 +245,19 @@ impl ConcurrentStatus {
             self.hold_pages.insert(tx.clone(), HashSet::new());
         }
 
-        self.hold_pages.get_mut(tx).unwrap().insert(page_id.clone());
+        if self.hold_pages.get_mut(tx).unwrap().insert(page_id.clone()) {
+            tx.count_latch_acquired();
+            self.new_latches.insert(tx.clone(), *page_id);
+        } else {
+            self.new_latches.remove(tx);
+        }
         return Ok(true);
     }
 
     /// Remove the relation between the transaction and its related pages.
     pub(crate) fn remove_relation(&mut self, tx: &Transaction) {
         self.dirty_pages.remove(tx);
+        self.savepoints.remove(&tx.get_id());
         self.release_latches(tx).unwrap();
     }
 
@@ -234,6 +272,7 @@ impl ConcurrentStatus {
         }
 
         self.hold_pages.remove(tx);
+        self.new_latches.remove(tx);
 
         return Ok(());
     }
@@ -251,34 +290,177 @@ impl ConcurrentStatus {
             }
         }
 
-        if let Some(_) = self.x_latch_map.get_mut(page_id) {
+        // the X-latch may be held by another transaction if `tx` never got
+        // the page
+        if self.x_latch_map.get(page_id) == Some(tx) {
             self.x_latch_map.remove(page_id);
         }
 
-        self.hold_pages.get_mut(tx).unwrap().remove(page_id);
+        if self.new_latches.get(tx) == Some(page_id) {
+            self.new_latches.remove(tx);
+        }
+
+        // the transaction may have released all its latches already (e.g. a
+        // page guard dropped after the transaction committed)
+        if let Some(pages) = self.hold_pages.get_mut(tx) {
+            pages.remove(page_id);
+        }
 
         return Ok(());
     }
 
+    /// Return true if the last latch request of `tx` was on `page_id` and
+    /// `tx` held no latch on the page before it, the page is forgotten after
+    /// that.
+    pub(crate) fn take_new_latch(&mut self, tx: &Transaction, page_id: &BTreePageID) -> bool {
+        if self.new_latches.get(tx) == Some(page_id) {
+            self.new_latches.remove(tx);
+            return true;
+        }
+        false
+    }
+
+    /// Release the latch a page guard acquired on `page_id`, unless `tx`
+    /// holds an X-latch on it, which is kept until the transaction ends.
+    pub(crate) fn release_guard_latch(
+        &mut self,
+        tx: &Transaction,
+        page_id: &BTreePageID,
+    ) -> SmallResult {
+        if self.x_latch_map.get(page_id) == Some(tx) {
+            return Ok(());
+        }
+        self.release_latch(tx, page_id)
+    }
+
     pub(crate) fn set_dirty_page(&mut self, tx: &Transaction, page_id: &BTreePageID) {
         if !self.dirty_pages.contains_key(tx) {
             self.dirty_pages.insert(tx.clone(), HashSet::new());
         }
 
-        self.dirty_pages
+        if self
+            .dirty_pages
             .get_mut(tx)
             .unwrap()
-            .insert(page_id.clone());
+            .insert(page_id.clone())
+        {
+            tx.count_page_dirtied();
+        }
+    }
+
+    /// Record a tuple modified by the transaction, for the commit hooks.
+    pub(crate) fn record_change(&mut self, tx: &Transaction, change: TupleChange) {
+        self.changes.entry(tx.get_id()).or_default().push(change);
+    }
+
+    /// Take the tuples modified by the transaction, in the order they were
+    /// modified.
+    pub(crate) fn take_changes(&mut self, tx: &Transaction) -> Vec<TupleChange> {
+        self.changes.remove(&tx.get_id()).unwrap_or_default()
+    }
+
+    pub(crate) fn changes_count(&self, tx: &Transaction) -> usize {
+        self.changes
+            .get(&tx.get_id())
+            .map_or(0, |changes| changes.len())
+    }
+
+    /// Forget the changes recorded after the first `len` ones.
+    pub(crate) fn truncate_changes(&mut self, tx: &Transaction, len: usize) {
+        if let Some(changes) = self.changes.get_mut(&tx.get_id()) {
+            changes.truncate(len);
+        }
+    }
+
+    /// Record that the transaction changed the schema of the table.
+    pub(crate) fn record_schema_change(&mut self, tx: &Transaction, table_id: u32) {
+        self.schema_changes
+            .entry(tx.get_id())
+            .or_default()
+            .insert(table_id);
+    }
+
+    /// Take the tables whose schema was changed by the transaction.
+    pub(crate) fn take_schema_changes(&mut self, tx: &Transaction) -> HashSet<u32> {
+        self.schema_changes.remove(&tx.get_id()).unwrap_or_default()
+    }
+
+    pub(crate) fn push_savepoint(&mut self, tx: &Transaction, savepoint: Savepoint) {
+        self.savepoints
+            .entry(tx.get_id())
+            .or_default()
+            .push(savepoint);
+    }
+
+    pub(crate) fn get_savepoint(&self, tx: &Transaction, id: SavepointId) -> Option<&Savepoint> {
+        self.savepoints
+            .get(&tx.get_id())?
+            .iter()
+            .find(|savepoint| savepoint.id == id)
+    }
+
+    /// Keep `image` as the content of the page `pid` in the savepoints of
+    /// `tx` taken before the page was dirtied, the page is about to be
+    /// written to disk so it can't be read back from there any more.
+    pub(crate) fn keep_in_savepoints(&mut self, tx: &Transaction, pid: &BTreePageID, image: &[u8]) {
+        if let Some(savepoints) = self.savepoints.get_mut(&tx.get_id()) {
+            for savepoint in savepoints {
+                savepoint
+                    .pages
+                    .entry(*pid)
+                    .or_insert_with(|| image.to_vec());
+            }
+        }
+    }
+
+    /// Drop the savepoints taken after the savepoint `id`.
+    pub(crate) fn release_savepoints_after(&mut self, tx: &Transaction, id: SavepointId) {
+        if let Some(savepoints) = self.savepoints.get_mut(&tx.get_id()) {
+            if let Some(i) = savepoints.iter().position(|savepoint| savepoint.id == id) {
+                savepoints.truncate(i + 1);
+            }
+        }
+    }
+
+    pub(crate) fn release_savepoint(&mut self, tx: &Transaction, id: SavepointId) {
+        if let Some(savepoints) = self.savepoints.get_mut(&tx.get_id()) {
+            savepoints.retain(|savepoint| savepoint.id != id);
+        }
+    }
+
+    /// Keep only the dirty pages of the transaction for which `keep` returns
+    /// true.
+    pub(crate) fn retain_dirty_pages<F>(&mut self, tx: &Transaction, keep: F)
+    where
+        F: Fn(&BTreePageID) -> bool,
+    {
+        if let Some(pages) = self.dirty_pages.get_mut(tx) {
+            pages.retain(|pid| keep(pid));
+        }
     }
 
     pub(crate) fn get_dirty_pages(&self, tx: &Transaction) -> HashSet<BTreePageID> {
         return self.dirty_pages.get(tx).unwrap_or(&HashSet::new()).clone();
     }
 
+    /// Get the number of distinct pages dirtied by all transactions.
+    pub(crate) fn dirty_pages_count(&self) -> usize {
+        self.dirty_pages
+            .values()
+            .flatten()
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
     pub fn hold_page_count(&self, tx: &Transaction) -> usize {
         return self.hold_pages.get(tx).unwrap_or(&HashSet::new()).len();
     }
 
+    /// Check whether any transaction holds a latch on the page.
+    pub(crate) fn is_latched(&self, page_id: &BTreePageID) -> bool {
+        self.x_latch_map.contains_key(page_id) || self.s_latch_map.contains_key(page_id)
+    }
+
     /// Get the corresponding transaction of the dirty page, return None if the
     /// page is not a dirty page.
     pub(crate) fn dirty_page_tx(&self, page_id: &BTreePageID) -> Option<Transaction> {
@@ -295,6 +477,7 @@ impl ConcurrentStatus {
         self.s_latch_map.clear();
         self.x_latch_map.clear();
         self.hold_pages.clear();
+        self.new_latches.clear();
         self.dirty_pages.clear();
     }
 }
@@ -330,6 +513,41 @@ impl ConcurrentStatus {
 
         return Some(min_tx_id);
     }
+
+    pub(crate) fn transactions_with_status(
+        &self,
+        status: &TransactionStatus,
+    ) -> Vec<TransactionID> {
+        self.transaction_status
+            .iter()
+            .filter(|(_, s)| *s == status)
+            .map(|(tx_id, _)| *tx_id)
+            .collect()
+    }
+
+    pub(crate) fn register_snapshot(&mut self, horizon: TransactionID) {
+        *self.snapshots.entry(horizon).or_default() += 1;
+    }
+
+    pub(crate) fn unregister_snapshot(&mut self, horizon: TransactionID) {
+        if let Some(count) = self.snapshots.get_mut(&horizon) {
+            *count -= 1;
+            if *count == 0 {
+                self.snapshots.remove(&horizon);
+            }
+        }
+    }
+
+    /// The tuples deleted by the transactions older than the returned one
+    /// are invisible to every transaction and snapshot, the vacuum can
+    /// purge them.
+    pub(crate) fn vacuum_horizon(&self) -> Option<TransactionID> {
+        let oldest_snapshot = self.snapshots.keys().min().cloned();
+        match (self.min_active_tx(), oldest_snapshot) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
 }
 
 impl fmt::Display for ConcurrentStatus {
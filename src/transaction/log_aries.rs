@@ -1,32 +1,46 @@
 use std::{
     collections::{HashMap, HashSet},
-    io::{Cursor, Read, Seek, SeekFrom, Write},
+    fs::{self, File},
+    io::{BufReader, Cursor, Read, Seek, SeekFrom, Write},
     mem::size_of,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use log::debug;
+use log::{debug, warn};
 
 use super::{Transaction, TransactionID};
 use crate::{
     btree::{
         buffer_pool::BufferPool,
         page::{
-            BTreeHeaderPage, BTreeInternalPage, BTreeLeafPage, BTreePage, BTreePageID,
-            BTreeRootPointerPage, PageCategory,
+            BTreeBasePage, BTreeHeaderPage, BTreeInternalPage, BTreeLeafPage, BTreePage,
+            BTreePageID, BTreeRootPointerPage, PageCategory,
         },
     },
-    error::SmallError,
+    common::Catalog,
+    error::{SmallError, SmallErrorKind},
     io::{read_into, Serializeable, SmallFile, SmallWriter},
+    storage::table_schema::TableSchema,
     transaction::TRANSACTION_ID_BYTES,
     types::SmallResult,
     utils::HandyRwLock,
     Database,
 };
 
-// TODO: add docs for it
-static START_RECORD_LEN: u64 = 17;
+/// The phases of `LogManager::recover_with_progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryPhase {
+    /// Find the transactions unfinished at the time of the crash.
+    Analysis,
+
+    /// Apply the changes of the committed transactions.
+    Redo,
+
+    /// Roll back the changes of the unfinished transactions.
+    Undo,
+}
 
 /// see:
 /// https://users.rust-lang.org/t/mapping-enum-u8/23400
@@ -40,6 +54,11 @@ enum RecordType {
     UPDATE,
     START,
     CHECKPOINT,
+
+    /// The previous schema of a table changed by a DDL statement, restored
+    /// when the transaction is rolled back.
+    #[allow(clippy::upper_case_acronyms)]
+    SCHEMA,
 }
 
 impl RecordType {
@@ -50,6 +69,7 @@ impl RecordType {
             2 => RecordType::UPDATE,
             3 => RecordType::START,
             4 => RecordType::CHECKPOINT,
+            5 => RecordType::SCHEMA,
             _ => panic!("invalid record type: {}", value),
         }
     }
@@ -68,11 +88,240 @@ impl Serializeable for RecordType {
     }
 }
 
+/// Set on the type byte of records carrying a timestamp. Records written by
+/// older versions don't have a timestamp, the flag tells the two formats
+/// apart.
+const TIMESTAMP_FLAG: u8 = 0x80;
+
+/// The leading part of a log record.
+///
+/// # Format
+///
+/// - 1 byte: record type, with `TIMESTAMP_FLAG` set if there is a timestamp
+/// - 8 bytes (only if `TIMESTAMP_FLAG` is set): wall-clock time of the
+///   record, in microseconds since the unix epoch
+struct RecordHeader {
+    record_type: RecordType,
+    timestamp: Option<u64>,
+}
+
+impl RecordHeader {
+    fn new(record_type: RecordType, timestamp: Option<u64>) -> Self {
+        Self {
+            record_type,
+            timestamp,
+        }
+    }
+}
+
+impl Serializeable for RecordHeader {
+    type Reference = ();
+
+    fn encode(&self, writer: &mut SmallWriter, _: &Self::Reference) {
+        match self.timestamp {
+            Some(timestamp) => {
+                (self.record_type as u8 | TIMESTAMP_FLAG).encode(writer, &());
+                timestamp.encode(writer, &());
+            }
+            None => self.record_type.encode(writer, &()),
+        }
+    }
+
+    fn decode<R: Read>(reader: &mut R, _: &Self::Reference) -> Self {
+        let value = u8::decode(reader, &());
+        let record_type = RecordType::from_u8(value & !TIMESTAMP_FLAG);
+
+        let mut timestamp = None;
+        if value & TIMESTAMP_FLAG != 0 {
+            timestamp = Some(u64::decode(reader, &()));
+        }
+
+        Self::new(record_type, timestamp)
+    }
+}
+
 /// Migrated from java version.
 ///
 /// TODO: Figure out what this is used for, and if it's needed.
 static NO_CHECKPOINT: u64 = 0;
 
+/// Size of the header of the log file: the offset of the last checkpoint,
+/// then the number of bytes cut from the front of the log (see `LogFile`).
+const LOG_HEADER_SIZE: u64 = 16;
+
+/// The log file, addressed by log offsets.
+///
+/// A record keeps its log offset for as long as it's in the log, since the
+/// offsets are written in the records and the checkpoints and kept by the
+/// savepoints. When `LogManager::truncate` cuts the front of the log, the
+/// number of bytes cut is written in the header, and the log offsets of the
+/// remaining records are translated to their position in the file.
+struct LogFile {
+    path: PathBuf,
+    file: SmallFile,
+
+    /// Number of bytes cut from the front of the log, the record at log
+    /// offset `o` is at `o - base` in the file.
+    base: u64,
+}
+
+impl LogFile {
+    fn new(path: PathBuf) -> Self {
+        let mut file = SmallFile::new(&path);
+
+        let mut base = 0;
+        if file.get_size().unwrap() >= LOG_HEADER_SIZE {
+            file.seek(SeekFrom::Start(size_of::<u64>() as u64)).unwrap();
+            base = read_into(&mut file, &());
+        }
+
+        Self { path, file, base }
+    }
+
+    /// Get the position in the file of the log offset `offset`, the offsets
+    /// cut from the log are mapped to the first record.
+    fn to_file(&self, offset: u64) -> u64 {
+        if offset < LOG_HEADER_SIZE {
+            offset
+        } else {
+            offset.saturating_sub(self.base).max(LOG_HEADER_SIZE)
+        }
+    }
+
+    fn to_log(&self, position: u64) -> u64 {
+        if position < LOG_HEADER_SIZE {
+            position
+        } else {
+            position + self.base
+        }
+    }
+
+    /// Get the log offset of the first record in the file.
+    fn first_record_offset(&self) -> u64 {
+        LOG_HEADER_SIZE + self.base
+    }
+
+    fn write<T: Serializeable>(&mut self, obj: &T, reference: &T::Reference) -> SmallResult {
+        self.file.write(obj, reference)
+    }
+
+    /// Get the log offset of the end of the file.
+    fn get_size(&self) -> Result<u64, SmallError> {
+        Ok(self.to_log(self.file.get_size()?))
+    }
+
+    /// Get the size of the file in bytes.
+    fn file_size(&self) -> Result<u64, SmallError> {
+        self.file.get_size()
+    }
+
+    fn get_current_position(&mut self) -> Result<u64, SmallError> {
+        let position = self.file.get_current_position()?;
+        Ok(self.to_log(position))
+    }
+
+    /// Truncate the file at the log offset `len`, an empty file starts over
+    /// from log offset 0.
+    fn set_len(&mut self, len: u64) -> SmallResult {
+        self.file.set_len(self.to_file(len))?;
+        if len < LOG_HEADER_SIZE {
+            self.base = 0;
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, SmallError> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.to_file(offset)),
+            pos => pos,
+        };
+        let position = self.file.seek(pos)?;
+        Ok(self.to_log(position))
+    }
+
+    fn flush(&mut self) -> SmallResult {
+        self.file.flush()
+    }
+
+    fn sync(&mut self) -> SmallResult {
+        self.file.sync()
+    }
+
+    /// Cut the records before the log offset `offset` from the file. The
+    /// header points to no checkpoint if the last one is cut.
+    ///
+    /// The records left are copied to a new file which replaces the log
+    /// once it's synced, so a crash leaves either the whole log or the cut
+    /// one.
+    fn cut_before(&mut self, offset: u64) -> SmallResult {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut last_checkpoint: u64 = read_into(&mut self.file, &());
+        if last_checkpoint < offset {
+            last_checkpoint = NO_CHECKPOINT;
+        }
+
+        let mut records = Vec::new();
+        self.file.seek(SeekFrom::Start(self.to_file(offset)))?;
+        self.file.read_to_end(&mut records)?;
+
+        let base = offset - LOG_HEADER_SIZE;
+        let mut content = Vec::with_capacity(LOG_HEADER_SIZE as usize + records.len());
+        content.extend_from_slice(&last_checkpoint.to_le_bytes());
+        content.extend_from_slice(&base.to_le_bytes());
+        content.extend_from_slice(&records);
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, &content)?;
+        SmallFile::new(&tmp_path).sync()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = SmallFile::new(&self.path);
+        self.file.seek(SeekFrom::End(0))?;
+        self.base = base;
+        Ok(())
+    }
+
+    /// Open a reader of the records in the file, from the first one to the
+    /// current end of the file.
+    fn reader(&self) -> Result<LogReader, SmallError> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(LOG_HEADER_SIZE))?;
+        Ok(LogReader {
+            reader: BufReader::new(file),
+            position: self.first_record_offset(),
+            end: self.get_size()?,
+        })
+    }
+}
+
+impl Read for LogFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+/// A read-only cursor over the records of the log file, with a file handle
+/// of its own so the log can be read without moving the cursor the records
+/// are written at (i.e. under the read lock of the log manager).
+struct LogReader {
+    reader: BufReader<File>,
+
+    /// The log offset of the next byte to read.
+    position: u64,
+
+    /// The log offset of the end of the file when the reader was opened.
+    end: u64,
+}
+
+impl Read for LogReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
 // TODO: change type of `checkpoint` to u64
 pub struct LogManager {
     /// Record the start position of each transaction.
@@ -82,7 +331,7 @@ pub struct LogManager {
     /// BEGIN_RECORD. (Why the last byte?)
     tx_start_position: HashMap<TransactionID, u64>,
 
-    file: SmallFile,
+    file: LogFile,
 
     /// The absolute position of the file descriptor cursor.
     current_offset: u64,
@@ -91,6 +340,10 @@ pub struct LogManager {
     ///
     /// TODO: Figure out what this is used for, and if it's needed.
     total_records: usize,
+
+    /// Timestamp of the last record written, used to keep the timestamps
+    /// monotonic when the wall clock goes backwards.
+    last_timestamp: u64,
     // no call to recover() and no append to log
     // recovery_undecided: bool,
 }
@@ -110,7 +363,7 @@ impl LogManager {
     /// entries, then first throw out the initial log file
     /// contents.
     pub fn new<P: AsRef<Path> + Clone>(file_path: P) -> Self {
-        let mut file = SmallFile::new(file_path);
+        let mut file = LogFile::new(file_path.as_ref().to_path_buf());
 
         let size = file.get_size().unwrap();
         file.seek(SeekFrom::End(0)).unwrap();
@@ -123,6 +376,8 @@ impl LogManager {
             current_offset: size,
 
             total_records: 0,
+
+            last_timestamp: 0,
         }
     }
 
@@ -135,10 +390,237 @@ impl LogManager {
         // self.recovery_undecided = true;
     }
 
+    /// Make all log records written so far durable.
+    pub fn sync(&mut self) -> SmallResult {
+        self.file.sync()
+    }
+
+    /// Get the size of the log file in bytes.
+    pub fn size(&self) -> Result<u64, SmallError> {
+        self.file.file_size()
+    }
+
+    /// Get the offset of the last checkpoint record, `None` if there is no
+    /// checkpoint in the log.
+    pub fn last_checkpoint_offset(&mut self) -> Result<Option<u64>, SmallError> {
+        if self.file.get_size()? == 0 {
+            return Ok(None);
+        }
+
+        let original_offset = self.file.get_current_position()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        let last_checkpoint: u64 = read_into(&mut self.file, &());
+        self.file.seek(SeekFrom::Start(original_offset))?;
+
+        if last_checkpoint == NO_CHECKPOINT {
+            Ok(None)
+        } else {
+            Ok(Some(last_checkpoint))
+        }
+    }
+
     pub fn records_count(&self) -> usize {
         self.total_records
     }
 
+    /// Get the timestamp for a new record: the current wall-clock time in
+    /// microseconds, never less than the timestamp of the previous record.
+    fn next_timestamp(&mut self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        self.last_timestamp = self.last_timestamp.max(now);
+        self.last_timestamp
+    }
+
+    /// Get the offset and timestamp of all records in the log, in log order.
+    /// The timestamp is `None` for records without one (checkpoints and
+    /// records written by older versions).
+    pub fn record_timestamps(&mut self) -> Result<Vec<(u64, Option<u64>)>, SmallError> {
+        let original_offset = self.file.get_current_position()?;
+
+        let mut timestamps = Vec::new();
+        let file_size = self.file.get_size()?;
+        self.file
+            .seek(SeekFrom::Start(self.file.first_record_offset()))?;
+        while self.file.get_current_position()? < file_size {
+            let offset = self.file.get_current_position()?;
+            let header = RecordHeader::decode(&mut self.file, &());
+            skip_record_body(&mut self.file, header.record_type)?;
+            timestamps.push((offset, header.timestamp));
+        }
+
+        self.file.seek(SeekFrom::Start(original_offset))?;
+        Ok(timestamps)
+    }
+
+    /// Get the offset of the end of the log, i.e. the position where the
+    /// next record will be written.
+    pub fn get_current_offset(&self) -> u64 {
+        self.current_offset
+    }
+
+    /// Redo the log up to `offset`, bringing the table files to the state
+    /// they had at that point of the log (point-in-time recovery).
+    ///
+    /// The after images of the transactions committed before `offset` are
+    /// written to disk in log order, records after `offset` and updates of
+    /// other transactions are ignored. The table files are expected to be a
+    /// base backup taken when the log started, so every change after it is
+    /// covered by the log.
+    ///
+    /// `offset` should be a record boundary (e.g. a value returned by
+    /// `get_current_offset`), a record crossing it is not applied.
+    pub fn replay_until(&mut self, offset: u64) -> SmallResult {
+        let end = offset.min(self.file.get_size()?);
+
+        // pass 1: find the transactions committed before the offset
+        let committed_transactions = self.committed_before(end)?;
+
+        // pass 2: apply the after images of the committed transactions
+        self.file
+            .seek(SeekFrom::Start(self.file.first_record_offset()))?;
+        while self.file.get_current_position()? < end {
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
+
+            match record_type {
+                RecordType::UPDATE => {
+                    let tid: TransactionID = read_into(&mut self.file, &());
+                    let pid: BTreePageID = read_into(&mut self.file, &());
+
+                    // skip the before page
+                    let _: Vec<u8> = read_into(&mut self.file, &());
+
+                    let after_page: Vec<u8> = read_into(&mut self.file, &());
+
+                    // skip the start position
+                    let _: u64 = read_into(&mut self.file, &());
+
+                    if committed_transactions.contains(&tid) {
+                        let table_rc = Database::mut_catalog().get_table(&pid.table_id).ok_or(
+                            SmallError::with_kind(
+                                SmallErrorKind::NotFound,
+                                &format!("table {} not found", pid.table_id),
+                            ),
+                        )?;
+                        table_rc.rl().write_page_to_disk(&pid, &after_page);
+
+                        // the cached page is stale now
+                        Database::mut_buffer_pool().discard_page(&pid);
+                    }
+                }
+                _ => skip_record_body(&mut self.file, record_type)?,
+            }
+        }
+
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /// Get the content of the page `pid` as of the log offset `lsn`, for
+    /// time-travel reads.
+    ///
+    /// This is the after image of the last update of the page by a
+    /// transaction committed before `lsn`. If there is no such update, it's
+    /// the before image of the first update of the page in the log, i.e. the
+    /// content when the log started. Return None if the page is not in the
+    /// log at all, so its current content is also its content at `lsn`.
+    ///
+    /// Only the range covered by the log can be read, the history before a
+    /// reset or a truncation of the log is lost.
+    ///
+    /// The log is scanned once, through a handle of its own, so the read
+    /// lock of the log manager is enough.
+    pub fn page_image_as_of(
+        &self,
+        pid: &BTreePageID,
+        lsn: u64,
+    ) -> Result<Option<Vec<u8>>, SmallError> {
+        let mut reader = self.file.reader()?;
+        let end = lsn.min(reader.end);
+
+        // the last update of the page ending before `end` by each
+        // transaction, with the offset it ends at, the one used is decided
+        // once the COMMIT records before `end` are known
+        let mut updates: HashMap<TransactionID, (u64, Vec<u8>)> = HashMap::new();
+        let mut committed_transactions = HashSet::new();
+        let mut first_before_image = None;
+        while reader.position < reader.end {
+            // the records after `end` only matter for the before image
+            if reader.position >= end && first_before_image.is_some() {
+                break;
+            }
+
+            let record_type = RecordHeader::decode(&mut reader, &()).record_type;
+            match record_type {
+                RecordType::UPDATE => {
+                    let tid: TransactionID = read_into(&mut reader, &());
+                    let record_pid: BTreePageID = read_into(&mut reader, &());
+                    let before_page: Vec<u8> = read_into(&mut reader, &());
+                    let after_page: Vec<u8> = read_into(&mut reader, &());
+
+                    // skip the start position
+                    let _: u64 = read_into(&mut reader, &());
+
+                    if &record_pid != pid {
+                        continue;
+                    }
+
+                    if first_before_image.is_none() {
+                        first_before_image = Some(before_page);
+                    }
+                    if reader.position <= end {
+                        updates.insert(tid, (reader.position, after_page));
+                    }
+                }
+                RecordType::COMMIT => {
+                    let tid: TransactionID = read_into(&mut reader, &());
+
+                    // skip the start position
+                    let _: u64 = read_into(&mut reader, &());
+
+                    if reader.position <= end {
+                        committed_transactions.insert(tid);
+                    }
+                }
+                _ => skip_record_body(&mut reader, record_type)?,
+            }
+        }
+
+        let image = updates
+            .into_iter()
+            .filter(|(tid, _)| committed_transactions.contains(tid))
+            .max_by_key(|(_, (position, _))| *position)
+            .map(|(_, (_, image))| image);
+        Ok(image.or(first_before_image))
+    }
+
+    /// Find the transactions whose COMMIT record ends before `end`.
+    fn committed_before(&mut self, end: u64) -> Result<HashSet<TransactionID>, SmallError> {
+        let mut committed_transactions = HashSet::new();
+        self.file
+            .seek(SeekFrom::Start(self.file.first_record_offset()))?;
+        while self.file.get_current_position()? < end {
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
+
+            match record_type {
+                RecordType::COMMIT => {
+                    let tid: TransactionID = read_into(&mut self.file, &());
+
+                    // skip the start position
+                    let _: u64 = read_into(&mut self.file, &());
+
+                    if self.file.get_current_position()? <= end {
+                        committed_transactions.insert(tid);
+                    }
+                }
+                _ => skip_record_body(&mut self.file, record_type)?,
+            }
+        }
+        Ok(committed_transactions)
+    }
+
     /// Recover the database system by ensuring that the updates of
     /// committed transactions are installed and that the
     /// updates of uncommitted transactions are not installed.
@@ -166,6 +648,20 @@ impl LogManager {
     /// operations that undo transactions are logged as well to
     /// avoid repeating them.
     pub fn recover(&mut self) -> SmallResult {
+        self.recover_with_progress(|_, _, _| {})
+    }
+
+    /// Same as `recover`, `progress` is called with the phase, the bytes of
+    /// the log processed by the phase so far and the bytes it has to process
+    /// in total, after each record and once more at the end of the phase.
+    ///
+    /// The pages of the committed transactions are forced to disk on commit,
+    /// so the redo phase has nothing to process, it's reported once with 0
+    /// bytes.
+    pub fn recover_with_progress<F>(&mut self, mut progress: F) -> SmallResult
+    where
+        F: FnMut(RecoveryPhase, u64, u64),
+    {
         // undo phase
 
         if cfg!(feature = "aries_no_steal") {
@@ -173,22 +669,35 @@ impl LogManager {
             return Ok(());
         }
 
+        // an empty log (e.g. a new database, or a lost WAL) has nothing to
+        // recover
+        if self.file.get_size()? == 0 {
+            progress(RecoveryPhase::Analysis, 0, 0);
+            progress(RecoveryPhase::Redo, 0, 0);
+            progress(RecoveryPhase::Undo, 0, 0);
+            self.reset_file()?;
+            return Ok(());
+        }
+
         // get all incomplete transactions (transactions that have
         // started but not committed or aborted at the time of the
         // crash)
-        let incomplete_transactions = self.get_incomplete_transactions()?;
+        let incomplete_transactions = self.get_incomplete_transactions(&mut progress)?;
 
-        self.file.seek(SeekFrom::End(0))?;
+        progress(RecoveryPhase::Redo, 0, 0);
+
+        let log_end = self.file.seek(SeekFrom::End(0))?;
+
+        while self.file.get_current_position()? > self.file.first_record_offset() {
+            let position = self.file.get_current_position()?;
+            progress(RecoveryPhase::Undo, log_end - position, log_end);
 
-        while self.file.get_current_position()? >= START_RECORD_LEN {
             let word_size = size_of::<u64>() as i64;
-            self.file
-                .seek(SeekFrom::Current(-word_size))
-                .or(Err(SmallError::new("io error")))?;
+            self.file.seek(SeekFrom::Current(-word_size))?;
 
             let record_start_pos = read_into(&mut self.file, &());
             self.file.seek(SeekFrom::Start(record_start_pos))?;
-            let record_type = read_into(&mut self.file, &());
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
 
             match record_type {
                 RecordType::START => {
@@ -209,12 +718,13 @@ impl LogManager {
 
                         // TODO: construct a new page from the before
                         // page
-                        let mut catalog = Database::mut_catalog();
-                        let table_rc = catalog
-                            .get_table(&pid.table_id)
-                            .unwrap_or_else(|| panic!("table {} not found", pid.table_id));
-                        let table = table_rc.rl();
-                        table.write_page_to_disk(&pid, &before_page);
+                        let table_rc = Database::mut_catalog().get_table(&pid.table_id);
+                        match table_rc {
+                            Some(table_rc) => {
+                                table_rc.rl().write_page_to_disk(&pid, &before_page);
+                            }
+                            None => warn_unknown_table(&pid),
+                        }
 
                         // skip the after page
                         let _: Vec<u8> = read_into(&mut self.file, &());
@@ -263,6 +773,17 @@ impl LogManager {
                     // skip the transaction id
                     let _: TransactionID = read_into(&mut self.file, &());
 
+                    // skip the start position
+                    let _: u64 = read_into(&mut self.file, &());
+                }
+                RecordType::SCHEMA => {
+                    let (tid, table_id, schema) = read_schema_change(&mut self.file);
+                    if incomplete_transactions.contains(&tid) {
+                        // the catalog has been loaded from the schema table
+                        // before its pages are restored
+                        Catalog::restore_schema(table_id, &schema, &Database::buffer_pool());
+                    }
+
                     // skip the start position
                     let _: u64 = read_into(&mut self.file, &());
                 }
@@ -271,6 +792,7 @@ impl LogManager {
             // in the end, seek to the start of the record
             self.file.seek(SeekFrom::Start(record_start_pos))?;
         }
+        progress(RecoveryPhase::Undo, log_end, log_end);
 
         self.reset_file()?;
 
@@ -279,16 +801,22 @@ impl LogManager {
 
     /// Resets the log file to the initial state.
     fn reset_file(&mut self) -> SmallResult {
-        self.file
-            .set_len(0)
-            .or(Err(SmallError::new("set_len failed")))?;
+        self.file.set_len(0).or(Err(SmallError::with_kind(
+            SmallErrorKind::Io,
+            "set_len failed",
+        )))?;
         self.file.seek(SeekFrom::Start(0))?;
         self.file.write(&NO_CHECKPOINT, &())?;
+        // nothing cut yet
+        self.file.write(&0u64, &())?;
         self.current_offset = self.file.get_current_position()?;
         Ok(())
     }
 
-    fn get_incomplete_transactions(&mut self) -> Result<HashSet<TransactionID>, SmallError> {
+    fn get_incomplete_transactions(
+        &mut self,
+        progress: &mut dyn FnMut(RecoveryPhase, u64, u64),
+    ) -> Result<HashSet<TransactionID>, SmallError> {
         self.file.seek(SeekFrom::Start(0))?;
         let last_checkpoint_position = read_into(&mut self.file, &());
 
@@ -299,9 +827,12 @@ impl LogManager {
             self.file.seek(SeekFrom::Start(last_checkpoint_position))?;
 
             // check the record type
-            let record_type: RecordType = read_into(&mut self.file, &());
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
             if record_type != RecordType::CHECKPOINT {
-                return Err(SmallError::new("invalid checkpoint record type"));
+                return Err(SmallError::with_kind(
+                    SmallErrorKind::Corruption,
+                    "invalid checkpoint record type",
+                ));
             }
 
             // skip the checkpoint id
@@ -319,13 +850,22 @@ impl LogManager {
 
             // skip the start position
             let _: u64 = read_into(&mut self.file, &());
+        } else {
+            self.file
+                .seek(SeekFrom::Start(self.file.first_record_offset()))?;
         }
 
         // step 5: read the log records, stop when we encounter the
         // EOF
         let file_size = self.file.get_size()?;
         while self.file.get_current_position()? < file_size {
-            let record_type = read_into(&mut self.file, &());
+            progress(
+                RecoveryPhase::Analysis,
+                self.file.get_current_position()?,
+                file_size,
+            );
+
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
 
             match record_type {
                 RecordType::START => {
@@ -381,25 +921,35 @@ impl LogManager {
 
                     self.show_log_contents();
 
+                    // skip the start position
+                    let _: u64 = read_into(&mut self.file, &());
+                }
+                RecordType::SCHEMA => {
+                    let _ = read_schema_change(&mut self.file);
+
                     // skip the start position
                     let _: u64 = read_into(&mut self.file, &());
                 }
             }
         }
+        progress(RecoveryPhase::Analysis, file_size, file_size);
 
         Ok(incomplete_transactions)
     }
 
     pub fn log_start(&mut self, tx: &Transaction) -> SmallResult {
         self.pre_append()?;
+        let start_offset = self.file.get_current_position()?;
 
-        self.file.write(&RecordType::START, &())?;
+        let header = RecordHeader::new(RecordType::START, Some(self.next_timestamp()));
+        self.file.write(&header, &())?;
         self.file.write(&tx.get_id(), &())?;
         self.file.write(&self.current_offset, &())?;
 
         self.tx_start_position
             .insert(tx.get_id(), self.current_offset);
         self.current_offset = self.file.get_current_position()?;
+        self.count_log_bytes(tx, start_offset);
 
         Ok(())
     }
@@ -409,14 +959,19 @@ impl LogManager {
     pub fn log_abort(&mut self, tx: &Transaction, page_cache: &mut BufferPool) -> SmallResult {
         let original_offset = self.file.get_current_position()?;
         self.rollback(tx, page_cache)?;
+        if let Some(start_position) = self.tx_start_position.get(&tx.get_id()) {
+            self.undo_schema_changes(tx, *start_position, page_cache)?;
+        }
         self.file.seek(SeekFrom::Start(original_offset))?;
 
-        self.file.write(&RecordType::ABORT, &())?;
+        let header = RecordHeader::new(RecordType::ABORT, Some(self.next_timestamp()));
+        self.file.write(&header, &())?;
         self.file.write(&tx.get_id(), &())?;
         self.file.write(&self.current_offset, &())?;
 
         self.current_offset = self.file.get_current_position()?;
         self.tx_start_position.remove(&tx.get_id());
+        self.count_log_bytes(tx, original_offset);
         Ok(())
     }
 
@@ -431,13 +986,16 @@ impl LogManager {
 
         // update record consists of
         // record type
+        // timestamp
         // transaction id
         // before page data (see writePageData)
         // after page data
         // start offset
-        // 4 + 8 + before page + after page + 8
+        // 1 + 8 + 8 + before page + after page + 8
 
-        self.file.write(&RecordType::UPDATE, &())?;
+        let start_offset = self.file.get_current_position()?;
+        let header = RecordHeader::new(RecordType::UPDATE, Some(self.next_timestamp()));
+        self.file.write(&header, &())?;
         self.file.write(&tx.get_id(), &())?;
         self.write_page(page_rc)?;
         self.file.write(&self.current_offset, &())?;
@@ -446,23 +1004,63 @@ impl LogManager {
 
         let current_offset = self.file.get_current_position()?;
         self.current_offset = current_offset;
+        self.count_log_bytes(tx, start_offset);
 
         return Ok(());
     }
 
+    /// Write a SCHEMA record for a DDL statement of `tx` changing the schema
+    /// of the table `table_id`, `before` is the schema before the change.
+    ///
+    /// # Format
+    ///
+    /// - record header (see `RecordHeader`)
+    /// - transaction id
+    /// - 4 bytes: table id
+    /// - the schema before the change (see `TableSchema`)
+    /// - 8 bytes: start offset
+    pub fn log_schema_change(
+        &mut self,
+        tx: &Transaction,
+        table_id: u32,
+        before: &TableSchema,
+    ) -> SmallResult {
+        self.pre_append()?;
+
+        let start_offset = self.file.get_current_position()?;
+        let header = RecordHeader::new(RecordType::SCHEMA, Some(self.next_timestamp()));
+        self.file.write(&header, &())?;
+        self.file.write(&tx.get_id(), &())?;
+        self.file.write(&table_id, &())?;
+        self.file.write(before, &())?;
+        self.file.write(&self.current_offset, &())?;
+
+        self.file.flush()?;
+
+        self.current_offset = self.file.get_current_position()?;
+        self.count_log_bytes(tx, start_offset);
+        Ok(())
+    }
+
     pub fn log_checkpoint(&mut self) -> SmallResult {
         // make sure we have buffer pool lock before proceeding
         let cache = Database::mut_buffer_pool();
+        self.log_checkpoint_with(&cache)
+    }
 
+    /// Same as `log_checkpoint`, for callers already holding the buffer
+    /// pool.
+    pub(crate) fn log_checkpoint_with(&mut self, cache: &BufferPool) -> SmallResult {
         self.pre_append()?;
 
         self.file.flush().unwrap();
 
-        cache.flush_all_pages(self);
+        cache.flush_all_pages(self)?;
 
         let checkpoint_start_position = self.file.get_current_position()?;
 
-        self.file.write(&RecordType::CHECKPOINT, &())?;
+        self.file
+            .write(&RecordHeader::new(RecordType::CHECKPOINT, None), &())?;
 
         // no tid , but leave space for convenience
         //
@@ -494,18 +1092,55 @@ impl LogManager {
         return Ok(());
     }
 
+    /// Cut the records which are no longer needed from the front of the
+    /// log. Should follow a checkpoint, so all pages the records describe
+    /// are on disk.
+    ///
+    /// The records of the running transactions are needed to roll them
+    /// back, and the last checkpoint to recover them, so the log is kept
+    /// from the first record of the oldest running transaction or the last
+    /// checkpoint, whichever comes first. When no transaction is running,
+    /// all records are cut. The log offsets of the records left don't
+    /// change.
+    ///
+    /// Return true if the log has been shrunk.
+    pub fn truncate(&mut self) -> Result<bool, SmallError> {
+        let keep_from = match self.tx_start_position.values().min().cloned() {
+            Some(oldest_start) => match self.last_checkpoint_offset()? {
+                Some(checkpoint) => oldest_start.min(checkpoint),
+                None => oldest_start,
+            },
+            None => self.current_offset,
+        };
+
+        if keep_from <= self.file.first_record_offset() {
+            return Ok(false);
+        }
+
+        self.file.cut_before(keep_from)?;
+        Ok(true)
+    }
+
     pub fn log_commit(&mut self, tx: &Transaction) -> SmallResult {
         self.pre_append()?;
+        let start_offset = self.file.get_current_position()?;
 
-        self.file.write(&RecordType::COMMIT, &())?;
+        let header = RecordHeader::new(RecordType::COMMIT, Some(self.next_timestamp()));
+        self.file.write(&header, &())?;
         self.file.write(&tx.get_id(), &())?;
         self.file.write(&self.current_offset, &())?;
 
         self.current_offset = self.file.get_current_position()?;
         self.tx_start_position.remove(&tx.get_id());
+        self.count_log_bytes(tx, start_offset);
         Ok(())
     }
 
+    /// Count the bytes written for `tx` since `start_offset`.
+    fn count_log_bytes(&self, tx: &Transaction, start_offset: u64) {
+        tx.count_log_bytes(self.current_offset - start_offset);
+    }
+
     /// Rollback the specified transaction, setting the state of any
     /// of pages it updated to their pre-updated state.
     ///
@@ -532,8 +1167,7 @@ impl LogManager {
 
         // step 3: read checkpoint, get the position of the specific
         // tx
-        let record_type = RecordType::decode(&mut self.file, &());
-        // let record_type: RecordType = read_into(&mut self.file, &());
+        let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
         if record_type != RecordType::CHECKPOINT {
             panic!("invalid checkpoint");
         }
@@ -563,7 +1197,7 @@ impl LogManager {
         // we encounter the EOF
         let file_size = self.file.get_size()?;
         while self.file.get_current_position()? < file_size {
-            let record_type = read_into(&mut self.file, &());
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
 
             match record_type {
                 RecordType::START => {
@@ -583,7 +1217,7 @@ impl LogManager {
                         self.recover_page(&pid, &before_image, page_cache)?;
 
                         // skip the after page
-                        let _ = self.read_page(&pid)?;
+                        let _: Vec<u8> = read_into(&mut self.file, &());
 
                         // skip the start position
                         let _: u64 = read_into(&mut self.file, &());
@@ -631,6 +1265,13 @@ impl LogManager {
                     // skip the transaction id
                     let _: TransactionID = read_into(&mut self.file, &());
 
+                    // skip the start position
+                    let _: u64 = read_into(&mut self.file, &());
+                }
+                RecordType::SCHEMA => {
+                    // the schema changes are undone by `undo_schema_changes`
+                    let _ = read_schema_change(&mut self.file);
+
                     // skip the start position
                     let _: u64 = read_into(&mut self.file, &());
                 }
@@ -640,6 +1281,45 @@ impl LogManager {
         return Ok(());
     }
 
+    /// Restore the schemas changed by the DDL statements of `tx` logged from
+    /// `start_position` on, in the reverse order of the changes.
+    ///
+    /// Unlike the page updates, the schema changes are found from the start
+    /// position of the transaction (or of a savepoint), there is no need for
+    /// a checkpoint.
+    pub(crate) fn undo_schema_changes(
+        &mut self,
+        tx: &Transaction,
+        start_position: u64,
+        page_cache: &BufferPool,
+    ) -> SmallResult {
+        let mut changes = Vec::new();
+        let file_size = self.file.get_size()?;
+        self.file.seek(SeekFrom::Start(start_position))?;
+        while self.file.get_current_position()? < file_size {
+            let record_type = RecordHeader::decode(&mut self.file, &()).record_type;
+            if record_type != RecordType::SCHEMA {
+                skip_record_body(&mut self.file, record_type)?;
+                continue;
+            }
+
+            let (tid, table_id, schema) = read_schema_change(&mut self.file);
+            if tid == tx.get_id() {
+                changes.push((table_id, schema));
+            }
+
+            // skip the start position
+            let _: u64 = read_into(&mut self.file, &());
+        }
+
+        self.file.seek(SeekFrom::End(0))?;
+
+        for (table_id, schema) in changes.iter().rev() {
+            Catalog::restore_schema(*table_id, schema, page_cache);
+        }
+        Ok(())
+    }
+
     fn write_page<PAGE: BTreePage>(&mut self, page_rc: Arc<RwLock<PAGE>>) -> SmallResult {
         let page = page_rc.read().unwrap();
         self.file.write(&page.get_pid(), &())?;
@@ -664,65 +1344,41 @@ impl LogManager {
         before_image: &Vec<u8>,
         buffer_pool: &mut BufferPool,
     ) -> SmallResult {
-        let table_rc = Database::mut_catalog().get_table(&pid.table_id).unwrap();
+        let table_rc = match Database::mut_catalog().get_table(&pid.table_id) {
+            Some(table_rc) => table_rc,
+            None => {
+                warn_unknown_table(pid);
+                return Ok(());
+            }
+        };
         let table = table_rc.rl();
 
         let schema = table.get_schema();
 
+        BTreeBasePage::check_page_data(pid, before_image)?;
+
         match pid.category {
             PageCategory::Leaf => {
                 let page = BTreeLeafPage::new(&pid, &before_image, &schema);
-                BufferPool::recover_page(&pid, page, &mut buffer_pool.leaf_buffer);
+                BufferPool::recover_page(&pid, page, &buffer_pool.leaf_buffer);
             }
             PageCategory::RootPointer => {
                 let page = BTreeRootPointerPage::new(&pid, &before_image, &schema);
-                BufferPool::recover_page(&pid, page, &mut buffer_pool.root_pointer_buffer);
+                BufferPool::recover_page(&pid, page, &buffer_pool.root_pointer_buffer);
             }
             PageCategory::Internal => {
                 let page = BTreeInternalPage::new(&pid, &before_image, &schema);
-                BufferPool::recover_page(&pid, page, &mut buffer_pool.internal_buffer);
+                BufferPool::recover_page(&pid, page, &buffer_pool.internal_buffer);
             }
             PageCategory::Header => {
                 let page = BTreeHeaderPage::new(&pid, &before_image, &schema);
-                BufferPool::recover_page(&pid, page, &mut buffer_pool.header_buffer);
+                BufferPool::recover_page(&pid, page, &buffer_pool.header_buffer);
             }
         }
 
         Ok(())
     }
 
-    fn read_page(&mut self, pid: &BTreePageID) -> Result<Arc<RwLock<dyn BTreePage>>, SmallError> {
-        // let pid: BTreePageID = read_into(&mut self.file, &());
-
-        let data: Vec<u8> = read_into(&mut self.file, &());
-
-        let mut catalog = Database::mut_catalog();
-        let table_rc = catalog.get_table(&pid.table_id).unwrap();
-        let table = table_rc.rl();
-
-        let schema = table.get_schema();
-        let _key_field = table.key_field;
-
-        match pid.category {
-            PageCategory::Leaf => {
-                let page = BTreeLeafPage::new(&pid, &data, &schema);
-                return Ok(Arc::new(RwLock::new(page)));
-            }
-            PageCategory::RootPointer => {
-                let page = BTreeRootPointerPage::new(&pid, &data, &schema);
-                return Ok(Arc::new(RwLock::new(page)));
-            }
-            PageCategory::Internal => {
-                let page = BTreeInternalPage::new(&pid, &data, &schema);
-                return Ok(Arc::new(RwLock::new(page)));
-            }
-            PageCategory::Header => {
-                let page = BTreeHeaderPage::new(&pid, &data, &schema);
-                return Ok(Arc::new(RwLock::new(page)));
-            }
-        }
-    }
-
     // We're about to append a log record. If we weren't sure whether
     // the DB wants to do recovery, we're sure now -- it didn't.
     // So truncate the log.
@@ -756,6 +1412,9 @@ impl LogManager {
             depiction.push_str(&format!("├── [8 bytes] no checkpoint\n",));
         }
 
+        let base: u64 = read_into(&mut self.file, &());
+        depiction.push_str(&format!("├── [8 bytes] bytes cut: {}\n", base));
+
         let mut offset = 0;
         let mut record_id = -1;
         while offset < self.current_offset {
@@ -766,20 +1425,25 @@ impl LogManager {
                 break;
             }
 
-            let record_type = read_into(&mut self.file, &());
+            let header = RecordHeader::decode(&mut self.file, &());
+            let record_type = header.record_type;
 
             depiction.push_str(&format!(
                 "├── {:?}-[pos {}]-[record {}]\n",
                 record_type, offset, record_id,
             ));
 
+            depiction.push_str(&format!(
+                "│   ├── [1 byte] record type: {:?}\n",
+                record_type,
+            ));
+
+            if let Some(timestamp) = header.timestamp {
+                depiction.push_str(&format!("│   ├── [8 bytes] timestamp: {}\n", timestamp,));
+            }
+
             match record_type {
                 RecordType::START => {
-                    depiction.push_str(&format!(
-                        "│   ├── [1 byte] record type: {:?}\n",
-                        record_type,
-                    ));
-
                     let tid: TransactionID = read_into(&mut self.file, &());
                     depiction.push_str(&format!(
                         "│   ├── [{} bytes] tid: {}\n",
@@ -793,11 +1457,6 @@ impl LogManager {
                     ));
                 }
                 RecordType::UPDATE => {
-                    depiction.push_str(&format!(
-                        "│   ├── [1 byte] record type: {:?}\n",
-                        record_type,
-                    ));
-
                     let tid: TransactionID = read_into(&mut self.file, &());
                     depiction.push_str(&format!(
                         "│   ├── [{} bytes] tid: {}\n",
@@ -828,11 +1487,6 @@ impl LogManager {
                     ));
                 }
                 RecordType::ABORT => {
-                    depiction.push_str(&format!(
-                        "│   ├── [1 byte] record type: {:?}\n",
-                        record_type,
-                    ));
-
                     let tid: TransactionID = read_into(&mut self.file, &());
                     depiction.push_str(&format!(
                         "│   ├── [{} bytes] tid: {}\n",
@@ -846,11 +1500,6 @@ impl LogManager {
                     ));
                 }
                 RecordType::CHECKPOINT => {
-                    depiction.push_str(&format!(
-                        "│   ├── [1 byte] record type: {:?}\n",
-                        record_type,
-                    ));
-
                     let checkpoint_id: i64 = read_into(&mut self.file, &());
                     depiction.push_str(&format!(
                         "│   ├── [8 bytes] checkpoint id: {}\n",
@@ -881,16 +1530,26 @@ impl LogManager {
                     ));
                 }
                 RecordType::COMMIT => {
+                    let tid: TransactionID = read_into(&mut self.file, &());
                     depiction.push_str(&format!(
-                        "│   ├── [1 byte] record type: {:?}\n",
-                        record_type,
+                        "│   ├── [{} bytes] tid: {}\n",
+                        TRANSACTION_ID_BYTES, tid,
                     ));
 
-                    let tid: TransactionID = read_into(&mut self.file, &());
+                    let start_offset: u64 = read_into(&mut self.file, &());
+                    depiction.push_str(&format!(
+                        "│   └── [8 bytes] start offset: {}\n",
+                        start_offset,
+                    ));
+                }
+                RecordType::SCHEMA => {
+                    let (tid, table_id, schema) = read_schema_change(&mut self.file);
                     depiction.push_str(&format!(
                         "│   ├── [{} bytes] tid: {}\n",
                         TRANSACTION_ID_BYTES, tid,
                     ));
+                    depiction.push_str(&format!("│   ├── [4 bytes] table id: {}\n", table_id));
+                    depiction.push_str(&format!("│   ├── schema before: {:?}\n", schema));
 
                     let start_offset: u64 = read_into(&mut self.file, &());
                     depiction.push_str(&format!(
@@ -917,10 +1576,11 @@ impl LogManager {
 
                 let page = BTreeLeafPage::new(&pid, bytes, &schema);
                 let iter = page.iter();
-                let typ = schema.get_fields()[0].get_type();
+                let key_pos = schema.get_key_pos();
+                let typ = schema.get_pkey().get_type();
                 let content = iter
                     .take(15)
-                    .map(|x| x.get_cell(0).to_bytes(&typ))
+                    .map(|x| x.get_cell(key_pos).to_bytes(&typ))
                     .collect::<Vec<_>>();
 
                 return format!("{:?}, content: {:?}...", page_category, content,);
@@ -931,3 +1591,67 @@ impl LogManager {
         }
     }
 }
+
+/// Log records may refer to tables which are not in the catalog (e.g. a
+/// table dropped or never persisted before the crash), their pages are
+/// skipped by the recovery.
+fn warn_unknown_table(pid: &BTreePageID) {
+    warn!(
+        "table {} not found in the catalog, skip the recovery of page {}",
+        pid.table_id, pid
+    );
+}
+
+/// Skip the rest of a record whose type has been read.
+fn skip_record_body<R: Read>(reader: &mut R, record_type: RecordType) -> SmallResult {
+    match record_type {
+        RecordType::START | RecordType::COMMIT | RecordType::ABORT => {
+            // skip the transaction id
+            let _: TransactionID = read_into(reader, &());
+        }
+        RecordType::UPDATE => {
+            // skip the transaction id
+            let _: TransactionID = read_into(reader, &());
+
+            // skip the page id
+            let _: BTreePageID = read_into(reader, &());
+
+            // skip the before page
+            let _: Vec<u8> = read_into(reader, &());
+
+            // skip the after page
+            let _: Vec<u8> = read_into(reader, &());
+        }
+        RecordType::CHECKPOINT => {
+            // skip the checkpoint id
+            let _: i64 = read_into(reader, &());
+
+            // skip the list of outstanding transactions
+            let tx_count: u64 = read_into(reader, &());
+            for _ in 0..tx_count {
+                // skip the transaction id
+                let _: TransactionID = read_into(reader, &());
+
+                // skip the start position
+                let _: u64 = read_into(reader, &());
+            }
+        }
+        RecordType::SCHEMA => {
+            let _ = read_schema_change(reader);
+        }
+    }
+
+    // skip the start position
+    let _: u64 = read_into(reader, &());
+    Ok(())
+}
+
+/// Read the body of a "SCHEMA" record (without the trailing start
+/// position): the transaction id, the table id and the previous schema
+/// of the table.
+fn read_schema_change<R: Read>(reader: &mut R) -> (TransactionID, u32, TableSchema) {
+    let tid: TransactionID = read_into(reader, &());
+    let table_id: u32 = read_into(reader, &());
+    let schema: TableSchema = read_into(reader, &());
+    (tid, table_id, schema)
+}
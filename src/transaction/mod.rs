@@ -7,4 +7,10 @@ pub use tx::*;
 mod concurrent_status;
 pub use concurrent_status::*;
 
+mod savepoint;
+pub use savepoint::*;
+
+mod snapshot;
+pub use snapshot::*;
+
 mod wait_for_graph;
@@ -0,0 +1,161 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{
+    btree::page::BTreePageID,
+    error::{SmallError, SmallErrorKind},
+    transaction::Transaction,
+    types::SmallResult,
+    utils::HandyRwLock,
+    Database,
+};
+
+static SAVEPOINT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Identifies a savepoint of a transaction, see `Transaction::savepoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SavepointId(u32);
+
+/// The state of a transaction when a savepoint was taken.
+pub(crate) struct Savepoint {
+    pub(crate) id: SavepointId,
+
+    /// The content of the dirty pages of the transaction. A page dirtied
+    /// after the savepoint is added before it's written to disk (see
+    /// `ConcurrentStatus::keep_in_savepoints`), the other pages are read
+    /// from disk.
+    pub(crate) pages: HashMap<BTreePageID, Vec<u8>>,
+
+    /// The end of the log, the schema changes logged after it are undone.
+    pub(crate) log_offset: u64,
+
+    /// Number of tuple changes recorded for the commit hooks.
+    pub(crate) changes_count: usize,
+}
+
+impl Transaction {
+    /// Mark the current state of the transaction, `rollback_to` undoes the
+    /// changes made after it while the transaction goes on.
+    ///
+    /// The dirty pages of the transaction are copied, so a savepoint costs
+    /// one page of memory per page modified so far.
+    pub fn savepoint(&self) -> Result<SavepointId, SmallError> {
+        self.check_active()?;
+
+        let mut pages = HashMap::new();
+        {
+            let buffer_pool = Database::buffer_pool();
+            for pid in Database::concurrent_status().get_dirty_pages(self) {
+                if let Some(image) = buffer_pool.get_page_image(&pid) {
+                    pages.insert(pid, image);
+                }
+            }
+        }
+
+        let log_offset = Database::log_manager().get_current_offset();
+
+        let id = SavepointId(SAVEPOINT_ID.fetch_add(1, Ordering::Relaxed));
+        let mut concurrent_status = Database::mut_concurrent_status();
+        let changes_count = concurrent_status.changes_count(self);
+        concurrent_status.push_savepoint(
+            self,
+            Savepoint {
+                id,
+                pages,
+                log_offset,
+                changes_count,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Undo the changes made by the transaction after the savepoint `id`.
+    ///
+    /// The savepoint is kept, so the transaction can roll back to it again,
+    /// the savepoints taken after it are released. The latches of the
+    /// transaction are kept until it ends.
+    ///
+    /// The schema changes (DDL) made after the savepoint are undone as well.
+    /// The pages getting their content back are logged as UPDATE records of
+    /// the transaction.
+    pub fn rollback_to(&self, id: SavepointId) -> SmallResult {
+        self.check_active()?;
+
+        let (pages, log_offset, changes_count) = {
+            let concurrent_status = Database::concurrent_status();
+            let savepoint = concurrent_status
+                .get_savepoint(self, id)
+                .ok_or(self.savepoint_not_found(id))?;
+            (
+                savepoint.pages.clone(),
+                savepoint.log_offset,
+                savepoint.changes_count,
+            )
+        };
+
+        // the schemas go first, the pages are decoded with them
+        Database::mut_log_manager().undo_schema_changes(
+            self,
+            log_offset,
+            &Database::buffer_pool(),
+        )?;
+
+        // pages dirtied before the savepoint (or written to disk after it)
+        // get their content back, the others are discarded, so they are read
+        // from disk again
+        let dirty_pages = Database::concurrent_status().get_dirty_pages(self);
+        let mut table_ids = HashSet::new();
+        {
+            let buffer_pool = Database::buffer_pool();
+            let mut log_manager = Database::mut_log_manager();
+            for pid in dirty_pages {
+                table_ids.insert(pid.get_table_id());
+                match pages.get(&pid) {
+                    Some(image) => {
+                        buffer_pool.restore_page_image(self, &pid, image, &mut log_manager)?
+                    }
+                    None => buffer_pool.discard_page(&pid),
+                }
+            }
+        }
+
+        {
+            let mut concurrent_status = Database::mut_concurrent_status();
+            concurrent_status.retain_dirty_pages(self, |pid| pages.contains_key(pid));
+            concurrent_status.truncate_changes(self, changes_count);
+            concurrent_status.release_savepoints_after(self, id);
+        }
+
+        // the structure of the trees may have changed
+        for table_id in table_ids {
+            if let Some(table_rc) = Database::mut_catalog().get_table(&table_id) {
+                table_rc.rl().invalidate_leaf_cache();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget the savepoint `id` and the savepoints taken after it, the
+    /// changes made after them are kept.
+    pub fn release_savepoint(&self, id: SavepointId) -> SmallResult {
+        self.check_active()?;
+
+        let mut concurrent_status = Database::mut_concurrent_status();
+        if concurrent_status.get_savepoint(self, id).is_none() {
+            return Err(self.savepoint_not_found(id));
+        }
+        concurrent_status.release_savepoints_after(self, id);
+        concurrent_status.release_savepoint(self, id);
+        Ok(())
+    }
+
+    fn savepoint_not_found(&self, id: SavepointId) -> SmallError {
+        SmallError::with_kind(
+            SmallErrorKind::NotFound,
+            &format!("savepoint {:?} not found in transaction {}", id, self),
+        )
+    }
+}
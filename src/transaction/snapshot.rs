@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+
+use crate::{
+    transaction::{Transaction, TransactionID, TransactionStatus},
+    Database,
+};
+
+/// The transactions whose changes a reader doesn't see, taken at one point
+/// in time so the reader sees the same tuples until it's done (e.g. a
+/// cursor paging back and forth through a table).
+///
+/// `Tuple::visible_to` asks for the current status of the creator and the
+/// deleter of a tuple, so a transaction committing in the meantime changes
+/// what is visible. Under a snapshot, the transactions which were in
+/// progress when it was taken stay in progress.
+///
+/// The tuples deleted by these transactions are kept by the vacuum until
+/// the snapshot is dropped, see `ConcurrentStatus::vacuum_horizon`.
+pub struct Snapshot {
+    tx_id: TransactionID,
+
+    /// The other transactions in progress when the snapshot was taken.
+    in_progress: HashSet<TransactionID>,
+
+    /// The oldest of `in_progress`, registered in the concurrent status.
+    horizon: Option<TransactionID>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of the transactions in progress for `tx`.
+    pub fn take(tx: &Transaction) -> Self {
+        let mut concurrent_status = Database::mut_concurrent_status();
+
+        let in_progress: HashSet<TransactionID> = concurrent_status
+            .transactions_with_status(&TransactionStatus::Active)
+            .into_iter()
+            .filter(|id| *id != tx.get_id())
+            .collect();
+        let horizon = in_progress.iter().min().cloned();
+        if let Some(horizon) = horizon {
+            concurrent_status.register_snapshot(horizon);
+        }
+
+        Self {
+            tx_id: tx.get_id(),
+            in_progress,
+            horizon,
+        }
+    }
+
+    pub fn get_tx_id(&self) -> TransactionID {
+        self.tx_id
+    }
+
+    pub(crate) fn get_in_progress(&self) -> &HashSet<TransactionID> {
+        &self.in_progress
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Some(horizon) = self.horizon {
+            Database::mut_concurrent_status().unregister_snapshot(horizon);
+        }
+    }
+}
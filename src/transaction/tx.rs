@@ -1,7 +1,19 @@
 use core::fmt;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use crate::{types::SmallResult, Database};
+use crate::{
+    btree::page::BTreePageID,
+    common::{Catalog, CommitEvent},
+    error::{SmallError, SmallErrorKind},
+    types::SmallResult,
+    Database,
+};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum TransactionStatus {
@@ -14,18 +26,108 @@ pub type TransactionID = u32;
 
 pub(crate) const TRANSACTION_ID_BYTES: usize = 4;
 
+/// The largest read-ahead window, in leaf pages.
+const MAX_READ_AHEAD_WINDOW: usize = 32;
+
 static TRANSACTION_ID: AtomicU32 = AtomicU32::new(1);
 
-#[derive(PartialEq, Eq, Clone)]
+/// Counters of the work done by a transaction, see `Transaction::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxStats {
+    /// Number of page requests made to the buffer pool, whether or not the
+    /// page was cached.
+    pub pages_read: usize,
+
+    /// Number of distinct pages the transaction has requested with write
+    /// permission.
+    pub pages_dirtied: usize,
+
+    /// Number of page latches granted to the transaction (a latch already
+    /// held is not counted again).
+    pub latches_acquired: usize,
+
+    /// Number of bytes of the log records written for the transaction.
+    pub log_bytes_written: u64,
+}
+
+/// The adaptive read-ahead of a transaction, see `Transaction::read_ahead`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadAheadStats {
+    /// Number of leaf pages loaded ahead when the transaction scans to the
+    /// next leaf page. Grows with sequential reads and shrinks with point
+    /// lookups.
+    pub window: usize,
+
+    /// Number of pages loaded ahead for the transaction.
+    pub pages_prefetched: usize,
+
+    /// Number of prefetched pages later reached by the scans of the
+    /// transaction.
+    pub hits: usize,
+}
+
+impl ReadAheadStats {
+    /// The fraction of the prefetched pages that have been used.
+    pub fn hit_rate(&self) -> f64 {
+        if self.pages_prefetched == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.pages_prefetched as f64
+    }
+}
+
+/// The read-ahead state of a transaction.
+#[derive(Default)]
+struct ReadAhead {
+    stats: ReadAheadStats,
+
+    /// The prefetched pages which haven't been reached yet.
+    prefetched: HashSet<BTreePageID>,
+}
+
+/// The counters behind `TxStats` and the read-ahead state, they are kept in
+/// the transaction (and shared by its clones) instead of the concurrent
+/// status or the log manager, so updating them doesn't take a global lock
+/// and they are freed with the transaction.
+#[derive(Default)]
+struct TxCounters {
+    pages_read: AtomicUsize,
+    pages_dirtied: AtomicUsize,
+    latches_acquired: AtomicUsize,
+    log_bytes_written: AtomicU64,
+
+    read_ahead: Mutex<ReadAhead>,
+}
+
+#[derive(Clone)]
 pub struct Transaction {
     // increase monotonically by 1
     id: TransactionID,
+
+    read_only: bool,
+
+    counters: Arc<TxCounters>,
 }
 
 impl Transaction {
     pub fn new() -> Self {
+        Self::with_access(false)
+    }
+
+    /// Start a transaction which only reads: inserting or deleting tuples
+    /// fails with `SmallErrorKind::ReadOnlyTransaction`, and so does
+    /// requesting a page with `Permission::ReadWrite`.
+    pub fn new_read_only() -> Self {
+        Self::with_access(true)
+    }
+
+    fn with_access(read_only: bool) -> Self {
         let id = TRANSACTION_ID.fetch_add(1, Ordering::Relaxed);
-        let instance = Self { id };
+        let instance = Self {
+            id,
+            read_only,
+            counters: Arc::default(),
+        };
         instance.start().unwrap();
 
         Database::mut_concurrent_status().set_transaction_status(&id, &TransactionStatus::Active);
@@ -37,15 +139,29 @@ impl Transaction {
         Database::mut_log_manager().log_start(self)
     }
 
+    /// Commit the transaction.
+    ///
+    /// The commit is all-or-nothing across the tables touched by the
+    /// transaction: if a page of any table fails to be logged or written, or
+    /// the "COMMIT" record fails to be written, the transaction is aborted and
+    /// the error is returned.
     pub fn commit(&self) -> SmallResult {
         // step 1: flush all related pages to disk (with "UPDATE" log record)
         //
         // (this is a disk operation, hence should be put before the "COMMIT" record is
         // written)
-        Database::mut_buffer_pool().flush_pages(self, &mut Database::mut_log_manager());
+        let flushed =
+            Database::mut_buffer_pool().flush_pages(self, &mut Database::mut_log_manager());
 
-        // step 2: write "COMMIT" log record
-        Database::mut_log_manager().log_commit(self)?;
+        // step 2: list the schema changes in the catalog file, then write
+        // "COMMIT" log record
+        let committed = flushed
+            .and_then(|_| Catalog::write_schema_changes(self))
+            .and_then(|_| Database::mut_log_manager().log_commit(self));
+        if let Err(e) = committed {
+            self.abort()?;
+            return Err(e);
+        }
 
         if cfg!(feature = "aries_no_force") {
             Database::mut_buffer_pool().write_pages(self);
@@ -60,6 +176,18 @@ impl Transaction {
         Database::mut_concurrent_status()
             .set_transaction_status(&self.id, &TransactionStatus::Committed);
 
+        // step 4: pass the changes to the commit hooks
+        //
+        // (the "COMMIT" record is durable, and the status lock is released
+        // before the hooks run, so they can use the database)
+        let changes = Database::mut_concurrent_status().take_changes(self);
+        if !changes.is_empty() {
+            Database::emit_commit(&CommitEvent {
+                tx_id: self.id,
+                changes,
+            });
+        }
+
         Ok(())
     }
 
@@ -88,6 +216,10 @@ impl Transaction {
         // these dirty pages)
         Database::mut_concurrent_status().remove_relation(self);
 
+        // the changes are rolled back, the commit hooks never see them
+        Database::mut_concurrent_status().take_changes(self);
+        Database::mut_concurrent_status().take_schema_changes(self);
+
         Database::mut_concurrent_status()
             .set_transaction_status(&self.id, &TransactionStatus::Aborted);
 
@@ -97,8 +229,119 @@ impl Transaction {
     pub fn get_id(&self) -> TransactionID {
         self.id
     }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Get the counters of the work done by the transaction so far, they are
+    /// kept after the transaction ends.
+    ///
+    /// Note that the pages written to disk and the UPDATE log records are
+    /// only produced on commit.
+    pub fn stats(&self) -> TxStats {
+        TxStats {
+            pages_read: self.counters.pages_read.load(Ordering::Relaxed),
+            pages_dirtied: self.counters.pages_dirtied.load(Ordering::Relaxed),
+            latches_acquired: self.counters.latches_acquired.load(Ordering::Relaxed),
+            log_bytes_written: self.counters.log_bytes_written.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn count_pages_read(&self, count: usize) {
+        self.counters.pages_read.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_page_dirtied(&self) {
+        self.counters.pages_dirtied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_latch_acquired(&self) {
+        self.counters
+            .latches_acquired
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_log_bytes(&self, count: u64) {
+        self.counters
+            .log_bytes_written
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Get the current read-ahead window of the transaction and how useful
+    /// the prefetched pages have been.
+    pub fn read_ahead(&self) -> ReadAheadStats {
+        self.counters.read_ahead.lock().unwrap().stats.clone()
+    }
+
+    /// Record that a scan of the transaction moved to the next leaf page,
+    /// the read-ahead window grows. Return the new window.
+    pub(crate) fn record_sequential_read(&self, pid: &BTreePageID) -> usize {
+        let mut read_ahead = self.counters.read_ahead.lock().unwrap();
+        if read_ahead.prefetched.remove(pid) {
+            read_ahead.stats.hits += 1;
+        }
+
+        let window = &mut read_ahead.stats.window;
+        *window = (*window * 2).clamp(1, MAX_READ_AHEAD_WINDOW);
+        *window
+    }
+
+    /// Record a point lookup of the transaction, the read-ahead window
+    /// shrinks.
+    pub(crate) fn record_point_lookup(&self) {
+        self.counters.read_ahead.lock().unwrap().stats.window /= 2;
+    }
+
+    pub(crate) fn record_prefetch(&self, pid: &BTreePageID) {
+        let mut read_ahead = self.counters.read_ahead.lock().unwrap();
+        read_ahead.stats.pages_prefetched += 1;
+        read_ahead.prefetched.insert(*pid);
+    }
+
+    /// Get the status of the transaction. All clones of a transaction share
+    /// the same status.
+    pub fn get_status(&self) -> Option<TransactionStatus> {
+        Database::concurrent_status().get_transaction_status(&self.id)
+    }
+
+    /// Return an error if the transaction has been committed or aborted, used
+    /// by operations which require an active transaction (e.g. insert, delete).
+    pub(crate) fn check_active(&self) -> SmallResult {
+        match self.get_status() {
+            Some(TransactionStatus::Active) => Ok(()),
+            status => {
+                let err_msg = format!("transaction {} is not active, status: {:?}", self, status);
+                Err(SmallError::with_kind(
+                    SmallErrorKind::InactiveTransaction,
+                    &err_msg,
+                ))
+            }
+        }
+    }
+
+    /// Return an error if the transaction is read-only, used by operations
+    /// which modify the data (e.g. insert, delete).
+    pub(crate) fn check_writable(&self) -> SmallResult {
+        if self.read_only {
+            let err_msg = format!("transaction {} is read-only", self);
+            return Err(SmallError::with_kind(
+                SmallErrorKind::ReadOnlyTransaction,
+                &err_msg,
+            ));
+        }
+        Ok(())
+    }
 }
 
+impl PartialEq for Transaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Transaction {}
+
 impl std::hash::Hash for Transaction {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.id.hash(state);
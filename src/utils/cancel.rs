@@ -0,0 +1,44 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    error::{SmallError, SmallErrorKind},
+    types::SmallResult,
+};
+
+/// A cooperative cancellation flag shared between the caller and a long
+/// running operation (e.g. a scan or a query).
+///
+/// The operation checks the token periodically and stops with a
+/// `SmallErrorKind::Cancelled` error once it has been tripped.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Return an `Cancelled` error if the token has been tripped.
+    pub fn check(&self) -> SmallResult {
+        if self.is_cancelled() {
+            return Err(SmallError::with_kind(
+                SmallErrorKind::Cancelled,
+                "operation cancelled",
+            ));
+        }
+        Ok(())
+    }
+}
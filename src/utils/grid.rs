@@ -0,0 +1,49 @@
+/// Render a table as an ASCII grid, e.g.
+///
+/// ```text
+/// +----+-------+
+/// | id | score |
+/// +----+-------+
+/// | 1  | 10    |
+/// | 2  | 20    |
+/// +----+-------+
+/// ```
+///
+/// Rows shorter than the header are padded with empty cells.
+pub fn render_grid(header: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, value) in row.iter().enumerate() {
+            if i < widths.len() {
+                widths[i] = widths[i].max(value.chars().count());
+            }
+        }
+    }
+
+    let separator = {
+        let mut line = String::from("+");
+        for width in &widths {
+            line.push_str(&"-".repeat(width + 2));
+            line.push('+');
+        }
+        line
+    };
+
+    let render_row = |values: &[String]| {
+        let mut line = String::from("|");
+        for (i, width) in widths.iter().enumerate() {
+            let value = values.get(i).map(|v| v.as_str()).unwrap_or("");
+            let padding = width - value.chars().count();
+            line.push_str(&format!(" {}{} |", value, " ".repeat(padding)));
+        }
+        line
+    };
+
+    let mut lines = vec![separator.clone(), render_row(header), separator.clone()];
+    for row in rows {
+        lines.push(render_row(row));
+    }
+    lines.push(separator);
+
+    lines.join("\n") + "\n"
+}
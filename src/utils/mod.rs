@@ -6,3 +6,9 @@ pub use console_log::*;
 
 mod number;
 pub use number::*;
+
+mod cancel;
+pub use cancel::*;
+
+mod grid;
+pub use grid::*;
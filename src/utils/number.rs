@@ -25,3 +25,15 @@ pub fn ceil_div<T: Number>(a: T, b: T) -> T {
 pub fn floor_div<T: Number>(a: T, b: T) -> T {
     a / b
 }
+
+/// Get the number of items to move from the fuller of two sibling pages
+/// holding `a` and `b` items, so that both end up with half of them (the
+/// fuller page keeps the extra item of an odd total).
+///
+/// Computed from the difference of the counts instead of their sum, so it
+/// can't overflow. The callers check it against the items the fuller page
+/// actually holds before moving them.
+pub fn balance_move_count(a: usize, b: usize) -> usize {
+    let (fewer, more) = if a < b { (a, b) } else { (b, a) };
+    (more - fewer) / 2
+}
@@ -1,9 +1,11 @@
 use std::{env, thread};
 
 use log::info;
-use small_db::utils::HandyRwLock;
+use small_db::{btree::table::WriteScene, utils::HandyRwLock};
 
-use crate::test_utils::{insert_random, new_random_btree_table, setup, TreeLayout};
+use crate::test_utils::{
+    insert_random, new_empty_btree_table, new_random_btree_table, setup, TreeLayout,
+};
 
 #[test]
 #[cfg(feature = "benchmark")]
@@ -60,3 +62,36 @@ fn test_insert_parallel() {
     );
     assert!(table.tuples_count() == total_rows);
 }
+
+#[test]
+#[cfg(feature = "benchmark")]
+fn test_benchmark_insert() {
+    setup();
+
+    let n = env::var("ACTION_COUNT")
+        .unwrap_or("50000".to_string())
+        .parse::<usize>()
+        .unwrap();
+
+    let sequential_rc = new_empty_btree_table("sequential", 2);
+    let sequential = sequential_rc
+        .rl()
+        .benchmark_insert(n, WriteScene::Sequential)
+        .unwrap();
+    info!("sequential insertion: {:?}", sequential);
+
+    let random_rc = new_empty_btree_table("random", 2);
+    let random = random_rc
+        .rl()
+        .benchmark_insert(n, WriteScene::Random)
+        .unwrap();
+    info!("random insertion: {:?}", random);
+
+    assert_eq!(sequential.ops, n);
+    assert_eq!(sequential_rc.rl().tuples_count(), n);
+    assert_eq!(random_rc.rl().tuples_count(), n);
+
+    // the timing is noisy, only catch a sequential insertion which is way
+    // slower than the random one
+    assert!(sequential.ops_per_sec > random.ops_per_sec * 0.8);
+}
@@ -1,19 +1,22 @@
+use std::{collections::HashSet, fs};
+
 use log::debug;
 use small_db::{
     btree::{
         buffer_pool::BufferPool,
         page::{BTreeInternalPageIterator, BTreePage},
-        table::BTreeTableIterator,
+        table::{BTreeTableIterator, MaintenanceOptions},
     },
-    storage::tuple::Cell,
-    transaction::Transaction,
-    utils::{ceil_div, floor_div, HandyRwLock},
-    Predicate,
+    storage::tuple::{Cell, WrappedTuple},
+    transaction::{Permission, Transaction},
+    utils::{balance_move_count, ceil_div, floor_div, HandyRwLock},
+    BTreeTable, Database, Predicate, SmallErrorKind,
 };
 
 use crate::test_utils::{
-    assert_true, delete_tuples, get_internal_page, get_leaf_page, insert_tuples,
-    internal_children_cap, leaf_records_cap, new_random_btree_table, setup, TreeLayout,
+    assert_true, crash, delete_tuples, get_internal_page, get_leaf_page, insert_tuples,
+    internal_children_cap, leaf_records_cap, new_empty_btree_table, new_int_tuples,
+    new_random_btree_table, setup, TreeLayout,
 };
 
 #[test]
@@ -68,7 +71,7 @@ fn test_merge_leaf_pages() {
 
     // delete the last two tuples
     let tx = Transaction::new();
-    let mut it = BTreeTableIterator::new(&tx, &table);
+    let mut it = BTreeTableIterator::new(&tx, &table).unwrap();
     table.delete_tuple(&tx, &it.next_back().unwrap()).unwrap();
     table.delete_tuple(&tx, &it.next_back().unwrap()).unwrap();
     tx.commit().unwrap();
@@ -135,12 +138,53 @@ fn test_reuse_deleted_pages() {
     table.check_integrity();
     assert_eq!(5, table.pages_count());
 
-    // insert enough tuples to ensure one of the leaf pages splits
+    // insert enough tuples to ensure the first leaf page splits twice
     insert_tuples(&table, leaf_records_cap());
 
-    // now there should be 3 leaf pages, 1 internal page, and 1 header
-    // page
+    // now there should be 4 leaf pages (the first split reuses the deleted
+    // page), 1 internal page, and 1 header page
+    assert_eq!(6, table.pages_count());
+}
+
+#[test]
+fn test_page_index_exhausted() {
+    setup();
+
+    // This should create a B+ tree with 3 leaf pages.
+    let table_rc = new_random_btree_table(
+        2,
+        leaf_records_cap() * 3,
+        None,
+        0,
+        TreeLayout::LastTwoEvenlyDistributed,
+    );
+    let table = table_rc.rl();
+
+    // free a leaf page
+    delete_tuples(&table, leaf_records_cap() + 2);
     assert_eq!(5, table.pages_count());
+
+    // the last page index is taken, but the split reuses the freed page
+    table.set_page_index(u32::MAX);
+    insert_tuples(&table, leaf_records_cap() / 4);
+    assert_eq!(5, table.pages_count());
+    table.check_integrity();
+
+    // a table without header pages has to take a new page index for the
+    // first header page
+    let table_rc = new_empty_btree_table("page_index_exhausted", 2);
+    let table = table_rc.rl();
+    insert_tuples(&table, leaf_records_cap());
+    table.set_page_index(u32::MAX);
+
+    let tx = Transaction::new();
+    let err = table
+        .insert_tuple(&tx, &new_int_tuples(-1, 2, &tx))
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::PageIndexExhausted);
+    tx.abort().unwrap();
+
+    assert_eq!(table.tuples_count(), leaf_records_cap());
 }
 
 #[test]
@@ -179,7 +223,7 @@ fn test_redistribute_internal_pages() {
     //
     // step 1: bring the left internal page to minimum occupancy
     let tx = Transaction::new();
-    let mut it = BTreeTableIterator::new(&tx, &table);
+    let mut it = BTreeTableIterator::new(&tx, &table).unwrap();
     for t in it.by_ref().take(50 * leaf_records_cap()) {
         table.delete_tuple(&tx, &t).unwrap();
     }
@@ -214,6 +258,66 @@ fn test_redistribute_internal_pages() {
     table.check_integrity();
 }
 
+#[test]
+fn test_redistribute_nearly_empty_leaf_page() {
+    setup();
+
+    // Create a B+ tree with two full leaf pages.
+    let cap = leaf_records_cap();
+    let table_rc = new_random_btree_table(2, cap * 2, None, 0, TreeLayout::EvenlyDistributed);
+    table_rc.wl().set_auto_merge(false);
+    let table = table_rc.rl();
+
+    let left_pod = get_leaf_page(&table, 1, 0);
+    let right_pid = left_pod.rl().get_right_pid().unwrap();
+    let tx = Transaction::new();
+    let right_pod = BufferPool::get_leaf_page(&tx, Permission::ReadOnly, &right_pid).unwrap();
+    tx.commit().unwrap();
+
+    // Empty the left page but one tuple, so the pages can't be merged.
+    delete_tuples(&table, cap - 1);
+    assert_true(left_pod.rl().tuples_count() == 1, &table);
+    assert_true(right_pod.rl().tuples_count() == cap, &table);
+
+    let tx = Transaction::new();
+    table.rebalance(&tx).unwrap();
+    tx.commit().unwrap();
+
+    // Half of the tuples of the right page moved to the left page.
+    table.check_integrity();
+    let left = left_pod.rl().tuples_count();
+    let right = right_pod.rl().tuples_count();
+    assert_eq!(left + right, cap + 1);
+    assert!(left.abs_diff(right) <= 1);
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), cap + 1);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_balance_move_count() {
+    // one page is empty, the other is full: half of the tuples move, both
+    // pages end up non-empty and within their capacity
+    let cap = leaf_records_cap();
+    for (left, right) in [(0, cap), (cap, 0)] {
+        let move_count = balance_move_count(left, right);
+        assert_eq!(move_count, cap / 2);
+
+        let (fewer, more) = (left.min(right) + move_count, left.max(right) - move_count);
+        assert!(fewer > 0 && fewer <= cap);
+        assert!(more > 0 && more <= cap);
+        assert!(more - fewer <= 1);
+    }
+
+    // nothing to move between balanced pages
+    assert_eq!(balance_move_count(cap, cap), 0);
+    assert_eq!(balance_move_count(cap, cap - 1), 0);
+
+    // the counts are never summed
+    assert_eq!(balance_move_count(usize::MAX, 0), usize::MAX / 2);
+    assert_eq!(balance_move_count(usize::MAX, usize::MAX - 2), 1);
+}
+
 #[test]
 fn test_delete_internal_pages() {
     // Use a small page size to speed up the test.
@@ -298,3 +402,254 @@ fn test_delete_by_condition() {
     table.check_integrity();
     debug!("tuples count: {}", table.tuples_count());
 }
+
+#[test]
+fn test_verify_free_space() {
+    setup();
+
+    // This should create a B+ tree with 3 leaf pages.
+    let table_rc = new_random_btree_table(
+        2,
+        leaf_records_cap() * 3,
+        None,
+        0,
+        TreeLayout::LastTwoEvenlyDistributed,
+    );
+    let table = table_rc.rl();
+
+    // delete enough tuples to ensure one page gets deleted
+    delete_tuples(&table, leaf_records_cap() + 2);
+
+    let tx = Transaction::new();
+    let discrepancies = table.verify_free_space(&tx).unwrap();
+    tx.commit().unwrap();
+    assert_true(discrepancies.is_empty(), &table);
+
+    // the deleted page should be reused by the split
+    insert_tuples(&table, leaf_records_cap());
+
+    let tx = Transaction::new();
+    let discrepancies = table.verify_free_space(&tx).unwrap();
+    tx.commit().unwrap();
+    assert_true(discrepancies.is_empty(), &table);
+}
+
+#[test]
+fn test_verify_leaf_chain() {
+    setup();
+
+    // This should create a B+ tree with 3 leaf pages.
+    let table_rc = new_random_btree_table(
+        2,
+        leaf_records_cap() * 3,
+        None,
+        0,
+        TreeLayout::LastTwoEvenlyDistributed,
+    );
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    table.verify_leaf_chain(&tx).unwrap();
+    tx.commit().unwrap();
+
+    // corrupt the back pointer of the middle leaf
+    let middle_rc = get_leaf_page(&table, 1, 1);
+    let left_pid = middle_rc.rl().get_left_pid();
+    let last_pid = middle_rc.rl().get_right_pid();
+    middle_rc.wl().set_left_pid(last_pid);
+
+    let tx = Transaction::new();
+    let err = table.verify_leaf_chain(&tx).unwrap_err();
+    tx.commit().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+
+    middle_rc.wl().set_left_pid(left_pid);
+    let tx = Transaction::new();
+    table.verify_leaf_chain(&tx).unwrap();
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_tombstones() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 100, None, 0, TreeLayout::Naturally);
+    table_rc.wl().set_retain_tombstones(true).unwrap();
+    let table = table_rc.rl();
+
+    let delete_tx = Transaction::new();
+    let deleted = BTreeTableIterator::new(&delete_tx, &table)
+        .unwrap()
+        .next()
+        .unwrap();
+    table.delete_tuple(&delete_tx, &deleted).unwrap();
+    delete_tx.commit().unwrap();
+
+    // the deleted tuple survives the vacuum as a tombstone
+    table.delete_invisible_tuples().unwrap();
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), 99);
+    let tombstones = table.tombstones(&tx).unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].get_cells(), deleted.get_cells());
+    tx.commit().unwrap();
+
+    // the deletion is not older than the threshold, still in the window
+    table.purge_tombstones(delete_tx.get_id()).unwrap();
+    let tx = Transaction::new();
+    assert_eq!(table.tombstones(&tx).unwrap().len(), 1);
+    tx.commit().unwrap();
+
+    table.purge_tombstones(delete_tx.get_id() + 1).unwrap();
+    let tx = Transaction::new();
+    assert!(table.tombstones(&tx).unwrap().is_empty());
+    assert_eq!(table.iter(&tx).count(), 99);
+    tx.commit().unwrap();
+
+    // the setting is persisted in the catalog, the tombstones survive the
+    // vacuum of the reopened table
+    let table_name = table.name.clone();
+    let delete_tx = Transaction::new();
+    let deleted = table.iter(&delete_tx).next().unwrap();
+    table.delete_tuple(&delete_tx, &deleted).unwrap();
+    delete_tx.commit().unwrap();
+    drop(table);
+    drop(table_rc);
+    crash();
+
+    let table_rc = BTreeTable::open(&table_name).unwrap();
+    let table = table_rc.rl();
+    assert!(table.get_retain_tombstones());
+    table.delete_invisible_tuples().unwrap();
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), 98);
+    assert_eq!(table.tombstones(&tx).unwrap().len(), 1);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_disable_auto_merge() {
+    setup();
+
+    let rows = leaf_records_cap() * 6;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::EvenlyDistributed);
+    table_rc.wl().set_auto_merge(false);
+    let table = table_rc.rl();
+
+    // delete 3 of every 5 tuples, leaving every leaf page 40% full
+    let tx = Transaction::new();
+    let tuples: Vec<WrappedTuple> = table.iter(&tx).collect();
+    let mut remaining = 0;
+    for (i, tuple) in tuples.iter().enumerate() {
+        if i % 5 < 3 {
+            table.delete_tuple(&tx, tuple).unwrap();
+        } else {
+            remaining += 1;
+        }
+    }
+    tx.commit().unwrap();
+    table.delete_invisible_tuples().unwrap();
+
+    // the under-full pages are kept as they are
+    let leaf_pod = get_leaf_page(&table, 1, 0);
+    assert_true(!leaf_pod.rl().stable(), &table);
+    let root_pod = get_internal_page(&table, 0, 0);
+    assert_true(root_pod.rl().children_count() == 6, &table);
+
+    let tx = Transaction::new();
+    table.rebalance(&tx).unwrap();
+    tx.commit().unwrap();
+
+    // the pages are merged back to at least half full
+    table.check_integrity();
+    let root_pod = get_internal_page(&table, 0, 0);
+    assert_true(root_pod.rl().children_count() < 6, &table);
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), remaining);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_maintenance() {
+    setup();
+
+    let rows = leaf_records_cap() * 6;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::EvenlyDistributed);
+    table_rc.wl().set_auto_merge(false);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let before = table.analyze(&tx).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(before.tuples_count, rows);
+
+    // delete 4 of every 5 tuples, the slots are kept until compaction and
+    // the pages are left under-full
+    let tx = Transaction::new();
+    let tuples: Vec<WrappedTuple> = table.iter(&tx).collect();
+    for (i, tuple) in tuples.iter().enumerate() {
+        if i % 5 != 0 {
+            table.delete_tuple(&tx, tuple).unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let degraded = table.analyze(&tx).unwrap();
+    tx.commit().unwrap();
+    assert!(degraded.leaf_occupancy < 0.3);
+
+    // skipped steps change nothing
+    let tx = Transaction::new();
+    let options = MaintenanceOptions {
+        compact: false,
+        rebalance: false,
+        analyze: false,
+        checkpoint: false,
+    };
+    table.maintenance(&tx, &options).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(table.get_analysis(), Some(degraded.clone()));
+
+    // the START record of the maintenance transaction
+    let tx_start = Database::log_manager().get_current_offset();
+    let tx = Transaction::new();
+    table
+        .maintenance(&tx, &MaintenanceOptions::default())
+        .unwrap();
+
+    // the log is cut up to the maintenance transaction, which is running,
+    // the records left keep their offsets
+    let wal_path = Database::global().get_path().join("wal.log");
+    let log_end = Database::log_manager().get_current_offset();
+    let log_size = fs::metadata(&wal_path).unwrap().len();
+    assert_eq!(log_size, 16 + log_end - tx_start);
+    let offsets = Database::mut_log_manager().record_timestamps().unwrap();
+    assert_eq!(offsets.first().unwrap().0, tx_start);
+    assert!(Database::mut_log_manager()
+        .last_checkpoint_offset()
+        .unwrap()
+        .is_some());
+
+    tx.commit().unwrap();
+    table.check_integrity();
+
+    // the statistics describe the tidied table
+    let analysis = table.get_analysis().unwrap();
+    let tx = Transaction::new();
+    let keys: HashSet<Cell> = table.iter(&tx).map(|t| t.get_cell(0)).collect();
+    assert_eq!(analysis.tuples_count, table.iter(&tx).count());
+    tx.commit().unwrap();
+    assert_eq!(analysis.tuples_count, ceil_div(rows, 5));
+    assert_eq!(analysis.distinct_keys, keys.len());
+    assert!(analysis.leaf_pages < degraded.leaf_pages);
+    assert!(analysis.leaf_occupancy > degraded.leaf_occupancy);
+
+    // with no transaction running, the whole log is cut
+    assert!(Database::mut_log_manager().truncate().unwrap());
+    assert_eq!(fs::metadata(&wal_path).unwrap().len(), 16);
+    assert!(Database::mut_log_manager()
+        .record_timestamps()
+        .unwrap()
+        .is_empty());
+}
@@ -1,18 +1,29 @@
+use std::{
+    cmp::Ordering,
+    sync::{Arc, RwLock},
+};
+
 use rand::Rng;
 use small_db::{
     btree::{
         buffer_pool::BufferPool,
-        table::{BTreeTableIterator, BTreeTableSearchIterator},
+        page::{BTreeInternalPageIterator, BTreeLeafPage, BTreePage, Entry, PageCategory},
+        table::{BTreeTableIterator, BTreeTableSearchIterator, KeyComparator, OnConflict},
     },
-    storage::tuple::Cell,
-    transaction::Transaction,
+    common::Catalog,
+    storage::{
+        table_schema::{Field, Type},
+        tuple::{Cell, Tuple, WrappedTuple},
+    },
+    transaction::{Permission, Transaction},
     utils::{ceil_div, HandyRwLock},
-    Op, Predicate,
+    BTreeTable, Database, Op, Predicate, SmallErrorKind, TableSchema,
 };
 
 use crate::test_utils::{
-    assert_true, get_internal_page, get_leaf_page, insert_tuples, internal_children_cap,
-    leaf_records_cap, new_int_tuples, new_random_btree_table, search_key, setup, TreeLayout,
+    assert_true, crash, delete_tuples, get_internal_page, get_leaf_page, insert_row, insert_tuples,
+    internal_children_cap, leaf_records_cap, new_empty_btree_table, new_int_tuples,
+    new_random_btree_table, search_key, setup, TreeLayout,
 };
 
 #[test]
@@ -44,19 +55,19 @@ fn test_insert_tuple() {
         table.insert_tuple(&tx, &tuple).unwrap();
         insert_value += 1;
 
-        // there are 3 pages: 1 root page + 2 leaf pages
-        assert_eq!(3, table.pages_count());
+        // there are 4 pages: 1 root page + 2 leaf pages + 1 header page
+        assert_eq!(4, table.pages_count());
     }
 
     // one more insert should cause page 2 to split
     let tuple = new_int_tuples(insert_value, 2, &tx);
     table.insert_tuple(&tx, &tuple).unwrap();
 
-    // there are 4 pages: 1 root page + 3 leaf pages
-    assert_true(table.pages_count() == 4, &table);
+    // there are 5 pages: 1 root page + 3 leaf pages + 1 header page
+    assert_true(table.pages_count() == 5, &table);
 
     // now make sure the records are sorted on the key field
-    let it = BTreeTableIterator::new(&tx, &table);
+    let it = BTreeTableIterator::new(&tx, &table).unwrap();
     for (i, tuple) in it.enumerate() {
         assert_eq!(Cell::Int64(i as i64), tuple.get_cell(0));
     }
@@ -85,17 +96,21 @@ fn test_insert_duplicate_tuples() {
 
     // now search for some ranges and make sure we find all the tuples
     let predicate = Predicate::new(table.key_field, Op::Equals, &Cell::Int64(1));
-    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate);
+    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate).unwrap();
     assert_eq!(it.count(), repetition_count);
 
     let predicate = Predicate::new(table.key_field, Op::GreaterThanOrEq, &Cell::Int64(2));
-    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate);
+    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate).unwrap();
     assert_eq!(it.count(), repetition_count * 3);
 
     let predicate = Predicate::new(table.key_field, Op::LessThan, &Cell::Int64(2));
-    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate);
+    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate).unwrap();
     assert_eq!(it.count(), repetition_count * 2);
 
+    let predicate = Predicate::new(table.key_field, Op::NotEquals, &Cell::Int64(2));
+    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate).unwrap();
+    assert_eq!(it.count(), repetition_count * 4);
+
     tx.commit().unwrap();
 }
 
@@ -119,8 +134,8 @@ fn test_split_leaf_page() {
     // now insert a tuple
     insert_tuples(&table, 1);
 
-    // there should now be 2 leaf pages + 1 internal page
-    assert_eq!(3, table.pages_count());
+    // there should now be 2 leaf pages + 1 internal page + 1 header page
+    assert_eq!(4, table.pages_count());
 
     let root_pod = get_internal_page(&table, 0, 0);
     assert_true(
@@ -158,9 +173,9 @@ fn test_split_root_page() {
 
     // there should be 3 internal pages now, since the origianl root
     // page split into 2 pages + 1 new root page
-    // and there is also a new leaf page
+    // and there is also a new leaf page and a new header page
     assert_true(
-        table.pages_count() == internal_children_cap() + 3 + 1,
+        table.pages_count() == internal_children_cap() + 3 + 1 + 1,
         &table,
     );
 
@@ -174,9 +189,9 @@ fn test_split_root_page() {
 
     // each child should have be stable
     let leaf_pod = get_internal_page(&table, 1, 0);
-    assert_true(leaf_pod.rl().stable(), &table);
+    assert_true(leaf_pod.rl().stable(table.get_fan_out()), &table);
     let right_pod = get_internal_page(&table, 1, 1);
-    assert_true(right_pod.rl().stable(), &table);
+    assert_true(right_pod.rl().stable(table.get_fan_out()), &table);
 
     // now insert some random tuples and make sure we can find them
     let tx = Transaction::new();
@@ -193,6 +208,44 @@ fn test_split_root_page() {
     tx.commit().unwrap();
 }
 
+#[test]
+fn test_split_leaf_page_skewed() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    table_rc.wl().set_split_ratio(0.9);
+    let table = table_rc.rl();
+
+    // fill the root leaf and insert one more tuple, all keys ascending
+    let cap = leaf_records_cap();
+    let tx = Transaction::new();
+    for key in 0..=cap {
+        insert_row(&table, &tx, key as i64);
+    }
+    tx.commit().unwrap();
+
+    // the left page keeps 90% of the full page, the new tuple goes to the
+    // right page
+    let keep = (cap as f64 * 0.9).round() as usize;
+    let tx = Transaction::new();
+    let left_rc = table.get_first_page(&tx, Permission::ReadOnly).unwrap();
+    let right_rc = table.get_last_page(&tx, Permission::ReadOnly).unwrap();
+    assert_eq!(left_rc.rl().tuples_count(), keep);
+    assert_eq!(right_rc.rl().tuples_count(), cap + 1 - keep);
+    assert_eq!(left_rc.rl().get_right_pid(), Some(right_rc.rl().get_pid()));
+    tx.commit().unwrap();
+
+    // all tuples are still reachable, in key order
+    let tx = Transaction::new();
+    let keys: Vec<Cell> = table.iter(&tx).map(|t| t.get_cell(0)).collect();
+    let expect: Vec<Cell> = (0..=cap).map(|k| Cell::Int64(k as i64)).collect();
+    assert_eq!(keys, expect);
+    for key in 0..=cap {
+        assert_eq!(search_key(&table, &tx, &Cell::Int64(key as i64)), 1);
+    }
+    tx.commit().unwrap();
+}
+
 #[test]
 fn test_split_internal_page() {
     // Use a small page size to speed up the test.
@@ -217,7 +270,7 @@ fn test_split_internal_page() {
     // now make sure we have enough records and they are all in sorted
     // order
     let tx = Transaction::new();
-    let it = BTreeTableIterator::new(&tx, &table);
+    let it = BTreeTableIterator::new(&tx, &table).unwrap();
     let mut previous = Cell::Int64(i64::MIN);
     let mut count: usize = 0;
     for t in it {
@@ -249,7 +302,7 @@ fn test_split_internal_page() {
 
     // now make sure we have enough records and they are all in sorted
     // order
-    let it = BTreeTableIterator::new(&tx, &table);
+    let it = BTreeTableIterator::new(&tx, &table).unwrap();
     let mut previous = Cell::Int64(i64::MIN);
     let mut count: usize = 0;
     for t in it {
@@ -294,3 +347,1009 @@ fn test_debug() {
 
     table.check_integrity();
 }
+
+#[test]
+fn test_insert_short_tuple() {
+    setup();
+
+    let table_rc = new_random_btree_table(3, 0, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+
+    // the tuple has fewer cells than the schema
+    let tuple = Tuple::new(&vec![Cell::Int64(1)], tx.get_id());
+    let err = table.insert_tuple(&tx, &tuple).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+
+    // the cells don't match the types of their fields
+    for cell in [Cell::Float64(1.0), Cell::Bytes(vec![1]), Cell::Null] {
+        let tuple = Tuple::new(&vec![Cell::Int64(1), Cell::Int64(2), cell], tx.get_id());
+        let err = table.insert_tuple(&tx, &tuple).unwrap_err();
+        assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+    }
+
+    // the table is still usable after the rejected insert
+    let tuple = new_int_tuples(1, 3, &tx);
+    table.insert_tuple(&tx, &tuple).unwrap();
+    tx.commit().unwrap();
+
+    assert_eq!(table.tuples_count(), 1);
+}
+
+#[test]
+fn test_secondary_sort() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    table_rc.wl().set_secondary_sort(Some(1));
+    let table = table_rc.rl();
+
+    // enough tuples with the same key to span several leaves, inserted in
+    // random order of the second column
+    let mut rng = rand::thread_rng();
+    let tx = Transaction::new();
+    let rows = leaf_records_cap() * 3;
+    for _ in 0..rows {
+        let key = rng.gen_range(0, 3);
+        let secondary = rng.gen_range(0, 10_000);
+        let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(secondary)], tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+    }
+    tx.commit().unwrap();
+    table.check_integrity();
+
+    let tx = Transaction::new();
+    let cells: Vec<Vec<Cell>> = table.iter(&tx).map(|t| t.get_cells()).collect();
+    tx.commit().unwrap();
+    assert_eq!(cells.len(), rows);
+
+    let mut sorted = cells.clone();
+    sorted.sort();
+    assert_eq!(cells, sorted);
+}
+
+#[test]
+fn test_auto_commit() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    // committed on success, visible to the later transactions
+    table
+        .insert_auto(&Tuple::new(&vec![Cell::Int64(1), Cell::Int64(2)], 0))
+        .unwrap();
+    assert_eq!(table.tuples_count(), 1);
+
+    // aborted on error, the table is unchanged and still usable
+    let err = table
+        .insert_auto(&Tuple::new(&vec![Cell::Int64(3)], 0))
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+    assert_eq!(table.tuples_count(), 1);
+
+    let tx = Transaction::new();
+    let tuple = table.get_by_key(&tx, &Cell::Int64(1)).unwrap().unwrap();
+    tx.commit().unwrap();
+    table.delete_auto(&tuple).unwrap();
+    assert_eq!(table.tuples_count(), 0);
+}
+
+#[test]
+fn test_insert_with_inactive_transaction() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    // committed transaction
+    let tx = Transaction::new();
+    table.insert_tuple(&tx, &new_int_tuples(1, 2, &tx)).unwrap();
+    tx.commit().unwrap();
+
+    let err = table
+        .insert_tuple(&tx, &new_int_tuples(2, 2, &tx))
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::InactiveTransaction);
+
+    // aborted transaction
+    let tx = Transaction::new();
+    tx.abort().unwrap();
+
+    let err = table
+        .insert_tuple(&tx, &new_int_tuples(3, 2, &tx))
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::InactiveTransaction);
+
+    assert_eq!(table.tuples_count(), 1);
+}
+
+#[test]
+fn test_read_only_transaction() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 100, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new_read_only();
+    assert!(tx.is_read_only());
+
+    let err = table
+        .insert_tuple(&tx, &new_int_tuples(1000, 2, &tx))
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::ReadOnlyTransaction);
+
+    let first_pid = table
+        .get_first_page(&tx, Permission::ReadOnly)
+        .unwrap()
+        .rl()
+        .get_pid();
+    let err = BufferPool::get_leaf_page(&tx, Permission::ReadWrite, &first_pid)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::ReadOnlyTransaction);
+
+    // the scan succeeds
+    assert_eq!(BTreeTableIterator::new(&tx, &table).unwrap().count(), 100);
+    tx.commit().unwrap();
+
+    assert_eq!(table.tuples_count(), 100);
+}
+
+#[test]
+fn test_import_csv() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let csv = "a,b\n1,10\n2,x\n3,30\n4\n";
+
+    let tx = Transaction::new();
+    let report = table.import_csv(&tx, csv.as_bytes(), true).unwrap();
+    tx.commit().unwrap();
+
+    assert_eq!(report.imported_rows, 2);
+    assert_eq!(report.errors.len(), 2);
+
+    // the bad integer field
+    assert_eq!(report.errors[0].line, 3);
+    assert_eq!(report.errors[0].column, 1);
+    assert_eq!(report.errors[0].value, "x");
+
+    // the short row
+    assert_eq!(report.errors[1].line, 5);
+
+    let tx = Transaction::new();
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(1)), 1);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(2)), 0);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(3)), 1);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_stream_round_trip() {
+    setup();
+
+    let rows = 1000;
+    let table_rc = new_random_btree_table(3, rows, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let mut buffer = Vec::new();
+    table.stream_to(&tx, &mut buffer).unwrap();
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let copy_rc = BTreeTable::load_from("copy", &tx, buffer.as_slice()).unwrap();
+    tx.commit().unwrap();
+    let copy = copy_rc.rl();
+
+    assert_eq!(
+        copy.get_schema().get_fields(),
+        table.get_schema().get_fields()
+    );
+    assert_eq!(copy.tuples_count(), rows);
+
+    let tx = Transaction::new();
+    let expect: Vec<Vec<Cell>> = table.iter(&tx).map(|t| t.get_cells()).collect();
+    let actual: Vec<Vec<Cell>> = copy.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(actual, expect);
+    tx.commit().unwrap();
+
+    copy.check_integrity();
+}
+
+#[test]
+fn test_copy_table() {
+    setup();
+
+    let rows = 1000;
+    let table_rc = new_random_btree_table(3, rows, None, 0, TreeLayout::Naturally);
+    table_rc.wl().set_max_fan_out(Some(4)).unwrap();
+    table_rc.wl().set_secondary_sort(Some(2));
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let same_key_rc = table.copy_to(&tx, "same_key", None).unwrap();
+    let new_key_rc = table.copy_to(&tx, "new_key", Some(1)).unwrap();
+    tx.commit().unwrap();
+    let same_key = same_key_rc.rl();
+    let new_key = new_key_rc.rl();
+
+    assert_eq!(same_key.key_field, table.key_field);
+    assert_eq!(new_key.key_field, 1);
+    assert!(new_key.get_schema().get_fields()[1].is_primary);
+    assert!(!new_key.get_schema().get_fields()[0].is_primary);
+
+    // the options of the source are kept
+    assert_eq!(same_key.get_fan_out(), 4);
+    assert_eq!(new_key.get_fan_out(), 4);
+    assert_eq!(same_key.get_secondary_sort(), Some(2));
+    assert_eq!(new_key.get_secondary_sort(), Some(2));
+    assert!(Database::catalog().search_table("new_key").is_some());
+
+    let tx = Transaction::new();
+    let expect: Vec<Vec<Cell>> = table.iter(&tx).map(|t| t.get_cells()).collect();
+
+    // the copy with the same key is identical to the source
+    let actual: Vec<Vec<Cell>> = same_key.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(actual, expect);
+
+    // the copy with a new key is sorted by it and holds all the rows
+    let actual: Vec<Vec<Cell>> = new_key.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(actual.len(), rows);
+    assert!(actual.windows(2).all(|w| w[0][1] <= w[1][1]));
+
+    let mut sorted_expect = expect.clone();
+    sorted_expect.sort();
+    let mut sorted_actual = actual.clone();
+    sorted_actual.sort();
+    assert_eq!(sorted_actual, sorted_expect);
+
+    // the key field must exist
+    let err = table.copy_to(&tx, "missing_key", Some(3)).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::NotFound);
+    tx.commit().unwrap();
+
+    same_key.check_integrity();
+    new_key.check_integrity();
+}
+
+#[test]
+fn test_varint_page_fill() {
+    setup();
+
+    let fixed_schema = TableSchema::new(vec![
+        Field::new("key", Type::Int64, true),
+        Field::new("value", Type::Int64, false),
+    ]);
+    let varint_schema = TableSchema::new(vec![
+        Field::new_varint("key", true),
+        Field::new_varint("value", false),
+    ]);
+
+    let fixed_rc = Arc::new(RwLock::new(BTreeTable::new("fixed", None, &fixed_schema)));
+    Catalog::add_table(Arc::clone(&fixed_rc), true).unwrap();
+    let varint_rc = Arc::new(RwLock::new(BTreeTable::new("varint", None, &varint_schema)));
+    Catalog::add_table(Arc::clone(&varint_rc), true).unwrap();
+    let fixed = fixed_rc.rl();
+    let varint = varint_rc.rl();
+
+    // one more row than a fixed-width leaf page can hold, all values fit in
+    // a 1-byte varint
+    let rows = BTreeLeafPage::calc_children_cap(&fixed_schema) + 1;
+    let tx = Transaction::new();
+    for i in 0..rows as i64 {
+        let cells = vec![Cell::Int64(i % 64), Cell::Int64(i % 10)];
+        fixed
+            .insert_tuple(&tx, &Tuple::new(&cells, tx.get_id()))
+            .unwrap();
+        varint
+            .insert_tuple(&tx, &Tuple::new(&cells, tx.get_id()))
+            .unwrap();
+    }
+    tx.commit().unwrap();
+
+    // the fixed-width table has been split, the varint one still fits in a
+    // single leaf page, which is far from full
+    let tx = Transaction::new();
+    assert_eq!(
+        fixed.get_root_pid(&tx).get_category(),
+        PageCategory::Internal
+    );
+    assert_eq!(varint.get_root_pid(&tx).get_category(), PageCategory::Leaf);
+
+    let leaf_rc = varint.get_first_page(&tx, Permission::ReadOnly).unwrap();
+    assert_eq!(leaf_rc.rl().tuples_count(), rows);
+    assert!(leaf_rc.rl().used_bytes() * 2 < BufferPool::get_page_size());
+    tx.commit().unwrap();
+
+    // the variable-width tuples are decoded when the page is read back
+    varint.reload();
+    let tx = Transaction::new();
+    let expect: Vec<Vec<Cell>> = fixed.iter(&tx).map(|t| t.get_cells()).collect();
+    let actual: Vec<Vec<Cell>> = varint.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(actual, expect);
+    tx.commit().unwrap();
+
+    // a NULL can't be stored in a varint field
+    let tx = Transaction::new();
+    let cells = vec![Cell::Int64(1000), Cell::Null];
+    let err = varint
+        .insert_tuple(&tx, &Tuple::new(&cells, tx.get_id()))
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+    tx.abort().unwrap();
+
+    fixed.check_integrity();
+    varint.check_integrity();
+}
+
+#[test]
+fn test_render_tree() {
+    setup();
+
+    let rows = 3 * leaf_records_cap();
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::EvenlyDistributed);
+    let table = table_rc.rl();
+
+    let depiction = table.render_tree(-1);
+    let leaves: Vec<&str> = depiction
+        .lines()
+        .filter(|line| line.contains("── leaf:"))
+        .collect();
+    assert_eq!(leaves.len(), 3);
+
+    // each leaf line reads "... (<tuples>/<slots> tuples) ..."
+    let tuples: usize = leaves
+        .iter()
+        .map(|line| {
+            let counts = line.split(" (").nth(1).unwrap();
+            counts.split('/').next().unwrap().parse::<usize>().unwrap()
+        })
+        .sum();
+    assert_eq!(tuples, rows);
+}
+
+/// Create a table holding the keys 1, 2, 3 (each row is `[key, key]`).
+fn new_conflict_table() -> Arc<RwLock<BTreeTable>> {
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+
+    let tx = Transaction::new();
+    for key in 1..=3 {
+        insert_row(&table_rc.rl(), &tx, key);
+    }
+    tx.commit().unwrap();
+    table_rc
+}
+
+/// The batch `[2, 200], [10, 10]`, its first tuple collides with a row of
+/// the table created by `new_conflict_table`.
+fn new_conflict_batch(tx: &Transaction) -> Vec<Tuple> {
+    vec![
+        Tuple::new(&vec![Cell::Int64(2), Cell::Int64(200)], tx.get_id()),
+        Tuple::new(&vec![Cell::Int64(10), Cell::Int64(10)], tx.get_id()),
+    ]
+}
+
+#[test]
+fn test_insert_tuples_on_conflict_error() {
+    setup();
+
+    let table_rc = new_conflict_table();
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let batch = new_conflict_batch(&tx);
+    let err = table
+        .insert_tuples(&tx, &batch, OnConflict::Error)
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::DuplicateKey);
+
+    // the batch is rejected as a whole
+    assert_eq!(BTreeTableIterator::new(&tx, &table).unwrap().count(), 3);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(10)), 0);
+    let existing = table.get_by_key(&tx, &Cell::Int64(2)).unwrap().unwrap();
+    assert_eq!(existing.get_cell(1), Cell::Int64(2));
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_insert_tuples_on_conflict_skip() {
+    setup();
+
+    let table_rc = new_conflict_table();
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let batch = new_conflict_batch(&tx);
+    table.insert_tuples(&tx, &batch, OnConflict::Skip).unwrap();
+
+    assert_eq!(BTreeTableIterator::new(&tx, &table).unwrap().count(), 4);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(2)), 1);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(10)), 1);
+    let existing = table.get_by_key(&tx, &Cell::Int64(2)).unwrap().unwrap();
+    assert_eq!(existing.get_cell(1), Cell::Int64(2));
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_insert_tuples_on_conflict_replace() {
+    setup();
+
+    let table_rc = new_conflict_table();
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let batch = new_conflict_batch(&tx);
+    table
+        .insert_tuples(&tx, &batch, OnConflict::Replace)
+        .unwrap();
+
+    assert_eq!(BTreeTableIterator::new(&tx, &table).unwrap().count(), 4);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(2)), 1);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(10)), 1);
+    let replaced = table.get_by_key(&tx, &Cell::Int64(2)).unwrap().unwrap();
+    assert_eq!(replaced.get_cell(1), Cell::Int64(200));
+    tx.commit().unwrap();
+}
+
+struct CaseInsensitive;
+
+impl KeyComparator for CaseInsensitive {
+    fn compare(&self, a: &Cell, b: &Cell) -> Ordering {
+        let a = a.get_bytes().unwrap().to_ascii_lowercase();
+        let b = b.get_bytes().unwrap().to_ascii_lowercase();
+        a.cmp(&b)
+    }
+}
+
+#[test]
+fn test_key_comparator() {
+    setup();
+
+    let schema = TableSchema::new(vec![
+        Field::new("name", Type::Bytes(16), true),
+        Field::new("id", Type::Int64, false),
+    ]);
+    let table_rc = Arc::new(RwLock::new(BTreeTable::new_with_comparator(
+        "case_insensitive",
+        None,
+        &schema,
+        Arc::new(CaseInsensitive),
+    )));
+    Catalog::add_table(table_rc.clone(), true).unwrap();
+    let table = table_rc.rl();
+
+    let new_tuple = |name: &str, id: i64, tx: &Transaction| {
+        Tuple::new(
+            &vec![Cell::Bytes(name.as_bytes().to_vec()), Cell::Int64(id)],
+            tx.get_id(),
+        )
+    };
+
+    // enough keys to spread over several leaves, in mixed case
+    let tx = Transaction::new();
+    let rows = 1000;
+    for i in 0..rows {
+        let name = if i % 2 == 0 {
+            format!("KEY-{:04}", i)
+        } else {
+            format!("key-{:04}", i)
+        };
+        table.insert_tuple(&tx, &new_tuple(&name, i, &tx)).unwrap();
+    }
+    table.insert_tuple(&tx, &new_tuple("abc", -1, &tx)).unwrap();
+    table.insert_tuple(&tx, &new_tuple("ABC", -2, &tx)).unwrap();
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let tuples: Vec<WrappedTuple> = table.iter(&tx).collect();
+    assert_eq!(tuples.len(), rows as usize + 2);
+
+    // the scan follows the case-insensitive order
+    let names: Vec<Vec<u8>> = tuples
+        .iter()
+        .map(|t| t.get_cell(0).get_bytes().unwrap().to_ascii_lowercase())
+        .collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+
+    // "abc" and "ABC" are neighbours in the same leaf
+    let positions: Vec<usize> = tuples
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.get_cell(1) == Cell::Int64(-1) || t.get_cell(1) == Cell::Int64(-2))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(positions.len(), 2);
+    assert_eq!(positions[0] + 1, positions[1]);
+    assert_eq!(
+        tuples[positions[0]].get_pid(),
+        tuples[positions[1]].get_pid()
+    );
+
+    // lookups route to the same region whatever the case of the key
+    for name in ["abc", "ABC", "aBc"] {
+        let found = table
+            .get_by_key(&tx, &Cell::Bytes(name.as_bytes().to_vec()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.get_pid(), tuples[positions[0]].get_pid());
+    }
+    let found = table
+        .get_by_key(&tx, &Cell::Bytes(b"Key-0501".to_vec()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.get_cell(1), Cell::Int64(501));
+
+    // the searches on the key compare by the order of the table, they don't
+    // stop at the keys which are greater byte-wise only
+    let bytes = |name: &str| Cell::Bytes(name.as_bytes().to_vec());
+    let search = |predicate: Predicate| -> Vec<i64> {
+        let mut ids: Vec<i64> = BTreeTableSearchIterator::new(&tx, &table, &predicate)
+            .unwrap()
+            .map(|t| t.get_cell(1).get_int64().unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+    assert_eq!(
+        search(Predicate::new(0, Op::Equals, &bytes("aBc"))),
+        vec![-2, -1]
+    );
+    assert_eq!(
+        search(Predicate::new(0, Op::Equals, &bytes("KEY-0501"))),
+        vec![501]
+    );
+    assert_eq!(
+        search(Predicate::new(0, Op::LessThan, &bytes("key-0003"))),
+        vec![-2, -1, 0, 1, 2]
+    );
+    assert_eq!(
+        search(Predicate::in_list(0, &[bytes("key-0010"), bytes("Abc")])),
+        vec![-2, -1, 10]
+    );
+    tx.commit().unwrap();
+
+    // the pages are checked against the order of the table, not the byte
+    // order of the keys ("key-0001" sorts after "KEY-0002" byte-wise)
+    table.check_integrity();
+}
+
+#[test]
+fn test_prefix_compressed_keys() {
+    setup();
+
+    let schema = TableSchema::new(vec![
+        Field::new("name", Type::Bytes(32), true),
+        Field::new("id", Type::Int64, false),
+    ]);
+    let table_rc = Arc::new(RwLock::new(BTreeTable::new("customers", None, &schema)));
+    Catalog::add_table(table_rc.clone(), true).unwrap();
+    let table = table_rc.rl();
+
+    // keys with a long common prefix
+    let name = |i: i64| Cell::Bytes(format!("customer-{:08}", i).into_bytes());
+
+    let tx = Transaction::new();
+    let rows = 12000;
+    for i in 0..rows {
+        let tuple = Tuple::new(&vec![name(i), Cell::Int64(i)], tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+    }
+    tx.commit().unwrap();
+
+    // read the internal pages back from disk
+    Database::mut_buffer_pool().clear();
+
+    // some internal page holds more entries than uncompressed keys
+    // would allow
+    let tx = Transaction::new();
+    let mut max_entries = 0;
+    let mut pids = vec![table.get_root_pid(&tx)];
+    while let Some(pid) = pids.pop() {
+        if pid.get_category() != PageCategory::Internal {
+            continue;
+        }
+
+        let page_rc = BufferPool::get_internal_page(&tx, Permission::ReadOnly, &pid).unwrap();
+        let page = page_rc.rl();
+        assert!(page.keys_disk_size() <= page.keys_capacity());
+        max_entries = max_entries.max(page.entries_count());
+        for e in BTreeInternalPageIterator::new(&page) {
+            pids.push(e.get_right_child());
+        }
+        pids.push(page.get_first_child_pid());
+    }
+    let uncompressed_cap = BufferPool::get_page_size() / (Type::Bytes(32).get_disk_size() + 4);
+    assert!(max_entries > uncompressed_cap);
+
+    // every key is routed to its tuple
+    for i in 0..rows {
+        let found = table.get_by_key(&tx, &name(i)).unwrap().unwrap();
+        assert_eq!(found.get_cell(1), Cell::Int64(i));
+    }
+    assert_eq!(table.iter(&tx).count(), rows as usize);
+    tx.commit().unwrap();
+    table.check_integrity();
+}
+
+/// Get the number of levels of the tree, and the most children an internal
+/// page has.
+fn tree_shape(table: &BTreeTable) -> (usize, usize) {
+    let tx = Transaction::new();
+    let mut depth = 1;
+    let mut max_children = 0;
+    let mut level = vec![table.get_root_pid(&tx)];
+    while level[0].get_category() == PageCategory::Internal {
+        let mut next_level = Vec::new();
+        for pid in &level {
+            let page_rc = BufferPool::get_internal_page(&tx, Permission::ReadOnly, pid).unwrap();
+            let page = page_rc.rl();
+            max_children = max_children.max(page.children_count());
+            next_level.push(page.get_first_child_pid());
+            for e in BTreeInternalPageIterator::new(&page) {
+                next_level.push(e.get_right_child());
+            }
+        }
+        level = next_level;
+        depth += 1;
+    }
+    tx.commit().unwrap();
+    (depth, max_children)
+}
+
+#[test]
+fn test_max_fan_out() {
+    setup();
+
+    let rows = leaf_records_cap() * 20;
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    insert_tuples(&table_rc.rl(), rows);
+    let (default_depth, _) = tree_shape(&table_rc.rl());
+
+    let capped_rc = new_empty_btree_table("capped", 2);
+    capped_rc.wl().set_max_fan_out(Some(4)).unwrap();
+    assert_eq!(capped_rc.rl().get_fan_out(), 4);
+    insert_tuples(&capped_rc.rl(), rows);
+
+    let capped = capped_rc.rl();
+    let (capped_depth, max_children) = tree_shape(&capped);
+    assert!(max_children <= 4);
+
+    // 20 leaves at least, at most 4 children per internal page
+    assert_eq!(default_depth, 2);
+    assert!(capped_depth >= 4);
+    let tx = Transaction::new();
+    assert_eq!(capped.iter(&tx).count(), rows);
+    tx.commit().unwrap();
+    capped.check_integrity();
+
+    // the merges on delete honor the cap, and the pages are at least half
+    // full relative to it
+    delete_tuples(&capped, rows / 2);
+    capped.check_integrity();
+    let (_, max_children) = tree_shape(&capped);
+    assert!(max_children <= 4);
+
+    // the cap is persisted in the catalog
+    drop(capped);
+    crash();
+    let capped_rc = BTreeTable::open("capped").unwrap();
+    assert_eq!(capped_rc.rl().get_fan_out(), 4);
+}
+
+#[test]
+fn test_page_ids() {
+    setup();
+
+    let table_rc = new_empty_btree_table("page_ids", 2);
+    let table = table_rc.rl();
+    insert_tuples(&table, leaf_records_cap() * 10);
+
+    let tx = Transaction::new();
+    let pids = table.page_ids(&tx).unwrap();
+
+    // `pages_count` counts the header pages but not the root pointer page
+    let headers = pids
+        .iter()
+        .filter(|pid| pid.get_category() == PageCategory::Header)
+        .count();
+    let root_pointers = pids
+        .iter()
+        .filter(|pid| pid.get_category() == PageCategory::RootPointer)
+        .count();
+    assert!(headers >= 1);
+    assert_eq!(root_pointers, 1);
+    assert_eq!(pids.len(), table.pages_count() + root_pointers);
+
+    // every pid reads back a page with the same id
+    for pid in &pids {
+        let read_pid = match pid.get_category() {
+            PageCategory::RootPointer => {
+                BufferPool::get_root_ptr_page(&tx, Permission::ReadOnly, pid)
+                    .unwrap()
+                    .rl()
+                    .get_pid()
+            }
+            PageCategory::Header => BufferPool::get_header_page(&tx, Permission::ReadOnly, pid)
+                .unwrap()
+                .rl()
+                .get_pid(),
+            PageCategory::Internal => BufferPool::get_internal_page(&tx, Permission::ReadOnly, pid)
+                .unwrap()
+                .rl()
+                .get_pid(),
+            PageCategory::Leaf => BufferPool::get_leaf_page(&tx, Permission::ReadOnly, pid)
+                .unwrap()
+                .rl()
+                .get_pid(),
+        };
+        assert_eq!(&read_pid, pid);
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_insert_leaf_cache() {
+    setup();
+
+    let table_rc = new_empty_btree_table("leaf_cache", 2);
+    let table = table_rc.rl();
+
+    // a sorted run in a single transaction, only the inserts following a
+    // split descend from the root
+    let rows = leaf_records_cap() * 10;
+    let descents = table.descent_count();
+    let tx = Transaction::new();
+    for key in 0..rows {
+        insert_row(&table, &tx, key as i64);
+    }
+    tx.commit().unwrap();
+    let descents = table.descent_count() - descents;
+    assert!(
+        descents * 10 <= rows,
+        "descents: {}, rows: {}",
+        descents,
+        rows
+    );
+
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), rows);
+    tx.commit().unwrap();
+    table.check_integrity();
+
+    // keys outside of the range of the cached leaf still find their page
+    let tx = Transaction::new();
+    for key in (0..rows).step_by(7) {
+        insert_row(&table, &tx, key as i64);
+    }
+    tx.commit().unwrap();
+    table.check_integrity();
+    let tx = Transaction::new();
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(7)), 2);
+    tx.commit().unwrap();
+}
+
+/// Insert `rows` tuples with distinct random keys built by `new_cells`, then
+/// check the tree and the lookups on the key field `key_field`.
+fn check_edge_schema<F>(table: &BTreeTable, key_field: usize, rows: usize, new_cells: F)
+where
+    F: Fn(i64) -> Vec<Cell>,
+{
+    let mut rng = rand::thread_rng();
+    let mut keys: Vec<i64> = Vec::new();
+    let tx = Transaction::new();
+    while keys.len() < rows {
+        let key = rng.gen_range(-1_000_000, 1_000_000);
+        if keys.contains(&key) {
+            continue;
+        }
+        let tuple = Tuple::new(&new_cells(key), tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+        keys.push(key);
+    }
+    tx.commit().unwrap();
+    table.check_integrity();
+
+    let tx = Transaction::new();
+
+    // the tree has grown past a single leaf
+    let root_pid = table.get_root_pid(&tx);
+    assert!(matches!(root_pid.get_category(), PageCategory::Internal));
+
+    let scanned: Vec<Cell> = table.iter(&tx).map(|t| t.get_cell(key_field)).collect();
+    keys.sort();
+    let expect: Vec<Cell> = keys.iter().map(|k| Cell::Int64(*k)).collect();
+    assert_eq!(scanned, expect);
+
+    for key in keys.iter().step_by(97) {
+        let found = table.get_by_key(&tx, &Cell::Int64(*key)).unwrap().unwrap();
+        assert_eq!(found.get_cells(), new_cells(*key));
+    }
+
+    let predicate = Predicate::new(key_field, Op::LessThan, &Cell::Int64(0));
+    let count = BTreeTableSearchIterator::new(&tx, table, &predicate)
+        .unwrap()
+        .count();
+    assert_eq!(count, keys.iter().filter(|k| **k < 0).count());
+
+    // delete half of the tuples, the pages are merged back
+    let tuples: Vec<WrappedTuple> = table.iter(&tx).step_by(2).collect();
+    for tuple in &tuples {
+        table.delete_tuple(&tx, tuple).unwrap();
+    }
+    tx.commit().unwrap();
+    table.check_integrity();
+
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), rows - tuples.len());
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_single_column_schema() {
+    setup();
+
+    // the only column is the key
+    let schema = TableSchema::new(vec![Field::new("id", Type::Int64, true)]);
+    let table_rc = Arc::new(RwLock::new(BTreeTable::new("single", None, &schema)));
+    Catalog::add_table(table_rc.clone(), true).unwrap();
+    let table = table_rc.rl();
+    assert_eq!(table.key_field, 0);
+
+    let rows = leaf_records_cap() * 10;
+    check_edge_schema(&table, 0, rows, |key| vec![Cell::Int64(key)]);
+}
+
+#[test]
+fn test_key_last_column() {
+    setup();
+
+    let schema = TableSchema::new(vec![
+        Field::new("value", Type::Int64, false),
+        Field::new("id", Type::Int64, true),
+    ]);
+    let table_rc = Arc::new(RwLock::new(BTreeTable::new("key_last", None, &schema)));
+    Catalog::add_table(table_rc.clone(), true).unwrap();
+    let table = table_rc.rl();
+    assert_eq!(table.key_field, 1);
+
+    let rows = leaf_records_cap() * 10;
+    check_edge_schema(&table, 1, rows, |key| {
+        vec![Cell::Int64(key.wrapping_mul(7)), Cell::Int64(key)]
+    });
+}
+
+#[test]
+fn test_tree_too_deep() {
+    setup();
+
+    let table_rc = new_empty_btree_table("cycle", 2);
+    let table = table_rc.rl();
+    insert_tuples(&table, leaf_records_cap() * 3);
+
+    // corrupt the tree: the right-most child of the root is the root itself
+    let root_rc = get_internal_page(&table, 0, 0);
+    let root_pid = root_rc.rl().get_pid();
+    {
+        let mut root = root_rc.wl();
+        let last = BTreeInternalPageIterator::new(&root).next_back().unwrap();
+        let mut entry = Entry::new(&last.get_key(), &last.get_left_child(), &root_pid);
+        entry.set_record_id(last.get_record_id());
+        root.update_entry(&entry);
+    }
+
+    // the descent stops with an error instead of overflowing the stack
+    let tx = Transaction::new();
+    let err = table.get_last_tuple(&tx, &root_pid).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::TreeTooDeep);
+    let err = table
+        .get_last_page(&tx, Permission::ReadOnly)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::TreeTooDeep);
+    let err = BTreeTableIterator::new(&tx, &table).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::TreeTooDeep);
+    tx.abort().unwrap();
+
+    // the integrity check reports the cycle as well
+    let err = table.try_check_integrity().unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+}
+
+#[test]
+fn test_tree_too_deep_multi_level() {
+    setup();
+
+    // a small fan-out gives a high tree for a few pages
+    let table_rc = new_empty_btree_table("cycle_multi_level", 2);
+    table_rc.wl().set_max_fan_out(Some(4)).unwrap();
+    let table = table_rc.rl();
+    let rows = leaf_records_cap() * 20;
+    insert_tuples(&table, rows);
+    let (depth, _) = tree_shape(&table);
+    assert!(depth >= 5);
+
+    // the descents of a sound tree are not stopped
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), rows);
+    tx.commit().unwrap();
+    table.check_integrity();
+
+    // corrupt the tree: the right-most child of the root is the root itself
+    let root_rc = get_internal_page(&table, 0, 0);
+    let root_pid = root_rc.rl().get_pid();
+    {
+        let mut root = root_rc.wl();
+        let last = BTreeInternalPageIterator::new(&root).next_back().unwrap();
+        let mut entry = Entry::new(&last.get_key(), &last.get_left_child(), &root_pid);
+        entry.set_record_id(last.get_record_id());
+        root.update_entry(&entry);
+    }
+
+    // the cycle is cut a few levels below log2 of the pages (a fan-out of 4
+    // leaves 2 children at least in each page), not after as many levels as
+    // the table has pages
+    let tx = Transaction::new();
+    let err = table.get_last_tuple(&tx, &root_pid).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::TreeTooDeep);
+    tx.abort().unwrap();
+    let reached: usize = err
+        .to_string()
+        .split("at depth ")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .unwrap()
+        .parse()
+        .unwrap();
+    let pages = table.pages_count();
+    assert!(reached <= pages.ilog2() as usize + 4);
+    assert!(reached < pages);
+}
+
+#[test]
+fn test_empty_internal_page() {
+    setup();
+
+    let table_rc = new_empty_btree_table("malformed", 2);
+    let table = table_rc.rl();
+    insert_tuples(&table, leaf_records_cap() * 3);
+
+    // corrupt the tree: remove all entries of the root
+    let root_rc = get_internal_page(&table, 0, 0);
+    let root_pid = root_rc.rl().get_pid();
+    {
+        let mut root = root_rc.wl();
+        let entries: Vec<Entry> = BTreeInternalPageIterator::new(&root).collect();
+        for e in entries {
+            root.delete_key_and_right_child(e.get_record_id());
+        }
+        assert_eq!(root.entries_count(), 0);
+    }
+
+    // the descents report the corruption instead of aborting
+    let tx = Transaction::new();
+    let err = table.get_last_tuple(&tx, &root_pid).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    let err = table.latch_free_scan(&tx).try_next().unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    let err = BTreeTableIterator::new(&tx, &table).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    let predicate = Predicate::new(table.key_field, Op::Equals, &Cell::Int64(7));
+    let err = BTreeTableSearchIterator::new(&tx, &table, &predicate)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    let err = table.get_by_key(&tx, &Cell::Int64(7)).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    tx.abort().unwrap();
+
+    let err = table.try_check_integrity().unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+}
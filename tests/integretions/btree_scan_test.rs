@@ -1,10 +1,31 @@
+use std::{
+    cmp::Ordering,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Bound,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, RwLock},
+    thread,
+};
+
 use rand::Rng;
 use small_db::{
-    btree::table::BTreeTableIterator, storage::tuple::Cell, transaction::Transaction,
-    utils::HandyRwLock,
+    btree::{
+        buffer_pool::BufferPool,
+        page::BTreePage,
+        table::{BTreeTableIterator, KeyComparator, MergedScan, OnMissing},
+    },
+    common::Catalog,
+    sql::executor::stream::{IndexSeekStream, Stream},
+    storage::tuple::{Cell, Tuple, WrappedTuple},
+    transaction::{Permission, Transaction},
+    utils::{CancellationToken, HandyRwLock},
+    BTreeTable, Database, SmallErrorKind, TableSchema,
 };
 
-use crate::test_utils::{new_random_btree_table, setup, TreeLayout};
+use crate::test_utils::{
+    crash, get_leaf_page, insert_row, leaf_records_cap, new_empty_btree_table,
+    new_random_btree_table, setup, TreeLayout,
+};
 
 fn run(rows: usize, columns: usize) {
     setup();
@@ -24,7 +45,7 @@ fn run(rows: usize, columns: usize) {
         TreeLayout::Naturally,
     );
     let table = table_rc.rl();
-    let mut it = BTreeTableIterator::new(&tx, &table);
+    let mut it = BTreeTableIterator::new(&tx, &table).unwrap();
     validate_scan(&mut it, &int_tuples);
 }
 
@@ -49,3 +70,948 @@ fn test_scan() {
         }
     }
 }
+
+#[test]
+fn test_scan_column() {
+    setup();
+
+    let columns = 3;
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(
+        columns,
+        1000,
+        Some(&mut int_tuples),
+        0,
+        TreeLayout::Naturally,
+    );
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    for col in 0..columns {
+        let expect: i128 = int_tuples
+            .iter()
+            .map(|row| row[col].get_int64().unwrap() as i128)
+            .sum();
+        let actual: i128 = table.scan_column::<i64>(&tx, col).map(|v| v as i128).sum();
+        assert_eq!(expect, actual);
+    }
+    tx.commit().unwrap();
+
+    // the cells are decoded from the disk, only the first leaf page is
+    // loaded into the buffer pool (by the descent)
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+    for col in 0..columns {
+        Database::mut_buffer_pool().clear();
+        let tx = Transaction::new();
+        let expect: Vec<i64> = table
+            .iter(&tx)
+            .map(|t| t.get_cell(col).get_int64().unwrap())
+            .collect();
+        Database::mut_buffer_pool().clear();
+        let actual: Vec<i64> = table.scan_column::<i64>(&tx, col).collect();
+        assert_eq!(expect, actual);
+        assert_eq!(Database::buffer_pool().leaf_buffer.len(), 1);
+        tx.commit().unwrap();
+    }
+}
+
+#[test]
+fn test_scan_cancel() {
+    setup();
+
+    let rows = 10000;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+
+    let token = CancellationToken::new();
+    let (started_sender, started_receiver) = mpsc::channel();
+    let (cancelled_sender, cancelled_receiver) = mpsc::channel();
+
+    let scan_token = token.clone();
+    let scan_table_rc = table_rc.clone();
+    let handle = thread::spawn(move || {
+        let tx = Transaction::new();
+        let mut it = BTreeTableIterator::new(&tx, &scan_table_rc.rl())
+            .unwrap()
+            .with_cancel_token(&scan_token);
+
+        let mut scanned = 0;
+        it.try_next().unwrap().unwrap();
+        scanned += 1;
+
+        // wait for the token to be tripped
+        started_sender.send(()).unwrap();
+        cancelled_receiver.recv().unwrap();
+
+        let result = loop {
+            match it.try_next() {
+                Ok(Some(_)) => scanned += 1,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        (result, scanned)
+    });
+
+    started_receiver.recv().unwrap();
+    token.cancel();
+    cancelled_sender.send(()).unwrap();
+
+    let (result, scanned) = handle.join().unwrap();
+    assert_eq!(result.unwrap_err().kind(), SmallErrorKind::Cancelled);
+    assert!(scanned < rows);
+
+    // a scan started with a tripped token fails on its first tuple
+    let tx = Transaction::new();
+    let mut it = BTreeTableIterator::new(&tx, &table_rc.rl())
+        .unwrap()
+        .with_cancel_token(&token);
+    match it.try_next() {
+        Err(e) => assert_eq!(e.kind(), SmallErrorKind::Cancelled),
+        Ok(_) => panic!("the scan should be cancelled"),
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_index_seek_cancel() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 1000, None, 0, TreeLayout::Naturally);
+
+    let tx = Transaction::new();
+    let token = CancellationToken::new();
+    let mut stream =
+        IndexSeekStream::range(&tx, table_rc.clone(), Bound::Unbounded, Bound::Unbounded)
+            .with_cancel_token(&token);
+    assert!(stream.next_batch().unwrap().is_some());
+
+    // the seek stops at the next batch
+    token.cancel();
+    match stream.next_batch() {
+        Err(e) => assert_eq!(e.kind(), SmallErrorKind::Cancelled),
+        Ok(_) => panic!("the cancelled seek should fail"),
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_range_scan() {
+    setup();
+
+    let rows = 2000;
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, rows, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let lower = int_tuples[100][0].clone();
+    let upper = int_tuples[1500][0].clone();
+    let expect: Vec<Cell> = int_tuples
+        .iter()
+        .map(|row| row[0].clone())
+        .filter(|key| key >= &lower && key <= &upper)
+        .collect();
+
+    let tx = Transaction::new();
+
+    // consume the range from the front
+    let forward: Vec<Cell> = table
+        .range(
+            &tx,
+            Bound::Included(lower.clone()),
+            Bound::Included(upper.clone()),
+        )
+        .unwrap()
+        .map(|t| t.get_cell(0))
+        .collect();
+    assert_eq!(forward, expect);
+
+    // consume the range from the back
+    let mut backward: Vec<Cell> = table
+        .range(
+            &tx,
+            Bound::Included(lower.clone()),
+            Bound::Included(upper.clone()),
+        )
+        .unwrap()
+        .rev()
+        .map(|t| t.get_cell(0))
+        .collect();
+    backward.reverse();
+    assert_eq!(backward, forward);
+
+    // consume the range from both ends, the cursors should never cross
+    let mut it = table
+        .range(
+            &tx,
+            Bound::Excluded(lower.clone()),
+            Bound::Excluded(upper.clone()),
+        )
+        .unwrap();
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    loop {
+        match it.next() {
+            Some(t) => front.push(t.get_cell(0)),
+            None => break,
+        }
+        match it.next_back() {
+            Some(t) => back.push(t.get_cell(0)),
+            None => break,
+        }
+    }
+    back.reverse();
+    front.extend(back);
+    let expect: Vec<Cell> = expect
+        .into_iter()
+        .filter(|key| key > &lower && key < &upper)
+        .collect();
+    assert_eq!(front, expect);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_get_by_key() {
+    setup();
+
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, 1000, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    // present keys
+    let tx = Transaction::new();
+    for row in int_tuples.iter().step_by(50) {
+        let tuple = table.get_by_key(&tx, &row[0]).unwrap().unwrap();
+        assert_eq!(tuple.get_cell(0), row[0]);
+    }
+    tx.commit().unwrap();
+
+    // absent key, the smallest key minus one is always absent
+    let tx = Transaction::new();
+    let min_key = int_tuples[0][0].get_int64().unwrap();
+    if min_key > i64::MIN {
+        assert!(table
+            .get_by_key(&tx, &Cell::Int64(min_key - 1))
+            .unwrap()
+            .is_none());
+    }
+    tx.commit().unwrap();
+
+    // duplicate keys, the first one in the scan order should be returned
+    let tx = Transaction::new();
+    let key = Cell::Int64(123);
+    for i in 0..700 {
+        let tuple = Tuple::new(&vec![key.clone(), Cell::Int64(i)], tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let first = table.iter(&tx).find(|t| t.get_cell(0) == key).unwrap();
+    let tuple = table.get_by_key(&tx, &key).unwrap().unwrap();
+    assert_eq!(tuple.get_cells(), first.get_cells());
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_get_by_key_on_missing() {
+    setup();
+
+    let table_rc = new_empty_btree_table("on_missing", 2);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 1);
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let present = Cell::Int64(1);
+    let absent = Cell::Int64(2);
+
+    // present key, both modes return the tuple
+    for mode in [OnMissing::Empty, OnMissing::Error] {
+        let tuple = table.get_by_key_with(&tx, &present, mode).unwrap();
+        assert_eq!(tuple.unwrap().get_cell(0), present);
+    }
+
+    // absent key
+    assert!(table
+        .get_by_key_with(&tx, &absent, OnMissing::Empty)
+        .unwrap()
+        .is_none());
+    let err = table
+        .get_by_key_with(&tx, &absent, OnMissing::Error)
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::NotFound);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_min_max_key() {
+    setup();
+
+    let empty_rc = new_empty_btree_table("empty", 2);
+    let tx = Transaction::new();
+    assert!(empty_rc.rl().min_key(&tx).unwrap().is_none());
+    assert!(empty_rc.rl().max_key(&tx).unwrap().is_none());
+    tx.commit().unwrap();
+
+    let table_rc = new_random_btree_table(2, 1000, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let tuples: Vec<WrappedTuple> = table.iter(&tx).collect();
+    assert_eq!(
+        table.min_key(&tx).unwrap(),
+        Some(tuples.first().unwrap().get_cell(0))
+    );
+    assert_eq!(
+        table.max_key(&tx).unwrap(),
+        Some(tuples.last().unwrap().get_cell(0))
+    );
+    tx.commit().unwrap();
+
+    // the deleted boundary tuples are skipped, the tuples are read again
+    // after each delete since a merge may move them
+    let tx = Transaction::new();
+    table.delete_tuple(&tx, tuples.first().unwrap()).unwrap();
+    tx.commit().unwrap();
+    let tx = Transaction::new();
+    let last = table.iter(&tx).last().unwrap();
+    table.delete_tuple(&tx, &last).unwrap();
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let tuples: Vec<WrappedTuple> = table.iter(&tx).collect();
+    assert_eq!(
+        table.min_key(&tx).unwrap(),
+        Some(tuples.first().unwrap().get_cell(0))
+    );
+    assert_eq!(
+        table.max_key(&tx).unwrap(),
+        Some(tuples.last().unwrap().get_cell(0))
+    );
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_leaf_find_slot() {
+    setup();
+
+    // a single full leaf
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(
+        2,
+        leaf_records_cap(),
+        Some(&mut int_tuples),
+        0,
+        TreeLayout::Naturally,
+    );
+    let table = table_rc.rl();
+
+    let leaf_rc = get_leaf_page(&table, 0, 0);
+    let leaf = leaf_rc.rl();
+    assert!(leaf.is_full());
+
+    // present keys
+    for row in &int_tuples {
+        let slot = leaf.find_slot(&row[0]).unwrap();
+        let tuple = leaf.iter().find(|t| t.get_slot_number() == slot).unwrap();
+        assert_eq!(tuple.get_cell(0), row[0]);
+    }
+
+    // absent keys, between and around the present ones
+    let keys: Vec<i64> = int_tuples
+        .iter()
+        .map(|row| row[0].get_int64().unwrap())
+        .collect();
+    for key in &keys {
+        for absent in [key.wrapping_sub(1), key.wrapping_add(1)].iter() {
+            if !keys.contains(absent) {
+                assert_eq!(leaf.find_slot(&Cell::Int64(*absent)), None);
+            }
+        }
+    }
+    drop(leaf);
+
+    let tx = Transaction::new();
+    assert!(table.contains_key(&tx, &int_tuples[0][0]).unwrap());
+    tx.commit().unwrap();
+}
+
+/// The binary search of a leaf skips the slots freed by deletions, and finds
+/// the tuples inserted in the slots freed.
+#[test]
+fn test_leaf_find_slot_deleted() {
+    setup();
+
+    // a single full leaf
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(
+        2,
+        leaf_records_cap(),
+        Some(&mut int_tuples),
+        0,
+        TreeLayout::Naturally,
+    );
+    let table = table_rc.rl();
+
+    // delete every other tuple, the deleted tuples are invisible before
+    // their slots are freed
+    let tx = Transaction::new();
+    for row in int_tuples.iter().step_by(2) {
+        let tuple = table.get_by_key(&tx, &row[0]).unwrap().unwrap();
+        table.delete_tuple(&tx, &tuple).unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    for (i, row) in int_tuples.iter().enumerate() {
+        assert_eq!(table.contains_key(&tx, &row[0]).unwrap(), i % 2 == 1);
+    }
+    tx.commit().unwrap();
+
+    table.delete_invisible_tuples().unwrap();
+    let leaf_rc = get_leaf_page(&table, 0, 0);
+    {
+        let leaf = leaf_rc.rl();
+        assert_eq!(leaf.empty_slots_count(), (int_tuples.len() + 1) / 2);
+        for (i, row) in int_tuples.iter().enumerate() {
+            match leaf.find_slot(&row[0]) {
+                Some(slot) => {
+                    assert_eq!(i % 2, 1);
+                    let tuple = leaf.iter().find(|t| t.get_slot_number() == slot).unwrap();
+                    assert_eq!(tuple.get_cell(0), row[0]);
+                }
+                None => assert_eq!(i % 2, 0),
+            }
+        }
+    }
+
+    // the deleted keys are inserted back into the freed slots
+    let tx = Transaction::new();
+    for row in int_tuples.iter().step_by(2) {
+        table
+            .insert_tuple(&tx, &Tuple::new(row, tx.get_id()))
+            .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let leaf = leaf_rc.rl();
+    assert_eq!(leaf.empty_slots_count(), 0);
+    for row in &int_tuples {
+        assert!(leaf.find_slot(&row[0]).is_some());
+    }
+    drop(leaf);
+
+    let tx = Transaction::new();
+    for row in &int_tuples {
+        assert!(table.contains_key(&tx, &row[0]).unwrap());
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_bloom_filter() {
+    setup();
+
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, 1000, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    table.enable_bloom_filter(&tx, 100_000, 4).unwrap();
+    for row in &int_tuples {
+        assert!(table.contains_key(&tx, &row[0]).unwrap());
+    }
+    tx.commit().unwrap();
+
+    // the keys inserted after the build are added to the filter
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 0);
+    assert!(table.contains_key(&tx, &Cell::Int64(0)).unwrap());
+    tx.commit().unwrap();
+
+    // the absent keys don't read any page
+    let keys: Vec<Cell> = int_tuples.iter().map(|row| row[0].clone()).collect();
+    Database::mut_buffer_pool().clear();
+    let misses = Database::buffer_pool().miss_count();
+    let tx = Transaction::new();
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+        let key = Cell::Int64(rng.gen_range(1, i64::MAX));
+        if !keys.contains(&key) {
+            assert!(table.get_by_key(&tx, &key).unwrap().is_none());
+        }
+    }
+    tx.commit().unwrap();
+    assert_eq!(Database::buffer_pool().miss_count(), misses);
+}
+
+/// Look up `key` after the buffer pool is emptied, and return the number of
+/// pages read by the lookup.
+fn lookup_reads(table: &BTreeTable, key: &Cell) -> usize {
+    Database::mut_buffer_pool().clear();
+    let misses = Database::buffer_pool().miss_count();
+    let tx = Transaction::new();
+    assert!(table.get_by_key(&tx, key).unwrap().is_none());
+    tx.commit().unwrap();
+    Database::buffer_pool().miss_count() - misses
+}
+
+#[test]
+fn test_bloom_filter_delete() {
+    setup();
+
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, 1000, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    table.enable_bloom_filter(&tx, 100_000, 4).unwrap();
+    tx.commit().unwrap();
+
+    let key = int_tuples[0][0].clone();
+    let tx = Transaction::new();
+    let tuple = table.get_by_key(&tx, &key).unwrap().unwrap();
+    table.delete_tuple(&tx, &tuple).unwrap();
+    tx.commit().unwrap();
+
+    // the tuple is still on the page, the key stays in the filter
+    assert!(lookup_reads(&table, &key) > 0);
+
+    // the key is removed from the filter with the tuple
+    table.delete_invisible_tuples().unwrap();
+    assert_eq!(lookup_reads(&table, &key), 0);
+
+    // the other keys are kept
+    let tx = Transaction::new();
+    for row in &int_tuples[1..] {
+        assert!(table.contains_key(&tx, &row[0]).unwrap());
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_bloom_filter_reopen() {
+    setup();
+
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, 1000, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table_name = table_rc.rl().name.clone();
+
+    let tx = Transaction::new();
+    table_rc.rl().enable_bloom_filter(&tx, 100_000, 4).unwrap();
+    tx.commit().unwrap();
+
+    // the filter is built again when the table is loaded
+    crash();
+    let table_rc = BTreeTable::open(&table_name).unwrap();
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    for row in &int_tuples {
+        assert!(table.contains_key(&tx, &row[0]).unwrap());
+    }
+    tx.commit().unwrap();
+
+    let keys: Vec<Cell> = int_tuples.iter().map(|row| row[0].clone()).collect();
+    let mut rng = rand::thread_rng();
+    for _ in 0..10 {
+        let key = Cell::Int64(rng.gen_range(1, i64::MAX));
+        if !keys.contains(&key) {
+            assert_eq!(lookup_reads(&table, &key), 0);
+        }
+    }
+
+    // the filter is dropped for good once disabled
+    table.disable_bloom_filter().unwrap();
+    drop(table);
+    crash();
+    let table_rc = BTreeTable::open(&table_name).unwrap();
+    assert!(lookup_reads(&table_rc.rl(), &Cell::Int64(0)) > 0);
+}
+
+#[test]
+fn test_bloom_filter_rebuild() {
+    setup();
+
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, 1000, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    table.enable_bloom_filter(&tx, 100_000, 4).unwrap();
+    tx.commit().unwrap();
+
+    let key = int_tuples[0][0].clone();
+    let tx = Transaction::new();
+    let tuple = table.get_by_key(&tx, &key).unwrap().unwrap();
+    table.delete_tuple(&tx, &tuple).unwrap();
+    tx.commit().unwrap();
+
+    // the rebuild drops the deleted tuple, and its key from the filter
+    let tx = Transaction::new();
+    assert_eq!(table.rebuild_from_leaves(&tx).unwrap(), 999);
+    tx.commit().unwrap();
+    assert_eq!(lookup_reads(&table, &key), 0);
+
+    let tx = Transaction::new();
+    for row in &int_tuples[1..] {
+        assert!(table.contains_key(&tx, &row[0]).unwrap());
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_scan_batches() {
+    setup();
+
+    let rows = 1050;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let expect: Vec<Vec<Cell>> = table.iter(&tx).map(|t| t.get_cells()).collect();
+
+    let batch_size = 100;
+    let batches: Vec<Vec<WrappedTuple>> = table.scan_batches(&tx, batch_size).collect();
+    assert_eq!(batches.len(), 11);
+    for (i, batch) in batches.iter().enumerate() {
+        if i == batches.len() - 1 {
+            assert_eq!(batch.len(), rows % batch_size);
+        } else {
+            assert_eq!(batch.len(), batch_size);
+        }
+    }
+
+    let actual: Vec<Vec<Cell>> = batches
+        .into_iter()
+        .flatten()
+        .map(|t| t.get_cells())
+        .collect();
+    assert_eq!(actual, expect);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_rebuild_from_leaves() {
+    setup();
+
+    let rows = 10 * leaf_records_cap();
+    let mut expect = Vec::new();
+    let table_rc = new_random_btree_table(2, rows, Some(&mut expect), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    // overwrite all internal pages with garbage
+    let page_size = BufferPool::get_page_size();
+    let mut corrupted = 0;
+    {
+        let mut file = table.get_file();
+        for page_index in 0..file.metadata().unwrap().len() / page_size as u64 {
+            let offset = page_index * page_size as u64;
+            let mut tag = [0u8; 4];
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.read_exact(&mut tag).unwrap();
+            if tag == [0, 0, 0, 1] {
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.write_all(&vec![0xff; page_size]).unwrap();
+                corrupted += 1;
+            }
+        }
+    }
+    assert!(corrupted > 0);
+    table.reload();
+
+    let tx = Transaction::new();
+    assert_eq!(table.rebuild_from_leaves(&tx).unwrap(), rows);
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let actual: Vec<Vec<Cell>> = table.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(actual, expect);
+    tx.commit().unwrap();
+
+    table.check_integrity();
+}
+
+#[test]
+fn test_merged_scan() {
+    setup();
+
+    // three shards with overlapping key ranges
+    let ranges = [(0, 200), (100, 300), (50, 250)];
+    let mut tables = Vec::new();
+    let mut expect = Vec::new();
+    let tx = Transaction::new();
+    for (i, (start, end)) in ranges.iter().enumerate() {
+        let table_rc = new_empty_btree_table(&format!("shard_{}", i), 2);
+        for key in *start..*end {
+            insert_row(&table_rc.rl(), &tx, key);
+            expect.push(key);
+        }
+        tables.push(table_rc);
+    }
+    tx.commit().unwrap();
+    expect.sort();
+
+    let guards: Vec<_> = tables.iter().map(|t| t.rl()).collect();
+    let shards: Vec<&BTreeTable> = guards.iter().map(|g| &**g).collect();
+
+    let tx = Transaction::new();
+    let actual: Vec<i64> = MergedScan::new(&tx, &shards)
+        .unwrap()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    assert_eq!(actual, expect);
+
+    // the tables must share the same schema, and there must be one at least
+    let other_rc = new_empty_btree_table("shard_other", 3);
+    let other = other_rc.rl();
+    let err = MergedScan::new(&tx, &[shards[0], &other]).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+    assert!(MergedScan::new(&tx, &[]).is_err());
+    tx.commit().unwrap();
+
+    // the keys are merged in the order of the comparator of the tables
+    let tx = Transaction::new();
+    let mut reversed = Vec::new();
+    for (i, (start, end)) in ranges.iter().enumerate() {
+        let table_rc = Arc::new(RwLock::new(BTreeTable::new_with_comparator(
+            &format!("reversed_shard_{}", i),
+            None,
+            &TableSchema::small_int_schema(2),
+            Arc::new(Reversed),
+        )));
+        Catalog::add_table(table_rc.clone(), true).unwrap();
+        for key in *start..*end {
+            insert_row(&table_rc.rl(), &tx, key);
+        }
+        reversed.push(table_rc);
+    }
+    tx.commit().unwrap();
+
+    let guards: Vec<_> = reversed.iter().map(|t| t.rl()).collect();
+    let shards: Vec<&BTreeTable> = guards.iter().map(|g| &**g).collect();
+    let tx = Transaction::new();
+    let actual: Vec<i64> = MergedScan::new(&tx, &shards)
+        .unwrap()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    tx.commit().unwrap();
+    expect.reverse();
+    assert_eq!(actual, expect);
+}
+
+struct Reversed;
+
+impl KeyComparator for Reversed {
+    fn compare(&self, a: &Cell, b: &Cell) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+#[test]
+fn test_sibling_cycle() {
+    setup();
+
+    let rows = 2 * leaf_records_cap();
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::EvenlyDistributed);
+    let table = table_rc.rl();
+
+    // make the right sibling of the last leaf point back to the first one
+    let first_rc = get_leaf_page(&table, 1, 0);
+    let first_pid = first_rc.rl().get_pid();
+    let last_pid = first_rc.rl().get_right_pid().unwrap();
+    let tx = Transaction::new();
+    let last_rc = BufferPool::get_leaf_page(&tx, Permission::ReadOnly, &last_pid).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(last_rc.rl().get_right_pid(), None);
+    last_rc.wl().set_right_pid(Some(first_pid));
+
+    let tx = Transaction::new();
+    let mut it = BTreeTableIterator::new(&tx, &table).unwrap();
+    let mut yielded = 0;
+    let err = loop {
+        match it.try_next() {
+            Ok(Some(_)) => yielded += 1,
+            Ok(None) => panic!("the scan ends without detecting the cycle"),
+            Err(e) => break e,
+        }
+        assert!(yielded <= rows);
+    };
+    assert_eq!(err.kind(), SmallErrorKind::CorruptSiblingChain);
+    assert_eq!(yielded, rows);
+
+    // the plain iterator panics at the cycle instead of looping forever
+    let mut it = BTreeTableIterator::new(&tx, &table).unwrap();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| it.by_ref().count()));
+    assert!(result.is_err());
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_scan_filter() {
+    setup();
+
+    let rows = 3 * leaf_records_cap();
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let is_multiple_of_3 = |cell: &Cell| match cell {
+        Cell::Int64(v) => v % 3 == 0,
+        _ => unreachable!(),
+    };
+
+    let tx = Transaction::new();
+    let actual: Vec<Vec<Cell>> = table
+        .scan_filter(&tx, |t| is_multiple_of_3(&t.get_cell(1)))
+        .map(|t| t.get_cells())
+        .collect();
+    let expect: Vec<Vec<Cell>> = table
+        .iter(&tx)
+        .filter(|t| is_multiple_of_3(&t.get_cell(1)))
+        .map(|t| t.get_cells())
+        .collect();
+    assert!(!expect.is_empty());
+    assert_eq!(actual, expect);
+
+    // the predicate runs once per visible tuple
+    let mut calls = 0;
+    let count = table
+        .scan_filter(&tx, |_| {
+            calls += 1;
+            false
+        })
+        .count();
+    assert_eq!(count, 0);
+    assert_eq!(calls, rows);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_diff_tables() {
+    setup();
+
+    let table_rc = new_empty_btree_table("origin", 2);
+    let copy_rc = new_empty_btree_table("copy", 2);
+    let table = table_rc.rl();
+    let copy = copy_rc.rl();
+
+    let tx = Transaction::new();
+    for key in 0..1000 {
+        let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(key * 2)], tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+        copy.insert_tuple(&tx, &tuple).unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    assert!(table.diff(&tx, &copy).unwrap().is_empty());
+
+    // delete some rows from the copy, update others and insert new ones
+    let deleted = [0, 500, 999];
+    let updated = [10, 11, 700];
+    for tuple in copy.iter(&tx).collect::<Vec<_>>() {
+        let key = tuple.get_cell(0).get_int64().unwrap();
+        if deleted.contains(&key) || updated.contains(&key) {
+            copy.delete_tuple(&tx, &tuple).unwrap();
+        }
+    }
+    for key in updated {
+        let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(-1)], tx.get_id());
+        copy.insert_tuple(&tx, &tuple).unwrap();
+    }
+    for key in [-5, 2000] {
+        let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(key)], tx.get_id());
+        copy.insert_tuple(&tx, &tuple).unwrap();
+    }
+
+    let diff = table.diff(&tx, &copy).unwrap();
+    let keys = |tuples: &[Tuple]| -> Vec<i64> {
+        tuples
+            .iter()
+            .map(|t| t.get_cell(0).get_int64().unwrap())
+            .collect()
+    };
+    assert_eq!(keys(&diff.only_in_self), deleted);
+    assert_eq!(keys(&diff.only_in_other), vec![-5, 2000]);
+    assert_eq!(diff.differing.len(), updated.len());
+    for ((origin, changed), key) in diff.differing.iter().zip(updated) {
+        assert_eq!(
+            origin.get_cells(),
+            vec![Cell::Int64(key), Cell::Int64(key * 2)]
+        );
+        assert_eq!(changed.get_cells(), vec![Cell::Int64(key), Cell::Int64(-1)]);
+    }
+
+    // the diff the other way round swaps the sides
+    let reverse = copy.diff(&tx, &table).unwrap();
+    assert_eq!(reverse.only_in_self, diff.only_in_other);
+    assert_eq!(reverse.only_in_other, diff.only_in_self);
+
+    // tables with different schemas can't be compared
+    let other_rc = new_empty_btree_table("other", 3);
+    let err = table.diff(&tx, &other_rc.rl()).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+
+    // a scan failing partway fails the diff instead of truncating a side
+    let first_pid = copy
+        .get_first_page(&tx, Permission::ReadOnly)
+        .unwrap()
+        .rl()
+        .get_pid();
+    let last_rc = copy.get_last_page(&tx, Permission::ReadOnly).unwrap();
+    assert_ne!(last_rc.rl().get_pid(), first_pid);
+    last_rc.wl().set_right_pid(Some(first_pid));
+    let err = table.diff(&tx, &copy).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::CorruptSiblingChain);
+    last_rc.wl().set_right_pid(None);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_keys() {
+    setup();
+
+    let empty_rc = new_empty_btree_table("empty", 2);
+    let tx = Transaction::new();
+    assert_eq!(empty_rc.rl().keys(&tx).count(), 0);
+    tx.commit().unwrap();
+
+    let table_rc = new_empty_btree_table("keys", 2);
+    let table = table_rc.rl();
+
+    // distinct keys spanning several leaf pages, inserted in random order
+    let mut rng = rand::thread_rng();
+    let mut inserted = std::collections::BTreeSet::new();
+    let tx = Transaction::new();
+    while inserted.len() < leaf_records_cap() * 3 {
+        let key = rng.gen_range(0, 100_000);
+        if inserted.insert(key) {
+            insert_row(&table, &tx, key);
+        }
+    }
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let keys: Vec<Cell> = table.keys(&tx).map(|key| key.unwrap()).collect();
+    let expect: Vec<Cell> = inserted.iter().map(|key| Cell::Int64(*key)).collect();
+    assert_eq!(keys, expect);
+    tx.commit().unwrap();
+
+    // the keys are decoded from the disk, only the first leaf page is
+    // loaded into the buffer pool (by the descent)
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+    Database::mut_buffer_pool().clear();
+    let tx = Transaction::new();
+    let keys: Vec<Cell> = table.keys(&tx).map(|key| key.unwrap()).collect();
+    assert_eq!(keys, expect);
+    assert_eq!(Database::buffer_pool().leaf_buffer.len(), 1);
+    tx.commit().unwrap();
+}
@@ -0,0 +1,303 @@
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use small_db::{
+    btree::{
+        buffer_pool::BufferPool,
+        page::{BTreeBasePage, BTreeLeafPage, BTreePage, BTreePageID, PageCategory},
+    },
+    storage::tuple::Cell,
+    transaction::{Permission, Transaction},
+    utils::HandyRwLock,
+    BTreeTable, Database, SmallErrorKind,
+};
+
+use crate::test_utils::{
+    crash, get_leaf_page, insert_row, insert_tuples, leaf_records_cap, new_empty_btree_table,
+    new_random_btree_table, search_key, setup, TreeLayout,
+};
+
+#[test]
+fn test_memory_usage() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 5000, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    Database::mut_buffer_pool().clear();
+    assert_eq!(Database::mut_buffer_pool().memory_usage(), 0);
+
+    // load all pages of the table
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), 5000);
+    tx.commit().unwrap();
+
+    let bp = Database::mut_buffer_pool();
+    let pages_count = bp.cached_pages_count();
+    assert!(pages_count > 1);
+
+    // each page costs a full page plus a small overhead
+    let usage = bp.memory_usage();
+    let page_size = BufferPool::get_page_size();
+    assert!(usage >= pages_count * page_size);
+    assert!(usage <= pages_count * (page_size + 128));
+}
+
+#[test]
+fn test_short_page() {
+    setup();
+
+    let table_rc = new_empty_btree_table("short", 2);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    for key in 0..10 {
+        insert_row(&table, &tx, key);
+    }
+    tx.commit().unwrap();
+
+    // the first leaf page follows the root pointer page, cut the file in
+    // the middle of it
+    let page_size = BufferPool::get_page_size();
+    table
+        .get_file()
+        .set_len((page_size + page_size / 2) as u64)
+        .unwrap();
+    table.reload();
+
+    let tx = Transaction::new();
+    let pid = BTreePageID::new(PageCategory::Leaf, table.get_id(), 1);
+    match BufferPool::get_leaf_page(&tx, Permission::ReadOnly, &pid) {
+        Ok(_) => panic!("a truncated page should not be loaded"),
+        Err(e) => assert_eq!(e.kind(), SmallErrorKind::ShortPage),
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_pin_page() {
+    setup();
+
+    let mut rows = Vec::new();
+    let table_rc = new_random_btree_table(2, 5000, Some(&mut rows), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+    rows.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+
+    Database::mut_buffer_pool().clear();
+    let pinned_pid = get_leaf_page(&table, 1, 0).rl().get_pid();
+    Database::mut_buffer_pool().pin(&pinned_pid);
+
+    let capacity = 4;
+    Database::buffer_pool().set_capacity(Some(capacity));
+
+    // look up keys far away from the pinned page, each lookup loads pages
+    // which have been evicted by the previous ones
+    let search_upper_half = || {
+        for row in &rows[rows.len() / 2..] {
+            let tx = Transaction::new();
+            assert!(table.get_by_key(&tx, &row[0]).unwrap().is_some());
+            tx.commit().unwrap();
+        }
+    };
+
+    let miss_count = Database::buffer_pool().miss_count();
+    search_upper_half();
+    {
+        let bp = Database::buffer_pool();
+        assert!(bp.miss_count() > miss_count + capacity);
+        assert!(bp.cached_pages_count() <= capacity + 1);
+        assert!(bp.is_cached(&pinned_pid));
+    }
+
+    // once unpinned, the page is the least recently used one
+    Database::mut_buffer_pool().unpin(&pinned_pid);
+    search_upper_half();
+    assert!(!Database::buffer_pool().is_cached(&pinned_pid));
+}
+
+#[test]
+fn test_read_ahead() {
+    setup();
+
+    let rows = 5000;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+    Database::mut_buffer_pool().clear();
+
+    // a long sequential scan grows the window
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), rows);
+    let sequential = tx.read_ahead();
+    tx.commit().unwrap();
+    assert!(sequential.window > 1);
+    assert!(sequential.pages_prefetched > 0);
+    assert!(sequential.hit_rate() > 0.5);
+
+    // point lookups interleaved with short scans (crossing a single page
+    // boundary) keep it small
+    let first_page_tuples = get_leaf_page(&table, 1, 0).rl().tuples_count();
+    let tx = Transaction::new();
+    for key in 0..50 {
+        table.get_by_key(&tx, &Cell::Int64(key)).unwrap();
+        let scanned = table.iter(&tx).take(first_page_tuples + 1).count();
+        assert_eq!(scanned, first_page_tuples + 1);
+    }
+    let interleaved = tx.read_ahead();
+    tx.commit().unwrap();
+    assert!(interleaved.window <= 2);
+    assert!(interleaved.window < sequential.window);
+}
+
+#[test]
+fn test_duplicate_page() {
+    setup();
+
+    let table_rc = new_empty_btree_table("duplicate", 2);
+    let table = table_rc.rl();
+
+    let pid = BTreePageID::new(PageCategory::Leaf, table.get_id(), 1000);
+    let new_page = || {
+        let page = <BTreeLeafPage as BTreePage>::new(
+            &pid,
+            &BTreeBasePage::empty_page_data(),
+            &table.schema,
+        );
+        Arc::new(RwLock::new(page))
+    };
+
+    let buffer_pool = Database::buffer_pool();
+    let page_rc = new_page();
+    buffer_pool
+        .leaf_buffer
+        .insert(pid, page_rc.clone())
+        .unwrap();
+
+    // inserting the same page again is harmless
+    buffer_pool
+        .leaf_buffer
+        .insert(pid, page_rc.clone())
+        .unwrap();
+
+    // another page under the same pid is caught, the cached page is kept
+    let err = buffer_pool.leaf_buffer.insert(pid, new_page()).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    assert!(Arc::ptr_eq(
+        &buffer_pool.leaf_buffer.get(&pid).unwrap(),
+        &page_rc
+    ));
+
+    // unless the page is replaced on purpose
+    let replacement = new_page();
+    buffer_pool.leaf_buffer.replace(pid, replacement.clone());
+    assert!(Arc::ptr_eq(
+        &buffer_pool.leaf_buffer.get(&pid).unwrap(),
+        &replacement
+    ));
+    buffer_pool.leaf_buffer.remove(&pid);
+}
+
+fn table_path(table: &BTreeTable) -> PathBuf {
+    Database::global()
+        .get_path()
+        .join(format!("{}.table", table.name))
+}
+
+/// Bytes of disk allocated to the table file. The length of the file
+/// doesn't tell the compressed pages apart since every page keeps its place
+/// in the file.
+#[cfg(unix)]
+fn allocated_bytes(table: &BTreeTable) -> u64 {
+    fs::metadata(table_path(table)).unwrap().blocks() * 512
+}
+
+#[test]
+fn test_compressed_table() {
+    setup();
+    // the compressed pages have to span several disk blocks to save any
+    BufferPool::set_page_size(16 * 1024);
+
+    let plain_rc = new_empty_btree_table("plain", 2);
+    let compressed_rc = new_empty_btree_table("compressed", 2);
+    compressed_rc.wl().set_compressed(true).unwrap();
+
+    let rows = leaf_records_cap() * 5;
+    insert_tuples(&plain_rc.rl(), rows);
+    insert_tuples(&compressed_rc.rl(), rows);
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+
+    // the pages hold as many tuples as the uncompressed ones
+    let plain = plain_rc.rl();
+    let compressed = compressed_rc.rl();
+    assert_eq!(compressed.pages_count(), plain.pages_count());
+    let file_len = |table: &BTreeTable| fs::metadata(table_path(table)).unwrap().len();
+    assert_eq!(file_len(&compressed), file_len(&plain));
+    #[cfg(unix)]
+    assert!(allocated_bytes(&compressed) < allocated_bytes(&plain) / 2);
+
+    // read the pages back from disk
+    Database::mut_buffer_pool().clear();
+
+    let tx = Transaction::new();
+    let expect: Vec<Vec<Cell>> = plain.iter(&tx).map(|t| t.get_cells()).collect();
+    let actual: Vec<Vec<Cell>> = compressed.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(actual.len(), rows);
+    assert_eq!(actual, expect);
+    assert_eq!(search_key(&compressed, &tx, &Cell::Int64(42)), 1);
+    tx.commit().unwrap();
+    compressed.check_integrity();
+
+    // the setting is persisted in the catalog
+    drop(plain);
+    drop(compressed);
+    crash();
+    let compressed_rc = BTreeTable::open("compressed").unwrap();
+    assert!(compressed_rc.rl().get_compressed());
+    let plain_rc = BTreeTable::open("plain").unwrap();
+    assert!(!plain_rc.rl().get_compressed());
+}
+
+#[test]
+fn test_corrupted_compressed_page() {
+    setup();
+
+    let table_rc = new_empty_btree_table("corrupted", 2);
+    table_rc.wl().set_compressed(true).unwrap();
+    insert_tuples(&table_rc.rl(), leaf_records_cap() * 2);
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+    Database::mut_buffer_pool().clear();
+
+    // garble the deflated bytes of a leaf page
+    let table = table_rc.rl();
+    let tx = Transaction::new();
+    let pid = table
+        .get_first_page(&tx, Permission::ReadOnly)
+        .unwrap()
+        .rl()
+        .get_pid();
+    tx.commit().unwrap();
+    Database::mut_buffer_pool().clear();
+
+    let path = table_path(&table);
+    let mut bytes = fs::read(&path).unwrap();
+    let start = pid.get_page_index() as usize * BufferPool::get_page_size();
+    // the compressed page token
+    assert_eq!(bytes[start..start + 4], [66, 66, 66, 66]);
+    for b in &mut bytes[start + 8..start + 64] {
+        *b = 0xff;
+    }
+    fs::write(&path, bytes).unwrap();
+
+    let tx = Transaction::new();
+    let err = BufferPool::get_leaf_page(&tx, Permission::ReadOnly, &pid).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+    tx.commit().unwrap();
+}
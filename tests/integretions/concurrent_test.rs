@@ -1,15 +1,28 @@
-use std::thread;
+use std::{
+    sync::{Arc, Barrier},
+    thread,
+    time::Duration,
+};
 
 use small_db::{
-    btree::{buffer_pool::BufferPool, table::BTreeTableSearchIterator},
-    storage::tuple::Tuple,
+    btree::{
+        buffer_pool::{BufferPool, DEFAULT_SHARDS_COUNT},
+        page::BTreePage,
+        table::BTreeTableSearchIterator,
+    },
+    common::Catalog,
+    sql::session::Session,
+    storage::tuple::{Cell, Tuple},
     transaction::{Permission, Transaction},
     types::Pod,
     utils::HandyRwLock,
-    BTreeTable, Op, Predicate,
+    BTreeTable, Database, Op, Predicate, SmallErrorKind, TableSchema,
 };
 
-use crate::test_utils::{insert_random, new_int_tuples, new_random_btree_table, setup, TreeLayout};
+use crate::test_utils::{
+    insert_random, insert_tuples, leaf_records_cap, new_empty_btree_table, new_int_tuples,
+    new_random_btree_table, setup, TreeLayout,
+};
 
 // Delete a tuple from the table.
 fn deleter(table_rc: &Pod<BTreeTable>, r: &crossbeam::channel::Receiver<Tuple>) {
@@ -19,7 +32,7 @@ fn deleter(table_rc: &Pod<BTreeTable>, r: &crossbeam::channel::Receiver<Tuple>)
 
     let tx = Transaction::new();
     let table = table_rc.rl();
-    let mut iter = BTreeTableSearchIterator::new(&tx, &table, &predicate);
+    let mut iter = BTreeTableSearchIterator::new(&tx, &table, &predicate).unwrap();
     let tuple = iter.next().unwrap();
     table.delete_tuple(&tx, &tuple).unwrap();
     tx.commit().unwrap();
@@ -163,7 +176,7 @@ fn test_concurrent() {
         let tx = Transaction::new();
         for tuple in receiver.iter() {
             let predicate = Predicate::new(table.key_field, Op::Equals, &tuple.get_cell(0));
-            let mut it = BTreeTableSearchIterator::new(&tx, &table_rc.rl(), &predicate);
+            let mut it = BTreeTableSearchIterator::new(&tx, &table_rc.rl(), &predicate).unwrap();
             assert!(it.next().is_some());
         }
         tx.commit().unwrap();
@@ -192,7 +205,10 @@ fn test_concurrent_page_access() {
     // timeout
     let read_tx = Transaction::new();
     let page = BufferPool::get_leaf_page(&read_tx, Permission::ReadOnly, &pid);
-    assert!(page.is_err());
+    match page {
+        Ok(_) => panic!("the page is held by another transaction"),
+        Err(e) => assert_eq!(e.kind(), SmallErrorKind::LockTimeout),
+    }
 }
 
 /// Make sure we can handle lots of (1000+) concurrent insert operations.
@@ -287,3 +303,435 @@ fn test_concurrent_delete() {
         assert_eq!(table.tuples_count(), row_count);
     }
 }
+
+#[test]
+fn test_page_guard() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 100, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    let tx = Transaction::new();
+    let root_pid = table.get_root_pid(&tx);
+
+    // borrow of guard start here
+    {
+        let guard = table
+            .pin_leaf(&tx, &root_pid, Permission::ReadOnly)
+            .unwrap();
+        assert_eq!(guard.rl().get_pid(), root_pid);
+        assert_eq!(Database::concurrent_status().hold_page_count(&tx), 1);
+    }
+    // borrow of guard end here
+
+    // the latch is released once the guard is dropped
+    assert_eq!(Database::concurrent_status().hold_page_count(&tx), 0);
+
+    // so another transaction can acquire the page exclusively
+    let other_tx = Transaction::new();
+    let guard = table
+        .pin_leaf(&other_tx, &root_pid, Permission::ReadWrite)
+        .unwrap();
+    drop(guard);
+
+    // the X-latch is kept until the transaction ends
+    assert_eq!(Database::concurrent_status().hold_page_count(&other_tx), 1);
+    other_tx.commit().unwrap();
+
+    tx.commit().unwrap();
+}
+
+/// A lookup through a page guard keeps the latches the transaction held on
+/// the page before.
+#[test]
+fn test_page_guard_keeps_held_latches() {
+    setup();
+
+    let mut int_tuples = Vec::new();
+    let table_rc = new_random_btree_table(2, 100, Some(&mut int_tuples), 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+    let key = int_tuples[0][0].clone();
+
+    for perm in [Permission::ReadOnly, Permission::ReadWrite] {
+        let tx = Transaction::new();
+        let root_pid = table.get_root_pid(&tx);
+        BufferPool::get_leaf_page(&tx, perm, &root_pid).unwrap();
+
+        assert!(table.get_by_key(&tx, &key).unwrap().is_some());
+        assert!(table.min_key(&tx).unwrap().is_some());
+        assert_eq!(table.keys(&tx).count(), 100);
+        assert_eq!(Database::concurrent_status().hold_page_count(&tx), 1);
+
+        tx.commit().unwrap();
+    }
+
+    // the latches acquired by the lookups alone are released
+    let tx = Transaction::new();
+    assert!(table.get_by_key(&tx, &key).unwrap().is_some());
+    assert!(table.max_key(&tx).unwrap().is_some());
+    assert_eq!(Database::concurrent_status().hold_page_count(&tx), 0);
+    tx.commit().unwrap();
+}
+
+/// A DROP TABLE issued while a scan is running on the table waits for the
+/// scan to finish, the scan never sees a partially dropped table.
+#[test]
+fn test_drop_table_during_scan() {
+    setup();
+
+    let rows = 10000;
+    let _table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+
+    let scan = thread::spawn(move || {
+        let mut session = Session::new();
+        let tx = Transaction::new();
+        let result = session.execute(&tx, "SELECT * FROM pg_catalog.test");
+        tx.commit().unwrap();
+        result.map(|r| r.into_rows().unwrap().data.len())
+    });
+
+    // give the scan a head start
+    thread::sleep(Duration::from_millis(10));
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+    session.execute(&tx, "DROP TABLE test").unwrap();
+    tx.commit().unwrap();
+
+    // either the scan completed fully, or the drop came first and the table
+    // is gone
+    match scan.join().unwrap() {
+        Ok(count) => assert_eq!(count, rows),
+        Err(e) => assert!(e.to_string().contains("not found"), "{}", e),
+    }
+    assert!(Database::catalog().search_table("test").is_none());
+}
+
+#[test]
+fn test_sharded_buffer_pool() {
+    BufferPool::set_shards_count(4);
+    setup();
+    assert_eq!(BufferPool::get_shards_count(), 4);
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+
+    // every thread inserts its own key range
+    let threads_count = 8;
+    let keys_per_thread = 100;
+    let mut threads = vec![];
+    for i in 0..threads_count {
+        let local_table = table_rc.clone();
+        let handle = thread::spawn(move || {
+            let start = i * keys_per_thread;
+            for key in start..start + keys_per_thread {
+                let tx = Transaction::new();
+                let tuple = new_int_tuples(key, 2, &tx);
+                local_table.rl().insert_tuple(&tx, &tuple).unwrap();
+                tx.commit().unwrap();
+            }
+        });
+        threads.push(handle);
+    }
+    for handle in threads {
+        handle.join().unwrap();
+    }
+
+    let table = table_rc.rl();
+    table.check_integrity();
+
+    let tx = Transaction::new();
+    let keys: Vec<i64> = table
+        .iter(&tx)
+        .map(|t| match t.get_cell(0) {
+            Cell::Int64(v) => v,
+            _ => unreachable!(),
+        })
+        .collect();
+    tx.commit().unwrap();
+    let expect: Vec<i64> = (0..threads_count * keys_per_thread).collect();
+    assert_eq!(keys, expect);
+
+    BufferPool::set_shards_count(DEFAULT_SHARDS_COUNT);
+}
+
+#[test]
+fn test_concurrent_split() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+
+    // the threads insert interleaved keys of the same range, so they keep
+    // splitting the same leaf pages
+    let threads_count = 8;
+    let keys_per_thread = 300;
+    let mut threads = vec![];
+    for i in 0..threads_count {
+        let local_table = table_rc.clone();
+        let handle = thread::spawn(move || {
+            for j in 0..keys_per_thread {
+                let key = j * threads_count + i;
+                let tx = Transaction::new();
+                let tuple = new_int_tuples(key, 2, &tx);
+                local_table.rl().insert_tuple(&tx, &tuple).unwrap();
+                tx.commit().unwrap();
+            }
+        });
+        threads.push(handle);
+    }
+    for handle in threads {
+        handle.join().unwrap();
+    }
+
+    let table = table_rc.rl();
+    table.check_integrity();
+
+    // both directions of the leaf chain see every key exactly once
+    let tx = Transaction::new();
+    let keys: Vec<i64> = table
+        .iter(&tx)
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    let mut reversed_keys: Vec<i64> = table
+        .iter(&tx)
+        .rev()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    tx.commit().unwrap();
+
+    let expect: Vec<i64> = (0..threads_count * keys_per_thread).collect();
+    assert_eq!(keys, expect);
+    reversed_keys.reverse();
+    assert_eq!(reversed_keys, expect);
+}
+
+#[test]
+fn test_shared_scan() {
+    setup();
+
+    let rows = 5000;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+
+    // the page reads of an independent scan
+    let tx = Transaction::new();
+    assert_eq!(table_rc.rl().iter(&tx).count(), rows);
+    let independent_reads = tx.stats().pages_read;
+    tx.commit().unwrap();
+
+    // the scans are all created before any of them is consumed, so they
+    // share the same pass
+    let scans_count = 4;
+    let barrier = Arc::new(Barrier::new(scans_count));
+    let mut threads = vec![];
+    for _ in 0..scans_count {
+        let local_table = table_rc.clone();
+        let local_barrier = barrier.clone();
+        let handle = thread::spawn(move || {
+            let tx = Transaction::new();
+            let scan = local_table.rl().shared_scan(&tx).unwrap();
+            local_barrier.wait();
+
+            let keys: Vec<Cell> = scan.map(|t| t.get_cell(0)).collect();
+            let pages_read = tx.stats().pages_read;
+            tx.commit().unwrap();
+            (keys, pages_read)
+        });
+        threads.push(handle);
+    }
+
+    let results: Vec<(Vec<Cell>, usize)> = threads.into_iter().map(|h| h.join().unwrap()).collect();
+
+    // every scan sees the complete, identical result
+    let tx = Transaction::new();
+    let expect: Vec<Cell> = table_rc.rl().iter(&tx).map(|t| t.get_cell(0)).collect();
+    tx.commit().unwrap();
+    assert_eq!(expect.len(), rows);
+    for (keys, _) in &results {
+        assert_eq!(keys, &expect);
+    }
+
+    // the leaf pages are read once for all scans
+    let total_reads: usize = results.iter().map(|(_, reads)| reads).sum();
+    assert!(total_reads < scans_count * independent_reads);
+}
+
+#[test]
+fn test_shared_scan_streams_pages() {
+    setup();
+
+    let table_rc = new_empty_btree_table("shared_scan_stream", 2);
+    let rows = leaf_records_cap() * 10;
+    insert_tuples(&table_rc.rl(), rows);
+
+    // move the scan a few leaf pages past the first one
+    let tx = Transaction::new();
+    let mut scan = table_rc.rl().shared_scan(&tx).unwrap();
+    let mut count = 0;
+    for _ in 0..leaf_records_cap() * 3 {
+        scan.try_next().unwrap().unwrap();
+        count += 1;
+    }
+
+    // the pass is done with the first leaf page, a writer changes it while
+    // the scan is running
+    let writer = Transaction::new();
+    let tuple = new_int_tuples(0, 2, &writer);
+    table_rc.rl().insert_tuple(&writer, &tuple).unwrap();
+    writer.commit().unwrap();
+
+    // a scan created now can't join the pass, it starts a new one and sees
+    // the new tuple
+    let late_tx = Transaction::new();
+    let late_scan = table_rc.rl().shared_scan(&late_tx).unwrap();
+    assert_eq!(late_scan.count(), rows + 1);
+    late_tx.commit().unwrap();
+
+    // the first scan read the first page before the write
+    while scan.try_next().unwrap().is_some() {
+        count += 1;
+    }
+    assert_eq!(count, rows);
+    drop(scan);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_concurrent_create_table() {
+    setup();
+
+    let threads_count = 8;
+    let barrier = Arc::new(Barrier::new(threads_count));
+    let mut threads = vec![];
+    for _ in 0..threads_count {
+        let local_barrier = barrier.clone();
+        let handle = thread::spawn(move || {
+            let schema = TableSchema::small_int_schema(2);
+            local_barrier.wait();
+            Catalog::get_or_create_table("shared_table", &schema, true).unwrap()
+        });
+        threads.push(handle);
+    }
+
+    let tables: Vec<Pod<BTreeTable>> = threads.into_iter().map(|h| h.join().unwrap()).collect();
+
+    // every thread gets the same table
+    let table_id = tables[0].rl().get_id();
+    for table_rc in &tables {
+        assert!(Arc::ptr_eq(table_rc, &tables[0]));
+        assert_eq!(table_rc.rl().get_id(), table_id);
+    }
+    let names = Database::catalog().list_tables();
+    assert_eq!(names.iter().filter(|n| *n == "shared_table").count(), 1);
+
+    // the table is initialized once and usable
+    let table = tables[0].rl();
+    let tx = Transaction::new();
+    table.insert_tuple(&tx, &new_int_tuples(1, 2, &tx)).unwrap();
+    tx.commit().unwrap();
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), 1);
+    tx.commit().unwrap();
+
+    // a different schema for an existing table is rejected
+    let err = Catalog::get_or_create_table("shared_table", &TableSchema::small_int_schema(3), true)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+}
+
+/// A latch-free scan keeps going while other threads insert into the pages
+/// it is reading, and returns every tuple present before the scan started.
+#[test]
+fn test_latch_free_scan_during_inserts() {
+    setup();
+
+    let rows = 2000;
+    let table_rc = new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+
+    let tx = Transaction::new();
+    let initial: Vec<Cell> = table_rc.rl().iter(&tx).map(|t| t.get_cell(0)).collect();
+    tx.commit().unwrap();
+
+    let threads_count = 4;
+    let mut threads = vec![];
+    for _ in 0..threads_count {
+        let local_table = table_rc.clone();
+        let handle = thread::spawn(move || {
+            for _ in 0..100 {
+                insert_random(local_table.clone(), 1, 2, None);
+            }
+        });
+        threads.push(handle);
+    }
+
+    let table = table_rc.rl();
+    let tx = Transaction::new();
+    let mut scanned = vec![];
+    let mut scan = table.latch_free_scan(&tx);
+    while let Some(t) = scan.try_next().unwrap() {
+        scanned.push(t.get_cell(0));
+    }
+    tx.commit().unwrap();
+
+    for handle in threads {
+        handle.join().unwrap();
+    }
+
+    // the result is in key order, and no initial tuple is missing
+    assert!(scanned.windows(2).all(|w| w[0] <= w[1]));
+    let mut remaining = scanned.iter().peekable();
+    for key in &initial {
+        while remaining.peek().map_or(false, |k| *k < key) {
+            remaining.next();
+        }
+        assert_eq!(remaining.next(), Some(key));
+    }
+    table.check_integrity();
+}
+
+/// The page being read by a latch-free scan is split by a committed insert,
+/// the scan finds its position again instead of following the stale sibling
+/// pointer.
+#[test]
+fn test_latch_free_scan_after_split() {
+    setup();
+
+    let table_rc = new_random_btree_table(2, 0, None, 0, TreeLayout::Naturally);
+    let table = table_rc.rl();
+
+    // the even keys, on a few leaf pages
+    let rows = leaf_records_cap() as i64 * 3;
+    let tx = Transaction::new();
+    for key in 0..rows {
+        table
+            .insert_tuple(&tx, &new_int_tuples(key * 2, 2, &tx))
+            .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let scan_tx = Transaction::new();
+    let mut scan = table.latch_free_scan(&scan_tx);
+    let mut scanned: Vec<i64> = scan
+        .by_ref()
+        .take(3)
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+
+    // fill the pages with the odd keys, every page is split
+    let tx = Transaction::new();
+    for key in 0..rows {
+        table
+            .insert_tuple(&tx, &new_int_tuples(key * 2 + 1, 2, &tx))
+            .unwrap();
+    }
+    tx.commit().unwrap();
+
+    scanned.extend(scan.map(|t| t.get_cell(0).get_int64().unwrap()));
+    scan_tx.commit().unwrap();
+
+    // no key is returned twice, and all the even keys are returned
+    assert!(scanned.windows(2).all(|w| w[0] < w[1]));
+    let evens: Vec<i64> = scanned.iter().copied().filter(|k| k % 2 == 0).collect();
+    let expect: Vec<i64> = (0..rows).map(|k| k * 2).collect();
+    assert_eq!(evens, expect);
+    table.check_integrity();
+}
@@ -0,0 +1,424 @@
+use std::{
+    fs,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use small_db::{
+    common::{Catalog, CommitEvent, OnMissingWal, OpenOptions},
+    sql::session::Session,
+    storage::{
+        table_schema::{Field, TableSchema, Type},
+        tuple::{Cell, Tuple},
+    },
+    transaction::Transaction,
+    utils::HandyRwLock,
+    BTreeTable, Database, SmallErrorKind,
+};
+
+use crate::test_utils::{insert_row, new_empty_btree_table, search_key, setup};
+
+#[test]
+fn test_open_multiple_databases() {
+    setup();
+
+    let db_a = Database::open("./data/tenant_a").unwrap();
+    let db_b = Database::open("./data/tenant_b").unwrap();
+
+    // both databases get a table with the same name but different rows
+    for (db, rows) in [(&db_a, 10), (&db_b, 20)] {
+        let _guard = Database::enter(db);
+
+        let table_rc = new_empty_btree_table("accounts", 2);
+        let table = table_rc.rl();
+        let tx = Transaction::new();
+        for key in 0..rows {
+            insert_row(&table, &tx, key);
+        }
+        tx.commit().unwrap();
+    }
+
+    for (db, rows) in [(&db_a, 10), (&db_b, 20)] {
+        let _guard = Database::enter(db);
+        assert_eq!(Database::global().get_path(), db.get_path());
+
+        let table_rc = Database::catalog().search_table("accounts").unwrap();
+        let table = table_rc.rl();
+        let tx = Transaction::new();
+        assert_eq!(table.iter(&tx).count(), rows as usize);
+        assert_eq!(
+            search_key(&table, &tx, &Cell::Int64(15)),
+            (rows > 15) as usize
+        );
+        tx.commit().unwrap();
+    }
+
+    // the global database doesn't see the tables of the opened ones
+    assert!(Database::catalog().search_table("accounts").is_none());
+}
+
+#[test]
+fn test_open_same_path_twice() {
+    setup();
+
+    let path = "./data/opened_twice";
+    let db = Database::open(path).unwrap();
+
+    // whatever the spelling of the path
+    for other_path in [path, "./data/../data/opened_twice"] {
+        let err = Database::open(other_path).err().unwrap();
+        assert_eq!(err.kind(), SmallErrorKind::AlreadyOpen);
+    }
+    let err = Database::open_and_recover(path).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::AlreadyOpen);
+
+    // nor the directory of the global database
+    let err = Database::open(Database::global().get_path()).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::AlreadyOpen);
+
+    // the directory is released by a close
+    {
+        let _guard = Database::enter(&db);
+        Database::close().unwrap();
+    }
+    let db = Database::open(path).unwrap();
+
+    // and when the last handle is dropped
+    drop(db);
+    Database::open(path).unwrap();
+}
+
+#[test]
+fn test_open_and_recover() {
+    setup();
+
+    let path = "./data/recovered";
+    {
+        let db = Database::open_and_recover(path).unwrap();
+        let _guard = Database::enter(&db);
+
+        let table_rc = new_empty_btree_table("accounts", 2);
+        let table = table_rc.rl();
+
+        let tx = Transaction::new();
+        insert_row(&table, &tx, 1);
+        tx.commit().unwrap();
+
+        // crash in the middle of a transaction whose changes reached the disk
+        let tx = Transaction::new();
+        insert_row(&table, &tx, 2);
+        Database::mut_buffer_pool()
+            .flush_all_pages(&mut Database::mut_log_manager())
+            .unwrap();
+    }
+
+    let db = Database::open_and_recover(path).unwrap();
+    let _guard = Database::enter(&db);
+
+    let table_rc = Database::catalog().search_table("accounts").unwrap();
+    let table = table_rc.rl();
+    let tx = Transaction::new();
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(1)), 1);
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(2)), 0);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_open_old_leaf_format() {
+    setup();
+
+    let path = "./data/old_leaf_format";
+    let varint_schema = TableSchema::new(vec![
+        Field::new_varint("key", true),
+        Field::new_varint("value", false),
+    ]);
+    let (fixed_path, varint_path) = {
+        let db = Database::open_and_recover(path).unwrap();
+        let _guard = Database::enter(&db);
+
+        // leave an empty slot between the tuples of the fixed-width table
+        let fixed_rc = new_empty_btree_table("accounts", 2);
+        let tx = Transaction::new();
+        for key in 1..=3 {
+            insert_row(&fixed_rc.rl(), &tx, key);
+        }
+        tx.commit().unwrap();
+        let tx = Transaction::new();
+        let tuple = fixed_rc.rl().iter(&tx).nth(1).unwrap();
+        fixed_rc.rl().delete_tuple(&tx, &tuple).unwrap();
+        tx.commit().unwrap();
+        fixed_rc.rl().delete_invisible_tuples().unwrap();
+
+        let varint_rc = Arc::new(RwLock::new(BTreeTable::new(
+            "counters",
+            None,
+            &varint_schema,
+        )));
+        Catalog::add_table(Arc::clone(&varint_rc), true).unwrap();
+        let tx = Transaction::new();
+        insert_row(&varint_rc.rl(), &tx, 1);
+        tx.commit().unwrap();
+
+        Database::close().unwrap();
+        (
+            db.get_path().join("accounts.table"),
+            db.get_path().join("counters.table"),
+        )
+    };
+
+    // a file written before the leaf page format was recorded has zeros in
+    // place of the version, after the header page index
+    let downgrade = |table_path| {
+        let mut bytes = fs::read(table_path).unwrap();
+        bytes[16..20].copy_from_slice(&[0; 4]);
+        fs::write(table_path, &bytes).unwrap();
+    };
+
+    // the layout of the fixed-width pages didn't change, the file is read
+    // as it is
+    downgrade(&fixed_path);
+    {
+        let db = Database::open_and_recover(path).unwrap();
+        let _guard = Database::enter(&db);
+        let table_rc = Database::catalog().search_table("accounts").unwrap();
+        let table = table_rc.rl();
+        let tx = Transaction::new();
+        assert_eq!(table.iter(&tx).count(), 2);
+        assert_eq!(search_key(&table, &tx, &Cell::Int64(1)), 1);
+        assert_eq!(search_key(&table, &tx, &Cell::Int64(2)), 0);
+        assert_eq!(search_key(&table, &tx, &Cell::Int64(3)), 1);
+        tx.commit().unwrap();
+        Database::close().unwrap();
+    }
+
+    // the varint pages are rejected instead of being misread
+    downgrade(&varint_path);
+    let err = Database::open_and_recover(path).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+}
+
+#[test]
+fn test_open_without_wal() {
+    setup();
+
+    let path = "./data/no_wal";
+    let error = OpenOptions {
+        on_missing_wal: OnMissingWal::Error,
+    };
+
+    // a new directory is a new database whatever the options
+    {
+        let db = Database::open_with(path, &error).unwrap();
+        let _guard = Database::enter(&db);
+
+        let table_rc = new_empty_btree_table("accounts", 2);
+        let table = table_rc.rl();
+        let tx = Transaction::new();
+        insert_row(&table, &tx, 1);
+        tx.commit().unwrap();
+        Database::close().unwrap();
+    }
+
+    // the WAL is lost, the table files are left
+    fs::remove_file("./data/no_wal/wal.log").unwrap();
+
+    let err = Database::open_with(path, &error).err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::NotFound);
+
+    // the tables are taken as they are on disk
+    let db = Database::open_with(path, &OpenOptions::default()).unwrap();
+    let _guard = Database::enter(&db);
+    let table_rc = Database::catalog().search_table("accounts").unwrap();
+    let table = table_rc.rl();
+    let tx = Transaction::new();
+    assert_eq!(search_key(&table, &tx, &Cell::Int64(1)), 1);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_on_commit() {
+    setup();
+
+    let table_rc = new_empty_btree_table("cdc", 2);
+    let table = table_rc.rl();
+
+    let events: Arc<Mutex<Vec<CommitEvent>>> = Arc::new(Mutex::new(Vec::new()));
+    let received = events.clone();
+    let hook = Database::on_commit(Arc::new(move |event: &CommitEvent| {
+        received.lock().unwrap().push(event.clone());
+    }));
+
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 1);
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    let tuple = table.get_by_key(&tx, &Cell::Int64(1)).unwrap().unwrap();
+    table.delete_tuple(&tx, &tuple).unwrap();
+    tx.commit().unwrap();
+
+    // the changes of an aborted transaction are not emitted
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 2);
+    tx.abort().unwrap();
+
+    let all_events = events.clone();
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+
+    let insert = &events[0].changes;
+    assert_eq!(insert.len(), 1);
+    assert_eq!(insert[0].table, "cdc");
+    assert!(insert[0].before.is_none());
+    assert_eq!(
+        insert[0].after.as_ref().unwrap().get_cell(0),
+        Cell::Int64(1)
+    );
+
+    let delete = &events[1].changes;
+    assert_eq!(delete.len(), 1);
+    assert_eq!(delete[0].table, "cdc");
+    assert_eq!(
+        delete[0].before.as_ref().unwrap().get_cell(0),
+        Cell::Int64(1)
+    );
+    assert!(delete[0].after.is_none());
+    drop(events);
+
+    // dropping the handle unregisters the hook
+    drop(hook);
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 3);
+    tx.commit().unwrap();
+    assert_eq!(all_events.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_reopen_table() {
+    setup();
+
+    let path = "./data/reopen";
+    let name_type = Type::Bytes(20);
+    let schema = TableSchema::new(vec![
+        Field::new("name", name_type, false),
+        Field::new("id", Type::Int64, true),
+        Field::new("balance", Type::Int64, false),
+    ]);
+    let row = |id: i64| {
+        vec![
+            Cell::new_bytes(format!("user{}", id).as_bytes(), &name_type),
+            Cell::Int64(id),
+            Cell::Int64(id * 10),
+        ]
+    };
+
+    {
+        let db = Database::open_and_recover(path).unwrap();
+        let _guard = Database::enter(&db);
+
+        let table_rc = Arc::new(RwLock::new(BTreeTable::new("accounts", None, &schema)));
+        Catalog::add_table(Arc::clone(&table_rc), true).unwrap();
+        let table = table_rc.rl();
+        let tx = Transaction::new();
+        for id in 0..100 {
+            table
+                .insert_tuple(&tx, &Tuple::new(&row(id), tx.get_id()))
+                .unwrap();
+        }
+        tx.commit().unwrap();
+        Database::close().unwrap();
+    }
+
+    let db = Database::open_and_recover(path).unwrap();
+    let _guard = Database::enter(&db);
+
+    // the schema is read back, with the key on the second column
+    let table_rc = BTreeTable::open("accounts").unwrap();
+    let table = table_rc.rl();
+    assert_eq!(table.get_schema().get_fields(), schema.get_fields());
+    assert_eq!(table.key_field, 1);
+
+    let tx = Transaction::new();
+    let rows: Vec<Vec<Cell>> = table.iter(&tx).map(|t| t.get_cells()).collect();
+    assert_eq!(rows, (0..100).map(row).collect::<Vec<_>>());
+    tx.commit().unwrap();
+
+    let err = BTreeTable::open("missing").err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::NotFound);
+}
+
+#[test]
+fn test_catalog_file() {
+    setup();
+
+    let path = "./data/catalog";
+    {
+        let db = Database::open_and_recover(path).unwrap();
+        let _guard = Database::enter(&db);
+
+        let mut session = Session::new();
+        for name in ["accounts", "orders", "events"] {
+            session
+                .run(&format!(
+                    "CREATE TABLE {} (id INTEGER PRIMARY KEY, amount INTEGER)",
+                    name
+                ))
+                .unwrap();
+        }
+        session
+            .run("INSERT INTO orders VALUES (1, 10), (2, 20)")
+            .unwrap();
+        session.run("DROP TABLE events").unwrap();
+
+        // the names may hold the separators of the file
+        let schema = TableSchema::new(vec![
+            Field::new("id:1", Type::Int64, true),
+            Field::new("a,\tb%", Type::Int64, false),
+        ]);
+        let table = BTreeTable::new("odd, name:\t%", None, &schema);
+        Catalog::add_table(Arc::new(RwLock::new(table)), true).unwrap();
+
+        // a table which can't be listed in the catalog file leaves no row
+        // in the "tables" table
+        fs::create_dir("./data/catalog/catalog.tmp").unwrap();
+        let table = BTreeTable::new("lost", None, &schema);
+        assert!(Catalog::add_table(Arc::new(RwLock::new(table)), true).is_err());
+        fs::remove_dir("./data/catalog/catalog.tmp").unwrap();
+        assert!(Database::catalog().search_table("lost").is_none());
+        let schema_table_rc = Database::mut_catalog().get_table_schemas();
+        let tx = Transaction::new();
+        let lost_rows = schema_table_rc
+            .rl()
+            .iter(&tx)
+            .filter(|t| t.get_cell(1).get_bytes().unwrap() == b"lost")
+            .count();
+        assert_eq!(lost_rows, 0);
+        tx.commit().unwrap();
+        Database::close().unwrap();
+    }
+
+    // the tables are listed by the catalog file, not by the rows of the
+    // "tables" table
+    fs::remove_file("./data/catalog/tables.table").unwrap();
+
+    let db = Database::open_and_recover(path).unwrap();
+    let _guard = Database::enter(&db);
+
+    let names = Database::catalog().list_tables();
+    assert!(names.contains(&"accounts".to_string()));
+    assert!(names.contains(&"orders".to_string()));
+    assert!(!names.contains(&"events".to_string()));
+    let table_rc = Database::catalog().search_table("odd, name:\t%").unwrap();
+    let fields = table_rc.rl().get_schema().get_fields().clone();
+    assert_eq!(fields[0].name, "id:1");
+    assert_eq!(fields[1].name, "a,\tb%");
+
+    let mut session = Session::new();
+    let rows = session
+        .run("SELECT * FROM orders")
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].get_cells(), vec![Cell::Int64(2), Cell::Int64(20)]);
+}
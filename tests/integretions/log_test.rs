@@ -1,12 +1,27 @@
+use std::{
+    convert::TryInto,
+    fs,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use log::debug;
 use small_db::{
-    btree::page::BTreePage, storage::tuple::Cell, transaction::Transaction, utils::HandyRwLock,
-    BTreeTable, Database,
+    btree::{buffer_pool::BufferPool, page::BTreePage},
+    common::Catalog,
+    storage::{
+        table_schema::{Field, Type},
+        tuple::Cell,
+    },
+    transaction::{RecoveryPhase, Transaction},
+    utils::HandyRwLock,
+    BTreeTable, Database, TableSchema,
 };
 
 use crate::test_utils::{
-    assert_true, crash, insert_row, new_empty_btree_table, new_random_btree_table, search_key,
-    setup, TreeLayout,
+    assert_true, crash, insert_row, leaf_records_cap, new_empty_btree_table,
+    new_random_btree_table, search_key, setup, TreeLayout,
 };
 
 /// Insert two tuples into the table, then commit the transaction. There is a
@@ -25,7 +40,9 @@ fn commit_insert(table: &BTreeTable, key_1: i64, key_2: i64) {
     insert_row(&table, &tx, key_1);
 
     // step 3: force flush all pages (from the buffer pool to disk)
-    Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
 
     // step 4: insert another tuple into the table
     insert_row(&table, &tx, key_2);
@@ -272,10 +289,14 @@ fn test_open_commit_checkpoint_open_crash() {
     // (since ARIES is a steal/no-force recovery algorithm, we
     // simulate the "steal" scenario here by flushing the buffer
     // pool)
-    Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
 
     insert_row(&table_1, &mut tx_1, 13);
-    Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
 
     insert_row(&table_1, &mut tx_1, 14);
 
@@ -287,7 +308,9 @@ fn test_open_commit_checkpoint_open_crash() {
     let mut tx_3 = Transaction::new();
     insert_row(&table_2, &mut tx_3, 28);
     // defeat NO-STEAL-based abort
-    Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
     insert_row(&table_2, &mut tx_3, 29);
 
     crash();
@@ -311,6 +334,43 @@ fn test_open_commit_checkpoint_open_crash() {
     tx.commit().unwrap();
 }
 
+#[test]
+fn test_truncate_open_crash() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+
+    commit_insert(&table, 1, 2);
+    commit_insert(&table, 3, 4);
+
+    // T1 inserts but does not commit, its pages reach the disk
+    let tx_1_start = Database::log_manager().get_current_offset();
+    let mut tx_1 = Transaction::new();
+    insert_row(&table, &mut tx_1, 12);
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+    insert_row(&table, &mut tx_1, 13);
+
+    // the log is cut up to the first record of T1
+    Database::mut_log_manager().log_checkpoint().unwrap();
+    assert!(Database::mut_log_manager().truncate().unwrap());
+    let offsets = Database::mut_log_manager().record_timestamps().unwrap();
+    assert_eq!(offsets.first().unwrap().0, tx_1_start);
+
+    // T1 is still rolled back from the records left
+    crash();
+
+    let tx = Transaction::new();
+    for key in 1..=4 {
+        assert_true(search_key(&table, &tx, &Cell::Int64(key)) == 1, &table);
+    }
+    assert_true(search_key(&table, &tx, &Cell::Int64(12)) == 0, &table);
+    assert_true(search_key(&table, &tx, &Cell::Int64(13)) == 0, &table);
+    tx.commit().unwrap();
+}
+
 #[test]
 fn test_open_commit_open_crash() {
     setup();
@@ -333,7 +393,9 @@ fn test_open_commit_open_crash() {
         let mut tx_1 = Transaction::new();
         insert_row(&table_1, &mut tx_1, 10);
         // defeat NO-STEAL-based abort
-        Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+        Database::mut_buffer_pool()
+            .flush_all_pages(&mut Database::mut_log_manager())
+            .unwrap();
         insert_row(&table_1, &mut tx_1, 11);
     }
 
@@ -347,7 +409,9 @@ fn test_open_commit_open_crash() {
         let mut tx_3 = Transaction::new();
         insert_row(&table_2, &mut tx_3, 30);
         // defeat NO-STEAL-based abort
-        Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+        Database::mut_buffer_pool()
+            .flush_all_pages(&mut Database::mut_log_manager())
+            .unwrap();
         insert_row(&table_2, &mut tx_3, 31);
     }
 
@@ -392,7 +456,9 @@ fn test_open_crash() {
     // step 1: write_tx inserts some data but does not commit
     let mut write_tx = Transaction::new();
     insert_row(&table_1, &mut write_tx, 8);
-    Database::mut_buffer_pool().flush_all_pages(&mut Database::mut_log_manager());
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
     insert_row(&table_1, &mut write_tx, 9);
 
     Database::mut_log_manager().show_log_contents();
@@ -422,3 +488,566 @@ fn test_new_table() {
 
     assert!(Database::catalog().search_table(table_name).is_some());
 }
+
+#[test]
+fn test_close() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+
+    commit_insert(&table, 1, 2);
+
+    // a transaction is still active, the database cannot be closed
+    let tx = Transaction::new();
+    assert!(Database::close().is_err());
+    tx.commit().unwrap();
+
+    Database::close().unwrap();
+
+    // the page cache is empty now, so the tuples are read from disk directly
+    let tx = Transaction::new();
+    assert_true(search_key(&table, &tx, &Cell::Int64(1)) == 1, &table);
+    assert_true(search_key(&table, &tx, &Cell::Int64(2)) == 1, &table);
+    tx.commit().unwrap();
+}
+
+/// The pages written by `close` are read back by a new instance of the
+/// database, with nothing left for the recovery.
+#[test]
+fn test_close_reopen() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let rows = 500;
+    {
+        let table = table_pod.rl();
+        let tx = Transaction::new();
+        for key in 0..rows {
+            insert_row(&table, &tx, key);
+        }
+        tx.commit().unwrap();
+    }
+
+    Database::close().unwrap();
+
+    // a new instance, the checkpoint written by `close` leaves nothing to
+    // recover
+    crash();
+
+    let table_rc = Database::catalog().search_table("table_1").unwrap();
+    let table = table_rc.rl();
+    let tx = Transaction::new();
+    for key in 0..rows {
+        assert_true(search_key(&table, &tx, &Cell::Int64(key)) == 1, &table);
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_reload() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+    let table_path = Database::global().get_path().join("table_1.table");
+
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 1);
+    tx.commit().unwrap();
+
+    let snapshot = fs::read(&table_path).unwrap();
+
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 2);
+    tx.commit().unwrap();
+
+    // revert the table file through another handle, the change is not
+    // visible until the table is reloaded
+    fs::write(&table_path, &snapshot).unwrap();
+
+    let tx = Transaction::new();
+    assert_true(search_key(&table, &tx, &Cell::Int64(2)) == 1, &table);
+    tx.commit().unwrap();
+
+    table.reload();
+
+    let tx = Transaction::new();
+    assert_true(search_key(&table, &tx, &Cell::Int64(1)) == 1, &table);
+    assert_true(search_key(&table, &tx, &Cell::Int64(2)) == 0, &table);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_replay_until() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+    let table_path = Database::global().get_path().join("table_1.table");
+
+    // take a base backup, the log starts from here
+    Database::mut_log_manager().reset();
+    let backup = fs::read(&table_path).unwrap();
+
+    let mut offsets = Vec::new();
+    for key in 1..=4 {
+        let tx = Transaction::new();
+        insert_row(&table, &tx, key);
+        tx.commit().unwrap();
+
+        offsets.push(Database::log_manager().get_current_offset());
+    }
+
+    // restore the backup and replay the log to the end of the second commit
+    fs::write(&table_path, &backup).unwrap();
+    table.reload();
+    Database::mut_log_manager()
+        .replay_until(offsets[1])
+        .unwrap();
+    table.reload();
+
+    let tx = Transaction::new();
+    assert_true(search_key(&table, &tx, &Cell::Int64(1)) == 1, &table);
+    assert_true(search_key(&table, &tx, &Cell::Int64(2)) == 1, &table);
+    assert_true(search_key(&table, &tx, &Cell::Int64(3)) == 0, &table);
+    assert_true(search_key(&table, &tx, &Cell::Int64(4)) == 0, &table);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_page_as_of() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+    Database::mut_log_manager().reset();
+    let start = Database::log_manager().get_current_offset();
+
+    let mut offsets = Vec::new();
+    for key in 1..=4 {
+        let tx = Transaction::new();
+        insert_row(&table, &tx, key);
+        tx.commit().unwrap();
+
+        offsets.push(Database::log_manager().get_current_offset());
+    }
+
+    let tx = Transaction::new();
+    let pid = table.get_root_pid(&tx);
+    let keys_as_of = |lsn: u64| -> Vec<Cell> {
+        BufferPool::get_leaf_page_as_of(&tx, &pid, lsn)
+            .unwrap()
+            .iter()
+            .map(|t| t.get_cell(0))
+            .collect()
+    };
+
+    assert_eq!(keys_as_of(start), vec![]);
+    assert_eq!(keys_as_of(offsets[0]), vec![Cell::Int64(1)]);
+    assert_eq!(keys_as_of(offsets[1]), vec![Cell::Int64(1), Cell::Int64(2)]);
+    assert_eq!(keys_as_of(offsets[3]).len(), 4);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_recover_with_progress() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+
+    for i in 0..20 {
+        commit_insert(&table, i * 2, i * 2 + 1);
+    }
+
+    // an unfinished transaction for the undo phase
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 100);
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+
+    let mut calls = Vec::new();
+    Database::mut_log_manager()
+        .recover_with_progress(|phase, done, total| calls.push((phase, done, total)))
+        .unwrap();
+
+    let phases = [
+        RecoveryPhase::Analysis,
+        RecoveryPhase::Redo,
+        RecoveryPhase::Undo,
+    ];
+    let mut last_phase = 0;
+    let mut last_done = 0;
+    for (phase, done, total) in &calls {
+        let phase = phases.iter().position(|p| p == phase).unwrap();
+        assert!(phase >= last_phase);
+        if phase > last_phase {
+            last_done = 0;
+        }
+        assert!(*done >= last_done && done <= total);
+        last_phase = phase;
+        last_done = *done;
+    }
+
+    for phase in &phases {
+        let reports: Vec<_> = calls.iter().filter(|(p, _, _)| p == phase).collect();
+        let (_, done, total) = reports.last().unwrap();
+        assert_eq!(done, total);
+        if *phase != RecoveryPhase::Redo {
+            assert!(reports.len() > 40);
+        }
+    }
+}
+
+/// The table files and the WAL are read back with plain little-endian
+/// integers, the byte order of all encoders.
+#[test]
+fn test_byte_order() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+
+    // grow the tree so the root is not the first leaf
+    let tx = Transaction::new();
+    for key in 0..leaf_records_cap() as i64 * 2 {
+        insert_row(&table, &tx, key);
+    }
+    tx.commit().unwrap();
+
+    // write all pages and a checkpoint
+    Database::close().unwrap();
+
+    let path = Database::global().get_path();
+    let table_bytes = fs::read(path.join("table_1.table")).unwrap();
+    let tx = Transaction::new();
+    let root_pid = table.get_root_pid(&tx);
+    tx.commit().unwrap();
+    assert!(root_pid.get_page_index() > 1);
+
+    // root pointer page: 4 bytes category, then the root page index
+    let root_index = u32::from_le_bytes(table_bytes[4..8].try_into().unwrap());
+    assert_eq!(root_index, root_pid.get_page_index());
+
+    // WAL: the offset of the last checkpoint comes first
+    let log_bytes = fs::read(path.join("wal.log")).unwrap();
+    let checkpoint = u64::from_le_bytes(log_bytes[0..8].try_into().unwrap());
+    assert_eq!(
+        Some(checkpoint),
+        Database::mut_log_manager()
+            .last_checkpoint_offset()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_log_timestamps() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+    Database::mut_log_manager().reset();
+
+    let before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64;
+
+    commit_insert(&table, 1, 2);
+
+    let tx = Transaction::new();
+    insert_row(&table, &tx, 3);
+    tx.abort().unwrap();
+
+    let after = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64;
+
+    // start, update and commit/abort records all carry a timestamp
+    let timestamps: Vec<u64> = Database::mut_log_manager()
+        .record_timestamps()
+        .unwrap()
+        .into_iter()
+        .filter_map(|(_, timestamp)| timestamp)
+        .collect();
+    assert!(timestamps.len() >= 4);
+
+    for timestamp in &timestamps {
+        assert!(before <= *timestamp && *timestamp <= after);
+    }
+    for pair in timestamps.windows(2) {
+        assert!(pair[0] <= pair[1]);
+    }
+}
+
+#[test]
+fn test_tx_stats() {
+    setup();
+
+    let table_rc = new_empty_btree_table("stats", 2);
+    let table = table_rc.rl();
+
+    // a few rows in an empty table, which all land on the root leaf
+    let tx = Transaction::new();
+    for key in 0..10 {
+        insert_row(&table, &tx, key);
+    }
+
+    let stats = tx.stats();
+    assert!(stats.pages_read >= 10);
+    assert!(stats.pages_dirtied >= 1 && stats.pages_dirtied <= 3);
+    assert!(stats.latches_acquired >= 1 && stats.latches_acquired <= stats.pages_read);
+    let start_bytes = stats.log_bytes_written;
+    assert!(start_bytes > 0);
+
+    // the UPDATE records are written on commit, each of them holds the
+    // before and after images of a page
+    tx.commit().unwrap();
+    let stats = tx.stats();
+    let page_size = BufferPool::get_page_size() as u64;
+    assert!(stats.log_bytes_written >= start_bytes + 2 * page_size);
+    assert!(stats.log_bytes_written <= start_bytes + 2 * page_size * 4);
+
+    // another transaction starts from zero
+    let other = Transaction::new();
+    assert_eq!(other.stats().pages_read, 0);
+    other.commit().unwrap();
+}
+
+#[test]
+fn test_commit_multiple_tables_atomically() {
+    setup();
+
+    let table_a_rc = new_empty_btree_table("atomic_a", 2);
+    let table_b_rc = new_empty_btree_table("atomic_b", 2);
+
+    let tx = Transaction::new();
+    insert_row(&table_a_rc.rl(), &tx, 1);
+    insert_row(&table_b_rc.rl(), &tx, 1);
+    tx.commit().unwrap();
+
+    let path_a = Database::global().get_path().join("atomic_a.table");
+    let path_b = Database::global().get_path().join("atomic_b.table");
+    let content_a = fs::read(&path_a).unwrap();
+    let content_b = fs::read(&path_b).unwrap();
+
+    let tx = Transaction::new();
+    for key in 10..20 {
+        insert_row(&table_a_rc.rl(), &tx, key);
+        insert_row(&table_b_rc.rl(), &tx, key);
+    }
+
+    // the pages of the second table can't be written from now on
+    *table_b_rc.rl().get_file() = fs::File::open(&path_b).unwrap();
+
+    assert!(tx.commit().is_err());
+
+    // neither table keeps a part of the transaction
+    assert_eq!(fs::read(&path_a).unwrap(), content_a);
+    assert_eq!(fs::read(&path_b).unwrap(), content_b);
+
+    let tx = Transaction::new();
+    assert_eq!(table_a_rc.rl().iter(&tx).count(), 1);
+    assert_eq!(table_b_rc.rl().iter(&tx).count(), 1);
+    assert_eq!(search_key(&table_a_rc.rl(), &tx, &Cell::Int64(10)), 0);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_sync() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+
+    let tx = Transaction::new();
+    for key in 0..100 {
+        insert_row(&table, &tx, key);
+    }
+    tx.commit().unwrap();
+
+    Database::sync().unwrap();
+
+    // drop the log, so nothing can be recovered from it after the crash
+    Database::mut_log_manager().reset();
+    crash();
+
+    let tx = Transaction::new();
+    assert_true(table.iter(&tx).count() == 100, &table);
+    for key in 0..100 {
+        assert_true(search_key(&table, &tx, &Cell::Int64(key)) == 1, &table);
+    }
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_recover_unknown_table() {
+    setup();
+
+    let table_pod_1 = new_empty_btree_table("table_1", 2);
+    let table_1 = table_pod_1.rl();
+
+    // the second table is not persisted in the catalog, so it's unknown to
+    // the recovery after the crash
+    let schema = TableSchema::small_int_schema(2);
+    let table_pod_2 = Arc::new(RwLock::new(BTreeTable::new("table_2", None, &schema)));
+    Catalog::add_table(table_pod_2.clone(), false).unwrap();
+
+    commit_insert(&table_1, 1, 2);
+
+    // an incomplete transaction touching both tables, its UPDATE records
+    // reach the log
+    let tx = Transaction::new();
+    insert_row(&table_1, &tx, 3);
+    insert_row(&table_pod_2.rl(), &tx, 3);
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+
+    // the recovery skips the pages of the unknown table instead of panicking
+    crash();
+    assert!(Database::catalog().search_table("table_2").is_none());
+
+    let tx = Transaction::new();
+    assert_true(search_key(&table_1, &tx, &Cell::Int64(1)) == 1, &table_1);
+    assert_true(search_key(&table_1, &tx, &Cell::Int64(2)) == 1, &table_1);
+    assert_true(search_key(&table_1, &tx, &Cell::Int64(3)) == 0, &table_1);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_health() {
+    setup();
+
+    let report = Database::health().unwrap();
+    assert_eq!(report.table_count, 0);
+    assert_eq!(report.dirty_pages, 0);
+
+    let table_pod_1 = new_empty_btree_table("table_1", 2);
+    let table_1 = table_pod_1.rl();
+    let _table_pod_2 = new_empty_btree_table("table_2", 2);
+
+    let tx = Transaction::new();
+    for key in 0..100 {
+        insert_row(&table_1, &tx, key);
+    }
+
+    let report = Database::health().unwrap();
+    assert_eq!(report.table_count, 2);
+    assert!(report.dirty_pages > 0);
+
+    tx.commit().unwrap();
+
+    let report = Database::health().unwrap();
+    assert_eq!(report.dirty_pages, 0);
+    assert!(report.total_pages >= 2);
+    assert!(report.wal_size > 0);
+
+    Database::mut_log_manager().log_checkpoint().unwrap();
+    let report = Database::health().unwrap();
+    assert!(report.last_checkpoint_offset.is_some());
+    assert!(report.last_checkpoint_offset.unwrap() < report.wal_size);
+}
+
+#[test]
+fn test_checkpointer() {
+    setup();
+
+    let table_pod = new_empty_btree_table("table_1", 2);
+    let table = table_pod.rl();
+
+    Database::start_checkpointer(Duration::from_millis(20)).unwrap();
+    assert!(Database::start_checkpointer(Duration::from_millis(20)).is_err());
+
+    for key in 0..10 {
+        let tx = Transaction::new();
+        insert_row(&table, &tx, key);
+        tx.commit().unwrap();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // wait for a checkpoint taken after the last commit
+    thread::sleep(Duration::from_millis(100));
+    Database::stop_checkpointer();
+
+    let report = Database::health().unwrap();
+    assert!(report.last_checkpoint_offset.is_some());
+
+    crash();
+
+    let tx = Transaction::new();
+    assert_true(table.iter(&tx).count() == 10, &table);
+    tx.commit().unwrap();
+}
+
+/// Add a column in a transaction which doesn't finish, the previous schema
+/// is restored on abort and by the recovery.
+#[test]
+fn test_abort_add_column() {
+    setup();
+
+    let table_name = "evolving";
+    new_empty_btree_table(table_name, 2);
+    let get_fields = || {
+        Database::catalog()
+            .search_table(table_name)
+            .unwrap()
+            .rl()
+            .get_schema()
+            .get_fields()
+            .clone()
+    };
+    let fields = get_fields();
+    let column = Field::new("extra", Type::Int64, false);
+    let catalog_file_lists_column = || {
+        let path = Database::global().get_path().join("catalog");
+        fs::read_to_string(path).unwrap().contains("extra")
+    };
+
+    // abort the transaction, the catalog file is only written on commit
+    let tx = Transaction::new();
+    Catalog::add_column(&tx, table_name, &column).unwrap();
+    assert_eq!(get_fields().len(), 3);
+    assert_eq!(get_fields()[2], column);
+    assert!(!catalog_file_lists_column());
+    tx.abort().unwrap();
+    assert!(!catalog_file_lists_column());
+    assert_eq!(get_fields(), fields);
+
+    crash();
+    let fields = get_fields();
+    assert_eq!(fields.len(), 2);
+
+    // crash before the transaction finishes, the new schema has reached the
+    // disk
+    let tx = Transaction::new();
+    Catalog::add_column(&tx, table_name, &column).unwrap();
+    Database::mut_buffer_pool()
+        .flush_all_pages(&mut Database::mut_log_manager())
+        .unwrap();
+
+    crash();
+    assert_eq!(get_fields(), fields);
+
+    // the table can still be used with the previous schema
+    let table_rc = Database::catalog().search_table(table_name).unwrap();
+    let tx = Transaction::new();
+    insert_row(&table_rc.rl(), &tx, 1);
+    tx.commit().unwrap();
+
+    // a column can't be added to a table with tuples
+    let tx = Transaction::new();
+    assert!(Catalog::add_column(&tx, table_name, &column).is_err());
+    tx.commit().unwrap();
+
+    // the committed column is listed in the catalog file
+    new_empty_btree_table("evolved", 2);
+    let tx = Transaction::new();
+    Catalog::add_column(&tx, "evolved", &column).unwrap();
+    assert!(!catalog_file_lists_column());
+    tx.commit().unwrap();
+    assert!(catalog_file_lists_column());
+}
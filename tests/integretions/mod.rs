@@ -2,7 +2,12 @@ mod benchmark;
 mod btree_delete_test;
 mod btree_insert_test;
 mod btree_scan_test;
+mod buffer_pool_test;
 mod concurrent_test;
+mod database_test;
 mod log_test;
+mod schema_test;
+mod server_test;
 mod sql_test;
+mod tuple_test;
 mod tx_isolation_test;
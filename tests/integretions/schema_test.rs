@@ -0,0 +1,97 @@
+use small_db::{
+    storage::{
+        table_schema::{Field, Type},
+        tuple::{Cell, Tuple},
+    },
+    SmallErrorKind, TableSchema,
+};
+
+#[test]
+fn test_schema_introspection() {
+    let schema = TableSchema::new(vec![
+        Field::new("id", Type::Int64, true),
+        Field::new("name", Type::Bytes(20), false),
+        Field::new("score", Type::Float64, false),
+        Field::new_varint("visits", false),
+    ]);
+
+    let columns = schema.columns();
+    assert_eq!(columns.len(), 4);
+
+    let names: Vec<&str> = columns.iter().map(|f| f.name()).collect();
+    assert_eq!(names, vec!["id", "name", "score", "visits"]);
+
+    let types: Vec<Type> = columns.iter().map(|f| f.cell_type()).collect();
+    assert_eq!(
+        types,
+        vec![Type::Int64, Type::Bytes(20), Type::Float64, Type::Int64]
+    );
+
+    let keys: Vec<bool> = columns.iter().map(|f| f.is_key()).collect();
+    assert_eq!(keys, vec![true, false, false, false]);
+
+    assert!(columns.iter().all(|f| !f.nullable()));
+}
+
+#[test]
+fn test_slot_encoding() {
+    let cases = vec![
+        (Type::Bool, Cell::Bool(true)),
+        (Type::Int64, Cell::Int64(-42)),
+        (Type::Float64, Cell::Float64(3.5)),
+        (Type::Bytes(20), Cell::Bytes(b"hello".to_vec())),
+        (Type::Bytes(20), Cell::Bytes(Vec::new())),
+    ];
+
+    for (t, cell) in cases {
+        let schema = TableSchema::new(vec![
+            Field::new("id", Type::Int64, true),
+            Field::new("value", t, false),
+        ]);
+        let tuple = Tuple::new(&vec![Cell::Int64(1), cell.clone()], 1);
+
+        let bytes = schema.encode_tuple(&tuple).unwrap();
+        assert_eq!(bytes.len(), schema.slot_size());
+
+        let decoded = schema.decode_tuple(&bytes).unwrap();
+        assert_eq!(decoded.get_cells(), vec![Cell::Int64(1), cell]);
+    }
+
+    let schema = TableSchema::new(vec![
+        Field::new("id", Type::Int64, true),
+        Field::new("name", Type::Bytes(4), false),
+    ]);
+
+    // cells which don't fit the schema are rejected
+    let too_long = Tuple::new(&vec![Cell::Int64(1), Cell::Bytes(b"hello".to_vec())], 1);
+    let err = schema.encode_tuple(&too_long).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+    let wrong_type = Tuple::new(&vec![Cell::Int64(1), Cell::Int64(2)], 1);
+    assert!(schema.encode_tuple(&wrong_type).is_err());
+
+    // so are truncated slots
+    let tuple = Tuple::new(&vec![Cell::Int64(1), Cell::Bytes(b"abc".to_vec())], 1);
+    let bytes = schema.encode_tuple(&tuple).unwrap();
+    let err = schema.decode_tuple(&bytes[..bytes.len() - 1]).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+
+    // and slots with a payload size larger than the field (the size comes
+    // after the two transaction ids and the key)
+    let mut bytes = schema.encode_tuple(&tuple).unwrap();
+    bytes[16..18].copy_from_slice(&100u16.to_le_bytes());
+    let err = schema.decode_tuple(&bytes).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+
+    // or with a varint which never ends
+    let schema = TableSchema::new(vec![
+        Field::new("id", Type::Int64, true),
+        Field::new_varint("visits", false),
+    ]);
+    let tuple = Tuple::new(&vec![Cell::Int64(1), Cell::Int64(7)], 1);
+    let mut bytes = schema.encode_tuple(&tuple).unwrap();
+    for b in bytes[16..].iter_mut() {
+        *b = 0xff;
+    }
+    let err = schema.decode_tuple(&bytes).unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::Corruption);
+}
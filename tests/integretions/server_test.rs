@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use small_db::server::{serve, AuthMethod, ServerConfig};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_postgres::{NoTls, SimpleQueryMessage};
+
+use crate::test_utils::setup;
+
+/// Get the rows of a simple query, as text.
+fn rows_of(messages: &[SimpleQueryMessage]) -> Vec<Vec<String>> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            SimpleQueryMessage::Row(row) => Some(
+                (0..row.len())
+                    .map(|i| row.get(i).unwrap_or("NULL").to_string())
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Run the pgwire server on an ephemeral port and talk to it with a real
+/// Postgres client, end to end through the wire protocol.
+///
+/// Ignored by default since it opens a TCP port, run it with
+/// `cargo test test_pg_client -- --ignored`.
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn test_pg_client() {
+    setup();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(serve(listener, AuthMethod::Trust));
+
+    let config = format!("host=127.0.0.1 port={} user=small dbname=default_db", port);
+    let (client, connection) = tokio_postgres::connect(&config, NoTls).await.unwrap();
+    tokio::spawn(connection);
+
+    client
+        .simple_query("CREATE TABLE orders (id INTEGER PRIMARY KEY, amount INTEGER)")
+        .await
+        .unwrap();
+
+    let messages = client
+        .simple_query("INSERT INTO orders VALUES (1, 10), (2, 20), (3, 30)")
+        .await
+        .unwrap();
+    assert!(messages
+        .iter()
+        .any(|m| matches!(m, SimpleQueryMessage::CommandComplete(3))));
+
+    let messages = client.simple_query("SELECT * FROM orders").await.unwrap();
+    assert_eq!(
+        rows_of(&messages),
+        vec![
+            vec!["1".to_string(), "10".to_string()],
+            vec!["2".to_string(), "20".to_string()],
+            vec!["3".to_string(), "30".to_string()],
+        ]
+    );
+
+    // a failed statement is reported to the client, the connection stays
+    // usable
+    assert!(client.simple_query("SELECT * FROM missing").await.is_err());
+    let messages = client
+        .simple_query("SELECT * FROM orders WHERE id = 2")
+        .await
+        .unwrap();
+    assert_eq!(
+        rows_of(&messages),
+        vec![vec!["2".to_string(), "20".to_string()]]
+    );
+
+    // every connection has a session of its own, the transaction opened by
+    // one client is not seen by another
+    let (other, connection) = tokio_postgres::connect(&config, NoTls).await.unwrap();
+    tokio::spawn(connection);
+    client.simple_query("BEGIN").await.unwrap();
+    other.simple_query("BEGIN").await.unwrap();
+    other.simple_query("ROLLBACK").await.unwrap();
+    client.simple_query("ROLLBACK").await.unwrap();
+
+    server.abort();
+}
+
+#[test]
+fn test_server_config() {
+    let vars: HashMap<&str, &str> = [
+        ("SMALL_DB_HOST", "0.0.0.0"),
+        ("SMALL_DB_PORT", "6543"),
+        ("SMALL_DB_PATH", "/tmp/small"),
+        ("SMALL_DB_USERS", "alice:secret,bob:pa:ss"),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    let config = ServerConfig::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+    assert_eq!(config.addr(), "0.0.0.0:6543");
+    assert_eq!(config.db_path.to_str(), Some("/tmp/small"));
+    let users: HashMap<String, String> = [("alice", "secret"), ("bob", "pa:ss")]
+        .iter()
+        .map(|(name, password)| (name.to_string(), password.to_string()))
+        .collect();
+    assert_eq!(config.auth, AuthMethod::Md5 { users });
+
+    // trust is only accepted with the dev flag
+    let dev_vars: HashMap<&str, &str> = [("SMALL_DB_AUTH", "trust"), ("SMALL_DB_DEV", "1")]
+        .iter()
+        .cloned()
+        .collect();
+    let config = ServerConfig::from_vars(|name| dev_vars.get(name).map(|v| v.to_string())).unwrap();
+    assert_eq!(config.auth, AuthMethod::Trust);
+    assert!(ServerConfig::from_vars(|name| {
+        Some("trust".to_string()).filter(|_| name == "SMALL_DB_AUTH")
+    })
+    .is_err());
+
+    // unset variables take the default value
+    let config = ServerConfig::from_vars(|_| None).unwrap();
+    assert_eq!(config, ServerConfig::default());
+    // without users, no connection is accepted
+    assert_eq!(
+        config.auth,
+        AuthMethod::Md5 {
+            users: HashMap::new()
+        }
+    );
+
+    assert!(ServerConfig::from_vars(|_| Some("not a port".to_string())).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_server_listens_on_configured_port() {
+    setup();
+
+    // pick a free port
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let config = ServerConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        ..ServerConfig::default()
+    };
+    let listener = config.bind().await.unwrap();
+    assert_eq!(listener.local_addr().unwrap().port(), port);
+    let server = tokio::spawn(serve(listener, config.auth.clone()));
+
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_ok());
+
+    server.abort();
+}
+
+/// Ignored by default since it opens a TCP port, run it with
+/// `cargo test test_pg_auth -- --ignored`.
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn test_pg_auth() {
+    setup();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let users = [("alice".to_string(), "secret".to_string())]
+        .iter()
+        .cloned()
+        .collect();
+    let server = tokio::spawn(serve(listener, AuthMethod::Md5 { users }));
+
+    let connect = |user: &str, password: &str| {
+        let config = format!(
+            "host=127.0.0.1 port={} user={} password={} dbname=default_db",
+            port, user, password
+        );
+        async move { tokio_postgres::connect(&config, NoTls).await }
+    };
+
+    // correct credentials
+    let (client, connection) = connect("alice", "secret").await.unwrap();
+    tokio::spawn(connection);
+    client
+        .simple_query("CREATE TABLE accounts (id INTEGER PRIMARY KEY, balance INTEGER)")
+        .await
+        .unwrap();
+
+    // wrong password
+    let err = connect("alice", "wrong").await.err().unwrap();
+    assert_eq!(err.as_db_error().map(|e| e.code().code()), Some("28P01"));
+
+    // an unknown user gets the same answer as a wrong password
+    let unknown = connect("mallory", "secret").await.err().unwrap();
+    let unknown = unknown.as_db_error().unwrap();
+    let wrong = err.as_db_error().unwrap();
+    assert_eq!(unknown.code(), wrong.code());
+    assert_eq!(unknown.message(), wrong.message());
+
+    server.abort();
+}
@@ -1,4 +1,16 @@
-use crate::test_utils::setup;
+use small_db::{
+    btree::table::{BTreeTableSearchIterator, OnConflict},
+    sql::{
+        repl::run_repl,
+        session::{ExecResult, Session},
+    },
+    storage::tuple::{Cell, Tuple},
+    transaction::Transaction,
+    utils::HandyRwLock,
+    Database, Op, Predicate, SmallErrorKind,
+};
+
+use crate::test_utils::{crash, new_empty_btree_table, new_random_btree_table, setup, TreeLayout};
 
 #[test]
 fn test_sql() {
@@ -13,3 +25,1277 @@ fn test_sql() {
 
     // handle_sql(sql);
 }
+
+#[test]
+fn test_where_key_and_column() {
+    setup();
+
+    let table_rc = new_empty_btree_table("seek", 2);
+
+    // keys 0..100, every key appears twice with different values on the
+    // second column
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..100 {
+            for value in [key % 3, key % 3 + 10] {
+                let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(value)], tx.get_id());
+                table.insert_tuple(&tx, &tuple).unwrap();
+            }
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let condition = "\"int-column-0\" = 42 AND \"int-column-1\" = 10";
+
+    // the key condition is served by an index seek, the other one by a
+    // filter on top of it
+    let sql = format!("EXPLAIN SELECT * FROM pg_catalog.seek WHERE {}", condition);
+    let plan: Vec<String> = session
+        .execute(&tx, &sql)
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data
+        .iter()
+        .map(|t| String::from_utf8(t.get_cell(0).get_bytes().unwrap()).unwrap())
+        .collect();
+    assert_eq!(plan.len(), 2);
+    assert!(plan[0].starts_with("Filter: int-column-1"));
+    assert!(plan[1].starts_with("IndexSeek on seek"));
+
+    let sql = format!("SELECT * FROM pg_catalog.seek WHERE {}", condition);
+    let result = session.execute(&tx, &sql).unwrap().into_rows().unwrap();
+    assert_eq!(result.data.len(), 1);
+    assert_eq!(
+        result.data[0].get_cells(),
+        vec![Cell::Int64(42), Cell::Int64(10)]
+    );
+
+    // no row matches both conditions
+    let sql = "SELECT * FROM pg_catalog.seek WHERE \"int-column-0\" = 42 AND \"int-column-1\" = 1";
+    let result = session.execute(&tx, sql).unwrap().into_rows().unwrap();
+    assert!(result.data.is_empty());
+
+    // only the plain SELECTs can be explained
+    let sql = "EXPLAIN INSERT INTO pg_catalog.seek VALUES (1, 2)";
+    assert!(session.execute(&tx, sql).is_err());
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_where_key_in_list() {
+    setup();
+
+    let table_rc = new_empty_btree_table("seek", 2);
+
+    // keys 0..100, every key appears twice with different values on the
+    // second column
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..100 {
+            for value in [key % 3, key % 3 + 10] {
+                let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(value)], tx.get_id());
+                table.insert_tuple(&tx, &tuple).unwrap();
+            }
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let sql = "EXPLAIN SELECT * FROM pg_catalog.seek WHERE \"int-column-0\" IN (42, 7, 42, 500)";
+    let plan = session.execute(&tx, sql).unwrap().into_rows().unwrap();
+    assert_eq!(plan.data.len(), 1);
+    assert!(
+        String::from_utf8(plan.data[0].get_cell(0).get_bytes().unwrap())
+            .unwrap()
+            .starts_with("IndexSeek on seek: int-column-0 In")
+    );
+
+    let sql = "SELECT * FROM pg_catalog.seek WHERE \"int-column-0\" IN (42, 7, 42, 500)";
+    let actual: Vec<Vec<Cell>> = session
+        .execute(&tx, sql)
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data
+        .iter()
+        .map(|t| t.get_cells())
+        .collect();
+
+    // the union of the equality scans, in key order
+    let mut expect = Vec::new();
+    for key in [7, 42, 500] {
+        let sql = format!(
+            "SELECT * FROM pg_catalog.seek WHERE \"int-column-0\" = {}",
+            key
+        );
+        for t in session
+            .execute(&tx, &sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+        {
+            expect.push(t.get_cells());
+        }
+    }
+    assert_eq!(expect.len(), 4);
+    assert_eq!(actual, expect);
+
+    // the same list on the table iterator
+    let predicate = Predicate::in_list(0, &[Cell::Int64(42), Cell::Int64(7), Cell::Int64(500)]);
+    let table = table_rc.rl();
+    let cells: Vec<Vec<Cell>> = BTreeTableSearchIterator::new(&tx, &table, &predicate)
+        .unwrap()
+        .map(|t| t.get_cells())
+        .collect();
+    assert_eq!(cells, expect);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_repl() {
+    setup();
+
+    let input = "
+        CREATE TABLE scores (
+            id INTEGER PRIMARY KEY,
+            score INTEGER
+        );
+        INSERT INTO scores VALUES (1, 10), (2, 200);
+        SELECT * FROM scores;
+        SELECT * FROM missing;
+        \\dt
+        \\d scores
+        \\q
+        SELECT * FROM scores;
+    ";
+
+    let mut output = Vec::new();
+    run_repl(input.as_bytes(), &mut output, false).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    let expect = "\
+OK
+OK, 2 rows affected
++----+-------+
+| id | score |
++----+-------+
+| 1  | 10    |
+| 2  | 200   |
++----+-------+
+(2 rows)
+ERROR: table missing not found
+";
+    assert!(output.starts_with(expect), "output:\n{}", output);
+
+    // \dt lists the new table along with the system tables
+    assert!(output.contains("| scores "));
+
+    // \d describes the columns
+    assert!(output.contains("| id     | Int64 | true        |"));
+    assert!(output.contains("| score  | Int64 | false       |"));
+
+    // nothing runs after \q
+    assert_eq!(output.matches("(2 rows)").count(), 1);
+}
+
+#[test]
+fn test_min_max_without_scan() {
+    setup();
+
+    let rows = 10000;
+    let mut cells = Vec::new();
+    let table_rc = new_random_btree_table(2, rows, Some(&mut cells), 0, TreeLayout::Naturally);
+    let pages_count = table_rc.rl().pages_count();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let query = |session: &mut Session, sql: &str| {
+        let result = session.execute(&tx, sql).unwrap().into_rows().unwrap();
+        assert_eq!(result.data.len(), 1);
+        result.data[0].get_cell(0)
+    };
+
+    // start from a cold cache, MAX(key) only reads the pages on the path to
+    // the last leaf (and the first one)
+    Database::mut_buffer_pool().clear();
+    let misses = Database::mut_buffer_pool().miss_count();
+    let max = query(
+        &mut session,
+        "SELECT MAX(\"int-column-0\") FROM pg_catalog.test",
+    );
+    let reads = Database::mut_buffer_pool().miss_count() - misses;
+    assert_eq!(max, cells.last().unwrap()[0]);
+    assert!(
+        reads <= 10 && reads * 10 < pages_count,
+        "reads: {}, pages: {}",
+        reads,
+        pages_count
+    );
+
+    let min = query(
+        &mut session,
+        "SELECT MIN(\"int-column-0\") FROM pg_catalog.test",
+    );
+    assert_eq!(min, cells[0][0]);
+
+    let count = query(&mut session, "SELECT COUNT(*) FROM pg_catalog.test");
+    assert_eq!(count, Cell::Int64(rows as i64));
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_fetch_in_chunks() {
+    setup();
+
+    let table_rc = new_empty_btree_table("chunks", 2);
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..25 {
+            let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(key * 2)], tx.get_id());
+            table.insert_tuple(&tx, &tuple).unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let sql = "SELECT * FROM pg_catalog.chunks";
+    let full = session.execute(&tx, sql).unwrap().into_rows().unwrap().data;
+    assert_eq!(full.len(), 25);
+
+    let mut result = session.execute(&tx, sql).unwrap().into_rows().unwrap();
+    let mut fetched = Vec::new();
+    loop {
+        let chunk = result.fetch(7);
+        if chunk.is_empty() {
+            break;
+        }
+        assert!(chunk.len() <= 7);
+        fetched.extend(chunk);
+    }
+    assert_eq!(fetched, full);
+
+    // the cursor stays at the end once the rows are consumed
+    assert!(result.fetch(7).is_empty());
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_pick_selective_index() {
+    setup();
+
+    let table_rc = new_empty_btree_table("pick", 2);
+
+    // keys 0..50, every key appears twice
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..50 {
+            for value in [key, key + 100] {
+                let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(value)], tx.get_id());
+                table.insert_tuple(&tx, &tuple).unwrap();
+            }
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    // both conditions can be served by the key index, the equality is the
+    // more selective one whichever order they are written in
+    for condition in [
+        "\"int-column-0\" IN (1, 2, 3, 4, 5) AND \"int-column-0\" = 3",
+        "\"int-column-0\" = 3 AND \"int-column-0\" IN (1, 2, 3, 4, 5)",
+    ] {
+        let sql = format!("EXPLAIN SELECT * FROM pg_catalog.pick WHERE {}", condition);
+        let plan: Vec<String> = session
+            .execute(&tx, &sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| String::from_utf8(t.get_cell(0).get_bytes().unwrap()).unwrap())
+            .collect();
+        assert_eq!(plan.len(), 2);
+        assert!(plan[0].starts_with("Filter: int-column-0 In"));
+        assert!(plan[1].starts_with("IndexSeek on pick: int-column-0 Equals"));
+
+        let sql = format!("SELECT * FROM pg_catalog.pick WHERE {}", condition);
+        let result = session.execute(&tx, &sql).unwrap().into_rows().unwrap();
+        assert_eq!(result.data.len(), 2);
+    }
+
+    // the statistics were collected by the first plan and are kept in the
+    // table for the next ones
+    let analysis = table_rc.rl().get_analysis().unwrap();
+    assert_eq!(analysis.tuples_count, 100);
+    assert_eq!(analysis.distinct_keys, 50);
+
+    // the hint names the index the plan has to use
+    let sql =
+        "EXPLAIN SELECT /*+ index(pick_pkey) */ * FROM pg_catalog.pick WHERE \"int-column-0\" = 3";
+    assert!(session.execute(&tx, sql).is_ok());
+    let sql =
+        "EXPLAIN SELECT /*+ index(pick_pkey) */ * FROM pg_catalog.pick WHERE \"int-column-1\" = 3";
+    assert!(session.execute(&tx, sql).is_err());
+    let sql =
+        "EXPLAIN SELECT /*+ index(missing) */ * FROM pg_catalog.pick WHERE \"int-column-0\" = 3";
+    assert!(session.execute(&tx, sql).is_err());
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_pick_secondary_index() {
+    setup();
+
+    let table_rc = new_empty_btree_table("pick_secondary", 2);
+
+    // 5 keys appearing 20 times each, the values are unique
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for value in 0..100 {
+            let tuple = Tuple::new(
+                &vec![Cell::Int64(value % 5), Cell::Int64(value)],
+                tx.get_id(),
+            );
+            table.insert_tuple(&tx, &tuple).unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+    session
+        .execute(
+            &tx,
+            "CREATE INDEX pick_secondary_value ON pick_secondary (\"int-column-1\")",
+        )
+        .unwrap();
+
+    let explain = |session: &mut Session, condition: &str| -> String {
+        let sql = format!(
+            "EXPLAIN SELECT * FROM pg_catalog.pick_secondary WHERE {}",
+            condition
+        );
+        let plan = session.execute(&tx, &sql).unwrap().into_rows().unwrap();
+        String::from_utf8(plan.data.last().unwrap().get_cell(0).get_bytes().unwrap()).unwrap()
+    };
+    let count = |session: &mut Session, condition: &str| -> usize {
+        let sql = format!(
+            "SELECT * FROM pg_catalog.pick_secondary WHERE {}",
+            condition
+        );
+        session
+            .execute(&tx, &sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .len()
+    };
+
+    // one value is more selective than one key
+    let condition = "\"int-column-0\" = 3 AND \"int-column-1\" = 43";
+    assert!(explain(&mut session, condition)
+        .starts_with("IndexSeek on pick_secondary using pick_secondary_value"));
+    assert_eq!(count(&mut session, condition), 1);
+
+    // 30 values are less selective than one key
+    let values: Vec<String> = (0..30).map(|v| v.to_string()).collect();
+    let condition = format!(
+        "\"int-column-0\" = 3 AND \"int-column-1\" IN ({})",
+        values.join(", ")
+    );
+    assert!(explain(&mut session, &condition)
+        .starts_with("IndexSeek on pick_secondary: int-column-0 Equals"));
+    assert_eq!(count(&mut session, &condition), 6);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_strict_point_select() {
+    setup();
+
+    let table_rc = new_empty_btree_table("strict", 2);
+    let tx = Transaction::new();
+    let tuple = Tuple::new(&vec![Cell::Int64(1), Cell::Int64(10)], tx.get_id());
+    table_rc.rl().insert_tuple(&tx, &tuple).unwrap();
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    // the hint has to be spelled exactly, it can come with other hints
+    let hints = [
+        ("", false),
+        ("/*+ strict */", true),
+        ("/*+ stricter */", false),
+        ("/*+ strict index(strict_pkey) */", true),
+    ];
+    for (hint, strict) in hints {
+        // present key
+        let sql = format!(
+            "SELECT {} * FROM pg_catalog.strict WHERE \"int-column-0\" = 1",
+            hint
+        );
+        let result = session.execute(&tx, &sql).unwrap().into_rows().unwrap();
+        assert_eq!(result.data.len(), 1);
+
+        // absent key
+        let sql = format!(
+            "SELECT {} * FROM pg_catalog.strict WHERE \"int-column-0\" = 2",
+            hint
+        );
+        let result = session.execute(&tx, &sql);
+        if !strict {
+            assert!(result.unwrap().into_rows().unwrap().data.is_empty());
+        } else {
+            assert_eq!(result.unwrap_err().kind(), SmallErrorKind::NotFound);
+        }
+    }
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_show_status() {
+    setup();
+
+    new_empty_btree_table("status", 2);
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+    let result = session
+        .execute(&tx, "SHOW STATUS")
+        .unwrap()
+        .into_rows()
+        .unwrap();
+    tx.commit().unwrap();
+
+    assert_eq!(
+        result.columns,
+        vec!["name".to_string(), "value".to_string()]
+    );
+    assert_eq!(
+        result.data[0].get_cells(),
+        vec![Cell::Bytes(b"table_count".to_vec()), Cell::Int64(1)]
+    );
+}
+
+#[test]
+fn test_where_non_key_column() {
+    setup();
+
+    let table_rc = new_empty_btree_table("filter", 2);
+
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..100 {
+            let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(key % 10)], tx.get_id());
+            table.insert_tuple(&tx, &tuple).unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let sql = "EXPLAIN SELECT * FROM pg_catalog.filter WHERE \"int-column-1\" >= 7";
+    let plan = session.execute(&tx, sql).unwrap().into_rows().unwrap();
+    assert_eq!(plan.data.len(), 2);
+    assert_eq!(
+        plan.data[1].get_cell(0),
+        Cell::Bytes(b"SeqScan on filter".to_vec())
+    );
+
+    let sql = "SELECT * FROM pg_catalog.filter WHERE \"int-column-1\" >= 7";
+    let result = session.execute(&tx, sql).unwrap().into_rows().unwrap();
+    assert_eq!(result.data.len(), 30);
+    for tuple in &result.data {
+        assert!(tuple.get_cell(1) >= Cell::Int64(7));
+    }
+
+    // the value may come first, and conditions on the key and on other
+    // columns can be mixed
+    let sql =
+        "SELECT * FROM pg_catalog.filter WHERE 3 > \"int-column-1\" AND \"int-column-0\" < 50";
+    let actual: Vec<i64> = session
+        .execute(&tx, sql)
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data
+        .iter()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    let expect: Vec<i64> = (0..50).filter(|key| key % 10 < 3).collect();
+    assert_eq!(actual, expect);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_create_drop_index() {
+    setup();
+
+    let table_rc = new_empty_btree_table("items", 2);
+
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..100 {
+            let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(key % 10)], tx.get_id());
+            table.insert_tuple(&tx, &tuple).unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    session
+        .execute(&tx, "CREATE INDEX items_value ON items (\"int-column-1\")")
+        .unwrap();
+
+    // the index is maintained by the later inserts
+    session
+        .execute(&tx, "INSERT INTO items VALUES (100, 3)")
+        .unwrap();
+
+    let condition = "\"int-column-1\" = 3";
+    let sql = format!("EXPLAIN SELECT * FROM pg_catalog.items WHERE {}", condition);
+    let plan = session.execute(&tx, &sql).unwrap().into_rows().unwrap();
+    assert_eq!(plan.data.len(), 2);
+    assert!(
+        String::from_utf8(plan.data[1].get_cell(0).get_bytes().unwrap())
+            .unwrap()
+            .starts_with("IndexSeek on items using items_value: int-column-1 In")
+    );
+
+    let sql = format!("SELECT * FROM pg_catalog.items WHERE {}", condition);
+    let keys: Vec<i64> = session
+        .execute(&tx, &sql)
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data
+        .iter()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    let mut expect: Vec<i64> = (0..100).filter(|key| key % 10 == 3).collect();
+    expect.push(100);
+    assert_eq!(keys, expect);
+
+    // and by the inserts and deletes through the table API
+    {
+        let table = table_rc.rl();
+        let tuple = Tuple::new(&vec![Cell::Int64(101), Cell::Int64(3)], tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+        let deleted = table.get_by_key(&tx, &Cell::Int64(13)).unwrap().unwrap();
+        table.delete_tuple(&tx, &deleted).unwrap();
+    }
+
+    // the duplicate values of an IN list are sought once
+    let sql = "SELECT * FROM pg_catalog.items WHERE \"int-column-1\" IN (3, 3, 4)";
+    let keys: Vec<i64> = session
+        .execute(&tx, sql)
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data
+        .iter()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    let mut expect: Vec<i64> = (0..100)
+        .filter(|key| key % 10 == 3 || key % 10 == 4)
+        .filter(|key| *key != 13)
+        .collect();
+    expect.extend([100, 101]);
+    assert_eq!(keys, expect);
+    tx.commit().unwrap();
+
+    // the index is registered again after a restart
+    drop(table_rc);
+    crash();
+    let mut session = Session::new();
+    let tx = Transaction::new();
+    let plan = session
+        .execute(&tx, &format!("EXPLAIN {}", sql))
+        .unwrap()
+        .into_rows()
+        .unwrap();
+    assert!(
+        String::from_utf8(plan.data[1].get_cell(0).get_bytes().unwrap())
+            .unwrap()
+            .starts_with("IndexSeek on items using items_value")
+    );
+    let keys: Vec<i64> = session
+        .execute(&tx, sql)
+        .unwrap()
+        .into_rows()
+        .unwrap()
+        .data
+        .iter()
+        .map(|t| t.get_cell(0).get_int64().unwrap())
+        .collect();
+    assert_eq!(keys, expect);
+    tx.commit().unwrap();
+
+    let tx = Transaction::new();
+    session.execute(&tx, "DROP INDEX items_value").unwrap();
+    let sql = format!("EXPLAIN SELECT * FROM pg_catalog.items WHERE {}", condition);
+    let plan = session.execute(&tx, &sql).unwrap().into_rows().unwrap();
+    assert_eq!(
+        plan.data[1].get_cell(0),
+        Cell::Bytes(b"SeqScan on items".to_vec())
+    );
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_error_kinds() {
+    setup();
+
+    let table_rc = new_empty_btree_table("kinds", 2);
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let err = session
+        .execute(&tx, "SELECT * FROM pg_catalog.missing")
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::NotFound);
+    assert_eq!(err.to_string(), "table missing not found");
+
+    let err = session
+        .execute(&tx, "SELECT * FROM pg_catalog.kinds WHERE missing = 1")
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::NotFound);
+
+    // a duplicate key is rejected with its own kind
+    let table = table_rc.rl();
+    let tuple = Tuple::new(&vec![Cell::Int64(1), Cell::Int64(1)], tx.get_id());
+    table.insert_tuple(&tx, &tuple).unwrap();
+    let err = table
+        .insert_tuples(&tx, &[tuple], OnConflict::Error)
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::DuplicateKey);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_exec_result() {
+    setup();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let result = session
+        .execute(
+            &tx,
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY, amount INTEGER)",
+        )
+        .unwrap();
+    assert!(matches!(result, ExecResult::Ddl));
+
+    let result = session
+        .execute(&tx, "INSERT INTO orders VALUES (1, 10), (2, 20), (3, 30)")
+        .unwrap();
+    assert_eq!(result.affected(), Some(3));
+
+    let result = session.execute(&tx, "SELECT * FROM orders").unwrap();
+    assert_eq!(result.affected(), None);
+    let rows = result.into_rows().unwrap();
+    assert_eq!(rows.columns, vec!["id".to_string(), "amount".to_string()]);
+    assert_eq!(rows.data.len(), 3);
+
+    // the table is created once, IF NOT EXISTS keeps the existing table if
+    // it has the same schema
+    session
+        .execute(
+            &tx,
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY, amount INTEGER)",
+        )
+        .unwrap_err();
+    let result = session
+        .execute(
+            &tx,
+            "CREATE TABLE IF NOT EXISTS orders (id INTEGER PRIMARY KEY, amount INTEGER)",
+        )
+        .unwrap();
+    assert!(matches!(result, ExecResult::Ddl));
+    let err = session
+        .execute(
+            &tx,
+            "CREATE TABLE IF NOT EXISTS orders (id INTEGER PRIMARY KEY)",
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::SchemaMismatch);
+    let rows = session.execute(&tx, "SELECT * FROM orders").unwrap();
+    assert_eq!(rows.into_rows().unwrap().data.len(), 3);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_max_rows() {
+    setup();
+
+    let rows = 10000;
+    new_random_btree_table(2, rows, None, 0, TreeLayout::Naturally);
+
+    let mut session = Session::new();
+    session.set_max_rows(Some(100));
+    let tx = Transaction::new();
+
+    let err = session
+        .execute(&tx, "SELECT * FROM pg_catalog.test")
+        .unwrap_err();
+    assert_eq!(err.kind(), SmallErrorKind::ResultTooLarge);
+
+    // small results and aggregates are not affected
+    let sql = "SELECT * FROM pg_catalog.test WHERE \"int-column-0\" = 42";
+    assert!(session.execute(&tx, sql).is_ok());
+    let result = session
+        .execute(&tx, "SELECT COUNT(*) FROM pg_catalog.test")
+        .unwrap()
+        .into_rows()
+        .unwrap();
+    assert_eq!(result.data[0].get_cell(0), Cell::Int64(rows as i64));
+
+    session.set_max_rows(None);
+    let result = session
+        .execute(&tx, "SELECT * FROM pg_catalog.test")
+        .unwrap()
+        .into_rows()
+        .unwrap();
+    assert_eq!(result.data.len(), rows);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_where_or() {
+    setup();
+
+    let table_rc = new_empty_btree_table("either", 2);
+
+    let tx = Transaction::new();
+    {
+        let table = table_rc.rl();
+        for key in 0..100 {
+            let tuple = Tuple::new(&vec![Cell::Int64(key), Cell::Int64(key % 10)], tx.get_id());
+            table.insert_tuple(&tx, &tuple).unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    let mut session = Session::new();
+    let tx = Transaction::new();
+
+    let explain = |session: &mut Session, sql: &str| -> Vec<String> {
+        session
+            .execute(&tx, &format!("EXPLAIN {}", sql))
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| String::from_utf8(t.get_cell(0).get_bytes().unwrap()).unwrap())
+            .collect()
+    };
+    let keys = |session: &mut Session, sql: &str| -> Vec<i64> {
+        session
+            .execute(&tx, sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| t.get_cell(0).get_int64().unwrap())
+            .collect()
+    };
+
+    // equalities on the key are served by a union of seeks, in key order
+    // and without duplicates
+    let sql = "SELECT * FROM pg_catalog.either WHERE \"int-column-0\" = 42 OR (\"int-column-0\" = 7 OR \"int-column-0\" = 42)";
+    let plan = explain(&mut session, sql);
+    assert_eq!(plan.len(), 1);
+    assert!(plan[0].starts_with("IndexSeek on either: int-column-0 In"));
+    assert_eq!(keys(&mut session, sql), vec![7, 42]);
+
+    // a disjunction across columns falls back to a full scan
+    let sql =
+        "SELECT * FROM pg_catalog.either WHERE \"int-column-1\" = 3 OR \"int-column-0\" >= 95";
+    let plan = explain(&mut session, sql);
+    assert_eq!(plan.len(), 2);
+    assert!(plan[0].starts_with("Filter: (int-column-1 Equals"));
+    assert_eq!(plan[1], "SeqScan on either");
+    let expect: Vec<i64> = (0..100).filter(|key| key % 10 == 3 || *key >= 95).collect();
+    assert_eq!(keys(&mut session, sql), expect);
+
+    // the disjunction is combined with the other conditions
+    let sql = "SELECT * FROM pg_catalog.either WHERE \"int-column-0\" < 50 AND (\"int-column-1\" = 3 OR \"int-column-1\" = 4)";
+    let expect: Vec<i64> = (0..50)
+        .filter(|key| key % 10 == 3 || key % 10 == 4)
+        .collect();
+    assert_eq!(keys(&mut session, sql), expect);
+
+    // a conjunction inside a disjunction is evaluated by a filtered scan
+    let sql = "SELECT * FROM pg_catalog.either WHERE (\"int-column-0\" < 30 AND \"int-column-1\" = 3) OR (\"int-column-0\" >= 90 AND (\"int-column-1\" = 1 OR \"int-column-1\" = 2))";
+    let plan = explain(&mut session, sql);
+    assert_eq!(
+        plan[0],
+        "Filter: ((int-column-0 LessThan 30 AND int-column-1 Equals 3) OR (int-column-0 GreaterThanOrEq 90 AND int-column-1 In [1, 2]))"
+    );
+    assert_eq!(plan[1], "SeqScan on either");
+    let expect: Vec<i64> = (0..100)
+        .filter(|key| {
+            (*key < 30 && key % 10 == 3) || (*key >= 90 && (key % 10 == 1 || key % 10 == 2))
+        })
+        .collect();
+    assert_eq!(keys(&mut session, sql), expect);
+
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_where_like() {
+    setup();
+
+    let mut session = Session::new();
+    session
+        .run("CREATE TABLE words (word VARCHAR(20) PRIMARY KEY, n INTEGER)")
+        .unwrap();
+    session
+        .run(
+            "INSERT INTO words VALUES ('apple', 1), ('apricot', 2), ('banana', 3), ('band', 4), \
+             ('bandana', 5), ('cab', 6), ('abc', 7), ('50%off', 8)",
+        )
+        .unwrap();
+
+    let explain = |session: &mut Session, sql: &str| -> Vec<String> {
+        session
+            .run(&format!("EXPLAIN {}", sql))
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| String::from_utf8(t.get_cell(0).get_bytes().unwrap()).unwrap())
+            .collect()
+    };
+    let words = |session: &mut Session, sql: &str| -> Vec<String> {
+        session
+            .run(sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| String::from_utf8(t.get_cell(0).get_bytes().unwrap()).unwrap())
+            .collect()
+    };
+
+    // a prefix pattern is served by a seek
+    let sql = "SELECT * FROM words WHERE word LIKE 'ap%'";
+    let plan = explain(&mut session, sql);
+    assert_eq!(plan[1], "PrefixSeek on words: word starts with 'ap'");
+    assert_eq!(words(&mut session, sql), vec!["apple", "apricot"]);
+
+    let sql = "SELECT * FROM words WHERE word LIKE 'band%'";
+    assert_eq!(words(&mut session, sql), vec!["band", "bandana"]);
+
+    // suffix and contains patterns scan the whole table
+    let sql = "SELECT * FROM words WHERE word LIKE '%na'";
+    let plan = explain(&mut session, sql);
+    assert_eq!(plan[1], "SeqScan on words");
+    assert_eq!(words(&mut session, sql), vec!["banana", "bandana"]);
+
+    let sql = "SELECT * FROM words WHERE word LIKE '%an%'";
+    assert_eq!(words(&mut session, sql), vec!["banana", "band", "bandana"]);
+
+    // `_` matches exactly one character
+    let sql = "SELECT * FROM words WHERE word LIKE 'ba_d'";
+    assert_eq!(words(&mut session, sql), vec!["band"]);
+
+    let sql = "SELECT * FROM words WHERE word LIKE '_a_'";
+    assert_eq!(words(&mut session, sql), vec!["cab"]);
+
+    let sql = "SELECT * FROM words WHERE word LIKE '%a_a%'";
+    assert_eq!(words(&mut session, sql), vec!["banana", "bandana"]);
+
+    // a `%` in the text is matched by a `%` of the pattern as a wildcard
+    let sql = "SELECT * FROM words WHERE word LIKE '%0%'";
+    assert_eq!(words(&mut session, sql), vec!["50%off"]);
+}
+
+#[test]
+fn test_session_transaction() {
+    setup();
+
+    let mut session = Session::new();
+    let count = |session: &mut Session| {
+        session
+            .run("SELECT * FROM accounts")
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .len()
+    };
+
+    // outside an explicit transaction the statements auto-commit
+    session
+        .run("CREATE TABLE accounts (id INTEGER PRIMARY KEY, balance INTEGER)")
+        .unwrap();
+    session.run("INSERT INTO accounts VALUES (1, 10)").unwrap();
+    assert!(session.current_tx().is_none());
+    assert_eq!(count(&mut session), 1);
+
+    // the insert is undone by the rollback
+    assert!(matches!(
+        session.run("BEGIN;").unwrap(),
+        ExecResult::Control
+    ));
+    session
+        .run("INSERT INTO accounts VALUES (2, 20), (3, 30)")
+        .unwrap();
+    assert_eq!(count(&mut session), 3);
+    session.run("ROLLBACK;").unwrap();
+    assert!(session.current_tx().is_none());
+    assert_eq!(count(&mut session), 1);
+
+    // the statements between BEGIN and COMMIT share the same transaction
+    session.run("BEGIN").unwrap();
+    let tx = session.current_tx().unwrap().clone();
+    session.run("INSERT INTO accounts VALUES (4, 40)").unwrap();
+    session.run("INSERT INTO accounts VALUES (5, 50)").unwrap();
+    assert_eq!(session.current_tx(), Some(&tx));
+    session.run("COMMIT").unwrap();
+    assert_eq!(count(&mut session), 3);
+
+    // nesting and finalizing without a transaction are rejected
+    session.run("BEGIN").unwrap();
+    assert!(session.run("BEGIN").is_err());
+    session.run("COMMIT").unwrap();
+    assert!(session.run("COMMIT").is_err());
+    assert!(session.run("ROLLBACK").is_err());
+
+    // the statements are recognized by the parser, whatever their spelling
+    session.run("/* open */ start transaction").unwrap();
+    assert!(session.current_tx().is_some());
+    session.run("commit work;").unwrap();
+    session.run("BEGIN").unwrap();
+    session.run("END").unwrap();
+    assert!(session.current_tx().is_none());
+
+    // after a failed statement the transaction block only accepts the
+    // statements ending it, its changes are rolled back
+    session.run("BEGIN").unwrap();
+    session.run("INSERT INTO accounts VALUES (6, 60)").unwrap();
+    assert!(session.run("SELECT * FROM missing").is_err());
+    let err = session.run("SELECT * FROM accounts").err().unwrap();
+    assert_eq!(err.kind(), SmallErrorKind::InactiveTransaction);
+    assert!(session.run("SAVEPOINT a").is_err());
+    assert!(session.run("BEGIN").is_err());
+    session.run("COMMIT").unwrap();
+    assert!(session.current_tx().is_none());
+    assert_eq!(count(&mut session), 3);
+
+    // a failed statement outside a transaction block only aborts itself
+    assert!(session.run("SELECT * FROM missing").is_err());
+    assert_eq!(count(&mut session), 3);
+
+    // a dropped table can't be brought back by a rollback, so DROP is
+    // rejected inside a transaction block
+    session.run("BEGIN").unwrap();
+    assert!(session.run("DROP TABLE accounts").is_err());
+    session.run("ROLLBACK").unwrap();
+
+    // several statements sent together are rejected before any of them runs
+    assert!(session
+        .run("INSERT INTO accounts VALUES (7, 70); INSERT INTO accounts VALUES (8, 80)")
+        .is_err());
+    assert!(session
+        .run("BEGIN; INSERT INTO accounts VALUES (7, 70); COMMIT")
+        .is_err());
+    assert!(session
+        .run("INSERT INTO accounts VALUES (7, 70); DROP TABLE accounts")
+        .is_err());
+    assert!(session.current_tx().is_none());
+    assert_eq!(count(&mut session), 3);
+
+    // so are the statements the executor doesn't support
+    assert!(session.run("TRUNCATE accounts").is_err());
+    assert!(session
+        .run("SELECT * FROM accounts UNION SELECT * FROM accounts")
+        .is_err());
+    assert_eq!(count(&mut session), 3);
+    session.run("DROP TABLE accounts").unwrap();
+    assert!(session.run("SELECT * FROM accounts").is_err());
+}
+
+#[test]
+fn test_savepoint() {
+    setup();
+
+    let mut session = Session::new();
+    let keys = |session: &mut Session| -> Vec<i64> {
+        session
+            .run("SELECT * FROM events")
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| t.get_cell(0).get_int64().unwrap())
+            .collect()
+    };
+    let insert = |session: &mut Session, keys: std::ops::Range<i64>| {
+        let values: Vec<String> = keys.map(|key| format!("({}, {})", key, key * 10)).collect();
+        let sql = format!("INSERT INTO events VALUES {}", values.join(", "));
+        session.run(&sql).unwrap();
+    };
+
+    session
+        .run("CREATE TABLE events (id INTEGER PRIMARY KEY, payload INTEGER)")
+        .unwrap();
+
+    // savepoints only exist in an explicit transaction
+    assert!(session.run("SAVEPOINT a").is_err());
+
+    session.run("BEGIN").unwrap();
+    insert(&mut session, 0..10);
+    session.run("SAVEPOINT a").unwrap();
+
+    // enough rows to split the pages after the savepoint
+    insert(&mut session, 10..2000);
+    assert_eq!(keys(&mut session).len(), 2000);
+
+    session.run("ROLLBACK TO SAVEPOINT a;").unwrap();
+    assert_eq!(keys(&mut session), (0..10).collect::<Vec<_>>());
+
+    // the savepoint is kept after the rollback, and the transaction goes on
+    insert(&mut session, 10..12);
+    session.run("ROLLBACK TO a").unwrap();
+    assert!(session.run("ROLLBACK TO SAVEPOINT missing").is_err());
+
+    session.run("SAVEPOINT b").unwrap();
+    session.run("RELEASE SAVEPOINT b").unwrap();
+    assert!(session.run("ROLLBACK TO b").is_err());
+
+    session.run("COMMIT").unwrap();
+
+    // only the first batch is persisted
+    assert_eq!(keys(&mut session), (0..10).collect::<Vec<_>>());
+
+    // the pages changed after the savepoint are written to disk before the
+    // rollback
+    session.run("BEGIN").unwrap();
+    session.run("SAVEPOINT a").unwrap();
+    insert(&mut session, 10..2000);
+    Database::sync().unwrap();
+    session.run("ROLLBACK TO a").unwrap();
+    assert_eq!(keys(&mut session), (0..10).collect::<Vec<_>>());
+    session.run("COMMIT").unwrap();
+    assert_eq!(keys(&mut session), (0..10).collect::<Vec<_>>());
+
+    // the schema changes after the savepoint are undone
+    session
+        .run("CREATE TABLE evolving (id INTEGER PRIMARY KEY, payload INTEGER)")
+        .unwrap();
+    session.run("BEGIN").unwrap();
+    session.run("SAVEPOINT a").unwrap();
+    session
+        .run("ALTER TABLE evolving ADD COLUMN extra INTEGER")
+        .unwrap();
+    session.run("ROLLBACK TO a").unwrap();
+    session.run("INSERT INTO evolving VALUES (1, 10)").unwrap();
+    session.run("COMMIT").unwrap();
+    let rows = session
+        .run("SELECT * FROM evolving")
+        .unwrap()
+        .into_rows()
+        .unwrap();
+    assert_eq!(rows.columns, vec!["id", "payload"]);
+    assert_eq!(rows.data.len(), 1);
+}
+
+#[test]
+fn test_cursor() {
+    setup();
+
+    let mut session = Session::new();
+    let fetch = |session: &mut Session, sql: &str| -> Vec<i64> {
+        session
+            .run(sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| t.get_cell(0).get_int64().unwrap())
+            .collect()
+    };
+
+    session
+        .run("CREATE TABLE events (id INTEGER PRIMARY KEY, payload INTEGER)")
+        .unwrap();
+    let values: Vec<String> = (0..1000)
+        .map(|key| format!("({}, {})", key, key % 2))
+        .collect();
+    session
+        .run(&format!("INSERT INTO events VALUES {}", values.join(", ")))
+        .unwrap();
+
+    // cursors only exist in an explicit transaction
+    assert!(session
+        .run("DECLARE c CURSOR FOR SELECT * FROM events")
+        .is_err());
+
+    session.run("BEGIN").unwrap();
+    session
+        .run("DECLARE c CURSOR FOR SELECT * FROM events WHERE payload = 0")
+        .unwrap();
+
+    // nothing precedes the first row
+    assert!(fetch(&mut session, "FETCH PRIOR FROM c").is_empty());
+
+    assert_eq!(fetch(&mut session, "FETCH FORWARD 3 FROM c"), vec![0, 2, 4]);
+    assert_eq!(fetch(&mut session, "FETCH NEXT FROM c"), vec![6]);
+
+    // the cursor scrolls back from its position
+    assert_eq!(fetch(&mut session, "FETCH BACKWARD 2 FROM c"), vec![4, 2]);
+    assert_eq!(fetch(&mut session, "FETCH PRIOR FROM c"), vec![0]);
+    assert!(fetch(&mut session, "FETCH PRIOR FROM c").is_empty());
+    assert_eq!(fetch(&mut session, "FETCH NEXT FROM c"), vec![0]);
+
+    let rest = fetch(&mut session, "FETCH ALL FROM c");
+    assert_eq!(rest, (2..1000).step_by(2).collect::<Vec<_>>());
+    assert!(fetch(&mut session, "FETCH FORWARD 5 FROM c").is_empty());
+    assert_eq!(
+        fetch(&mut session, "FETCH BACKWARD 3 FROM c"),
+        vec![998, 996, 994]
+    );
+
+    session.run("CLOSE c").unwrap();
+    assert!(session.run("FETCH NEXT FROM c").is_err());
+
+    // rolling back to a savepoint closes the cursors declared after it
+    session
+        .run("DECLARE before_sp CURSOR FOR SELECT * FROM events")
+        .unwrap();
+    session.run("SAVEPOINT sp").unwrap();
+    session
+        .run("DECLARE after_sp CURSOR FOR SELECT * FROM events")
+        .unwrap();
+    session.run("ROLLBACK TO SAVEPOINT sp").unwrap();
+    assert!(session.run("FETCH NEXT FROM after_sp").is_err());
+    assert_eq!(fetch(&mut session, "FETCH NEXT FROM before_sp"), vec![0]);
+    session.run("CLOSE ALL").unwrap();
+
+    // the cursors are closed with the transaction
+    session
+        .run("DECLARE d CURSOR FOR SELECT * FROM events")
+        .unwrap();
+    session.run("COMMIT").unwrap();
+    assert!(session.run("FETCH NEXT FROM d").is_err());
+}
+
+#[test]
+fn test_cursor_snapshot() {
+    setup();
+
+    let mut session = Session::new();
+    let fetch = |session: &mut Session, sql: &str| -> Vec<i64> {
+        session
+            .run(sql)
+            .unwrap()
+            .into_rows()
+            .unwrap()
+            .data
+            .iter()
+            .map(|t| t.get_cell(0).get_int64().unwrap())
+            .collect()
+    };
+
+    session
+        .run("CREATE TABLE snapshot_events (id INTEGER PRIMARY KEY, payload INTEGER)")
+        .unwrap();
+    let values: Vec<String> = (0..100)
+        .map(|key| format!("({}, {})", key, key % 2))
+        .collect();
+    session
+        .run(&format!(
+            "INSERT INTO snapshot_events VALUES {}",
+            values.join(", ")
+        ))
+        .unwrap();
+
+    // the rows come in key order with all the columns
+    session.run("BEGIN").unwrap();
+    for query in [
+        "SELECT id FROM snapshot_events",
+        "SELECT * FROM snapshot_events ORDER BY id DESC",
+        "SELECT * FROM snapshot_events LIMIT 10",
+    ] {
+        assert!(session
+            .run(&format!("DECLARE c CURSOR FOR {}", query))
+            .is_err());
+        session.run("ROLLBACK").unwrap();
+        session.run("BEGIN").unwrap();
+    }
+    session.run("ROLLBACK").unwrap();
+
+    // a transaction in progress when the cursor is declared commits before
+    // the rows are fetched
+    let writer = Transaction::new();
+    session.run("BEGIN").unwrap();
+    session
+        .run("DECLARE c CURSOR FOR SELECT * FROM snapshot_events WHERE payload = 0")
+        .unwrap();
+    {
+        let table_rc = Database::catalog().search_table("snapshot_events").unwrap();
+        let table = table_rc.rl();
+        let predicate = Predicate::new(table.key_field, Op::Equals, &Cell::Int64(2));
+        table.delete_tuples(&writer, &predicate).unwrap();
+        let tuple = Tuple::new(&vec![Cell::Int64(100), Cell::Int64(0)], writer.get_id());
+        table.insert_tuple(&writer, &tuple).unwrap();
+    }
+    writer.commit().unwrap();
+
+    // its changes are not seen by the cursor
+    let rows = fetch(&mut session, "FETCH ALL FROM c");
+    assert_eq!(rows, (0..100).step_by(2).collect::<Vec<_>>());
+    assert_eq!(fetch(&mut session, "FETCH BACKWARD 2 FROM c"), vec![98, 96]);
+    assert_eq!(
+        fetch(&mut session, "FETCH BACKWARD ALL FROM c"),
+        (0..48).rev().map(|i| i * 2).collect::<Vec<_>>()
+    );
+    session.run("COMMIT").unwrap();
+}
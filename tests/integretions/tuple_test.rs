@@ -0,0 +1,157 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use small_db::{
+    common::Catalog,
+    storage::{
+        table_schema::{Field, Type},
+        tuple::{Cell, Tuple},
+    },
+    transaction::Transaction,
+    utils::HandyRwLock,
+    BTreeTable, TableSchema,
+};
+
+use crate::test_utils::setup;
+
+#[test]
+fn test_tuple_hash() {
+    setup();
+
+    let tx_1 = Transaction::new();
+    let tx_2 = Transaction::new();
+
+    let new_tuple = |cells: Vec<Cell>, tx: &Transaction| Tuple::new(&cells, tx.get_id());
+
+    let mut set = HashSet::new();
+
+    // equal cells, the creating transaction doesn't matter
+    set.insert(new_tuple(
+        vec![Cell::Int64(1), Cell::Bytes(b"a".to_vec())],
+        &tx_1,
+    ));
+    set.insert(new_tuple(
+        vec![Cell::Int64(1), Cell::Bytes(b"a".to_vec())],
+        &tx_2,
+    ));
+    assert_eq!(set.len(), 1);
+
+    // unequal cells
+    set.insert(new_tuple(
+        vec![Cell::Int64(1), Cell::Bytes(b"b".to_vec())],
+        &tx_1,
+    ));
+    set.insert(new_tuple(
+        vec![Cell::Int64(2), Cell::Bytes(b"a".to_vec())],
+        &tx_1,
+    ));
+    assert_eq!(set.len(), 3);
+
+    // NaN is equal to NaN, 0.0 is equal to -0.0
+    set.insert(new_tuple(vec![Cell::Float64(f64::NAN)], &tx_1));
+    set.insert(new_tuple(vec![Cell::Float64(-f64::NAN)], &tx_1));
+    set.insert(new_tuple(vec![Cell::Float64(0.0)], &tx_1));
+    set.insert(new_tuple(vec![Cell::Float64(-0.0)], &tx_1));
+    assert_eq!(set.len(), 5);
+
+    // cells of different variants are different
+    set.insert(new_tuple(vec![Cell::Bool(true)], &tx_1));
+    set.insert(new_tuple(vec![Cell::Int64(1)], &tx_1));
+    set.insert(new_tuple(vec![Cell::Null], &tx_1));
+    set.insert(new_tuple(vec![Cell::Null], &tx_1));
+    assert_eq!(set.len(), 8);
+
+    tx_1.commit().unwrap();
+    tx_2.commit().unwrap();
+}
+
+#[test]
+fn test_cell_order() {
+    let mut cells = vec![
+        Cell::Bytes(b"b".to_vec()),
+        Cell::Float64(f64::NAN),
+        Cell::Float64(1.5),
+        Cell::Bytes(b"ab".to_vec()),
+        Cell::Int64(-3),
+        Cell::Float64(f64::NEG_INFINITY),
+        Cell::Null,
+        Cell::Bool(true),
+        Cell::Bytes(b"a".to_vec()),
+        Cell::Bool(false),
+    ];
+    cells.sort();
+    assert_eq!(
+        cells,
+        vec![
+            Cell::Null,
+            Cell::Bool(false),
+            Cell::Bool(true),
+            Cell::Int64(-3),
+            Cell::Float64(f64::NEG_INFINITY),
+            Cell::Float64(1.5),
+            Cell::Float64(f64::NAN),
+            Cell::Bytes(b"a".to_vec()),
+            Cell::Bytes(b"ab".to_vec()),
+            Cell::Bytes(b"b".to_vec()),
+        ]
+    );
+
+    // consistent with the equality
+    assert_eq!(
+        Cell::Float64(-0.0).cmp(&Cell::Float64(0.0)),
+        std::cmp::Ordering::Equal
+    );
+    assert_eq!(
+        Cell::Float64(f64::NAN).cmp(&Cell::Float64(-f64::NAN)),
+        std::cmp::Ordering::Equal
+    );
+}
+
+#[test]
+fn test_random_tuple() {
+    setup();
+
+    let schema = TableSchema::new(vec![
+        Field::new("id", Type::Int64, true),
+        Field::new("score", Type::Float64, false),
+        Field::new("name", Type::Bytes(20), false),
+        Field::new("active", Type::Bool, false),
+        Field::new_varint("visits", false),
+    ]);
+    let table_rc = Arc::new(RwLock::new(BTreeTable::new("random", None, &schema)));
+    Catalog::add_table(table_rc.clone(), true).unwrap();
+    let table = table_rc.rl();
+
+    let mut rng = rand::thread_rng();
+    let rows = 10000;
+    let tx = Transaction::new();
+    for _ in 0..rows {
+        let tuple = Tuple::random(&schema, &mut rng, tx.get_id());
+        table.insert_tuple(&tx, &tuple).unwrap();
+    }
+    tx.commit().unwrap();
+
+    table.check_integrity();
+    let tx = Transaction::new();
+    assert_eq!(table.iter(&tx).count(), rows);
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_cell_display_string() {
+    assert_eq!(Cell::Null.to_display_string(), "NULL");
+    assert_eq!(Cell::Bool(true).to_display_string(), "true");
+    assert_eq!(Cell::Int64(-42).to_display_string(), "-42");
+    assert_eq!(Cell::Float64(1.5).to_display_string(), "1.500000");
+    assert_eq!(Cell::Float64(f64::NAN).to_display_string(), "NaN");
+
+    let text = Cell::Bytes(b"it's".to_vec());
+    assert_eq!(text.to_display_string(), "it's");
+    assert_eq!(text.to_display_string_with(true), "'it''s'");
+
+    // only the strings are quoted
+    assert_eq!(Cell::Null.to_display_string_with(true), "NULL");
+    assert_eq!(Cell::Int64(7).to_display_string_with(true), "7");
+}
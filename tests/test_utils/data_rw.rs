@@ -10,7 +10,7 @@ use super::new_int_tuples;
 
 pub fn delete_tuples(table: &BTreeTable, count: usize) {
     let tx = Transaction::new();
-    let mut it = BTreeTableIterator::new(&tx, &table);
+    let mut it = BTreeTableIterator::new(&tx, &table).unwrap();
     for _ in 0..count {
         table.delete_tuple(&tx, &it.next().unwrap()).unwrap();
     }
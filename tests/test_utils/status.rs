@@ -21,7 +21,6 @@ use small_db::{
     BTreeTable, Database, TableSchema,
 };
 
-use super::internal_children_cap;
 use crate::test_utils::{debug::print_features, new_int_tuples};
 
 pub const TEST_DB: &str = "test";
@@ -64,7 +63,7 @@ pub enum TreeLayout {
 pub fn new_empty_btree_table(table_name: &str, columns: usize) -> Arc<RwLock<BTreeTable>> {
     let schema = TableSchema::small_int_schema(columns);
     let table_rc = Arc::new(RwLock::new(BTreeTable::new(table_name, None, &schema)));
-    Catalog::add_table(Arc::clone(&table_rc), true);
+    Catalog::add_table(Arc::clone(&table_rc), true).unwrap();
     return table_rc;
 }
 
@@ -89,7 +88,7 @@ pub fn new_random_btree_table(
 ) -> Arc<RwLock<BTreeTable>> {
     let schema = TableSchema::small_int_schema(columns);
     let table_rc = Arc::new(RwLock::new(BTreeTable::new(TEST_DB, None, &schema)));
-    Catalog::add_table(Arc::clone(&table_rc), true);
+    Catalog::add_table(Arc::clone(&table_rc), true).unwrap();
 
     let mut write_tx = Transaction::new();
 
@@ -215,7 +214,7 @@ fn sequential_insert_into_table(
     }
 
     // stage 2: write internal pages
-    let interanl_buckets = get_buckets(leaf_buckets.len(), internal_children_cap(), tree_layout);
+    let interanl_buckets = get_buckets(leaf_buckets.len(), table.get_fan_out(), tree_layout);
 
     // leaf index in the leaves vector
     let mut leaf_index = 0;
@@ -275,7 +274,7 @@ fn write_internal_pages(
         let internal = internals[0].rl();
         table.set_root_pid(tx, &internal.get_pid());
         return *page_index;
-    } else if internals.len() <= internal_children_cap() {
+    } else if internals.len() <= table.get_fan_out() {
         // write a new internal page (the root page)
         *page_index += 1;
         let pid = BTreePageID::new(
@@ -299,6 +298,7 @@ fn write_internal_pages(
                 let key = table
                     .get_last_tuple(tx, &left_rc.rl().get_pid())
                     .unwrap()
+                    .unwrap()
                     .get_cell(table.key_field);
                 // borrow of right_rc ends here
 
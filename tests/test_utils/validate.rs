@@ -7,7 +7,7 @@ use small_db::{
 // Search for a key in the table and return the number of records.
 pub fn search_key(table: &BTreeTable, tx: &Transaction, key: &Cell) -> usize {
     let predicate = Predicate::new(table.key_field, Op::Equals, key);
-    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate);
+    let it = BTreeTableSearchIterator::new(&tx, &table, &predicate).unwrap();
     return it.count();
 }
 